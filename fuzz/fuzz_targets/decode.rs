@@ -0,0 +1,8 @@
+#![no_main]
+
+// Decode, re-encode and disassemble random words (c.f., src/fuzz.rs).
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    rvsim::fuzz::decode(data);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+// Run random words as a program on a fresh hart (c.f., src/fuzz.rs).
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    rvsim::fuzz::execute(data);
+});
@@ -0,0 +1,85 @@
+"""RISCOF DUT plugin for the simulator.
+
+Each test is compiled with the GNU toolchain against env/model_test.h and env/link.ld, then run as
+
+    rvsim --isa <ISA from the ISA YAML> --signature <test>/DUT-rvsim.signature my.elf
+
+The simulator configures the hart with the extensions of the ISA string (the others are illegal),
+runs the test until it writes tohost and dumps the memory between begin_signature and end_signature
+as 32-bit hex lines.
+"""
+
+import logging
+import os
+
+import riscof.utils as utils
+from riscof.pluginTemplate import pluginTemplate
+
+logger = logging.getLogger()
+
+
+class rvsim(pluginTemplate):
+    __model__ = "rvsim"
+    __version__ = "0.1.0"
+
+    def __init__(self, *args, **kwargs):
+        super().__init__(*args, **kwargs)
+
+        config = kwargs.get("config")
+        if config is None:
+            print("Please enter input file paths in configuration.")
+            raise SystemExit(1)
+
+        # the simulator binary, in PATH if no directory is configured
+        self.dut_exe = os.path.join(config.get("PATH", ""), "rvsim")
+        self.num_jobs = str(config.get("jobs", 1))
+        self.pluginpath = os.path.abspath(config["pluginpath"])
+        self.isa_spec = os.path.abspath(config["ispec"])
+        self.platform_spec = os.path.abspath(config["pspec"])
+        # target_run=0 only compiles the tests
+        self.target_run = config.get("target_run", "1") != "0"
+
+    def initialise(self, suite, work_dir, archtest_env):
+        self.work_dir = work_dir
+        self.suite_dir = suite
+        self.compile_cmd = (
+            "riscv{1}-unknown-elf-gcc -march={0} -static -mcmodel=medany -fvisibility=hidden"
+            " -nostdlib -nostartfiles -g"
+            " -T " + self.pluginpath + "/env/link.ld"
+            " -I " + self.pluginpath + "/env/"
+            " -I " + archtest_env + " {2} -o {3} {4}"
+        )
+
+    def build(self, isa_yaml, platform_yaml):
+        ispec = utils.load_yaml(isa_yaml)["hart0"]
+        self.xlen = "64" if 64 in ispec["supported_xlen"] else "32"
+        # the simulator reads riscv-config ISA strings as they are
+        self.isa = ispec["ISA"]
+        self.compile_cmd += " -mabi=" + ("lp64 " if self.xlen == "64" else "ilp32 ")
+
+    def runTests(self, testList):
+        makefile = os.path.join(self.work_dir, "Makefile." + self.name[:-1])
+        if os.path.exists(makefile):
+            os.remove(makefile)
+        make = utils.makeUtil(makefilePath=makefile)
+        make.makeCommand = "make -k -j" + self.num_jobs
+
+        for testname in testList:
+            testentry = testList[testname]
+            test = testentry["test_path"]
+            test_dir = testentry["work_dir"]
+            elf = "my.elf"
+            sig_file = os.path.join(test_dir, self.name[:-1] + ".signature")
+
+            compile_macros = " -D" + " -D".join(testentry["macros"])
+            cmd = self.compile_cmd.format(testentry["isa"].lower(), self.xlen, test, elf, compile_macros)
+            if self.target_run:
+                simcmd = "{0} --isa {1} --signature {2} {3}".format(self.dut_exe, self.isa, sig_file, elf)
+            else:
+                simcmd = 'echo "NO RUN"'
+            make.add_target("@cd {0}; {1}; {2};".format(test_dir, cmd, simcmd))
+
+        make.execute_all(self.work_dir)
+
+        if not self.target_run:
+            raise SystemExit(0)
@@ -0,0 +1,785 @@
+use std::collections::HashMap;
+
+use crate::csr;
+use crate::inst::{Format, Inst, Op};
+use crate::memory::{Memory, RAM_BASE};
+use crate::register::Reg;
+
+// Assembler for GNU-syntax RV32 assembly (the instructions of inst::OPS), for tests and quick
+// experiments. It understands labels, `.text`/`.data`, the data directives (.word, .half, .byte,
+// .ascii, .asciz, .zero), .align, .equ, %hi/%lo, numeric local labels (1: ... 1b/1f) and the usual
+// pseudo-instructions. There is no linker: .text is placed at the origin and .data right after it,
+// and every reference is resolved in place.
+//
+// Two passes: the first lays out the statements and defines the labels, the second encodes them.
+// An instruction's size must not depend on a label, so `li` with a label operand always takes
+// lui+addi, as do la, call and tail.
+
+// An assembled program: `image` is loaded at `origin`, execution starts at `entry` (`_start`, or
+// the origin if there is no such label).
+#[derive(Debug)]
+pub struct Program {
+    pub origin      : u32,
+    pub entry       : u32,
+    pub image       : Vec<u8>,
+    pub symbols     : HashMap<String, u32>,
+}
+
+impl Program {
+    // Copy the image into a memory mapped at `base`.
+    pub fn load(&self, mem: &mut Memory, base: u32) {
+        mem.loadImage(self.origin - base, &self.image);
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<u32> {
+        self.symbols.get(name).copied()
+    }
+}
+
+// Assemble a program to be loaded at the start of RAM.
+pub fn assemble(src: &str) -> Result<Program, String> {
+    assembleAt(src, RAM_BASE)
+}
+
+pub fn assembleAt(src: &str, origin: u32) -> Result<Program, String> {
+    let mut a = Assembler::new();
+    for (n, line) in src.lines().enumerate() {
+        a.line(line).map_err(|e| format!("line {}: {}", n + 1, e))?;
+        a.lineno += 1;
+    }
+    a.finish(origin)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Section {
+    Text,
+    Data,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Inst(String, Vec<String>),      // mnemonic and operands
+    Values(u32, Vec<String>),       // .word/.half/.byte: width and expressions
+    Bytes(Vec<u8>),
+    Align(u32, u8),                 // padding: alignment and fill byte
+}
+
+#[derive(Debug)]
+struct Stmt {
+    lineno      : usize,
+    section     : Section,
+    offset      : u32,
+    kind        : Kind,
+}
+
+struct Assembler {
+    lineno      : usize,
+    section     : Section,
+    size        : [u32; 2],         // bytes in .text, .data so far
+    align       : [u32; 2],         // largest alignment requested in each section
+    stmts       : Vec<Stmt>,
+    labels      : HashMap<String, (Section, u32)>,
+    consts      : HashMap<String, i64>,
+    locals      : HashMap<String, u32>,   // definitions of each numeric label so far
+}
+
+// Operands are separated by commas outside parentheses and quotes.
+fn splitOperands(s: &str) -> Vec<String> {
+    let mut out = vec![];
+    let mut cur = String::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in s.chars() {
+        match c {
+            _ if escaped            => escaped = false,
+            '\\' if quoted          => escaped = true,
+            '"'                     => quoted = !quoted,
+            '(' if !quoted          => depth += 1,
+            ')' if !quoted          => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                out.push(cur.trim().to_string());
+                cur.clear();
+                continue;
+            },
+            _                       => {},
+        }
+        cur.push(c);
+    }
+    if !cur.trim().is_empty() || !out.is_empty() {
+        out.push(cur.trim().to_string());
+    }
+    out
+}
+
+// Text before a '#' comment (not inside a string).
+fn stripComment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped    => escaped = false,
+            '\\' if quoted  => escaped = true,
+            '"'             => quoted = !quoted,
+            '#' if !quoted  => return &line[..i],
+            _               => {},
+        }
+    }
+    line
+}
+
+fn isSymbolChar(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'
+}
+
+fn parseString(s: &str) -> Result<Vec<u8>, String> {
+    let inner = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')).ok_or(format!("expected a string, found '{}'", s))?;
+    let mut out = vec![];
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        out.push(match chars.next() {
+            Some('n')   => b'\n',
+            Some('t')   => b'\t',
+            Some('r')   => b'\r',
+            Some('0')   => 0,
+            Some('\\')  => b'\\',
+            Some('"')   => b'"',
+            Some('\'')  => b'\'',
+            c           => return Err(format!("unknown escape sequence '\\{}'", c.map_or(String::new(), |c| c.to_string()))),
+        });
+    }
+    Ok(out)
+}
+
+fn register(s: &str) -> Result<u32, String> {
+    Reg::parse(s).ok_or(format!("expected a register, found '{}'", s))
+}
+
+// "off(reg)", "(reg)"; the offset is an expression, possibly %lo(...).
+fn splitMemory(s: &str) -> Result<(&str, u32), String> {
+    let open = s.rfind('(').ok_or(format!("expected a memory operand, found '{}'", s))?;
+    let reg = s[open + 1..].strip_suffix(')').ok_or(format!("expected a memory operand, found '{}'", s))?;
+    Ok((s[..open].trim(), register(reg.trim())?))
+}
+
+// pred/succ of FENCE: a subset of "iorw"
+fn fenceSet(s: &str) -> Result<i32, String> {
+    let mut bits = 0;
+    for c in s.chars() {
+        bits |= match c {
+            'i' => 8,
+            'o' => 4,
+            'r' => 2,
+            'w' => 1,
+            _   => return Err(format!("bad fence operand '{}'", s)),
+        };
+    }
+    Ok(bits)
+}
+
+// Numeric labels may be defined many times; this is the symbol of the k-th definition of n.
+fn localName(n: &str, k: u32) -> String {
+    format!(".L{}${}", n, k)
+}
+
+fn hi(v: i64) -> i64 {
+    ((v + 0x800) >> 12) & 0xFFFFF
+}
+
+fn lo(v: i64) -> i64 {
+    ((v as i32) << 20 >> 20) as i64
+}
+
+fn fits(v: i64, bits: u32) -> bool {
+    -(1 << (bits - 1)) <= v && v < (1 << (bits - 1))
+}
+
+// Expression parser: numbers ('c', 0x.., 0b.., decimal), symbols, C's binary operators, unary - ~,
+// parentheses, %hi() and %lo(). `lookup` resolves symbols.
+struct Expr<'a, F: Fn(&str) -> Option<i64>> {
+    s           : &'a [u8],
+    pos         : usize,
+    lookup      : F,
+}
+
+impl<'a, F: Fn(&str) -> Option<i64>> Expr<'a, F> {
+    fn skip(&mut self) {
+        while self.pos < self.s.len() && self.s[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        self.skip();
+        if self.s.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn word(&mut self) -> &'a str {
+        self.skip();
+        let start = self.pos;
+        while self.pos < self.s.len() && isSymbolChar(self.s[self.pos] as char) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.s[start..self.pos]).unwrap_or("")
+    }
+
+    fn expr(&mut self) -> Result<i64, String> {
+        self.binary(0)
+    }
+
+    // Binary operators by precedence, as in C: | ^ & (<< >>) (+ -) (* / %).
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        const LEVELS: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut v = self.binary(level + 1)?;
+        loop {
+            self.skip();
+            let op = match LEVELS[level].iter().find(|op| self.s[self.pos..].starts_with(op.as_bytes())) {
+                Some(op)    => *op,
+                None        => return Ok(v),
+            };
+            self.pos += op.len();
+            let r = self.binary(level + 1)?;
+            v = match op {
+                "|"     => v | r,
+                "^"     => v ^ r,
+                "&"     => v & r,
+                "<<"    => v.wrapping_shl(r as u32),
+                ">>"    => v.wrapping_shr(r as u32),
+                "+"     => v.wrapping_add(r),
+                "-"     => v.wrapping_sub(r),
+                "*"     => v.wrapping_mul(r),
+                _ if r == 0 => return Err("division by zero".to_string()),
+                "/"     => v.wrapping_div(r),
+                _       => v.wrapping_rem(r),
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        if self.eat(b'-') {
+            return Ok(self.unary()?.wrapping_neg());
+        }
+        if self.eat(b'~') {
+            return Ok(!self.unary()?);
+        }
+        if self.eat(b'(') {
+            let v = self.expr()?;
+            return if self.eat(b')') { Ok(v) } else { Err("missing ')'".to_string()) };
+        }
+        if self.eat(b'%') {
+            let f = self.word();
+            if !self.eat(b'(') {
+                return Err(format!("expected '(' after %{}", f));
+            }
+            let v = self.expr()?;
+            if !self.eat(b')') {
+                return Err("missing ')'".to_string());
+            }
+            return match f {
+                "hi"    => Ok(hi(v)),
+                "lo"    => Ok(lo(v)),
+                _       => Err(format!("unknown relocation %{}", f)),
+            };
+        }
+        if self.eat(b'\'') {
+            let c = *self.s.get(self.pos).ok_or("bad character constant")? as i64;
+            self.pos += 1;
+            return if self.eat(b'\'') { Ok(c) } else { Err("bad character constant".to_string()) };
+        }
+        let w = self.word();
+        if w.is_empty() {
+            return Err(format!("expected an expression, found '{}'", String::from_utf8_lossy(&self.s[self.pos..])));
+        }
+        if w.as_bytes()[0].is_ascii_digit() {
+            let n = w.replace('_', "");
+            let v = if let Some(h) = n.strip_prefix("0x").or(n.strip_prefix("0X")) {
+                i64::from_str_radix(h, 16)
+            } else if let Some(b) = n.strip_prefix("0b").or(n.strip_prefix("0B")) {
+                i64::from_str_radix(b, 2)
+            } else {
+                n.parse::<i64>()
+            };
+            return v.map_err(|_| format!("bad number '{}'", w));
+        }
+        (self.lookup)(w).ok_or(format!("undefined symbol '{}'", w))
+    }
+}
+
+impl Assembler {
+    fn new() -> Assembler {
+        Assembler {
+            lineno      : 0,
+            section     : Section::Text,
+            size        : [0; 2],
+            align       : [4; 2],
+            stmts       : vec![],
+            labels      : HashMap::new(),
+            consts      : HashMap::new(),
+            locals      : HashMap::new(),
+        }
+    }
+
+    fn push(&mut self, kind: Kind, size: u32) {
+        let s = self.section as usize;
+        self.stmts.push(Stmt { lineno: self.lineno, section: self.section, offset: self.size[s], kind: kind });
+        self.size[s] += size;
+    }
+
+    // Replace references to numeric labels ("1b", "2f") with the name of the definition they mean.
+    fn localRefs(&self, operand: &str) -> String {
+        let mut out = String::new();
+        let mut chars = operand.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if !isSymbolChar(c) {
+                out.push(c);
+                continue;
+            }
+            let mut end = i + c.len_utf8();
+            while let Some(&(j, d)) = chars.peek() {
+                if !isSymbolChar(d) {
+                    break;
+                }
+                end = j + d.len_utf8();
+                chars.next();
+            }
+            let word = &operand[i..end];
+            let (n, dir) = word.split_at(word.len() - 1);
+            let defined = self.locals.get(n).copied().unwrap_or(0);
+            match dir {
+                "b" if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()) => out += &localName(n, defined),
+                "f" if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()) => out += &localName(n, defined + 1),
+                _   => out += word,
+            }
+        }
+        out
+    }
+
+    // Value of an expression that only uses .equ constants (None if it refers to a label).
+    fn constant(&self, e: &str) -> Option<i64> {
+        self.eval(e, |n| self.consts.get(n).copied()).ok()
+    }
+
+    fn eval<F: Fn(&str) -> Option<i64>>(&self, e: &str, lookup: F) -> Result<i64, String> {
+        let mut p = Expr { s: e.as_bytes(), pos: 0, lookup: lookup };
+        let v = p.expr()?;
+        p.skip();
+        if p.pos != p.s.len() {
+            return Err(format!("junk at the end of expression '{}'", e));
+        }
+        Ok(v)
+    }
+
+    // First pass: one source line.
+    fn line(&mut self, line: &str) -> Result<(), String> {
+        let mut rest = stripComment(line).trim();
+        // labels
+        while let Some(colon) = rest.find(':') {
+            let name = &rest[..colon];
+            if name.is_empty() || !name.chars().all(isSymbolChar) {
+                break;
+            }
+            let name = if name.chars().all(|c| c.is_ascii_digit()) {
+                let k = self.locals.entry(name.to_string()).or_insert(0);
+                *k += 1;
+                localName(name, *k)
+            } else {
+                name.to_string()
+            };
+            let s = self.section as usize;
+            if self.labels.insert(name.clone(), (self.section, self.size[s])).is_some() {
+                return Err(format!("symbol '{}' is already defined", name));
+            }
+            rest = rest[colon + 1..].trim();
+        }
+        if rest.is_empty() {
+            return Ok(());
+        }
+        let (head, args) = match rest.find(char::is_whitespace) {
+            Some(i) => (&rest[..i], rest[i..].trim()),
+            None    => (rest, ""),
+        };
+        let head = head.to_ascii_lowercase();
+        let operands: Vec<String> = splitOperands(args).iter()
+            .map(|o| if o.starts_with('"') { o.clone() } else { self.localRefs(o) })
+            .collect();
+        if head.starts_with('.') {
+            return self.directive(&head, args, operands);
+        }
+        let size = self.size(&head, &operands)?;
+        self.push(Kind::Inst(head, operands), size);
+        Ok(())
+    }
+
+    fn directive(&mut self, name: &str, args: &str, operands: Vec<String>) -> Result<(), String> {
+        match name {
+            ".text"                 => self.section = Section::Text,
+            ".data" | ".rodata" | ".bss" => self.section = Section::Data,
+            ".section"              => {
+                let s = operands.first().map(|s| s.as_str()).unwrap_or("");
+                self.section = if s.starts_with(".text") { Section::Text } else { Section::Data };
+            },
+            ".word" | ".4byte" | ".long" => self.values(4, operands),
+            ".half" | ".2byte" | ".short" => self.values(2, operands),
+            ".byte"                 => self.values(1, operands),
+            ".ascii" | ".asciz" | ".string" => {
+                for s in &operands {
+                    let mut bytes = parseString(s)?;
+                    if name != ".ascii" {
+                        bytes.push(0);
+                    }
+                    let n = bytes.len() as u32;
+                    self.push(Kind::Bytes(bytes), n);
+                }
+            },
+            ".zero" | ".space" | ".skip" => {
+                let n = operands.first().and_then(|e| self.constant(e)).ok_or(format!("{} needs a constant size", name))?;
+                let fill = operands.get(1).and_then(|e| self.constant(e)).unwrap_or(0) as u8;
+                self.push(Kind::Bytes(vec![fill; n as usize]), n as u32);
+            },
+            // .align is a power of two on RISC-V, as .p2align; .balign is in bytes
+            ".align" | ".p2align" | ".balign" => {
+                let n = operands.first().and_then(|e| self.constant(e)).ok_or(format!("{} needs a constant", name))? as u32;
+                let bytes = if name == ".balign" { n } else { 1u32.checked_shl(n).unwrap_or(0) };
+                if !bytes.is_power_of_two() {
+                    return Err(format!("bad alignment {}", n));
+                }
+                let s = self.section as usize;
+                self.align[s] = self.align[s].max(bytes);
+                let pad = self.size[s].wrapping_neg() & (bytes - 1);
+                let fill = operands.get(1).and_then(|e| self.constant(e)).unwrap_or(0) as u8;
+                self.push(Kind::Align(bytes, fill), pad);
+            },
+            ".equ" | ".set"         => {
+                let (sym, e) = match operands.as_slice() {
+                    [s, e]  => (s, e),
+                    _       => return Err(format!("usage: {} name, value", name)),
+                };
+                let v = self.constant(e).ok_or(format!("{}: '{}' is not a constant", name, e))?;
+                self.consts.insert(sym.clone(), v);
+            },
+            // accepted for compatibility, no effect on a single flat image
+            ".globl" | ".global" | ".local" | ".type" | ".size" | ".option" | ".file" | ".ident"
+                | ".attribute" | ".cfi_startproc" | ".cfi_endproc" | ".loc" => {},
+            _                       => return Err(format!("unknown directive '{}{}{}'", name, if args.is_empty() { "" } else { " " }, args)),
+        }
+        Ok(())
+    }
+
+    fn values(&mut self, width: u32, operands: Vec<String>) {
+        let n = width * operands.len() as u32;
+        self.push(Kind::Values(width, operands), n);
+    }
+
+    // Size of an instruction statement, known without the labels.
+    fn size(&self, mnemonic: &str, operands: &[String]) -> Result<u32, String> {
+        let n = match mnemonic {
+            "li"                    => match operands.get(1).and_then(|e| self.constant(e)) {
+                Some(v) if fits(v, 12) || lo(v) == 0 => 1,
+                _                   => 2,
+            },
+            "la" | "lla" | "call" | "tail" => 2,
+            _                       => 1,
+        };
+        Ok(4 * n)
+    }
+
+    // Second pass: lay the sections out and encode every statement.
+    fn finish(self, origin: u32) -> Result<Program, String> {
+        let text = origin;
+        let align = self.align[Section::Data as usize];
+        let data = (text + self.size[Section::Text as usize] + align - 1) & !(align - 1);
+        let base = |s: Section| if s == Section::Text { text } else { data };
+
+        let mut symbols: HashMap<String, u32> = self.labels.iter().map(|(n, &(s, off))| (n.clone(), base(s) + off)).collect();
+        for (n, &v) in &self.consts {
+            symbols.entry(n.clone()).or_insert(v as u32);
+        }
+        let end = data + self.size[Section::Data as usize];
+        let mut image = vec![0u8; (end - origin) as usize];
+
+        for st in &self.stmts {
+            let at = (base(st.section) + st.offset - origin) as usize;
+            let pc = base(st.section) + st.offset;
+            let lookup = |n: &str| symbols.get(n).map(|&v| v as i64);
+            let bytes = match &st.kind {
+                Kind::Inst(m, ops)      => {
+                    let mut out = vec![];
+                    for i in self.expand(m, ops, pc, &lookup).map_err(|e| format!("line {}: {}", st.lineno + 1, e))? {
+                        out.extend_from_slice(&i.encode().map_err(|e| format!("line {}: {}", st.lineno + 1, e))?.to_le_bytes());
+                    }
+                    out
+                },
+                Kind::Values(w, exprs)  => {
+                    let mut out = vec![];
+                    for e in exprs {
+                        let v = self.eval(e, lookup).map_err(|e| format!("line {}: {}", st.lineno + 1, e))?;
+                        out.extend_from_slice(&(v as u32).to_le_bytes()[..*w as usize]);
+                    }
+                    out
+                },
+                Kind::Bytes(b)          => b.clone(),
+                Kind::Align(n, fill)    => {
+                    let pad = (pc.wrapping_neg() & (n - 1)) as usize;
+                    // code is padded with nops
+                    if st.section == Section::Text && *fill == 0 && pad.is_multiple_of(4) {
+                        0x0000_0013u32.to_le_bytes().repeat(pad / 4)
+                    } else {
+                        vec![*fill; pad]
+                    }
+                },
+            };
+            image[at..at + bytes.len()].copy_from_slice(&bytes);
+        }
+
+        Ok(Program {
+            origin      : origin,
+            entry       : symbols.get("_start").copied().unwrap_or(origin),
+            image       : image,
+            symbols     : symbols,
+        })
+    }
+
+    // The instructions an instruction statement (possibly a pseudo-instruction) stands for.
+    fn expand<F: Fn(&str) -> Option<i64>>(&self, m: &str, ops: &[String], pc: u32, lookup: &F) -> Result<Vec<Inst>, String> {
+        let eval = |e: &str| self.eval(e, lookup);
+        let reg = |i: usize| register(ops.get(i).map(|s| s.as_str()).unwrap_or(""));
+        let imm = |i: usize| eval(ops.get(i).map(|s| s.as_str()).unwrap_or("")).map(|v| v as i32);
+        let target = |i: usize| imm(i).map(|t| (t as u32).wrapping_sub(pc) as i32);
+        let csrAddr = |i: usize| {
+            let s = ops.get(i).map(|s| s.as_str()).unwrap_or("");
+            csr::parse(s).map(|a| a as i32).map_or_else(|| imm(i), Ok)
+        };
+        let arity = |n: usize| {
+            if ops.len() == n { Ok(()) } else { Err(format!("'{}' takes {} operand{}", m, n, if n == 1 { "" } else { "s" })) }
+        };
+        let one = |op: Op, rd: u32, rs1: u32, rs2: u32, imm: i32| -> Result<Vec<Inst>, String> { Ok(vec![Inst::new(op, rd, rs1, rs2, imm)]) };
+        // sequences of two instructions: lui/auipc + addi/jalr/load
+        let hiLo = |first: Op, rd: u32, v: i64, second: Op, rd2: u32| -> Result<Vec<Inst>, String> {
+            Ok(vec![
+                Inst::new(first, rd, 0, 0, (hi(v) << 12) as i32),
+                Inst::new(second, rd2, rd, 0, lo(v) as i32),
+            ])
+        };
+
+        match m {
+            "nop"               => { arity(0)?; one(Op::ADDI, 0, 0, 0, 0) },
+            "li"                => {
+                arity(2)?;
+                let rd = reg(0)?;
+                match self.constant(&ops[1]) {
+                    Some(v) if fits(v, 12)  => one(Op::ADDI, rd, 0, 0, v as i32),
+                    Some(v) if lo(v) == 0   => one(Op::LUI, rd, 0, 0, (hi(v) << 12) as i32),
+                    _                       => hiLo(Op::LUI, rd, eval(&ops[1])?, Op::ADDI, rd),
+                }
+            },
+            "la" | "lla"        => { arity(2)?; hiLo(Op::AUIPC, reg(0)?, eval(&ops[1])? - pc as i64, Op::ADDI, reg(0)?) },
+            "call"              => { arity(1)?; hiLo(Op::AUIPC, 1, eval(&ops[0])? - pc as i64, Op::JALR, 1) },
+            "tail"              => { arity(1)?; hiLo(Op::AUIPC, 6, eval(&ops[0])? - pc as i64, Op::JALR, 0) },
+            "mv"                => { arity(2)?; one(Op::ADDI, reg(0)?, reg(1)?, 0, 0) },
+            "not"               => { arity(2)?; one(Op::XORI, reg(0)?, reg(1)?, 0, -1) },
+            "neg"               => { arity(2)?; one(Op::SUB, reg(0)?, 0, reg(1)?, 0) },
+            "seqz"              => { arity(2)?; one(Op::SLTIU, reg(0)?, reg(1)?, 0, 1) },
+            "snez"              => { arity(2)?; one(Op::SLTU, reg(0)?, 0, reg(1)?, 0) },
+            "sltz"              => { arity(2)?; one(Op::SLT, reg(0)?, reg(1)?, 0, 0) },
+            "sgtz"              => { arity(2)?; one(Op::SLT, reg(0)?, 0, reg(1)?, 0) },
+            "beqz"              => { arity(2)?; one(Op::BEQ, 0, reg(0)?, 0, target(1)?) },
+            "bnez"              => { arity(2)?; one(Op::BNE, 0, reg(0)?, 0, target(1)?) },
+            "bltz"              => { arity(2)?; one(Op::BLT, 0, reg(0)?, 0, target(1)?) },
+            "bgez"              => { arity(2)?; one(Op::BGE, 0, reg(0)?, 0, target(1)?) },
+            "blez"              => { arity(2)?; one(Op::BGE, 0, 0, reg(0)?, target(1)?) },
+            "bgtz"              => { arity(2)?; one(Op::BLT, 0, 0, reg(0)?, target(1)?) },
+            "bgt"               => { arity(3)?; one(Op::BLT, 0, reg(1)?, reg(0)?, target(2)?) },
+            "ble"               => { arity(3)?; one(Op::BGE, 0, reg(1)?, reg(0)?, target(2)?) },
+            "bgtu"              => { arity(3)?; one(Op::BLTU, 0, reg(1)?, reg(0)?, target(2)?) },
+            "bleu"              => { arity(3)?; one(Op::BGEU, 0, reg(1)?, reg(0)?, target(2)?) },
+            "j"                 => { arity(1)?; one(Op::JAL, 0, 0, 0, target(0)?) },
+            "jal" if ops.len() == 1 => one(Op::JAL, 1, 0, 0, target(0)?),
+            "jr"                => { arity(1)?; one(Op::JALR, 0, reg(0)?, 0, 0) },
+            "jalr" if ops.len() == 1 => one(Op::JALR, 1, reg(0)?, 0, 0),
+            "jalr" if ops.len() == 3 => one(Op::JALR, reg(0)?, reg(1)?, 0, imm(2)?),
+            "ret"               => { arity(0)?; one(Op::JALR, 0, 1, 0, 0) },
+            "csrr"              => { arity(2)?; one(Op::CSRRS, reg(0)?, 0, 0, csrAddr(1)?) },
+            "csrw" | "csrs" | "csrc" => {
+                arity(2)?;
+                let op = Op::parse(&format!("csrr{}", &m[3..])).unwrap();
+                one(op, 0, reg(1)?, 0, csrAddr(0)?)
+            },
+            "csrwi" | "csrsi" | "csrci" => {
+                arity(2)?;
+                let op = Op::parse(&format!("csrr{}", &m[3..])).unwrap();
+                one(op, 0, imm(1)? as u32, 0, csrAddr(0)?)
+            },
+            "rdcycle" | "rdtime" | "rdinstret" | "rdcycleh" | "rdtimeh" | "rdinstreth" => {
+                arity(1)?;
+                one(Op::CSRRS, reg(0)?, 0, 0, csr::parse(&m[2..]).unwrap() as i32)
+            },
+            "unimp"             => { arity(0)?; one(Op::CSRRW, 0, 0, 0, csr::CYCLE as i32) },
+            "fence" if ops.is_empty() => one(Op::FENCE, 0, 0, 0, 0xFF),
+            "fence.tso"         => { arity(0)?; one(Op::FENCE, 0, 0, 0, 0x833) },
+            "pause"             => { arity(0)?; one(Op::FENCE, 0, 0, 0, 0x010) },
+            _                   => self.base(m, ops, pc, lookup),
+        }
+    }
+
+    // An instruction of the base table, with its own operand syntax.
+    fn base<F: Fn(&str) -> Option<i64>>(&self, m: &str, ops: &[String], pc: u32, lookup: &F) -> Result<Vec<Inst>, String> {
+        // AMOs take .aq/.rl/.aqrl suffixes
+        let (name, aqrl) = match m.rsplit_once('.') {
+            Some((n, "aq")) if Op::parse(n).is_some()   => (n, 0b10),
+            Some((n, "rl")) if Op::parse(n).is_some()   => (n, 0b01),
+            Some((n, "aqrl")) if Op::parse(n).is_some() => (n, 0b11),
+            _                                           => (m, 0),
+        };
+        let op = Op::parse(name).ok_or(format!("unknown instruction '{}'", m))?;
+        let eval = |e: &str| self.eval(e, lookup).map(|v| v as i32);
+        let want = |n: usize| {
+            if ops.len() == n { Ok(()) } else { Err(format!("'{}' takes {} operand{}", m, n, if n == 1 { "" } else { "s" })) }
+        };
+        let memory = |s: &str| -> Result<(i32, u32), String> {
+            let (off, r) = splitMemory(s)?;
+            Ok((if off.is_empty() { 0 } else { eval(off)? }, r))
+        };
+        let csrAddr = |s: &str| csr::parse(s).map(|a| a as i32).map_or_else(|| eval(s), Ok);
+        let inst = match op.format() {
+            Format::R       => { want(3)?; Inst::new(op, register(&ops[0])?, register(&ops[1])?, register(&ops[2])?, 0) },
+            Format::I if matches!(op, Op::LB | Op::LH | Op::LW | Op::LBU | Op::LHU | Op::JALR) => {
+                want(2)?;
+                let (off, rs1) = memory(&ops[1])?;
+                Inst::new(op, register(&ops[0])?, rs1, 0, off)
+            },
+            Format::I | Format::Shift => { want(3)?; Inst::new(op, register(&ops[0])?, register(&ops[1])?, 0, eval(&ops[2])?) },
+            Format::S       => {
+                want(2)?;
+                let (off, rs1) = memory(&ops[1])?;
+                Inst::new(op, 0, rs1, register(&ops[0])?, off)
+            },
+            Format::B       => { want(3)?; Inst::new(op, 0, register(&ops[0])?, register(&ops[1])?, eval(&ops[2])?.wrapping_sub(pc as i32)) },
+            Format::U       => {
+                want(2)?;
+                let v = eval(&ops[1])?;
+                if !(-0x80000..0x100000).contains(&v) {
+                    return Err(format!("immediate out of range: {}", v));
+                }
+                Inst::new(op, register(&ops[0])?, 0, 0, v << 12)
+            },
+            Format::J       => { want(2)?; Inst::new(op, register(&ops[0])?, 0, 0, eval(&ops[1])?.wrapping_sub(pc as i32)) },
+            Format::Amo     => {
+                want(3)?;
+                let (off, rs1) = memory(&ops[2])?;
+                if off != 0 {
+                    return Err(format!("'{}' takes no offset", m));
+                }
+                Inst::new(op, register(&ops[0])?, rs1, register(&ops[1])?, aqrl)
+            },
+            Format::Lr      => {
+                want(2)?;
+                let (off, rs1) = memory(&ops[1])?;
+                if off != 0 {
+                    return Err(format!("'{}' takes no offset", m));
+                }
+                Inst::new(op, register(&ops[0])?, rs1, 0, aqrl)
+            },
+            Format::Csr     => { want(3)?; Inst::new(op, register(&ops[0])?, register(&ops[2])?, 0, csrAddr(&ops[1])?) },
+            Format::CsrI    => { want(3)?; Inst::new(op, register(&ops[0])?, eval(&ops[2])? as u32, 0, csrAddr(&ops[1])?) },
+            Format::Fence if op == Op::FENCE => { want(2)?; Inst::new(op, 0, 0, 0, fenceSet(&ops[0])? << 4 | fenceSet(&ops[1])?) },
+            Format::Cbo     => {
+                want(1)?;
+                let (off, rs1) = memory(&ops[0])?;
+                if off != 0 {
+                    return Err(format!("'{}' takes no offset", m));
+                }
+                Inst::new(op, 0, rs1, 0, 0)
+            },
+            Format::Fence | Format::None => { want(0)?; Inst::new(op, 0, 0, 0, 0) },
+        };
+        if aqrl != 0 && !matches!(op.format(), Format::Amo | Format::Lr) {
+            return Err(format!("unknown instruction '{}'", m));
+        }
+        Ok(vec![inst])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::*;
+
+    fn words(p: &Program) -> Vec<u32> {
+        p.image.chunks(4).map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]])).collect()
+    }
+
+    #[test]
+    fn test_instructions() {
+        let p = assemble("addi a0, sp, 16\nlw a0, 8(s0)\nsw a0, 12(sp)\namoadd.w.aqrl a0, a1, (a2)\ncsrrs a0, mstatus, zero").unwrap();
+        assert_eq!(words(&p), vec![0x0101_0513, 0x0084_2503, 0x00a1_2623, 0x06b6_252f, 0x3000_2573]);
+    }
+
+    #[test]
+    fn test_labels_and_pseudo_instructions() {
+        let src = "
+            _start:
+                li   a0, 5          # counter
+            1:
+                addi a0, a0, -1
+                bnez a0, 1b
+                j    1f
+            1:
+            done:
+                ret
+        ";
+        let p = assemble(src).unwrap();
+        assert_eq!(p.entry, RAM_BASE);
+        assert_eq!(p.symbol("done"), Some(RAM_BASE + 16));
+        assert_eq!(words(&p), vec![0x0050_0513, 0xfff5_0513, 0xfe05_1ee3, 0x0040_006f, 0x0000_8067]);
+    }
+
+    #[test]
+    fn test_data_and_relocations() {
+        let src = "
+            .text
+                lui  a0, %hi(msg)
+                addi a0, a0, %lo(msg)
+                la   a1, value
+                li   a2, 0x12345678
+            .data
+            value:  .word 0xdeadbeef, value
+            msg:    .asciz \"hi\\n\"
+        ";
+        let p = assemble(src).unwrap();
+        let value = p.symbol("value").unwrap();
+        let msg = p.symbol("msg").unwrap();
+        assert_eq!(value, RAM_BASE + 24);
+        assert_eq!(msg, value + 8);
+        let w = words(&p);
+        assert_eq!(w[0], 0x8000_0537);
+        assert_eq!(w[1], 0x0205_0513);
+        assert_eq!(w[4], 0x1234_5637);
+        assert_eq!(w[5], 0x6786_0613);
+        assert_eq!(&w[6..8], &[0xdead_beef, value]);
+        assert_eq!(&p.image[32..36], b"hi\n\0");
+
+        let mut mem = Memory::withSize(0x1000);
+        p.load(&mut mem, RAM_BASE);
+        assert_eq!(mem.readMem(24) as u32, 0xdead_beef);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(assemble("addi a0, a0, 4096").unwrap_err().contains("out of range"));
+        assert!(assemble("nop\nfoo a0").unwrap_err().starts_with("line 2:"));
+        assert!(assemble("j nowhere").unwrap_err().contains("undefined symbol"));
+        assert!(assemble("x: nop\nx: nop").is_err());
+    }
+}
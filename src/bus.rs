@@ -0,0 +1,630 @@
+use std::fmt;
+
+use crate::cache;
+
+// Access width of a single bus transaction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Width {
+    Byte        = 1,
+    Half        = 2,
+    Word        = 4,
+}
+
+impl Width {
+    pub fn bytes(&self) -> u32 {
+        *self as u32
+    }
+
+    pub fn mask(&self) -> u32 {
+        match self {
+            Width::Byte => 0x0000_00FF,
+            Width::Half => 0x0000_FFFF,
+            Width::Word => 0xFFFF_FFFF,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BusError {
+    Unmapped(u32),              // no region decodes the address
+    ReadOnly(u32),              // write to a read-only device (e.g. ROM)
+    Unsupported(u32, Width),    // the device does not accept this access width / offset
+    Overlap(u32, u32),          // attach() of a region that overlaps an existing one
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BusError::Unmapped(a)       => write!(f, "unmapped address 0x{:08x}", a),
+            BusError::ReadOnly(a)       => write!(f, "write to read-only address 0x{:08x}", a),
+            BusError::Unsupported(a, w) => write!(f, "unsupported {:?} access at 0x{:08x}", w, a),
+            BusError::Overlap(b, s)     => write!(f, "region 0x{:08x}+0x{:x} overlaps an existing region", b, s),
+        }
+    }
+}
+
+// Kind of data access a watchpoint triggers on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Watch {
+    Write,
+    Read,
+    Access,
+}
+
+// A data access made by the hart, as recorded for instruction traces.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Access {
+    pub addr        : u32,
+    pub width       : Width,
+    pub value       : u32,
+    pub write       : bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Watchpoint {
+    addr        : u32,
+    len         : u32,
+    kind        : Watch,
+}
+
+// A peripheral (or memory) that can be attached to the system bus. Offsets passed to read/write are
+// relative to the base address of the region the device is mapped at, and values are right-aligned
+// to the access width.
+pub trait Device {
+    // Human readable name used in memory map listings and error messages.
+    fn name(&self) -> &str;
+
+    // Size of the address window decoded by this device, in bytes.
+    fn size(&self) -> u32;
+
+    fn read(&mut self, offset: u32, width: Width) -> Result<u32, BusError>;
+
+    fn write(&mut self, offset: u32, width: Width, value: u32) -> Result<(), BusError>;
+
+    // Called once per retired instruction so that devices can advance their internal state.
+    fn tick(&mut self) {}
+
+    // Level of the device's interrupt line.
+    fn interrupt(&self) -> bool {
+        false
+    }
+
+    // Cache block size of memory-like devices. Devices returning None are treated as I/O: they are
+    // never cached and CMO instructions targeting them raise an access fault.
+    fn blockSize(&self) -> Option<u32> {
+        None
+    }
+
+    // Set once the guest has asked the device to end the simulation, with the exit status to report.
+    fn exitStatus(&self) -> Option<u32> {
+        None
+    }
+
+    // Whether the guest has asked the device to reset the machine since the last call.
+    fn takeResetRequest(&mut self) -> bool {
+        false
+    }
+}
+
+struct Region {
+    base        : u32,
+    size        : u32,
+    irq         : Option<u32>,
+    dev         : Box<dyn Device>,
+}
+
+impl Region {
+    fn contains(&self, addr: u32) -> bool {
+        addr >= self.base && addr - self.base < self.size
+    }
+}
+
+// Devices report errors with region-relative offsets; turn them back into physical addresses.
+fn rebase(e: BusError, addr: u32) -> BusError {
+    match e {
+        BusError::ReadOnly(_)           => BusError::ReadOnly(addr),
+        BusError::Unsupported(_, w)     => BusError::Unsupported(addr, w),
+        BusError::Unmapped(_)           => BusError::Unmapped(addr),
+        e                               => e,
+    }
+}
+
+// Routing table from physical addresses to devices. This is what the data cache sits in front of.
+pub struct AddressMap {
+    regions     : Vec<Region>,
+}
+
+impl AddressMap {
+    fn find(&mut self, addr: u32) -> Result<&mut Region, BusError> {
+        self.regions.iter_mut().find(|r| r.contains(addr)).ok_or(BusError::Unmapped(addr))
+    }
+
+    pub fn read(&mut self, addr: u32, width: Width) -> Result<u32, BusError> {
+        let region = self.find(addr)?;
+        let offset = addr - region.base;
+        if offset + width.bytes() > region.size {
+            return Err(BusError::Unsupported(addr, width));
+        }
+        region.dev.read(offset, width).map_err(|e| rebase(e, addr))
+    }
+
+    pub fn write(&mut self, addr: u32, width: Width, value: u32) -> Result<(), BusError> {
+        let region = self.find(addr)?;
+        let offset = addr - region.base;
+        if offset + width.bytes() > region.size {
+            return Err(BusError::Unsupported(addr, width));
+        }
+        region.dev.write(offset, width, value & width.mask()).map_err(|e| rebase(e, addr))
+    }
+
+    pub fn blockSize(&mut self, addr: u32) -> Option<u32> {
+        self.find(addr).ok().and_then(|r| r.dev.blockSize())
+    }
+}
+
+// The system bus: routes every physical access to the region that decodes it. Accesses to memory-like
+// regions go through the data cache model when one is enabled.
+pub struct Bus {
+    map         : AddressMap,
+    dcache      : Option<cache::DataCache>,
+    watchpoints : Vec<Watchpoint>,
+    watch_hit   : Option<(Watch, u32)>,
+    accesses    : Option<Vec<Access>>,
+}
+
+impl fmt::Debug for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Bus").field("regions", &self.memoryMap()).field("dcache", &self.dcache.is_some()).finish()
+    }
+}
+
+impl Default for Bus {
+    fn default() -> Bus {
+        Bus::new()
+    }
+}
+
+impl Bus {
+    pub fn new() -> Bus {
+        Bus {
+            map: AddressMap { regions: vec![] },
+            dcache: None,
+            watchpoints: vec![],
+            watch_hit: None,
+            accesses: None,
+        }
+    }
+
+    pub fn attach(&mut self, base: u32, dev: Box<dyn Device>) -> Result<(), BusError> {
+        self.attachRegion(base, None, dev)
+    }
+
+    // Attach a device whose interrupt line is wired to the given interrupt number.
+    pub fn attachWithIrq(&mut self, base: u32, irq: u32, dev: Box<dyn Device>) -> Result<(), BusError> {
+        self.attachRegion(base, Some(irq), dev)
+    }
+
+    fn attachRegion(&mut self, base: u32, irq: Option<u32>, dev: Box<dyn Device>) -> Result<(), BusError> {
+        let size = dev.size();
+        let end = base as u64 + size as u64;
+        for r in &self.map.regions {
+            if (base as u64) < r.base as u64 + r.size as u64 && (r.base as u64) < end {
+                return Err(BusError::Overlap(base, size));
+            }
+        }
+        self.map.regions.push(Region { base: base, size: size, irq: irq, dev: dev });
+        self.map.regions.sort_by_key(|r| r.base);
+        Ok(())
+    }
+
+    // (base, size, name) of every attached region, sorted by base address.
+    pub fn memoryMap(&self) -> Vec<(u32, u32, String)> {
+        self.map.regions.iter().map(|r| (r.base, r.size, r.dev.name().to_string())).collect()
+    }
+
+    pub fn enableDataCache(&mut self, sets: u32, ways: u32, line_size: u32) {
+        self.dcache = Some(cache::DataCache::new(sets, ways, line_size));
+    }
+
+    fn cached(&mut self, addr: u32) -> bool {
+        self.dcache.is_some() && self.map.blockSize(addr).is_some()
+    }
+
+    // Data-side read as seen by the hart.
+    pub fn read(&mut self, addr: u32, width: Width) -> Result<u32, BusError> {
+        if !self.watchpoints.is_empty() {
+            self.checkWatchpoints(addr, width, false);
+        }
+        let v = if !self.cached(addr) {
+            self.map.read(addr, width)?
+        } else {
+            let c = self.dcache.as_mut().unwrap();
+            let mut v: u32 = 0;
+            for i in 0..width.bytes() {
+                v |= (c.readByte(addr.wrapping_add(i), &mut self.map)? as u32) << (8 * i);
+            }
+            v
+        };
+        if let Some(a) = self.accesses.as_mut() {
+            a.push(Access { addr: addr, width: width, value: v, write: false });
+        }
+        Ok(v)
+    }
+
+    // Data-side write as seen by the hart.
+    pub fn write(&mut self, addr: u32, width: Width, value: u32) -> Result<(), BusError> {
+        if !self.watchpoints.is_empty() {
+            self.checkWatchpoints(addr, width, true);
+        }
+        if !self.cached(addr) {
+            self.map.write(addr, width, value)?;
+        } else {
+            let c = self.dcache.as_mut().unwrap();
+            for i in 0..width.bytes() {
+                c.writeByte(addr.wrapping_add(i), (value >> (8 * i)) as u8, &mut self.map)?;
+            }
+        }
+        if let Some(a) = self.accesses.as_mut() {
+            a.push(Access { addr: addr, width: width, value: value & width.mask(), write: true });
+        }
+        Ok(())
+    }
+
+    // Instruction fetch. There is no instruction cache model, so fetches always go to the device.
+    pub fn fetch(&mut self, addr: u32) -> Result<u32, BusError> {
+        self.map.read(addr, Width::Word)
+    }
+
+    // Accesses that bypass the cache, e.g. from a loader, a debugger or a DMA-capable device model.
+    pub fn readUncached(&mut self, addr: u32, width: Width) -> Result<u32, BusError> {
+        self.map.read(addr, width)
+    }
+
+    pub fn writeUncached(&mut self, addr: u32, width: Width, value: u32) -> Result<(), BusError> {
+        self.map.write(addr, width, value)
+    }
+
+    // Bulk copies between guest memory and the host through the hart's (cached) view of memory, used
+    // by host-side services such as syscall emulation.
+    pub fn readBytes(&mut self, addr: u32, len: usize) -> Result<Vec<u8>, BusError> {
+        let mut buf = Vec::with_capacity(len);
+        for i in 0..len as u32 {
+            buf.push(self.read(addr.wrapping_add(i), Width::Byte)? as u8);
+        }
+        Ok(buf)
+    }
+
+    pub fn writeBytes(&mut self, addr: u32, data: &[u8]) -> Result<(), BusError> {
+        for (i, b) in data.iter().enumerate() {
+            self.write(addr.wrapping_add(i as u32), Width::Byte, *b as u32)?;
+        }
+        Ok(())
+    }
+
+    // NUL-terminated string starting at addr (without the terminator).
+    pub fn readCString(&mut self, addr: u32) -> Result<Vec<u8>, BusError> {
+        let mut buf = vec![];
+        loop {
+            let c = self.read(addr.wrapping_add(buf.len() as u32), Width::Byte)? as u8;
+            if c == 0 {
+                return Ok(buf);
+            }
+            buf.push(c);
+        }
+    }
+
+    // Debugger and host-side (HTIF) access to the hart's view of memory. Watchpoints do not trigger,
+    // accesses are not recorded, and written data is made visible to instruction fetch (e.g. for
+    // software breakpoints patched into the code).
+    pub fn peek(&mut self, addr: u32, len: usize) -> Result<Vec<u8>, BusError> {
+        let watchpoints = std::mem::take(&mut self.watchpoints);
+        let accesses = self.accesses.take();
+        let result = self.readBytes(addr, len);
+        self.watchpoints = watchpoints;
+        self.accesses = accesses;
+        result
+    }
+
+    pub fn poke(&mut self, addr: u32, data: &[u8]) -> Result<(), BusError> {
+        let watchpoints = std::mem::take(&mut self.watchpoints);
+        let accesses = self.accesses.take();
+        let result = self.writeBytes(addr, data);
+        self.watchpoints = watchpoints;
+        self.accesses = accesses;
+        self.fenceI();
+        result
+    }
+
+    // Record the hart's data accesses (read/write, not fetches or uncached accesses) for tracing.
+    pub fn recordAccesses(&mut self, on: bool) {
+        self.accesses = if on { Some(vec![]) } else { None };
+    }
+
+    // The accesses recorded since the last call.
+    pub fn takeAccesses(&mut self) -> Vec<Access> {
+        self.accesses.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub fn addWatchpoint(&mut self, addr: u32, len: u32, kind: Watch) {
+        self.watchpoints.push(Watchpoint { addr: addr, len: len.max(1), kind: kind });
+    }
+
+    pub fn removeWatchpoint(&mut self, addr: u32, len: u32, kind: Watch) -> bool {
+        let w = Watchpoint { addr: addr, len: len.max(1), kind: kind };
+        match self.watchpoints.iter().position(|x| *x == w) {
+            Some(i) => {
+                self.watchpoints.remove(i);
+                true
+            },
+            None    => false,
+        }
+    }
+
+    // The first watchpoint hit since the last call: its kind and the accessed address.
+    pub fn takeWatchHit(&mut self) -> Option<(Watch, u32)> {
+        self.watch_hit.take()
+    }
+
+    fn checkWatchpoints(&mut self, addr: u32, width: Width, write: bool) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+        let end = addr as u64 + width.bytes() as u64;
+        self.watch_hit = self.watchpoints.iter()
+            .find(|w| (w.addr as u64) < end && (addr as u64) < w.addr as u64 + w.len as u64 && match w.kind {
+                Watch::Write    => write,
+                Watch::Read     => !write,
+                Watch::Access   => true,
+            })
+            .map(|w| (w.kind, addr));
+    }
+
+    pub fn loadImage(&mut self, addr: u32, image: &[u8]) -> Result<(), BusError> {
+        for (i, b) in image.iter().enumerate() {
+            self.map.write(addr + i as u32, Width::Byte, *b as u32)?;
+        }
+        Ok(())
+    }
+
+    // CMO operations on the block containing addr. Only memory-like regions support them.
+    pub fn cboClean(&mut self, addr: u32) -> Result<(), BusError> {
+        self.map.blockSize(addr).ok_or(BusError::Unsupported(addr, Width::Byte))?;
+        match self.dcache.as_mut() {
+            Some(c) => c.clean(addr, &mut self.map),
+            None    => Ok(()),
+        }
+    }
+
+    pub fn cboFlush(&mut self, addr: u32) -> Result<(), BusError> {
+        self.map.blockSize(addr).ok_or(BusError::Unsupported(addr, Width::Byte))?;
+        match self.dcache.as_mut() {
+            Some(c) => c.flush(addr, &mut self.map),
+            None    => Ok(()),
+        }
+    }
+
+    pub fn cboInval(&mut self, addr: u32) -> Result<(), BusError> {
+        self.map.blockSize(addr).ok_or(BusError::Unsupported(addr, Width::Byte))?;
+        if let Some(c) = self.dcache.as_mut() {
+            c.inval(addr);
+        }
+        Ok(())
+    }
+
+    pub fn cboZero(&mut self, addr: u32) -> Result<(), BusError> {
+        let block_size = self.map.blockSize(addr).ok_or(BusError::Unsupported(addr, Width::Byte))?;
+        match self.dcache.as_mut() {
+            Some(c) => c.zero(addr, &mut self.map),
+            None    => {
+                let base = addr & !(block_size - 1);
+                for off in (0..block_size).step_by(4) {
+                    self.map.write(base + off, Width::Word, 0)?;
+                }
+                Ok(())
+            },
+        }
+    }
+
+    // FENCE.I: instruction fetches bypass the data cache, so dirty lines must reach memory first.
+    pub fn fenceI(&mut self) {
+        if let Some(c) = self.dcache.as_mut() {
+            let _ = c.cleanAll(&mut self.map);
+        }
+    }
+
+    pub fn tick(&mut self) {
+        for r in self.map.regions.iter_mut() {
+            r.dev.tick();
+        }
+    }
+
+    // Exit status requested by the first device that asked to stop the simulation.
+    pub fn exitStatus(&self) -> Option<u32> {
+        self.map.regions.iter().find_map(|r| r.dev.exitStatus())
+    }
+
+    // Whether any device has asked for a machine reset since the last call.
+    pub fn takeResetRequest(&mut self) -> bool {
+        let mut any = false;
+        for r in self.map.regions.iter_mut() {
+            any |= r.dev.takeResetRequest();
+        }
+        any
+    }
+
+    // Bitmask of interrupt numbers whose line is currently asserted.
+    pub fn pendingIrqs(&self) -> u32 {
+        let mut pending = 0;
+        for r in &self.map.regions {
+            if let Some(irq) = r.irq {
+                if r.dev.interrupt() {
+                    pending |= 1 << irq;
+                }
+            }
+        }
+        return pending;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::bus::*;
+    use crate::memory::{Memory, Rom};
+
+    type Log = Rc<RefCell<Vec<(bool, u32, u32)>>>;  // (write, offset, value)
+
+    // Four word-wide registers that log every access; the interrupt line is up while register 0 is
+    // non-zero.
+    struct Regs {
+        regs        : [u32; 4],
+        log         : Log,
+        ticks       : Rc<RefCell<u32>>,
+    }
+
+    impl Device for Regs {
+        fn name(&self) -> &str {
+            "regs"
+        }
+
+        fn size(&self) -> u32 {
+            0x10
+        }
+
+        fn read(&mut self, offset: u32, width: Width) -> Result<u32, BusError> {
+            if width != Width::Word {
+                return Err(BusError::Unsupported(offset, width));
+            }
+            self.log.borrow_mut().push((false, offset, self.regs[offset as usize / 4]));
+            Ok(self.regs[offset as usize / 4])
+        }
+
+        fn write(&mut self, offset: u32, width: Width, value: u32) -> Result<(), BusError> {
+            if width != Width::Word {
+                return Err(BusError::Unsupported(offset, width));
+            }
+            self.log.borrow_mut().push((true, offset, value));
+            self.regs[offset as usize / 4] = value;
+            Ok(())
+        }
+
+        fn tick(&mut self) {
+            *self.ticks.borrow_mut() += 1;
+        }
+
+        fn interrupt(&self) -> bool {
+            self.regs[0] != 0
+        }
+    }
+
+    fn regs() -> (Box<Regs>, Log, Rc<RefCell<u32>>) {
+        let log = Rc::new(RefCell::new(vec![]));
+        let ticks = Rc::new(RefCell::new(0));
+        (Box::new(Regs { regs: [0; 4], log: log.clone(), ticks: ticks.clone() }), log, ticks)
+    }
+
+    #[test]
+    fn test_routing() {
+        let mut b = Bus::new();
+        let (dev, log, ticks) = regs();
+        b.attachWithIrq(0x2000, 3, dev).unwrap();
+        b.attach(0x1000, Box::new(Memory::withSize(0x100))).unwrap();
+        b.attach(0x3000, Box::new(Rom::new(&[1, 2, 3, 4, 5]))).unwrap();
+        assert_eq!(b.memoryMap(), vec![
+            (0x1000, 0x100, "ram".to_string()),
+            (0x2000, 0x10, "regs".to_string()),
+            (0x3000, 0x8, "rom".to_string()),
+        ]);
+
+        // devices see region-relative offsets, and their errors are reported at the physical address
+        b.write(0x2008, Width::Word, 0x1234_5678).unwrap();
+        assert_eq!(b.read(0x2008, Width::Word), Ok(0x1234_5678));
+        assert_eq!(*log.borrow(), vec![(true, 8, 0x1234_5678), (false, 8, 0x1234_5678)]);
+        assert_eq!(b.read(0x2002, Width::Half), Err(BusError::Unsupported(0x2002, Width::Half)));
+        assert_eq!(b.write(0x3000, Width::Byte, 0), Err(BusError::ReadOnly(0x3000)));
+        assert_eq!(b.read(0x3004, Width::Byte), Ok(5));
+
+        // values are truncated to the access width; accesses may not run past the end of a region
+        b.write(0x1000, Width::Half, 0xAABB_CCDD).unwrap();
+        assert_eq!(b.read(0x1000, Width::Word), Ok(0xCCDD));
+        assert_eq!(b.read(0x10FE, Width::Word), Err(BusError::Unsupported(0x10FE, Width::Word)));
+        assert_eq!(b.read(0x10FC, Width::Word), Ok(0));
+
+        for a in [0x0, 0x1100, 0x2010, 0xFFFF_FFFF] {
+            assert_eq!(b.read(a, Width::Byte), Err(BusError::Unmapped(a)));
+            assert_eq!(b.write(a, Width::Byte, 0), Err(BusError::Unmapped(a)));
+            assert_eq!(b.fetch(a), Err(BusError::Unmapped(a)));
+        }
+
+        b.tick();
+        b.tick();
+        assert_eq!(*ticks.borrow(), 2);
+        assert_eq!(b.pendingIrqs(), 0);
+        b.write(0x2000, Width::Word, 1).unwrap();
+        assert_eq!(b.pendingIrqs(), 1 << 3);
+        assert_eq!(b.exitStatus(), None);
+    }
+
+    #[test]
+    fn test_overlap() {
+        let mut b = Bus::new();
+        b.attach(0x1000, Box::new(Memory::withSize(0x1000))).unwrap();
+        for base in [0x1000, 0x0800, 0x1FFF, 0x0002] {
+            let mem = Memory::withSize(0xFFF);
+            assert_eq!(b.attach(base, Box::new(mem)), Err(BusError::Overlap(base, 0xFFF)));
+        }
+        // adjacent regions, and a region ending at the top of the address space
+        b.attach(0x0800, Box::new(Memory::withSize(0x800))).unwrap();
+        b.attach(0x2000, Box::new(Memory::withSize(0x10))).unwrap();
+        b.attach(0xFFFF_FFF0, Box::new(Memory::withSize(0x10))).unwrap();
+        assert_eq!(b.attach(0xFFFF_FFFC, Box::new(Memory::withSize(0x10))), Err(BusError::Overlap(0xFFFF_FFFC, 0x10)));
+        assert_eq!(b.memoryMap().len(), 4);
+        b.write(0xFFFF_FFFC, Width::Word, 7).unwrap();
+        assert_eq!(b.read(0xFFFF_FFFC, Width::Word), Ok(7));
+    }
+
+    #[test]
+    fn test_cached_paths() {
+        let mut b = Bus::new();
+        let (dev, log, _) = regs();
+        b.attach(0x2000, dev).unwrap();
+        b.attach(0x1000, Box::new(Memory::withSize(0x100))).unwrap();
+        b.enableDataCache(4, 2, 64);
+
+        // I/O is never cached and does not support the CMO operations
+        b.write(0x2004, Width::Word, 9).unwrap();
+        assert_eq!(*log.borrow(), vec![(true, 4, 9)]);
+        assert_eq!(b.readUncached(0x2004, Width::Word), Ok(9));
+        assert_eq!(b.cboFlush(0x2000), Err(BusError::Unsupported(0x2000, Width::Byte)));
+        assert_eq!(b.cboZero(0x2000), Err(BusError::Unsupported(0x2000, Width::Byte)));
+
+        // memory writes stay in the cache until written back; fetches and uncached reads miss them
+        b.write(0x1010, Width::Word, 0x0000_0013).unwrap();
+        assert_eq!(b.readUncached(0x1010, Width::Word), Ok(0));
+        assert_eq!(b.fetch(0x1010), Ok(0));
+        assert_eq!(b.read(0x1010, Width::Word), Ok(0x13));
+        b.fenceI();
+        assert_eq!(b.fetch(0x1010), Ok(0x13));
+
+        // uncached writes bypass a cached line, which keeps the stale data
+        b.writeUncached(0x1010, Width::Word, 0x73).unwrap();
+        assert_eq!(b.read(0x1010, Width::Word), Ok(0x13));
+        assert_eq!(b.peek(0x1010, 4), Ok(vec![0x13, 0, 0, 0]));
+
+        // poke goes through the cache and makes the data visible to fetch
+        b.poke(0x1020, &[0x6F, 0, 0, 0]).unwrap();
+        assert_eq!(b.fetch(0x1020), Ok(0x6F));
+
+        b.recordAccesses(true);
+        b.read(0x1010, Width::Byte).unwrap();
+        b.readUncached(0x1010, Width::Word).unwrap();
+        b.fetch(0x1010).unwrap();
+        b.write(0x2000, Width::Word, 1).unwrap();
+        assert_eq!(b.takeAccesses(), vec![
+            Access { addr: 0x1010, width: Width::Byte, value: 0x13, write: false },
+            Access { addr: 0x2000, width: Width::Word, value: 1, write: true },
+        ]);
+    }
+}
@@ -0,0 +1,324 @@
+use crate::bus::{AddressMap, BusError, Width};
+
+// Set-associative write-back data cache model. The line size should equal the cache block size of the
+// backing memory so that the CMO instructions operate on exactly one line.
+#[derive(Debug)]
+pub struct DataCache {
+    sets        : u32,
+    ways        : u32,
+    line_size   : u32,
+    lines       : Vec<Line>,
+    clock       : u64,
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    valid       : bool,
+    dirty       : bool,
+    tag         : u32,
+    last_used   : u64,
+    data        : Vec<u8>,
+}
+
+impl DataCache {
+    pub fn new(sets: u32, ways: u32, line_size: u32) -> DataCache {
+        assert!(sets.is_power_of_two() && line_size.is_power_of_two());
+        let empty = Line {
+            valid       : false,
+            dirty       : false,
+            tag         : 0,
+            last_used   : 0,
+            data        : vec![0; line_size as usize],
+        };
+        DataCache {
+            sets        : sets,
+            ways        : ways,
+            line_size   : line_size,
+            lines       : vec![empty; (sets * ways) as usize],
+            clock       : 0,
+        }
+    }
+
+    pub fn getLineSize(&self) -> u32 {
+        return self.line_size;
+    }
+
+    fn lineBase(&self, addr: u32) -> u32 {
+        addr & !(self.line_size - 1)
+    }
+
+    fn setIndex(&self, addr: u32) -> u32 {
+        (addr / self.line_size) & (self.sets - 1)
+    }
+
+    fn tagOf(&self, addr: u32) -> u32 {
+        addr / self.line_size / self.sets
+    }
+
+    fn lookup(&self, addr: u32) -> Option<usize> {
+        let set = self.setIndex(addr);
+        let tag = self.tagOf(addr);
+        (0..self.ways)
+            .map(|w| (set * self.ways + w) as usize)
+            .find(|&i| self.lines[i].valid && self.lines[i].tag == tag)
+    }
+
+    fn writeBack(&mut self, idx: usize, set: u32, m: &mut AddressMap) -> Result<(), BusError> {
+        let line = &mut self.lines[idx];
+        if line.valid && line.dirty {
+            let base = (line.tag * self.sets + set) * self.line_size;
+            for (i, b) in line.data.iter().enumerate() {
+                m.write(base + i as u32, Width::Byte, *b as u32)?;
+            }
+            line.dirty = false;
+        }
+        Ok(())
+    }
+
+    // 該当ラインを探し、無ければLRUのウェイを追い出してメモリから読み込む
+    fn fill(&mut self, addr: u32, m: &mut AddressMap) -> Result<usize, BusError> {
+        self.clock += 1;
+        if let Some(idx) = self.lookup(addr) {
+            self.lines[idx].last_used = self.clock;
+            return Ok(idx);
+        }
+
+        let set = self.setIndex(addr);
+        let victim = (0..self.ways)
+            .map(|w| (set * self.ways + w) as usize)
+            .min_by_key(|&i| if self.lines[i].valid { self.lines[i].last_used } else { 0 })
+            .unwrap();
+        self.writeBack(victim, set, m)?;
+
+        let base = self.lineBase(addr);
+        let tag = self.tagOf(addr);
+        let line = &mut self.lines[victim];
+        for i in 0..self.line_size {
+            line.data[i as usize] = m.read(base + i, Width::Byte)? as u8;
+        }
+        line.valid = true;
+        line.dirty = false;
+        line.tag = tag;
+        line.last_used = self.clock;
+        return Ok(victim);
+    }
+
+    pub fn readByte(&mut self, addr: u32, m: &mut AddressMap) -> Result<u8, BusError> {
+        let idx = self.fill(addr, m)?;
+        return Ok(self.lines[idx].data[(addr & (self.line_size - 1)) as usize]);
+    }
+
+    pub fn writeByte(&mut self, addr: u32, imm: u8, m: &mut AddressMap) -> Result<(), BusError> {
+        let idx = self.fill(addr, m)?;
+        let line = &mut self.lines[idx];
+        line.data[(addr & (self.line_size - 1)) as usize] = imm;
+        line.dirty = true;
+        Ok(())
+    }
+
+    // cbo.clean: a copy of the cache block is written back to memory if the block is dirty.
+    pub fn clean(&mut self, addr: u32, m: &mut AddressMap) -> Result<(), BusError> {
+        if let Some(idx) = self.lookup(addr) {
+            let set = self.setIndex(addr);
+            self.writeBack(idx, set, m)?;
+        }
+        Ok(())
+    }
+
+    // cbo.flush: the cache block is written back if dirty and then invalidated.
+    pub fn flush(&mut self, addr: u32, m: &mut AddressMap) -> Result<(), BusError> {
+        if let Some(idx) = self.lookup(addr) {
+            let set = self.setIndex(addr);
+            self.writeBack(idx, set, m)?;
+            self.lines[idx].valid = false;
+        }
+        Ok(())
+    }
+
+    pub fn cleanAll(&mut self, m: &mut AddressMap) -> Result<(), BusError> {
+        for idx in 0..self.lines.len() {
+            let set = idx as u32 / self.ways;
+            self.writeBack(idx, set, m)?;
+        }
+        Ok(())
+    }
+
+    // cbo.inval: the cache block is deallocated without writing back, so any dirty data is lost.
+    pub fn inval(&mut self, addr: u32) {
+        if let Some(idx) = self.lookup(addr) {
+            self.lines[idx].valid = false;
+            self.lines[idx].dirty = false;
+        }
+    }
+
+    // cbo.zero: allocate the block (if not already present) and fill it with zeros.
+    pub fn zero(&mut self, addr: u32, m: &mut AddressMap) -> Result<(), BusError> {
+        let idx = self.fill(addr, m)?;
+        let line = &mut self.lines[idx];
+        line.data.fill(0);
+        line.dirty = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bus::{Bus, Width};
+    use crate::memory::Memory;
+    use crate::simulator::{Simulator, StopReason};
+
+    // Two direct-mapped sets of 64-byte lines in front of 4 KiB of memory at 0.
+    fn bus() -> Bus {
+        let mut b = Bus::new();
+        b.attach(0, Box::new(Memory::withSize(0x1000))).unwrap();
+        b.enableDataCache(2, 1, 64);
+        b
+    }
+
+    #[test]
+    fn test_write_back() {
+        let mut b = bus();
+        b.write(0x10, Width::Word, 0x1234).unwrap();
+        assert_eq!(b.readUncached(0x10, Width::Word), Ok(0));
+        b.cboClean(0x3C).unwrap();
+        assert_eq!(b.readUncached(0x10, Width::Word), Ok(0x1234));
+
+        // the line stays cached after a clean, but not after a flush
+        b.writeUncached(0x10, Width::Word, 0x5678).unwrap();
+        assert_eq!(b.read(0x10, Width::Word), Ok(0x1234));
+        b.write(0x10, Width::Word, 0x9ABC).unwrap();
+        b.cboFlush(0x00).unwrap();
+        assert_eq!(b.readUncached(0x10, Width::Word), Ok(0x9ABC));
+        b.writeUncached(0x10, Width::Word, 0xDEF0).unwrap();
+        assert_eq!(b.read(0x10, Width::Word), Ok(0xDEF0));
+
+        // 0x90 maps to the same set and evicts the dirty line
+        b.write(0x10, Width::Word, 0x1111).unwrap();
+        b.read(0x90, Width::Word).unwrap();
+        assert_eq!(b.readUncached(0x10, Width::Word), Ok(0x1111));
+    }
+
+    #[test]
+    fn test_inval_and_zero() {
+        let mut b = bus();
+        b.writeUncached(0x40, Width::Word, 0xAAAA).unwrap();
+        b.write(0x40, Width::Word, 0x5555).unwrap();
+        b.cboInval(0x40).unwrap();
+        assert_eq!(b.read(0x40, Width::Word), Ok(0xAAAA));
+
+        for a in (0x80..0x100).step_by(4) {
+            b.writeUncached(a, Width::Word, 0xFFFF_FFFF).unwrap();
+        }
+        b.cboZero(0xA4).unwrap();
+        assert_eq!(b.peek(0x7C, 0x4C).unwrap(), [&[0; 0x44][..], &[0xFF; 8][..]].concat());
+        assert_eq!(b.readUncached(0x80, Width::Word), Ok(0xFFFF_FFFF));
+        b.cboClean(0x80).unwrap();
+        assert_eq!(b.readUncached(0xBC, Width::Word), Ok(0));
+        assert_eq!(b.readUncached(0xC0, Width::Word), Ok(0xFFFF_FFFF));
+
+        // without a cache the operations go straight to memory, and only memory supports them
+        let mut b = Bus::new();
+        let mut mem = Memory::withSize(0x100);
+        mem.setBlockSize(16);
+        b.attach(0, Box::new(mem)).unwrap();
+        b.poke(0, &[0xFF; 0x40]).unwrap();
+        b.cboZero(0x14).unwrap();
+        assert_eq!(b.peek(0, 0x30).unwrap(), [[0xFF; 16], [0; 16], [0xFF; 16]].concat());
+        assert!(b.cboClean(0x1000).is_err());
+    }
+
+
+    #[test]
+    fn test_lru() {
+        // one set, two ways: touching 0x00 makes 0x40 the victim when 0x80 is filled
+        let mut b = Bus::new();
+        b.attach(0, Box::new(Memory::withSize(0x1000))).unwrap();
+        b.enableDataCache(1, 2, 64);
+        b.write(0x00, Width::Byte, 1).unwrap();
+        b.write(0x40, Width::Byte, 2).unwrap();
+        b.read(0x00, Width::Byte).unwrap();
+        b.read(0x80, Width::Byte).unwrap();
+        assert_eq!(b.readUncached(0x00, Width::Byte), Ok(0));
+        assert_eq!(b.readUncached(0x40, Width::Byte), Ok(2));
+    }
+
+    const CBO_INVAL : u32 = 0x0005_200F;  // cbo.inval (a0)
+    const CBO_CLEAN : u32 = 0x0015_200F;
+    const CBO_ZERO  : u32 = 0x0045_200F;
+
+    // Enter U-mode with the given envcfg values and run `body` with a0 pointing into a 32-byte block
+    // of a memory region at 0x9000_0000 that is filled with 0xFF.
+    fn user(menvcfg: u32, senvcfg: u32, body: &str) -> (Simulator, StopReason) {
+        let src = format!("
+            li   t0, {}
+            csrw menvcfg, t0
+            li   t0, {}
+            csrw senvcfg, t0
+            li   t0, user
+            csrw mepc, t0
+            csrw mstatus, zero
+            .word 0x30200073
+        user:
+            li   a0, 0x90000034
+            {}
+        done:
+            li   t0, 0x100000
+            li   t1, 0x5555
+            sw   t1, 0(t0)
+        ", menvcfg, senvcfg, body);
+        let mut mem = Memory::withSize(0x1000);
+        mem.setBlockSize(32);
+        let mut sim = Simulator::builder()
+            .image("cbo.s", src.into_bytes())
+            .device(0x9000_0000, Box::new(mem))
+            .binary(0x9000_0000, vec![0xFF; 0x100])
+            .finisher(0x100000)
+            .stopOnTrap(true)
+            .build()
+            .unwrap();
+        sim.cpu().bus().enableDataCache(4, 2, 32);
+        let stop = sim.run(100);
+        (sim, stop)
+    }
+
+    fn word(w: u32) -> String {
+        format!(".word 0x{:08x}", w)
+    }
+
+    #[test]
+    fn test_cbo_zero_block_size() {
+        let (mut sim, stop) = user(0x80, 0x80, &word(CBO_ZERO));
+        assert_eq!(stop, StopReason::Halted(0));
+        assert_eq!(sim.readMemory(0x9000_001C, 0x28).unwrap(), [&[0xFF; 4][..], &[0; 32][..], &[0xFF; 4][..]].concat());
+    }
+
+    #[test]
+    fn test_cbo_gating() {
+        let trapped = |menvcfg, senvcfg, op| match user(menvcfg, senvcfg, &word(op)).1 {
+            StopReason::Trap { cause, .. }  => cause,
+            _                               => 0,
+        };
+        // CBCFE and CBZE must be set in both registers for U-mode
+        assert_eq!(trapped(0x40, 0x00, CBO_CLEAN), 2);
+        assert_eq!(trapped(0x00, 0x40, CBO_CLEAN), 2);
+        assert_eq!(trapped(0x40, 0x40, CBO_CLEAN), 0);
+        assert_eq!(trapped(0x40, 0x40, CBO_ZERO), 2);
+        assert_eq!(trapped(0x80, 0x00, CBO_ZERO), 2);
+        // CBIE 00 and the reserved 10 are illegal
+        assert_eq!(trapped(0x30, 0x00, CBO_INVAL), 2);
+        assert_eq!(trapped(0x30, 0x20, CBO_INVAL), 2);
+        assert_eq!(trapped(0x30, 0x30, CBO_INVAL), 0);
+    }
+
+    #[test]
+    fn test_cbo_inval_modes() {
+        // a dirty byte survives cbo.inval when either CBIE field selects flush, and is lost otherwise
+        let body = format!("li a1, 0x55\nsb a1, 0(a0)\n{}", word(CBO_INVAL));
+        for (menvcfg, senvcfg, byte) in [(0x10, 0x30, 0x55), (0x30, 0x10, 0x55), (0x30, 0x30, 0xFF)] {
+            let (mut sim, stop) = user(menvcfg, senvcfg, &body);
+            assert_eq!(stop, StopReason::Halted(0));
+            assert_eq!(sim.readMemory(0x9000_0034, 1).unwrap(), [byte]);
+            assert_eq!(sim.cpu().bus().readUncached(0x9000_0034, Width::Byte), Ok(byte as u32));
+        }
+    }
+}
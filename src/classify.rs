@@ -0,0 +1,257 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::bus::Bus;
+use crate::core::Exception;
+use crate::csr;
+use crate::disasm;
+use crate::inst::{Format, Inst, Op};
+use crate::isa::{self, Isa};
+use crate::memory::{self, Memory};
+use crate::CPU;
+
+// Legality of 32-bit encodings (the `classify` subcommand). Every word is classified twice: `expected`
+// derives it from the specification -- the MATCH/MASK table of inst.rs, the extensions the ISA string
+// enables and the CSRs that exist in such a hart -- and `Probe::legal` executes the word on a reset
+// hart in M-mode, where it is illegal if it raises an illegal instruction exception at its own pc. A
+// sweep walks a range of encodings (all 2^32 by default) on worker threads and reports the words the
+// two disagree on.
+
+// CSRs of a hart with M-mode (c.f., Table 2.2 - 2.5), with the extension each comes with ("" for the
+// ones every M-mode hart has). The hardware performance monitor counters and events must exist,
+// though they may be hardwired to zero (c.f., Section 3.1.10). senvcfg is there with U-mode alone, for
+// the U-mode cbo enables.
+const CSRS: [(u32, u32, &str); 19] = [
+    (csr::SENVCFG,      csr::SENVCFG,       "u"),
+    (csr::MSTATUS,      csr::MISA,          ""),
+    (csr::MIE,          csr::MTVEC,         ""),
+    (csr::MCOUNTEREN,   csr::MCOUNTEREN,    "u"),
+    (csr::MENVCFG,      csr::MENVCFG,       "u"),
+    (csr::MSTATUSH,     csr::MSTATUSH,      ""),
+    (csr::MENVCFGH,     csr::MENVCFGH,      "u"),
+    (0x323,             0x33F,              ""),    // mhpmevent3 - mhpmevent31
+    (csr::MSCRATCH,     csr::MIP,           ""),
+    (csr::MCYCLE,       csr::MCYCLE,        ""),
+    (csr::MINSTRET,     csr::MINSTRET,      ""),
+    (0xB03,             0xB1F,              ""),    // mhpmcounter3 - mhpmcounter31
+    (csr::MCYCLEH,      csr::MCYCLEH,       ""),
+    (csr::MINSTRETH,    csr::MINSTRETH,     ""),
+    (0xB83,             0xB9F,              ""),    // mhpmcounter3h - mhpmcounter31h
+    (csr::CYCLE,        csr::INSTRET,       "zicntr"),
+    (csr::CYCLEH,       csr::INSTRETH,      "zicntr"),
+    (csr::MVENDORID,    csr::MHARTID,       ""),
+    (0xF15,             0xF15,              ""),    // mconfigptr
+];
+
+fn csrExists(addr: u32, isa: &Isa) -> bool {
+    CSRS.iter().any(|(first, last, ext)| (*first..=*last).contains(&addr) && (ext.is_empty() || isa.has(ext)))
+}
+
+// Whether `w` is an instruction an M-mode hart implementing `isa` must execute.
+pub fn expected(w: u32, isa: &Isa) -> bool {
+    let inst = match Inst::decode(w) {
+        Some(i) => i,
+        None    => return false,
+    };
+    if !isa.has(inst.op.extension()) {
+        return false;
+    }
+    match inst.op.format() {
+        // c.f., Section 2.1: a write to a read-only CSR (csr[11:10] == 0b11) is illegal. CSRRW(I)
+        // always writes, the set / clear forms only when rs1 (uimm) is not zero.
+        Format::Csr | Format::CsrI  => {
+            let addr = inst.imm as u32;
+            let write = matches!(inst.op, Op::CSRRW | Op::CSRRWI) || inst.rs1 != 0;
+            csrExists(addr, isa) && !(write && addr >> 10 == 0b11)
+        },
+        _                           => true,
+    }
+}
+
+// A hart with the extensions of `isa` and a little RAM at RAM_BASE that executes one word at a time
+// from the reset state.
+pub struct Probe {
+    cpu         : CPU,
+}
+
+impl Probe {
+    pub fn new(isa: &Isa) -> Probe {
+        let mut bus = Bus::new();
+        bus.attach(memory::RAM_BASE, Box::new(Memory::withSize(4096))).unwrap();
+        Probe { cpu: CPU::withIsa(bus, memory::RAM_BASE, isa.clone()) }
+    }
+
+    pub fn legal(&mut self, w: u32) -> bool {
+        self.cpu.reset(memory::RAM_BASE);
+        self.cpu.bus().poke(memory::RAM_BASE, &w.to_le_bytes()).unwrap();
+        self.cpu.step();
+        let csr = self.cpu.csr();
+        !(csr.readCsr(csr::MCAUSE) == Exception::IllegalInstruction as u32 && csr.readCsr(csr::MEPC) == memory::RAM_BASE)
+    }
+}
+
+// Words a sweep keeps for the report; the rest are only counted.
+pub const MAX_MISMATCHES: usize = 100;
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub words       : u64,
+    pub legal       : u64,                  // as expected
+    pub mismatches  : u64,
+    pub samples     : Vec<(u32, bool)>,     // word and the expected legality
+}
+
+impl Report {
+    fn add(&mut self, other: Report) {
+        self.words += other.words;
+        self.legal += other.legal;
+        self.mismatches += other.mismatches;
+        self.samples.extend(other.samples);
+        self.samples.sort();
+        self.samples.truncate(MAX_MISMATCHES);
+    }
+}
+
+// Words handed to a worker at a time.
+const CHUNK: u64 = 1 << 16;
+
+// Classify the words from `from` up to and excluding `to`.
+pub fn sweep(from: u64, to: u64, isa: &Isa, jobs: usize) -> Report {
+    let next = AtomicU64::new(from);
+    let total = Mutex::new(Report::default());
+    thread::scope(|s| {
+        for _ in 0..jobs.max(1) {
+            s.spawn(|| {
+                let mut probe = Probe::new(isa);
+                let mut report = Report::default();
+                loop {
+                    let start = next.fetch_add(CHUNK, Ordering::Relaxed);
+                    if start >= to {
+                        break;
+                    }
+                    for w in start..to.min(start + CHUNK) {
+                        let w = w as u32;
+                        let legal = expected(w, isa);
+                        report.words += 1;
+                        report.legal += legal as u64;
+                        if probe.legal(w) != legal {
+                            report.mismatches += 1;
+                            if report.samples.len() < MAX_MISMATCHES {
+                                report.samples.push((w, legal));
+                            }
+                        }
+                    }
+                }
+                total.lock().unwrap().add(report);
+            });
+        }
+    });
+    total.into_inner().unwrap()
+}
+
+pub fn format(r: &Report) -> String {
+    let mut out = String::new();
+    for (w, legal) in &r.samples {
+        let (e, o) = if *legal { ("legal", "illegal") } else { ("illegal", "legal") };
+        out += &format!("{:08x}  {:<28} expected {}, executed as {}\n", w, disasm::disassemble(*w, memory::RAM_BASE), e, o);
+    }
+    if r.mismatches > r.samples.len() as u64 {
+        out += &format!("... and {} more\n", r.mismatches - r.samples.len() as u64);
+    }
+    out += &format!("{} words: {} legal, {} illegal, {} mismatches\n", r.words, r.legal, r.words - r.legal, r.mismatches);
+    out
+}
+
+fn usage(prog: &str) -> ! {
+    eprintln!("usage: {} classify [options]", prog);
+    eprintln!("  --isa <string>                                ISA the words are classified for (default: {})", isa::DEFAULT);
+    eprintln!("  --from <word>                                 first word (default: 0)");
+    eprintln!("  --to <word>                                   last word (default: 0xffffffff)");
+    eprintln!("  --jobs <n>                                    worker threads (default: the number of CPUs)");
+    std::process::exit(1);
+}
+
+fn parseWord(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(h) => u32::from_str_radix(h, 16).ok().map(|w| w as u64),
+        None    => s.parse::<u32>().ok().map(|w| w as u64),
+    }
+}
+
+// `classify` subcommand; returns the exit status: 0 if there was no mismatch.
+pub fn main(args: &[String]) -> i32 {
+    let mut jobs = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let mut isa_string = isa::DEFAULT.to_string();
+    let (mut from, mut to) = (0, u32::MAX as u64);
+
+    let mut i = 2;
+    while i < args.len() {
+        let value = args.get(i + 1).cloned().unwrap_or_default();
+        match args[i].as_str() {
+            "--isa"         => isa_string = value,
+            "--from"        => from = parseWord(&value).unwrap_or_else(|| usage(&args[0])),
+            "--to"          => to = parseWord(&value).unwrap_or_else(|| usage(&args[0])),
+            "--jobs"        => jobs = value.parse().unwrap_or_else(|_| usage(&args[0])),
+            _               => usage(&args[0]),
+        }
+        i += 2;
+    }
+    let isa = match Isa::parse(&isa_string).and_then(|isa| isa.check().map(|_| isa)) {
+        Ok(isa) => isa,
+        Err(e)  => {
+            eprintln!("{}", e);
+            return 1;
+        },
+    };
+
+    let report = sweep(from, to + 1, &isa, jobs);
+    print!("{}", format(&report));
+    if report.mismatches == 0 { 0 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::classify::*;
+
+    #[test]
+    fn test_expected() {
+        let isa = Isa::parse(isa::DEFAULT).unwrap();
+        assert!(expected(0x0000_0013, &isa));           // nop
+        assert!(expected(0x3400_2573, &isa));           // csrr a0, mscratch
+        assert!(expected(0xC000_2573, &isa));           // rdcycle a0
+        assert!(!expected(0xC000_1073, &isa));          // unimp: csrrw x0, cycle, x0
+        assert!(!expected(0xC000_A073, &isa));          // csrs cycle, ra
+        assert!(!expected(0x7C00_2573, &isa));          // custom CSR
+        assert!(!expected(0x1020_0073, &isa));          // sret without S-mode
+        assert!(!expected(0x0000_0000, &isa));
+
+        let isa = Isa::parse("rv32i_zicsr").unwrap();
+        assert!(!expected(0x02B5_0533, &isa));          // mul
+        assert!(!expected(0xC000_2573, &isa));          // rdcycle without Zicntr
+        assert!(expected(0xB000_2573, &isa));           // csrr a0, mcycle
+    }
+
+    #[test]
+    fn test_sweep() {
+        // random words under the major opcodes of the implemented instructions (random words alone
+        // are mostly illegal), then every word of a small range
+        for s in [isa::DEFAULT, "rv32i_zicsr", "rv32imu_zicntr_zicboz"] {
+            let isa = Isa::parse(s).unwrap();
+            let mut probe = Probe::new(&isa);
+            let mut x: u32 = 0x2545_F491;
+            for _ in 0..100_000 {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                let opcode = [0b0110111, 0b0010111, 0b1101111, 0b1100111, 0b1100011, 0b0000011, 0b0100011,
+                              0b0010011, 0b0110011, 0b0001111, 0b1110011, 0b0101111][(x >> 7) as usize % 12];
+                let w = x & !0x7F | opcode;
+                assert_eq!(probe.legal(w), expected(w, &isa), "{} {:08x}", s, w);
+            }
+            let r = sweep(0x3400_0000, 0x3401_0000, &isa, 2);
+            assert_eq!(r.words, 0x10000);
+            assert_eq!(r.mismatches, 0, "{}", format(&r));
+        }
+    }
+}
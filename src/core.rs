@@ -1,6 +1,6 @@
 // c.f., Table 26.1: RISC-V base opcode map
-#[derive(Debug)]
-enum Opcode {
+#[derive(Debug, PartialEq)]
+pub enum Opcode {
     LOAD        = 0b0000011,
     LOAD_FP     = 0b0000111,
     MISC_MEM    = 0b0001111,
@@ -27,8 +27,37 @@ enum Opcode {
     SYSTEM      = 0b1110011,
 }
 
-#[derive(Debug)]
-enum Funct3OpImm {
+impl Opcode {
+    pub fn decode(v: u32) -> Option<Opcode> {
+        match v {
+            0b0000011   => Some(Opcode::LOAD),
+            0b0000111   => Some(Opcode::LOAD_FP),
+            0b0001111   => Some(Opcode::MISC_MEM),
+            0b0010011   => Some(Opcode::OP_IMM),
+            0b0010111   => Some(Opcode::AUIPC),
+            0b0011011   => Some(Opcode::OP_IMM_32),
+            0b0100011   => Some(Opcode::STORE),
+            0b0100111   => Some(Opcode::STORE_FP),
+            0b0101111   => Some(Opcode::AMO),
+            0b0110011   => Some(Opcode::OP),
+            0b0110111   => Some(Opcode::LUI),
+            0b0111011   => Some(Opcode::OP_32),
+            0b1000011   => Some(Opcode::MADD),
+            0b1000111   => Some(Opcode::MSUB),
+            0b1001011   => Some(Opcode::MMSUB),
+            0b1001111   => Some(Opcode::NMADD),
+            0b1010011   => Some(Opcode::OP_FP),
+            0b1100011   => Some(Opcode::BRANCH),
+            0b1100111   => Some(Opcode::JALR),
+            0b1101111   => Some(Opcode::JAL),
+            0b1110011   => Some(Opcode::SYSTEM),
+            _           => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Funct3OpImm {
     ADDI        = 0b000,
     SLLI        = 0b001,
     SLTI        = 0b010,
@@ -39,8 +68,24 @@ enum Funct3OpImm {
     ANDI        = 0b111,
 }
 
-#[derive(Debug)]
-enum Funct3Op {
+impl Funct3OpImm {
+    pub fn decode(v: u32) -> Option<Funct3OpImm> {
+        match v {
+            0b000       => Some(Funct3OpImm::ADDI),
+            0b001       => Some(Funct3OpImm::SLLI),
+            0b010       => Some(Funct3OpImm::SLTI),
+            0b011       => Some(Funct3OpImm::SLTIU),
+            0b100       => Some(Funct3OpImm::XORI),
+            0b101       => Some(Funct3OpImm::SRLISRAI),
+            0b110       => Some(Funct3OpImm::ORI),
+            0b111       => Some(Funct3OpImm::ANDI),
+            _           => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Funct3Op {
     ADDSUB      = 0b000,
     SLL         = 0b001,
     SLT         = 0b010,
@@ -51,9 +96,267 @@ enum Funct3Op {
     AND         = 0b111,
 }
 
-#[derive(Debug)]
-enum OpLabel {
-    
+impl Funct3Op {
+    pub fn decode(v: u32) -> Option<Funct3Op> {
+        match v {
+            0b000       => Some(Funct3Op::ADDSUB),
+            0b001       => Some(Funct3Op::SLL),
+            0b010       => Some(Funct3Op::SLT),
+            0b011       => Some(Funct3Op::SLTU),
+            0b100       => Some(Funct3Op::XOR),
+            0b101       => Some(Funct3Op::SRLSRA),
+            0b110       => Some(Funct3Op::OR),
+            0b111       => Some(Funct3Op::AND),
+            _           => None,
+        }
+    }
+}
+
+// c.f., Chapter 2.6: Load and Store Instructions
+#[derive(Debug, PartialEq)]
+pub enum Funct3Load {
+    LB          = 0b000,
+    LH          = 0b001,
+    LW          = 0b010,
+    LBU         = 0b100,
+    LHU         = 0b101,
+}
+
+impl Funct3Load {
+    pub fn decode(v: u32) -> Option<Funct3Load> {
+        match v {
+            0b000       => Some(Funct3Load::LB),
+            0b001       => Some(Funct3Load::LH),
+            0b010       => Some(Funct3Load::LW),
+            0b100       => Some(Funct3Load::LBU),
+            0b101       => Some(Funct3Load::LHU),
+            _           => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Funct3Store {
+    SB          = 0b000,
+    SH          = 0b001,
+    SW          = 0b010,
+}
+
+impl Funct3Store {
+    pub fn decode(v: u32) -> Option<Funct3Store> {
+        match v {
+            0b000       => Some(Funct3Store::SB),
+            0b001       => Some(Funct3Store::SH),
+            0b010       => Some(Funct3Store::SW),
+            _           => None,
+        }
+    }
+}
+
+// c.f., Chapter 2.5: Control Transfer Instructions
+#[derive(Debug, PartialEq)]
+pub enum Funct3Branch {
+    BEQ         = 0b000,
+    BNE         = 0b001,
+    BLT         = 0b100,
+    BGE         = 0b101,
+    BLTU        = 0b110,
+    BGEU        = 0b111,
+}
+
+impl Funct3Branch {
+    pub fn decode(v: u32) -> Option<Funct3Branch> {
+        match v {
+            0b000       => Some(Funct3Branch::BEQ),
+            0b001       => Some(Funct3Branch::BNE),
+            0b100       => Some(Funct3Branch::BLT),
+            0b101       => Some(Funct3Branch::BGE),
+            0b110       => Some(Funct3Branch::BLTU),
+            0b111       => Some(Funct3Branch::BGEU),
+            _           => None,
+        }
+    }
+}
+
+// c.f., Chapter 7: "M" Standard Extension for Integer Multiplication and Division (funct7 = 0b0000001)
+#[derive(Debug, PartialEq)]
+pub enum Funct3M {
+    MUL         = 0b000,
+    MULH        = 0b001,
+    MULHSU      = 0b010,
+    MULHU       = 0b011,
+    DIV         = 0b100,
+    DIVU        = 0b101,
+    REM         = 0b110,
+    REMU        = 0b111,
+}
+
+impl Funct3M {
+    pub fn decode(v: u32) -> Option<Funct3M> {
+        match v {
+            0b000       => Some(Funct3M::MUL),
+            0b001       => Some(Funct3M::MULH),
+            0b010       => Some(Funct3M::MULHSU),
+            0b011       => Some(Funct3M::MULHU),
+            0b100       => Some(Funct3M::DIV),
+            0b101       => Some(Funct3M::DIVU),
+            0b110       => Some(Funct3M::REM),
+            0b111       => Some(Funct3M::REMU),
+            _           => None,
+        }
+    }
+}
+
+// c.f., Chapter 9: "Zicsr" / Section 2.8: Environment Call and Breakpoints
+#[derive(Debug, PartialEq)]
+pub enum Funct3System {
+    PRIV        = 0b000,
+    CSRRW       = 0b001,
+    CSRRS       = 0b010,
+    CSRRC       = 0b011,
+    CSRRWI      = 0b101,
+    CSRRSI      = 0b110,
+    CSRRCI      = 0b111,
+}
+
+impl Funct3System {
+    pub fn decode(v: u32) -> Option<Funct3System> {
+        match v {
+            0b000       => Some(Funct3System::PRIV),
+            0b001       => Some(Funct3System::CSRRW),
+            0b010       => Some(Funct3System::CSRRS),
+            0b011       => Some(Funct3System::CSRRC),
+            0b101       => Some(Funct3System::CSRRWI),
+            0b110       => Some(Funct3System::CSRRSI),
+            0b111       => Some(Funct3System::CSRRCI),
+            _           => None,
+        }
+    }
+}
+
+// funct12 of the SYSTEM/PRIV instructions (rs1 = rd = 0)
+#[derive(Debug, PartialEq)]
+pub enum Funct12Priv {
+    ECALL       = 0x000,
+    EBREAK      = 0x001,
+    SRET        = 0x102,
+    WFI         = 0x105,
+    MRET        = 0x302,
+}
+
+impl Funct12Priv {
+    pub fn decode(v: u32) -> Option<Funct12Priv> {
+        match v {
+            0x000       => Some(Funct12Priv::ECALL),
+            0x001       => Some(Funct12Priv::EBREAK),
+            0x102       => Some(Funct12Priv::SRET),
+            0x105       => Some(Funct12Priv::WFI),
+            0x302       => Some(Funct12Priv::MRET),
+            _           => None,
+        }
+    }
+}
+
+// c.f., Chapter 2.7: Memory Ordering Instructions / CMO extensions
+#[derive(Debug, PartialEq)]
+pub enum Funct3MiscMem {
+    FENCE       = 0b000,
+    FENCE_I     = 0b001,
+    CBO         = 0b010,
+}
+
+impl Funct3MiscMem {
+    pub fn decode(v: u32) -> Option<Funct3MiscMem> {
+        match v {
+            0b000       => Some(Funct3MiscMem::FENCE),
+            0b001       => Some(Funct3MiscMem::FENCE_I),
+            0b010       => Some(Funct3MiscMem::CBO),
+            _           => None,
+        }
+    }
+}
+
+// c.f., CMO spec: Zicbom / Zicboz (rd = 0, funct12 = imm[11:0])
+#[derive(Debug, PartialEq)]
+pub enum CboFunct12 {
+    CBO_INVAL   = 0b0000_0000_0000,
+    CBO_CLEAN   = 0b0000_0000_0001,
+    CBO_FLUSH   = 0b0000_0000_0010,
+    CBO_ZERO    = 0b0000_0000_0100,
+}
+
+impl CboFunct12 {
+    pub fn decode(v: u32) -> Option<CboFunct12> {
+        match v {
+            0b0000_0000_0000    => Some(CboFunct12::CBO_INVAL),
+            0b0000_0000_0001    => Some(CboFunct12::CBO_CLEAN),
+            0b0000_0000_0010    => Some(CboFunct12::CBO_FLUSH),
+            0b0000_0000_0100    => Some(CboFunct12::CBO_ZERO),
+            _                   => None,
+        }
+    }
+}
+
+// c.f., Chapter 8: "A" Standard Extension for Atomic Instructions (funct7[6:2], funct3 = 0b010 for .W)
+#[derive(Debug, PartialEq)]
+pub enum Funct5Amo {
+    AMOADD      = 0b00000,
+    AMOSWAP     = 0b00001,
+    LR          = 0b00010,
+    SC          = 0b00011,
+    AMOXOR      = 0b00100,
+    AMOOR       = 0b01000,
+    AMOAND      = 0b01100,
+    AMOMIN      = 0b10000,
+    AMOMAX      = 0b10100,
+    AMOMINU     = 0b11000,
+    AMOMAXU     = 0b11100,
+}
+
+impl Funct5Amo {
+    pub fn decode(v: u32) -> Option<Funct5Amo> {
+        match v {
+            0b00000     => Some(Funct5Amo::AMOADD),
+            0b00001     => Some(Funct5Amo::AMOSWAP),
+            0b00010     => Some(Funct5Amo::LR),
+            0b00011     => Some(Funct5Amo::SC),
+            0b00100     => Some(Funct5Amo::AMOXOR),
+            0b01000     => Some(Funct5Amo::AMOOR),
+            0b01100     => Some(Funct5Amo::AMOAND),
+            0b10000     => Some(Funct5Amo::AMOMIN),
+            0b10100     => Some(Funct5Amo::AMOMAX),
+            0b11000     => Some(Funct5Amo::AMOMINU),
+            0b11100     => Some(Funct5Amo::AMOMAXU),
+            _           => None,
+        }
+    }
+}
+
+// c.f., Table 3.6: Machine cause register (mcause) values after trap
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exception {
+    InstructionAddressMisaligned    = 0,
+    InstructionAccessFault          = 1,
+    IllegalInstruction              = 2,
+    Breakpoint                      = 3,
+    LoadAddressMisaligned           = 4,
+    LoadAccessFault                 = 5,
+    StoreAddressMisaligned          = 6,
+    StoreAccessFault                = 7,
+    EcallFromUMode                  = 8,
+    EcallFromSMode                  = 9,
+    EcallFromMMode                  = 11,
+    InstructionPageFault            = 12,
+    LoadPageFault                   = 13,
+    StorePageFault                  = 15,
+}
+
+// c.f., Table 1.1: RISC-V privilege levels
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Privilege {
+    User        = 0b00,
+    Supervisor  = 0b01,
+    Machine     = 0b11,
 }
 
 /*
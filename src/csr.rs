@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+
+use crate::core::{Exception, Privilege};
+use crate::isa::Isa;
+
+// c.f., Table 2.2 - 2.5: Currently allocated RISC-V CSR addresses
+pub const SENVCFG       : u32 = 0x10A;
+pub const MSTATUS       : u32 = 0x300;
+pub const MISA          : u32 = 0x301;
+pub const MIE           : u32 = 0x304;
+pub const MTVEC         : u32 = 0x305;
+pub const MCOUNTEREN    : u32 = 0x306;
+pub const MENVCFG       : u32 = 0x30A;
+pub const MSTATUSH      : u32 = 0x310;
+pub const MENVCFGH      : u32 = 0x31A;
+pub const MSCRATCH      : u32 = 0x340;
+pub const MEPC          : u32 = 0x341;
+pub const MCAUSE        : u32 = 0x342;
+pub const MTVAL         : u32 = 0x343;
+pub const MIP           : u32 = 0x344;
+pub const MCYCLE        : u32 = 0xB00;
+pub const MINSTRET      : u32 = 0xB02;
+pub const MCYCLEH       : u32 = 0xB80;
+pub const MINSTRETH     : u32 = 0xB82;
+pub const CYCLE         : u32 = 0xC00;
+pub const TIME          : u32 = 0xC01;
+pub const INSTRET       : u32 = 0xC02;
+pub const CYCLEH        : u32 = 0xC80;
+pub const TIMEH         : u32 = 0xC81;
+pub const INSTRETH      : u32 = 0xC82;
+pub const MVENDORID     : u32 = 0xF11;
+pub const MARCHID       : u32 = 0xF12;
+pub const MIMPID        : u32 = 0xF13;
+pub const MHARTID       : u32 = 0xF14;
+pub const MCONFIGPTR    : u32 = 0xF15;
+
+// c.f., Section 3.1.10: Hardware Performance Monitor. mhpmcounter3-31 (and their upper halves) and
+// mhpmevent3-31 must exist but may be hardwired to zero, as they are here.
+pub const MHPMEVENT3    : u32 = 0x323;
+pub const MHPMEVENT31   : u32 = 0x33F;
+pub const MHPMCOUNTER3  : u32 = 0xB03;
+pub const MHPMCOUNTER31 : u32 = 0xB1F;
+pub const MHPMCOUNTER3H : u32 = 0xB83;
+pub const MHPMCOUNTER31H: u32 = 0xB9F;
+
+fn hardwiredZero(addr: u32) -> bool {
+    (MHPMEVENT3..=MHPMEVENT31).contains(&addr) || (MHPMCOUNTER3..=MHPMCOUNTER31).contains(&addr) ||
+    (MHPMCOUNTER3H..=MHPMCOUNTER31H).contains(&addr) || addr == MCONFIGPTR
+}
+
+// CSRs this hart implements besides the hardwired ones. Accessing any other address raises an illegal
+// instruction exception.
+const IMPLEMENTED: [u32; 28] = [
+    SENVCFG, MSTATUS, MISA, MIE, MTVEC, MCOUNTEREN, MENVCFG, MSTATUSH, MENVCFGH, MSCRATCH, MEPC,
+    MCAUSE, MTVAL, MIP, MCYCLE, MINSTRET, MCYCLEH, MINSTRETH, CYCLE, TIME, INSTRET, CYCLEH, TIMEH,
+    INSTRETH, MVENDORID, MARCHID, MIMPID, MHARTID,
+];
+
+// Assembler names of the implemented CSRs.
+const NAMES: [(u32, &str); 29] = [
+    (SENVCFG, "senvcfg"), (MSTATUS, "mstatus"), (MISA, "misa"), (MIE, "mie"), (MTVEC, "mtvec"),
+    (MCOUNTEREN, "mcounteren"), (MENVCFG, "menvcfg"), (MSTATUSH, "mstatush"), (MENVCFGH, "menvcfgh"),
+    (MSCRATCH, "mscratch"), (MEPC, "mepc"), (MCAUSE, "mcause"), (MTVAL, "mtval"), (MIP, "mip"),
+    (MCYCLE, "mcycle"), (MINSTRET, "minstret"), (MCYCLEH, "mcycleh"), (MINSTRETH, "minstreth"),
+    (CYCLE, "cycle"), (TIME, "time"), (INSTRET, "instret"), (CYCLEH, "cycleh"), (TIMEH, "timeh"),
+    (INSTRETH, "instreth"), (MVENDORID, "mvendorid"), (MARCHID, "marchid"), (MIMPID, "mimpid"),
+    (MHARTID, "mhartid"), (MCONFIGPTR, "mconfigptr"),
+];
+
+pub fn name(addr: u32) -> Option<&'static str> {
+    NAMES.iter().find(|(a, _)| *a == addr).map(|(_, n)| *n)
+}
+
+pub fn parse(name: &str) -> Option<u32> {
+    NAMES.iter().find(|(_, n)| *n == name).map(|(a, _)| *a)
+}
+
+// c.f., Section 3.1.1: Machine ISA Register (misa) -- MXL=1 (XLEN=32) with the I, M and A extensions
+// and user mode by default (see Isa::misa for other configurations).
+pub const MISA_MXL_32   : u32 = 1 << 30;
+pub const MISA_EXT      : u32 = (1 << 26) - 1;  // Extensions field: bit 0 is A, bit 25 is Z
+pub const MISA_DEFAULT  : u32 = MISA_MXL_32 | misaBit('I') | misaBit('M') | misaBit('A') | misaBit('U');
+
+const fn misaBit(letter: char) -> u32 {
+    1 << (letter as u32 - 'A' as u32)
+}
+
+// c.f., Section 3.1.6: Machine Status Registers (mstatus)
+pub const MSTATUS_MIE   : u32 = 1 << 3;
+pub const MSTATUS_MPIE  : u32 = 1 << 7;
+pub const MSTATUS_MPP   : u32 = 0b11 << 11;
+
+// c.f., Section 3.1.9: Machine Interrupt Registers (mip and mie)
+pub const MIP_MEIP      : u32 = 1 << 11;
+
+// c.f., Section 3.1.18: Machine Environment Configuration Register (menvcfg)
+pub const ENVCFG_FIOM   : u32 = 1 << 0;
+pub const ENVCFG_CBIE   : u32 = 0b11 << 4;
+pub const ENVCFG_CBCFE  : u32 = 1 << 6;
+pub const ENVCFG_CBZE   : u32 = 1 << 7;
+
+// What cbo.inval actually does after the CBIE fields have been taken into account.
+#[derive(Debug, PartialEq)]
+pub enum InvalAction {
+    Flush,
+    Inval,
+}
+
+#[derive(Debug)]
+pub struct Csr {
+    csrs: HashMap<u32, u32>,
+    instret: u64,
+    user: bool,     // U-mode is implemented
+    zicntr: bool,   // the unprivileged counters are
+}
+
+impl Default for Csr {
+    fn default() -> Csr {
+        Csr::new()
+    }
+}
+
+impl Csr {
+    pub fn new() -> Self {
+        let mut csrs = HashMap::new();
+        csrs.insert(MISA, MISA_DEFAULT);
+        return Self {
+            csrs: csrs,
+            instret: 0,
+            user: true,
+            zicntr: true,
+        };
+    }
+
+    // The CSRs of a hart with the extensions of `isa`: misa reports them, the envcfg registers and
+    // mcounteren exist only with U-mode and the unprivileged counters only with Zicntr. Without
+    // U-mode, mstatus.MPP is always M.
+    pub fn setIsa(&mut self, isa: &Isa) {
+        self.user = isa.has("u");
+        self.zicntr = isa.has("zicntr");
+        self.csrs.insert(MISA, isa.misa());
+        if !self.user {
+            self.csrs.insert(MSTATUS, self.readCsr(MSTATUS) | MSTATUS_MPP);
+        }
+    }
+
+    fn exists(&self, addr: u32) -> bool {
+        match addr {
+            SENVCFG | MCOUNTEREN | MENVCFG | MENVCFGH           => self.user,
+            CYCLE | TIME | INSTRET | CYCLEH | TIMEH | INSTRETH  => self.zicntr,
+            _                                                   => IMPLEMENTED.contains(&addr) || hardwiredZero(addr),
+        }
+    }
+
+    // The privilege mode xRET returns to when xPP holds the least-privileged supported mode.
+    pub fn leastPrivileged(&self) -> Privilege {
+        if self.user { Privilege::User } else { Privilege::Machine }
+    }
+
+    // Raw access to the CSR file, without permission checks or WARL legalisation. The counters are
+    // derived from the number of retired instructions (one instruction per cycle and per time tick).
+    pub fn readCsr(&self, addr: u32) -> u32 {
+        match addr {
+            MCYCLE | MINSTRET | CYCLE | TIME | INSTRET          => self.instret as u32,
+            MCYCLEH | MINSTRETH | CYCLEH | TIMEH | INSTRETH     => (self.instret >> 32) as u32,
+            _                                                   => *self.csrs.get(&addr).unwrap_or(&0),
+        }
+    }
+
+    pub fn writeCsr(&mut self, addr: u32, imm: u32) {
+        match addr {
+            MCYCLE | MINSTRET   => self.instret = (self.instret & !0xFFFF_FFFF) | imm as u64,
+            MCYCLEH | MINSTRETH => self.instret = (self.instret & 0xFFFF_FFFF) | ((imm as u64) << 32),
+            _                   => { self.csrs.insert(addr, imm); },
+        }
+    }
+
+    pub fn retire(&mut self) {
+        self.instret = self.instret.wrapping_add(1);
+    }
+
+    pub fn getInstret(&self) -> u64 {
+        return self.instret;
+    }
+
+    // Permission check for a CSR instruction (c.f., Section 2.1: CSR Address Mapping Conventions).
+    // csr[11:10] == 0b11 marks read-only registers and csr[9:8] the lowest privilege level that may
+    // access it. Returns the current value.
+    pub fn access(&self, addr: u32, mode: Privilege, write: bool) -> Result<u32, Exception> {
+        if !self.exists(addr) {
+            return Err(Exception::IllegalInstruction);
+        }
+        if (addr >> 8) & 0b11 > mode as u32 {
+            return Err(Exception::IllegalInstruction);
+        }
+        if write && (addr >> 10) & 0b11 == 0b11 {
+            return Err(Exception::IllegalInstruction);
+        }
+        // user-level counters are only readable when enabled in mcounteren
+        let counter = addr & !0x080;
+        if mode < Privilege::Machine && (CYCLE..=INSTRET).contains(&counter) && self.readCsr(MCOUNTEREN) & (1 << (addr & 0x1F)) == 0 {
+            return Err(Exception::IllegalInstruction);
+        }
+        Ok(self.readCsr(addr))
+    }
+
+    // Write from a CSR instruction. Fields are legalised as WARL: unsupported values are replaced by
+    // legal ones and read-only fields keep their value.
+    pub fn writeWarl(&mut self, addr: u32, imm: u32) {
+        match addr {
+            MISA        => {},  // misa is not writable
+            MSTATUS     => {
+                let mut v = imm & (MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP);
+                // only M and U are implemented, so MPP can only hold 0b11 or 0b00 (0b11 without U)
+                if v & MSTATUS_MPP != MSTATUS_MPP {
+                    v &= !MSTATUS_MPP;
+                }
+                if !self.user {
+                    v |= MSTATUS_MPP;
+                }
+                self.writeCsr(MSTATUS, v);
+            },
+            MSTATUSH    => {},
+            MTVEC       => self.writeCsr(MTVEC, imm & !0b11),  // direct mode only
+            MEPC        => self.writeCsr(MEPC, imm & !0b11),
+            MIE         => self.writeCsr(MIE, imm & MIP_MEIP),
+            MIP         => {},  // MEIP is driven by the devices
+            MENVCFG | SENVCFG => {
+                let v = imm & (ENVCFG_FIOM | ENVCFG_CBIE | ENVCFG_CBCFE | ENVCFG_CBZE);
+                self.writeCsr(addr, v);
+            },
+            MENVCFGH    => {},
+            a if hardwiredZero(a) => {},
+            _           => self.writeCsr(addr, imm),
+        }
+    }
+
+    // cbo.clean / cbo.flush are enabled below M-mode by menvcfg.CBCFE, and in U-mode additionally by
+    // senvcfg.CBCFE. Otherwise an illegal instruction exception is raised.
+    pub fn checkCboCleanFlush(&self, mode: Privilege) -> Result<(), Exception> {
+        if mode < Privilege::Machine && self.readCsr(MENVCFG) & ENVCFG_CBCFE == 0 {
+            return Err(Exception::IllegalInstruction);
+        }
+        if mode == Privilege::User && self.readCsr(SENVCFG) & ENVCFG_CBCFE == 0 {
+            return Err(Exception::IllegalInstruction);
+        }
+        Ok(())
+    }
+
+    // cbo.zero is gated the same way by the CBZE bits.
+    pub fn checkCboZero(&self, mode: Privilege) -> Result<(), Exception> {
+        if mode < Privilege::Machine && self.readCsr(MENVCFG) & ENVCFG_CBZE == 0 {
+            return Err(Exception::IllegalInstruction);
+        }
+        if mode == Privilege::User && self.readCsr(SENVCFG) & ENVCFG_CBZE == 0 {
+            return Err(Exception::IllegalInstruction);
+        }
+        Ok(())
+    }
+
+    // CBIE: 00 = illegal, 01 = execute as flush, 11 = execute as invalidate, 10 = reserved (treated
+    // as illegal). If any of the applicable fields selects flush, the instruction performs a flush.
+    pub fn checkCboInval(&self, mode: Privilege) -> Result<InvalAction, Exception> {
+        let mut action = InvalAction::Inval;
+        let mut fields = vec![];
+        if mode < Privilege::Machine {
+            fields.push((self.readCsr(MENVCFG) & ENVCFG_CBIE) >> 4);
+        }
+        if mode == Privilege::User {
+            fields.push((self.readCsr(SENVCFG) & ENVCFG_CBIE) >> 4);
+        }
+        for cbie in fields {
+            match cbie {
+                0b01    => action = InvalAction::Flush,
+                0b11    => {},
+                _       => return Err(Exception::IllegalInstruction),
+            }
+        }
+        Ok(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::csr::*;
+
+    #[test]
+    fn test_cbo_envcfg() {
+        let mut c = Csr::new();
+        // M-mode is never gated
+        assert_eq!(c.checkCboCleanFlush(Privilege::Machine), Ok(()));
+        assert_eq!(c.checkCboZero(Privilege::Machine), Ok(()));
+        assert_eq!(c.checkCboInval(Privilege::Machine), Ok(InvalAction::Inval));
+        assert_eq!(c.checkCboCleanFlush(Privilege::User), Err(Exception::IllegalInstruction));
+        assert_eq!(c.checkCboZero(Privilege::User), Err(Exception::IllegalInstruction));
+        assert_eq!(c.checkCboInval(Privilege::User), Err(Exception::IllegalInstruction));
+
+        // U-mode needs both menvcfg and senvcfg, S-mode only menvcfg
+        c.writeWarl(MENVCFG, ENVCFG_CBCFE | ENVCFG_CBZE);
+        assert_eq!(c.checkCboCleanFlush(Privilege::Supervisor), Ok(()));
+        assert_eq!(c.checkCboCleanFlush(Privilege::User), Err(Exception::IllegalInstruction));
+        c.writeWarl(SENVCFG, ENVCFG_CBCFE);
+        assert_eq!(c.checkCboCleanFlush(Privilege::User), Ok(()));
+        assert_eq!(c.checkCboZero(Privilege::User), Err(Exception::IllegalInstruction));
+        c.writeWarl(SENVCFG, ENVCFG_CBZE);
+        assert_eq!(c.checkCboZero(Privilege::User), Ok(()));
+        assert_eq!(c.checkCboCleanFlush(Privilege::User), Err(Exception::IllegalInstruction));
+
+        // CBIE: 00 and 10 are illegal, 01 flushes, 11 invalidates; any flush field wins
+        let cbie = |c: &mut Csr, m: u32, s: u32| {
+            c.writeWarl(MENVCFG, m << 4);
+            c.writeWarl(SENVCFG, s << 4);
+        };
+        cbie(&mut c, 0b11, 0b11);
+        assert_eq!(c.checkCboInval(Privilege::User), Ok(InvalAction::Inval));
+        assert_eq!(c.checkCboInval(Privilege::Supervisor), Ok(InvalAction::Inval));
+        cbie(&mut c, 0b01, 0b11);
+        assert_eq!(c.checkCboInval(Privilege::User), Ok(InvalAction::Flush));
+        cbie(&mut c, 0b11, 0b01);
+        assert_eq!(c.checkCboInval(Privilege::User), Ok(InvalAction::Flush));
+        assert_eq!(c.checkCboInval(Privilege::Supervisor), Ok(InvalAction::Inval));
+        cbie(&mut c, 0b11, 0b00);
+        assert_eq!(c.checkCboInval(Privilege::User), Err(Exception::IllegalInstruction));
+        assert_eq!(c.checkCboInval(Privilege::Supervisor), Ok(InvalAction::Inval));
+        cbie(&mut c, 0b10, 0b11);
+        assert_eq!(c.checkCboInval(Privilege::Supervisor), Err(Exception::IllegalInstruction));
+        assert_eq!(c.checkCboInval(Privilege::Machine), Ok(InvalAction::Inval));
+    }
+}
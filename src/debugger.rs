@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::bus::Width;
+use crate::disasm;
+use crate::register::Reg;
+use crate::CPU;
+
+// Interactive command-line debugger (--debug). Addresses may be numbers, symbols of the loaded ELF
+// file or register names, optionally with a "+offset".
+
+const HELP: &str = "\
+commands:
+  s, step [N]              execute N instructions (default 1)
+  c, continue              run until a breakpoint or the end of the program
+  b, break <addr>          set a breakpoint (without an address: list breakpoints)
+  d, delete <addr>         remove a breakpoint
+  regs                     show the registers
+  x/NFU <addr>             examine memory: N units, F = x|d|u|i, U = b|h|w (e.g. x/16wx sp)
+  set reg <name> <value>   write a register (also: set pc <value>)
+  disas [addr] [N]         disassemble N instructions (default: 8 at the pc)
+  q, quit                  end the simulation";
+
+pub struct Debugger {
+    symbols         : HashMap<String, u32>,
+    names           : disasm::Symbols,
+    breakpoints     : Vec<u32>,
+}
+
+fn number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex)   => u32::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None        => s.parse::<i64>().ok().map(|v| v as u32),
+    }
+}
+
+impl Debugger {
+    pub fn new(mut symbols: HashMap<String, u32>) -> Debugger {
+        // assembler-local labels only clutter the output
+        symbols.retain(|name, _| !name.starts_with(".L"));
+        Debugger {
+            names           : disasm::Symbols::new(&symbols),
+            symbols         : symbols,
+            breakpoints     : vec![],
+        }
+    }
+
+    fn address(&self, cpu: &mut CPU, s: &str) -> Result<u32, String> {
+        let (base, off) = match s.split_once('+') {
+            Some((b, o))    => (b, number(o).ok_or(format!("bad offset '{}'", o))?),
+            None            => (s, 0),
+        };
+        let base = base.trim_start_matches('$');
+        let v = if let Some(v) = number(base) {
+            v
+        } else if let Some(&v) = self.symbols.get(base) {
+            v
+        } else if base == "pc" {
+            cpu.register().getPC()
+        } else if let Some(r) = Reg::parse(base) {
+            cpu.register().getReg(r)
+        } else {
+            return Err(format!("unknown symbol or register '{}'", base));
+        };
+        Ok(v.wrapping_add(off))
+    }
+
+    // " <symbol+off>" for an address inside the nearest preceding symbol.
+    fn symbolize(&self, addr: u32) -> String {
+        self.names.describe(addr)
+    }
+
+    fn showInst(&self, cpu: &mut CPU, addr: u32) -> String {
+        match cpu.bus().peek(addr, 4) {
+            Ok(b)   => {
+                let inst = u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+                format!("0x{:08x}{}:  {:08x}  {}", addr, self.symbolize(addr), inst, disasm::disassembleWith(inst, addr, &self.names))
+            },
+            Err(e)  => format!("0x{:08x}:  {}", addr, e),
+        }
+    }
+
+    fn regs(&self, cpu: &mut CPU) {
+        print!("{}", cpu.register().dump());
+    }
+
+    // x/NFU addr
+    fn examine(&self, cpu: &mut CPU, fmt: &str, arg: Option<&str>) -> Result<(), String> {
+        let addr = self.address(cpu, arg.ok_or("x: missing address")?)?;
+        let digits: String = fmt.chars().take_while(|c| c.is_ascii_digit()).collect();
+        let count = if digits.is_empty() { 1 } else { digits.parse().map_err(|_| "x: bad count")? };
+        let mut format = 'x';
+        let mut width = Width::Word;
+        for c in fmt[digits.len()..].chars() {
+            match c {
+                'x' | 'd' | 'u' | 'i'   => format = c,
+                'b'                     => width = Width::Byte,
+                'h'                     => width = Width::Half,
+                'w'                     => width = Width::Word,
+                _                       => return Err(format!("x: unknown format letter '{}'", c)),
+            }
+        }
+        if format == 'i' {
+            for i in 0..count {
+                println!("{}", self.showInst(cpu, addr.wrapping_add(4 * i)));
+            }
+            return Ok(());
+        }
+        let size = width.bytes();
+        let per_line = 16 / size;
+        for i in 0..count {
+            let a = addr.wrapping_add(i * size);
+            if i % per_line == 0 {
+                print!("0x{:08x}{}:", a, self.symbolize(a));
+            }
+            let b = cpu.bus().peek(a, size as usize).map_err(|e| e.to_string())?;
+            let v = b.iter().rev().fold(0u32, |v, &b| v << 8 | b as u32);
+            match format {
+                'x' => print!("  0x{:0w$x}", v, w = 2 * size as usize),
+                'd' => print!("  {}", (v << (32 - 8 * size)) as i32 >> (32 - 8 * size)),
+                _   => print!("  {}", v),
+            }
+            if i % per_line == per_line - 1 || i == count - 1 {
+                println!();
+            }
+        }
+        Ok(())
+    }
+
+    // Execute up to `count` instructions, stopping early at a breakpoint. Returns the exit status if
+    // the program ended.
+    fn resume(&self, cpu: &mut CPU, count: Option<u64>) -> Option<u32> {
+        let mut n: u64 = 0;
+        loop {
+            if let Some(status) = cpu.step() {
+                return Some(status);
+            }
+            n += 1;
+            let pc = cpu.register().getPC();
+            if count.is_some_and(|c| n >= c) {
+                return None;
+            }
+            if self.breakpoints.contains(&pc) {
+                println!("breakpoint at 0x{:08x}{}", pc, self.symbolize(pc));
+                return None;
+            }
+        }
+    }
+
+    // One command line. Returns the exit status once the session is over.
+    fn command(&mut self, cpu: &mut CPU, line: &str) -> Result<Option<u32>, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (cmd, args) = match words.split_first() {
+            Some((c, a))    => (*c, a),
+            None            => return Ok(None),
+        };
+        match cmd {
+            "s" | "step"        => {
+                let n = match args.first() {
+                    Some(a) => number(a).ok_or(format!("step: bad count '{}'", a))? as u64,
+                    None    => 1,
+                };
+                if let Some(status) = self.resume(cpu, Some(n)) {
+                    return Ok(Some(status));
+                }
+                let pc = cpu.register().getPC();
+                println!("{}", self.showInst(cpu, pc));
+            },
+            "c" | "continue"    => {
+                if let Some(status) = self.resume(cpu, None) {
+                    return Ok(Some(status));
+                }
+                let pc = cpu.register().getPC();
+                println!("{}", self.showInst(cpu, pc));
+            },
+            "b" | "break"       => match args.first() {
+                Some(a) => {
+                    let addr = self.address(cpu, a)?;
+                    if !self.breakpoints.contains(&addr) {
+                        self.breakpoints.push(addr);
+                    }
+                    println!("breakpoint {} at 0x{:08x}{}", self.breakpoints.len(), addr, self.symbolize(addr));
+                },
+                None    => {
+                    for (i, b) in self.breakpoints.iter().enumerate() {
+                        println!("{}: 0x{:08x}{}", i + 1, b, self.symbolize(*b));
+                    }
+                },
+            },
+            "d" | "delete"      => {
+                let addr = self.address(cpu, args.first().ok_or("delete: missing address")?)?;
+                let before = self.breakpoints.len();
+                self.breakpoints.retain(|&b| b != addr);
+                if self.breakpoints.len() == before {
+                    return Err(format!("no breakpoint at 0x{:08x}", addr));
+                }
+            },
+            "regs"              => self.regs(cpu),
+            "set"               => {
+                let (name, value) = match args {
+                    ["reg", name, value] | [name @ "pc", value] => (*name, *value),
+                    _               => return Err("usage: set reg <name> <value>".to_string()),
+                };
+                let v = self.address(cpu, value)?;
+                match (name.trim_start_matches('$'), Reg::parse(name.trim_start_matches('$'))) {
+                    ("pc", _)       => cpu.register().setPC(v),
+                    (_, Some(r))    => cpu.register().setReg(r, v),
+                    _               => return Err(format!("unknown register '{}'", name)),
+                }
+            },
+            "disas"             => {
+                let addr = match args.first() {
+                    Some(a) => self.address(cpu, a)?,
+                    None    => cpu.register().getPC(),
+                };
+                let n = match args.get(1) {
+                    Some(a) => number(a).ok_or(format!("disas: bad count '{}'", a))?,
+                    None    => 8,
+                };
+                for i in 0..n {
+                    println!("{}", self.showInst(cpu, addr.wrapping_add(4 * i)));
+                }
+            },
+            "h" | "help"        => println!("{}", HELP),
+            c if c.starts_with("x/") => self.examine(cpu, &c[2..], args.first().copied())?,
+            "x"                 => self.examine(cpu, "", args.first().copied())?,
+            _                   => return Err(format!("unknown command '{}' (try 'help')", cmd)),
+        }
+        Ok(None)
+    }
+
+    // Read commands from stdin until the program exits or the user quits. An empty line repeats the
+    // previous command. Returns the exit status.
+    pub fn repl(&mut self, cpu: &mut CPU) -> u32 {
+        let stdin = io::stdin();
+        let mut last = String::new();
+        let pc = cpu.register().getPC();
+        println!("{}", self.showInst(cpu, pc));
+        loop {
+            print!("(dbg) ");
+            let _ = io::stdout().flush();
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+                return 0;
+            }
+            let line = if line.trim().is_empty() { last.clone() } else { line.trim().to_string() };
+            if line == "q" || line == "quit" {
+                return 0;
+            }
+            match self.command(cpu, &line) {
+                Ok(Some(status))    => {
+                    println!("program exited with status {}", status);
+                    return status;
+                },
+                Ok(None)            => {},
+                Err(e)              => println!("{}", e),
+            }
+            last = line;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::debugger::*;
+    use crate::finisher::FINISHER_BASE;
+    use crate::simulator::Simulator;
+
+    const SRC: &str = "
+        start:
+            li   a0, 3
+        loop:
+            addi a0, a0, -1
+            bnez a0, loop
+            li   t0, 0x100000
+            li   t1, 0x5555
+            sw   t1, 0(t0)
+    ";
+
+    fn session() -> (Debugger, Simulator) {
+        let sim = Simulator::builder().image("dbg.s", SRC.as_bytes().to_vec()).finisher(FINISHER_BASE).build().unwrap();
+        (Debugger::new(sim.symbols().clone()), sim)
+    }
+
+    #[test]
+    fn test_number() {
+        assert_eq!(number("0x8000_0000"), Some(0x8000_0000));
+        assert_eq!(number("42"), Some(42));
+        assert_eq!(number("-4"), Some(0xFFFF_FFFC));
+        assert_eq!(number("0xg"), None);
+        assert_eq!(number("sp"), None);
+    }
+
+    #[test]
+    fn test_address() {
+        let (d, mut sim) = session();
+        let loop_ = sim.symbol("loop").unwrap();
+        sim.setReg(2, 0x1000);
+        let cpu = sim.cpu();
+        assert_eq!(d.address(cpu, "loop"), Ok(loop_));
+        assert_eq!(d.address(cpu, "loop+0x4"), Ok(loop_ + 4));
+        assert_eq!(d.address(cpu, "$sp+8"), Ok(0x1008));
+        assert_eq!(d.address(cpu, "x2"), Ok(0x1000));
+        assert_eq!(d.address(cpu, "pc"), Ok(0x8000_0000));
+        assert!(d.address(cpu, "nowhere").is_err());
+        assert!(d.address(cpu, "loop+x").is_err());
+    }
+
+    #[test]
+    fn test_commands() {
+        let (mut d, mut sim) = session();
+        let loop_ = sim.symbol("loop").unwrap();
+        let cpu = sim.cpu();
+        assert_eq!(d.command(cpu, ""), Ok(None));
+        assert!(d.command(cpu, "frobnicate").is_err());
+        assert!(d.command(cpu, "step x").is_err());
+        assert!(d.command(cpu, "x/4q sp").is_err());
+        assert!(d.command(cpu, "x/4wx").is_err());
+        assert!(d.command(cpu, "set a0").is_err());
+        assert!(d.command(cpu, "set reg q9 1").is_err());
+        assert!(d.command(cpu, "delete loop").is_err());
+
+        // breakpoints stop `continue` before the instruction executes
+        d.command(cpu, "break loop").unwrap();
+        d.command(cpu, "b loop").unwrap();
+        assert_eq!(d.breakpoints, [loop_]);
+        d.command(cpu, "c").unwrap();
+        assert_eq!((cpu.register().getPC(), cpu.register().getReg(10)), (loop_, 3));
+        d.command(cpu, "continue").unwrap();
+        assert_eq!(cpu.register().getReg(10), 2);
+        d.command(cpu, "step 2").unwrap();
+        assert_eq!((cpu.register().getPC(), cpu.register().getReg(10)), (loop_, 1));
+
+        // registers and the pc can be changed
+        d.command(cpu, "set reg a0 0x10").unwrap();
+        d.command(cpu, "set $a1 a0+1").unwrap_err();
+        d.command(cpu, "set reg $a1 a0+1").unwrap();
+        assert_eq!((cpu.register().getReg(10), cpu.register().getReg(11)), (0x10, 0x11));
+        d.command(cpu, "set pc start").unwrap();
+        assert_eq!(cpu.register().getPC(), 0x8000_0000);
+
+        // without breakpoints the program runs to its end
+        d.command(cpu, "delete loop").unwrap();
+        assert_eq!(d.command(cpu, "c"), Ok(Some(0)));
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use crate::csr;
+use crate::elf::Elf;
+use crate::inst::{Format, Inst, Op};
+use crate::register::Reg;
+
+// Disassembler producing GNU-style assembly: ABI register names, and the pseudo-instructions objdump
+// prints (li, mv, ret, j, beqz, csrr, ...) in place of the instructions they stand for. Branch and
+// jump targets are absolute addresses, followed by the symbol they fall in when one is known.
+
+// Symbols of a program, for naming addresses.
+#[derive(Debug, Default)]
+pub struct Symbols {
+    sorted          : Vec<(u32, String)>,
+}
+
+impl Symbols {
+    // Assembler-local labels (.L*) and mapping symbols ($x, $d) are left out.
+    pub fn new(symbols: &HashMap<String, u32>) -> Symbols {
+        let mut sorted: Vec<(u32, String)> = symbols.iter()
+            .filter(|(name, _)| !name.starts_with(".L") && !name.starts_with('$'))
+            .map(|(name, &addr)| (addr, name.clone()))
+            .collect();
+        sorted.sort();
+        Symbols { sorted: sorted }
+    }
+
+    // The symbol an address is at.
+    pub fn at(&self, addr: u32) -> Option<&str> {
+        let i = self.sorted.partition_point(|(a, _)| *a < addr);
+        self.sorted.get(i).filter(|(a, _)| *a == addr).map(|(_, n)| n.as_str())
+    }
+
+    // " <symbol>" or " <symbol+0xoff>" for the nearest symbol at or below addr, or "".
+    pub fn describe(&self, addr: u32) -> String {
+        let i = self.sorted.partition_point(|(a, _)| *a <= addr);
+        if i == 0 {
+            return String::new();
+        }
+        // the first of several names for the same address
+        let base = self.sorted[i - 1].0;
+        let j = self.sorted.partition_point(|(a, _)| *a < base);
+        let name = &self.sorted[j].1;
+        if base == addr {
+            format!(" <{}>", name)
+        } else {
+            format!(" <{}+0x{:x}>", name, addr - base)
+        }
+    }
+}
+
+fn x(r: u32) -> &'static str {
+    Reg::name(r)
+}
+
+fn csrName(addr: u32) -> String {
+    match csr::name(addr) {
+        Some(n) => n.to_string(),
+        None    => format!("0x{:x}", addr),
+    }
+}
+
+// pred/succ sets of FENCE: "iorw" letters
+fn fenceSet(bits: u32) -> String {
+    "iorw".chars().enumerate().filter(|(i, _)| bits & (8 >> i) != 0).map(|(_, c)| c).collect()
+}
+
+fn aqrl(imm: i32) -> &'static str {
+    match imm & 0b11 {
+        0b10    => ".aq",
+        0b01    => ".rl",
+        0b11    => ".aqrl",
+        _       => "",
+    }
+}
+
+pub fn disassemble(word: u32, pc: u32) -> String {
+    disassembleWith(word, pc, &Symbols::default())
+}
+
+pub fn disassembleWith(word: u32, pc: u32, symbols: &Symbols) -> String {
+    match Inst::decode(word) {
+        Some(i) => format(&i, pc, symbols),
+        None    => format!(".word 0x{:08x}", word),
+    }
+}
+
+// Text of a decoded instruction at address pc.
+pub fn format(i: &Inst, pc: u32, symbols: &Symbols) -> String {
+    let target = |off: i32| {
+        let t = pc.wrapping_add(off as u32);
+        format!("0x{:x}{}", t, symbols.describe(t))
+    };
+    let (rd, rs1, rs2, imm) = (i.rd, i.rs1, i.rs2, i.imm);
+    let name = i.op.name();
+    match i.op {
+        Op::ADDI if rd == 0 && rs1 == 0 && imm == 0 => "nop".to_string(),
+        Op::ADDI if rs1 == 0            => format!("li {}, {}", x(rd), imm),
+        Op::ADDI if imm == 0            => format!("mv {}, {}", x(rd), x(rs1)),
+        Op::XORI if imm == -1           => format!("not {}, {}", x(rd), x(rs1)),
+        Op::SLTIU if imm == 1           => format!("seqz {}, {}", x(rd), x(rs1)),
+        Op::SUB if rs1 == 0             => format!("neg {}, {}", x(rd), x(rs2)),
+        Op::SLTU if rs1 == 0            => format!("snez {}, {}", x(rd), x(rs2)),
+        Op::SLT if rs2 == 0             => format!("sltz {}, {}", x(rd), x(rs1)),
+        Op::SLT if rs1 == 0             => format!("sgtz {}, {}", x(rd), x(rs2)),
+        Op::BEQ if rs2 == 0             => format!("beqz {}, {}", x(rs1), target(imm)),
+        Op::BNE if rs2 == 0             => format!("bnez {}, {}", x(rs1), target(imm)),
+        Op::BLT if rs2 == 0             => format!("bltz {}, {}", x(rs1), target(imm)),
+        Op::BGE if rs2 == 0             => format!("bgez {}, {}", x(rs1), target(imm)),
+        Op::BLT if rs1 == 0             => format!("bgtz {}, {}", x(rs2), target(imm)),
+        Op::BGE if rs1 == 0             => format!("blez {}, {}", x(rs2), target(imm)),
+        Op::JAL if rd == 0              => format!("j {}", target(imm)),
+        Op::JAL if rd == 1              => format!("jal {}", target(imm)),
+        Op::JALR if rd == 0 && rs1 == 1 && imm == 0 => "ret".to_string(),
+        Op::JALR if rd == 0 && imm == 0 => format!("jr {}", x(rs1)),
+        Op::JALR if rd == 1 && imm == 0 => format!("jalr {}", x(rs1)),
+        Op::CSRRW if rd == 0 && rs1 == 0 && imm as u32 == csr::CYCLE => "unimp".to_string(),
+        Op::CSRRS if rs1 == 0           => match imm as u32 {
+            csr::CYCLE | csr::TIME | csr::INSTRET | csr::CYCLEH | csr::TIMEH | csr::INSTRETH => format!("rd{} {}", csrName(imm as u32), x(rd)),
+            _                           => format!("csrr {}, {}", x(rd), csrName(imm as u32)),
+        },
+        Op::CSRRW | Op::CSRRS | Op::CSRRC if rd == 0 => format!("csr{} {}, {}", &name[4..], csrName(imm as u32), x(rs1)),
+        Op::CSRRWI | Op::CSRRSI | Op::CSRRCI if rd == 0 => format!("csr{} {}, {}", &name[4..], csrName(imm as u32), rs1),
+        Op::FENCE                       => {
+            let (fm, pred, succ) = ((imm as u32) >> 8, (imm as u32 >> 4) & 0xF, imm as u32 & 0xF);
+            match (fm, pred, succ) {
+                (0b0000, 0b1111, 0b1111)    => "fence".to_string(),
+                (0b1000, 0b0011, 0b0011)    => "fence.tso".to_string(),
+                (0b0000, 0b0001, 0b0000)    => "pause".to_string(),
+                _                           => format!("fence {}, {}", fenceSet(pred), fenceSet(succ)),
+            }
+        },
+        _                               => match i.op.format() {
+            Format::R       => format!("{} {}, {}, {}", name, x(rd), x(rs1), x(rs2)),
+            Format::I if matches!(i.op, Op::LB | Op::LH | Op::LW | Op::LBU | Op::LHU | Op::JALR) => format!("{} {}, {}({})", name, x(rd), imm, x(rs1)),
+            Format::I       => format!("{} {}, {}, {}", name, x(rd), x(rs1), imm),
+            Format::Shift   => format!("{} {}, {}, {}", name, x(rd), x(rs1), imm),
+            Format::S       => format!("{} {}, {}({})", name, x(rs2), imm, x(rs1)),
+            Format::B       => format!("{} {}, {}, {}", name, x(rs1), x(rs2), target(imm)),
+            Format::U       => format!("{} {}, 0x{:x}", name, x(rd), (imm as u32) >> 12),
+            Format::J       => format!("{} {}, {}", name, x(rd), target(imm)),
+            Format::Amo     => format!("{}{} {}, {}, ({})", name, aqrl(imm), x(rd), x(rs2), x(rs1)),
+            Format::Lr      => format!("{}{} {}, ({})", name, aqrl(imm), x(rd), x(rs1)),
+            Format::Csr     => format!("{} {}, {}, {}", name, x(rd), csrName(imm as u32), x(rs1)),
+            Format::CsrI    => format!("{} {}, {}, {}", name, x(rd), csrName(imm as u32), rs1),
+            Format::Cbo     => format!("{} 0({})", name, x(rs1)),
+            Format::Fence | Format::None => name.to_string(),
+        },
+    }
+}
+
+// objdump -d style listing of the executable sections of an ELF file.
+pub fn listing(path: &str, e: &Elf) -> String {
+    let symbols = Symbols::new(&e.symbols);
+    let mut out = format!("\n{}:     file format elf32-littleriscv\n", path);
+    for s in e.sections.iter().filter(|s| s.isCode()) {
+        out += &format!("\n\nDisassembly of section {}:\n", s.name);
+        let data = e.sectionData(s);
+        for (k, w) in data.chunks(4).enumerate() {
+            let addr = s.addr + 4 * k as u32;
+            if let Some(name) = symbols.at(addr) {
+                out += &format!("\n{:08x} <{}>:\n", addr, name);
+            }
+            if w.len() < 4 {
+                out += &format!("{:8x}:\t{}\n", addr, w.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" "));
+                break;
+            }
+            let word = u32::from_le_bytes([w[0], w[1], w[2], w[3]]);
+            out += &format!("{:8x}:\t{:08x}\t{}\n", addr, word, disassembleWith(word, addr, &symbols));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disasm::*;
+
+    #[test]
+    fn test_base_instructions() {
+        assert_eq!(disassemble(0x0101_0513, 0), "addi a0, sp, 16");
+        assert_eq!(disassemble(0x0084_2503, 0), "lw a0, 8(s0)");
+        assert_eq!(disassemble(0x00a1_2623, 0), "sw a0, 12(sp)");
+        assert_eq!(disassemble(0x0000_0000, 0), ".word 0x00000000");
+    }
+
+    #[test]
+    fn test_pseudo_instructions() {
+        assert_eq!(disassemble(0x0000_0013, 0), "nop");
+        assert_eq!(disassemble(0x0050_0513, 0), "li a0, 5");
+        assert_eq!(disassemble(0x0005_8513, 0), "mv a0, a1");
+        assert_eq!(disassemble(0x0000_8067, 0), "ret");
+        assert_eq!(disassemble(0xff5f_f06f, 0x1000), "j 0xff4");
+        assert_eq!(disassemble(0x0005_0463, 0x1000), "beqz a0, 0x1008");
+        assert_eq!(disassemble(0x3000_2573, 0), "csrr a0, mstatus");
+    }
+
+    #[test]
+    fn test_ops_table_matches_op() {
+        for (i, info) in crate::inst::OPS.iter().enumerate() {
+            assert_eq!(info.op as usize, i, "{}", info.name);
+        }
+    }
+
+    #[test]
+    fn test_symbolized_target() {
+        let mut map = HashMap::new();
+        map.insert("loop".to_string(), 0x1000);
+        let symbols = Symbols::new(&map);
+        assert_eq!(disassembleWith(0xff5f_f06f, 0x100c, &symbols), "j 0x1000 <loop>");
+        assert_eq!(disassembleWith(0x0040_00ef, 0x1000, &symbols), "jal 0x1004 <loop+0x4>");
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+
+use crate::bus;
+
+// c.f., System V ABI / ELF-32 object file format
+const ELFCLASS32        : u8 = 1;
+const ELFDATA2LSB       : u8 = 1;
+const EM_RISCV          : u16 = 243;
+const PT_LOAD           : u32 = 1;
+const SHT_SYMTAB        : u32 = 2;
+const SHT_NOBITS        : u32 = 8;
+const SHF_EXECINSTR     : u32 = 0x4;
+
+// parse() rejects segments whose file range or memory ranges (at vaddr and paddr) overflow a u32.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub vaddr       : u32,
+    pub paddr       : u32,
+    pub offset      : u32,
+    pub filesz      : u32,
+    pub memsz       : u32,
+    pub flags       : u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name        : String,
+    pub kind        : u32,
+    pub flags       : u32,
+    pub addr        : u32,
+    pub offset      : u32,
+    pub size        : u32,
+}
+
+impl Section {
+    // Section holding instructions (.text and the like).
+    pub fn isCode(&self) -> bool {
+        self.flags & SHF_EXECINSTR != 0 && self.kind != SHT_NOBITS
+    }
+}
+
+#[derive(Debug)]
+pub struct Elf {
+    pub entry       : u32,
+    pub phoff       : u32,
+    pub phentsize   : u16,
+    pub phnum       : u16,
+    pub segments    : Vec<Segment>,
+    pub sections    : Vec<Section>,
+    pub symbols     : HashMap<String, u32>,
+    data            : Vec<u8>,
+}
+
+fn half(d: &[u8], off: usize) -> Result<u16, String> {
+    d.get(off..off + 2).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or("truncated ELF file".to_string())
+}
+
+fn word(d: &[u8], off: usize) -> Result<u32, String> {
+    d.get(off..off + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok_or("truncated ELF file".to_string())
+}
+
+pub fn isElf(data: &[u8]) -> bool {
+    data.len() >= 4 && &data[0..4] == b"\x7fELF"
+}
+
+impl Elf {
+    pub fn parse(data: Vec<u8>) -> Result<Elf, String> {
+        if !isElf(&data) || data.len() < 52 {
+            return Err("not an ELF file".to_string());
+        }
+        if data[4] != ELFCLASS32 || data[5] != ELFDATA2LSB {
+            return Err("not a 32-bit little-endian ELF file".to_string());
+        }
+        if half(&data, 18)? != EM_RISCV {
+            return Err("not a RISC-V ELF file".to_string());
+        }
+
+        let entry = word(&data, 24)?;
+        let phoff = word(&data, 28)?;
+        let shoff = word(&data, 32)? as usize;
+        let phentsize = half(&data, 42)?;
+        let phnum = half(&data, 44)?;
+        let shentsize = half(&data, 46)? as usize;
+        let shnum = half(&data, 48)? as usize;
+        let shstrndx = half(&data, 50)? as usize;
+
+        // program headers
+        let mut segments = vec![];
+        for i in 0..phnum as usize {
+            let ph = phoff as usize + i * phentsize as usize;
+            if word(&data, ph)? != PT_LOAD {
+                continue;
+            }
+            let seg = Segment {
+                offset  : word(&data, ph + 4)?,
+                vaddr   : word(&data, ph + 8)?,
+                paddr   : word(&data, ph + 12)?,
+                filesz  : word(&data, ph + 16)?,
+                memsz   : word(&data, ph + 20)?,
+                flags   : word(&data, ph + 24)?,
+            };
+            match seg.offset.checked_add(seg.filesz) {
+                Some(end) if end as usize <= data.len() => {},
+                _ => return Err("truncated ELF segment".to_string()),
+            }
+            if seg.vaddr.checked_add(seg.memsz).is_none() || seg.paddr.checked_add(seg.memsz).is_none() {
+                return Err("ELF segment wraps around the address space".to_string());
+            }
+            if seg.filesz > seg.memsz {
+                return Err("ELF segment is larger in the file than in memory".to_string());
+            }
+            segments.push(seg);
+        }
+
+        // section headers, named from the section header string table
+        let mut sections = vec![];
+        if shnum > 0 && shstrndx < shnum {
+            let stroff = word(&data, shoff + shstrndx * shentsize + 16)? as usize;
+            for i in 0..shnum {
+                let sh = shoff + i * shentsize;
+                let start = stroff + word(&data, sh)? as usize;
+                let name = match data.get(start..).and_then(|d| d.iter().position(|&c| c == 0)) {
+                    Some(n) => String::from_utf8_lossy(&data[start..start + n]).into_owned(),
+                    None    => return Err("truncated ELF string table".to_string()),
+                };
+                let sec = Section {
+                    name    : name,
+                    kind    : word(&data, sh + 4)?,
+                    flags   : word(&data, sh + 8)?,
+                    addr    : word(&data, sh + 12)?,
+                    offset  : word(&data, sh + 16)?,
+                    size    : word(&data, sh + 20)?,
+                };
+                if sec.kind != SHT_NOBITS && sec.offset as usize + sec.size as usize > data.len() {
+                    return Err("truncated ELF section".to_string());
+                }
+                sections.push(sec);
+            }
+        }
+
+        // symbol table (optional; stripped binaries simply have no symbols)
+        let mut symbols = HashMap::new();
+        for i in 0..shnum {
+            let sh = shoff + i * shentsize;
+            if word(&data, sh + 4)? != SHT_SYMTAB {
+                continue;
+            }
+            let symoff = word(&data, sh + 16)? as usize;
+            let symsize = word(&data, sh + 20)? as usize;
+            let strtab = word(&data, sh + 24)? as usize;
+            let stroff = word(&data, shoff + strtab * shentsize + 16)? as usize;
+            for s in (symoff..symoff + symsize).step_by(16) {
+                let name = word(&data, s)? as usize;
+                let value = word(&data, s + 4)?;
+                let start = stroff + name;
+                let end = match data.get(start..).and_then(|d| d.iter().position(|&c| c == 0)) {
+                    Some(n) => start + n,
+                    None    => return Err("truncated ELF string table".to_string()),
+                };
+                if end > start {
+                    symbols.insert(String::from_utf8_lossy(&data[start..end]).into_owned(), value);
+                }
+            }
+        }
+
+        Ok(Elf {
+            entry       : entry,
+            phoff       : phoff,
+            phentsize   : phentsize,
+            phnum       : phnum,
+            segments    : segments,
+            sections    : sections,
+            symbols     : symbols,
+            data        : data,
+        })
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<u32> {
+        self.symbols.get(name).copied()
+    }
+
+    // Contents of a section in the file (empty for .bss-like sections).
+    pub fn sectionData(&self, s: &Section) -> &[u8] {
+        if s.kind == SHT_NOBITS {
+            return &[];
+        }
+        &self.data[s.offset as usize..s.offset as usize + s.size as usize]
+    }
+
+    // Copy every PT_LOAD segment to its physical address and zero the .bss part (memsz > filesz).
+    pub fn load(&self, bus: &mut bus::Bus) -> Result<(), bus::BusError> {
+        for seg in &self.segments {
+            self.loadSegment(bus, seg, seg.paddr)?;
+        }
+        Ok(())
+    }
+
+    // Same as load(), but at the virtual addresses. User-mode emulation has no address translation,
+    // so the guest's virtual address space is the bus address space.
+    pub fn loadVirtual(&self, bus: &mut bus::Bus) -> Result<(), bus::BusError> {
+        for seg in &self.segments {
+            self.loadSegment(bus, seg, seg.vaddr)?;
+        }
+        Ok(())
+    }
+
+    fn loadSegment(&self, bus: &mut bus::Bus, seg: &Segment, addr: u32) -> Result<(), bus::BusError> {
+        let start = seg.offset as usize;
+        let end = start + seg.filesz as usize;
+        bus.loadImage(addr, &self.data[start..end])?;
+        for i in seg.filesz..seg.memsz {
+            bus.writeUncached(addr + i, bus::Width::Byte, 0)?;
+        }
+        Ok(())
+    }
+
+    // Address of the program headers in the loaded image (for AT_PHDR), if a segment covers them.
+    pub fn phdrAddr(&self) -> Option<u32> {
+        self.segments.iter()
+            .find(|s| s.offset <= self.phoff && self.phoff - s.offset < s.filesz)
+            .map(|s| s.vaddr + (self.phoff - s.offset))
+    }
+
+    // End of the highest loaded segment, i.e. where the program break starts.
+    pub fn end(&self) -> u32 {
+        self.segments.iter().map(|s| s.vaddr + s.memsz).max().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::elf::*;
+    use crate::bus::{Bus, Width};
+    use crate::htif::Htif;
+    use crate::memory::{self, Memory};
+
+    // A minimal executable: PT_LOAD segments as (vaddr, filesz, memsz), followed by a symbol table.
+    fn image(segs: &[(u32, u32, u32)], syms: &[(&str, u32)]) -> Vec<u8> {
+        let mut d = vec![0u8; 52 + 32 * segs.len()];
+        d[0..8].copy_from_slice(b"\x7fELF\x01\x01\x01\x00");
+        d[16..18].copy_from_slice(&2u16.to_le_bytes());
+        d[18..20].copy_from_slice(&EM_RISCV.to_le_bytes());
+        d[24..28].copy_from_slice(&segs.first().map_or(0, |s| s.0).to_le_bytes());
+        d[28..32].copy_from_slice(&52u32.to_le_bytes());
+        d[42..44].copy_from_slice(&32u16.to_le_bytes());
+        d[44..46].copy_from_slice(&(segs.len() as u16).to_le_bytes());
+        for (i, &(vaddr, filesz, memsz)) in segs.iter().enumerate() {
+            let ph = 52 + 32 * i;
+            for (j, v) in [PT_LOAD, d.len() as u32, vaddr, vaddr, filesz, memsz, 5, 4].iter().enumerate() {
+                d[ph + 4 * j..ph + 4 * j + 4].copy_from_slice(&v.to_le_bytes());
+            }
+            d.extend((0..filesz).map(|b| b as u8 + 1));
+        }
+
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; 16];
+        for &(name, value) in syms {
+            symtab.extend((strtab.len() as u32).to_le_bytes());
+            symtab.extend(value.to_le_bytes());
+            symtab.extend([0u8; 8]);
+            strtab.extend(name.bytes().chain([0]));
+        }
+        let shstrtab = b"\0.symtab\0.strtab\0.shstrtab\0";
+        let mut shdrs = vec![0u8; 40];
+        for (name, kind, body, link) in [(1, SHT_SYMTAB, &symtab[..], 2), (9, 3, &strtab[..], 0), (17, 3, &shstrtab[..], 0)] {
+            for v in [name, kind, 0, 0, d.len() as u32, body.len() as u32, link, 0, 1, 0] {
+                shdrs.extend(v.to_le_bytes());
+            }
+            d.extend(body);
+        }
+        let shoff = d.len() as u32;
+        d[32..36].copy_from_slice(&shoff.to_le_bytes());
+        d[46..48].copy_from_slice(&40u16.to_le_bytes());
+        d[48..50].copy_from_slice(&4u16.to_le_bytes());
+        d[50..52].copy_from_slice(&3u16.to_le_bytes());
+        d.extend(shdrs);
+        d
+    }
+
+    #[test]
+    fn test_parse_and_load() {
+        let base = memory::RAM_BASE;
+        let e = Elf::parse(image(&[(base, 4, 8), (base + 0x100, 2, 2)], &[("tohost", base + 0x200)])).unwrap();
+        assert_eq!(e.entry, base);
+        assert_eq!(e.segments.len(), 2);
+        assert_eq!(e.sections.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), ["", ".symtab", ".strtab", ".shstrtab"]);
+        assert_eq!(e.symbol("tohost"), Some(base + 0x200));
+        assert_eq!(e.end(), base + 0x102);
+        assert_eq!(e.phdrAddr(), None);
+
+        let mut bus = Bus::new();
+        bus.attach(base, Box::new(Memory::withSize(4096))).unwrap();
+        bus.loadImage(base, &[0xff; 8]).unwrap();
+        e.load(&mut bus).unwrap();
+        assert_eq!(bus.peek(base, 8).unwrap(), [1, 2, 3, 4, 0, 0, 0, 0]);
+        assert_eq!(bus.peek(base + 0x100, 2).unwrap(), [1, 2]);
+        assert!(Htif::fromElf(&e).is_some());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let d = image(&[(memory::RAM_BASE, 16, 16)], &[]);
+        assert!(Elf::parse(d[..40].to_vec()).is_err());
+        assert!(Elf::parse(d[..52 + 32 + 8].to_vec()).is_err());
+
+        // a file range past the end of the file
+        let mut d = d;
+        d[52 + 16..52 + 20].copy_from_slice(&u32::MAX.to_le_bytes());
+        d[52 + 20..52 + 24].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Elf::parse(d).is_err());
+    }
+
+    #[test]
+    fn test_bad_segments() {
+        let base = memory::RAM_BASE;
+        assert!(Elf::parse(image(&[(0xffff_fff0, 4, 0x20)], &[])).is_err());
+        assert!(Elf::parse(image(&[(base, 4, 2)], &[])).is_err());
+        assert!(Elf::parse(image(&[(0xffff_ffe0, 4, 0x10)], &[])).is_ok());
+
+        // linkers emit overlapping segments (e.g. the ELF header and .text on one page); they are
+        // loaded in order
+        let mut bus = Bus::new();
+        bus.attach(base, Box::new(Memory::withSize(4096))).unwrap();
+        let e = Elf::parse(image(&[(base, 4, 0x20), (base + 2, 1, 1)], &[])).unwrap();
+        e.load(&mut bus).unwrap();
+        assert_eq!(bus.peek(base, 4).unwrap(), [1, 2, 1, 4]);
+
+        // a segment that does not fit in memory fails to load
+        let mut bus = Bus::new();
+        bus.attach(base, Box::new(Memory::withSize(16))).unwrap();
+        let e = Elf::parse(image(&[(base + 8, 4, 16)], &[])).unwrap();
+        assert!(e.load(&mut bus).is_err());
+        assert_eq!(bus.read(base + 8, Width::Byte).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_missing_tohost() {
+        let e = Elf::parse(image(&[(memory::RAM_BASE, 4, 4)], &[("fromhost", memory::RAM_BASE + 8)])).unwrap();
+        assert_eq!(e.symbol("tohost"), None);
+        assert!(Htif::fromElf(&e).is_none());
+
+        let e = Elf::parse(image(&[(memory::RAM_BASE, 4, 4)], &[])).unwrap();
+        assert!(e.symbols.is_empty());
+        assert!(Htif::fromElf(&e).is_none());
+    }
+}
@@ -0,0 +1,115 @@
+use crate::bus::{BusError, Device, Width};
+
+// Default base address (same as the "sifive,test0" device on QEMU virt)
+pub const FINISHER_BASE: u32 = 0x0010_0000;
+
+// c.f., QEMU hw/misc/sifive_test.c
+const FINISHER_FAIL     : u32 = 0x3333;
+const FINISHER_PASS     : u32 = 0x5555;
+const FINISHER_RESET    : u32 = 0x7777;
+
+// SiFive test finisher / syscon. A 32-bit write of 0x5555 ends the simulation successfully, while
+// 0x3333 | (code << 16) ends it with `code` as the exit status, and 0x7777 resets the hart.
+#[derive(Debug)]
+pub struct Finisher {
+    status      : Option<u32>,
+    reset       : bool,
+}
+
+impl Default for Finisher {
+    fn default() -> Finisher {
+        Finisher::new()
+    }
+}
+
+impl Finisher {
+    pub fn new() -> Finisher {
+        Finisher { status: None, reset: false }
+    }
+}
+
+impl Device for Finisher {
+    fn name(&self) -> &str {
+        "sifive_test"
+    }
+
+    fn size(&self) -> u32 {
+        0x1000
+    }
+
+    fn read(&mut self, _offset: u32, _width: Width) -> Result<u32, BusError> {
+        Ok(0)
+    }
+
+    fn write(&mut self, offset: u32, width: Width, value: u32) -> Result<(), BusError> {
+        if offset != 0 || width != Width::Word {
+            return Err(BusError::Unsupported(offset, width));
+        }
+        match value & 0xFFFF {
+            FINISHER_PASS   => self.status = Some(0),
+            FINISHER_FAIL   => self.status = Some(value >> 16),
+            FINISHER_RESET  => self.reset = true,
+            _               => {},
+        }
+        Ok(())
+    }
+
+    fn exitStatus(&self) -> Option<u32> {
+        self.status
+    }
+
+    fn takeResetRequest(&mut self) -> bool {
+        std::mem::take(&mut self.reset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::finisher::*;
+    use crate::memory;
+    use crate::simulator::{Simulator, StopReason};
+
+    #[test]
+    fn test_commands() {
+        let mut f = Finisher::new();
+        assert_eq!(f.write(4, Width::Word, FINISHER_PASS), Err(BusError::Unsupported(4, Width::Word)));
+        assert!(f.write(0, Width::Half, FINISHER_PASS).is_err());
+        f.write(0, Width::Word, 0x1234).unwrap();
+        assert_eq!(f.exitStatus(), None);
+        f.write(0, Width::Word, FINISHER_RESET).unwrap();
+        assert!(f.takeResetRequest());
+        assert!(!f.takeResetRequest());
+        f.write(0, Width::Word, 42 << 16 | FINISHER_FAIL).unwrap();
+        assert_eq!(f.exitStatus(), Some(42));
+
+        let mut f = Finisher::new();
+        f.write(0, Width::Word, FINISHER_PASS).unwrap();
+        assert_eq!(f.exitStatus(), Some(0));
+    }
+
+    #[test]
+    fn test_reset() {
+        // the first run counts itself in memory and resets the hart, the second fails with the count
+        let src = "
+            li   t0, 0x80001000
+            lw   t1, 0(t0)
+            addi t1, t1, 1
+            sw   t1, 0(t0)
+            li   t2, 0x100000
+            li   a0, 1
+            bne  t1, a0, finish
+            li   a0, 0x7777
+            sw   a0, 0(t2)
+        spin:
+            j    spin
+        finish:
+            slli t1, t1, 16
+            li   a0, 0x3333
+            or   t1, t1, a0
+            sw   t1, 0(t2)
+        ";
+        let mut sim = Simulator::builder().image("reset.s", src.as_bytes().to_vec()).finisher(FINISHER_BASE).build().unwrap();
+        assert_eq!(sim.run(100), StopReason::Halted(2));
+        assert_eq!(sim.readMemory(memory::RAM_BASE + 0x1000, 4).unwrap(), [2, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::bus::Bus;
+use crate::disasm;
+use crate::inst::Inst;
+use crate::memory::{self, Memory};
+use crate::CPU;
+
+// Entry points of the cargo-fuzz targets in fuzz/ (`cargo fuzz run decode`, `cargo fuzz run execute`).
+// The input is taken as little-endian 32-bit words; a target fails when anything panics. Keeping the
+// harness here lets the unit tests run it over pseudo-random input without libFuzzer.
+
+// RAM of the execute target: small, as a fresh machine is built for every input.
+pub const RAM_SIZE  : usize = 64 * 1024;
+
+// Steps run per input. Traps go back to the start of the program, so the words get executed with
+// the state the previous round left behind.
+pub const STEPS     : usize = 256;
+
+fn words(data: &[u8]) -> Vec<u32> {
+    data.chunks_exact(4).map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]])).collect()
+}
+
+// Decode, re-encode and disassemble every word.
+pub fn decode(data: &[u8]) {
+    for w in words(data) {
+        if let Some(inst) = Inst::decode(w) {
+            assert_eq!(inst.encode(), Ok(w), "{:08x}", w);
+        }
+        disasm::disassemble(w, memory::RAM_BASE);
+    }
+}
+
+// Run the words as a program at RAM_BASE. The registers are seeded from the words too, every other
+// one pointing into RAM, so that loads, stores and AMOs reach memory as well as fault.
+pub fn execute(data: &[u8]) {
+    let program = words(data);
+    if program.is_empty() {
+        return;
+    }
+    let mut bus = Bus::new();
+    bus.attach(memory::RAM_BASE, Box::new(Memory::withSize(RAM_SIZE))).unwrap();
+    bus.poke(memory::RAM_BASE, &data[..program.len() * 4]).unwrap();
+
+    let mut cpu = CPU::new(bus, memory::RAM_BASE);
+    for r in 1..32 {
+        let w = program[r as usize % program.len()];
+        let v = if r % 2 == 1 { memory::RAM_BASE + (w % RAM_SIZE as u32) } else { w };
+        cpu.register().setReg(r, v);
+    }
+    cpu.csr().writeCsr(crate::csr::MTVEC, memory::RAM_BASE);
+    for _ in 0..STEPS {
+        if cpu.step().is_some() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fuzz::*;
+    use crate::inst::OPS;
+
+    // xorshift32, as in the inst tests.
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_random_programs() {
+        // half of the words are random operands under the fixed bits of an operation, as random
+        // words alone are mostly illegal
+        let mut rng = Rng(0x1234_5678);
+        for _ in 0..2000 {
+            let data: Vec<u8> = (0..16).flat_map(|_| {
+                let w = rng.next();
+                let info = &OPS[rng.next() as usize % OPS.len()];
+                let w = if w & 1 == 0 { w } else { w & !info.mask | info.value };
+                w.to_le_bytes()
+            }).collect();
+            decode(&data);
+            execute(&data);
+        }
+    }
+}
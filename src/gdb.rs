@@ -0,0 +1,580 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use crate::bus::Watch;
+use crate::register::Reg;
+use crate::CPU;
+
+// GDB remote serial protocol stub (c.f., GDB manual, Appendix E "GDB Remote Serial Protocol"). The
+// hart is driven one CPU::step() at a time; software breakpoints are kept here and compared with the
+// pc instead of being patched into memory.
+
+const SIGINT                : u8 = 2;
+const SIGTRAP               : u8 = 5;
+const PC_REGNUM             : u32 = 32;
+// how many instructions run between checks for a ^C from gdb
+const POLL_INTERVAL         : u32 = 4096;
+// largest packet gdb may send or is sent (qSupported); a memory read returns at most half as many bytes
+const PACKET_SIZE           : usize = 0x4000;
+
+const TARGET_XML_HEAD       : &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>riscv:rv32</architecture>
+  <feature name="org.gnu.gdb.riscv.cpu">
+"#;
+const TARGET_XML_TAIL       : &str = r#"  </feature>
+</target>
+"#;
+
+trait Stream: Read + Write {
+    fn setNonblocking(&self, on: bool) -> io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn setNonblocking(&self, on: bool) -> io::Result<()> {
+        self.set_nonblocking(on)
+    }
+}
+
+impl Stream for UnixStream {
+    fn setNonblocking(&self, on: bool) -> io::Result<()> {
+        self.set_nonblocking(on)
+    }
+}
+
+// Why the target stopped.
+#[derive(Debug, Clone, Copy)]
+enum Stop {
+    Step,
+    Breakpoint,
+    Watchpoint(Watch, u32),
+    Interrupt,
+    Exited(u32),
+}
+
+pub struct Stub {
+    conn            : Box<dyn Stream>,
+    breakpoints     : Vec<u32>,
+    no_ack          : bool,
+    last            : Stop,
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &[u8]) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    s.chunks(2).map(|c| u8::from_str_radix(std::str::from_utf8(c).ok()?, 16).ok()).collect()
+}
+
+fn number(s: &[u8]) -> Option<u32> {
+    u32::from_str_radix(std::str::from_utf8(s).ok()?, 16).ok()
+}
+
+// "addr,len" (optionally followed by ":data")
+fn addrLen(s: &[u8]) -> Option<(u32, u32, &[u8])> {
+    let (head, data) = match s.iter().position(|&c| c == b':') {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None    => (s, &s[s.len()..]),
+    };
+    let comma = head.iter().position(|&c| c == b',')?;
+    Some((number(&head[..comma])?, number(&head[comma + 1..])?, data))
+}
+
+// The RV32 integer register set, named after register::Reg.
+fn targetXml() -> String {
+    let mut xml = TARGET_XML_HEAD.to_string();
+    for i in 0..32 {
+        let ty = match i {
+            1       => "code_ptr",
+            2..=4   => "data_ptr",
+            _       => "int",
+        };
+        xml += &format!("    <reg name=\"{}\" bitsize=\"32\" type=\"{}\" regnum=\"{}\"/>\n", Reg::name(i), ty, i);
+    }
+    xml += &format!("    <reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"{}\"/>\n", PC_REGNUM);
+    xml + TARGET_XML_TAIL
+}
+
+// Wait for gdb to connect on `addr`: "unix:PATH", "HOST:PORT" or just a port on localhost.
+pub fn listen(addr: &str) -> io::Result<Stub> {
+    let conn: Box<dyn Stream> = match addr.strip_prefix("unix:") {
+        Some(path)  => {
+            let _ = std::fs::remove_file(path);
+            let listener = UnixListener::bind(path)?;
+            eprintln!("gdb: waiting for a connection on {}", path);
+            Box::new(listener.accept()?.0)
+        },
+        None        => {
+            let addr = if addr.contains(':') { addr.to_string() } else { format!("127.0.0.1:{}", addr) };
+            let listener = TcpListener::bind(&addr)?;
+            eprintln!("gdb: waiting for a connection on {}", addr);
+            let (stream, _) = listener.accept()?;
+            stream.set_nodelay(true)?;
+            Box::new(stream)
+        },
+    };
+    Ok(Stub::new(conn))
+}
+
+impl Stub {
+    fn new(conn: Box<dyn Stream>) -> Stub {
+        Stub {
+            conn            : conn,
+            breakpoints     : vec![],
+            no_ack          : false,
+            last            : Stop::Step,
+        }
+    }
+
+    fn readByte(&mut self) -> io::Result<u8> {
+        let mut b = [0u8; 1];
+        self.conn.read_exact(&mut b)?;
+        Ok(b[0])
+    }
+
+    // Next packet payload, with '}' escapes removed. Stray acks and ^C between packets are dropped.
+    fn readPacket(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            while self.readByte()? != b'$' {}
+            let mut data = vec![];
+            let mut sum: u8 = 0;
+            loop {
+                let c = self.readByte()?;
+                if c == b'#' {
+                    break;
+                }
+                sum = sum.wrapping_add(c);
+                data.push(c);
+            }
+            let check = [self.readByte()?, self.readByte()?];
+            if self.no_ack {
+                return Ok(unescape(&data));
+            }
+            if number(&check) == Some(sum as u32) {
+                self.conn.write_all(b"+")?;
+                return Ok(unescape(&data));
+            }
+            self.conn.write_all(b"-")?;
+        }
+    }
+
+    fn sendPacket(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut out = vec![b'$'];
+        for &c in data {
+            if matches!(c, b'#' | b'$' | b'}' | b'*') {
+                out.push(b'}');
+                out.push(c ^ 0x20);
+            } else {
+                out.push(c);
+            }
+        }
+        let sum = out[1..].iter().fold(0u8, |s, &c| s.wrapping_add(c));
+        out.extend(format!("#{:02x}", sum).bytes());
+        loop {
+            self.conn.write_all(&out)?;
+            if self.no_ack {
+                return Ok(());
+            }
+            // retransmit on '-'
+            loop {
+                match self.readByte()? {
+                    b'+'    => return Ok(()),
+                    b'-'    => break,
+                    _       => {},
+                }
+            }
+        }
+    }
+
+    // Has gdb sent a ^C while the target was running?
+    fn interrupted(&mut self) -> bool {
+        if self.conn.setNonblocking(true).is_err() {
+            return false;
+        }
+        let mut b = [0u8; 1];
+        let hit = matches!(self.conn.read(&mut b), Ok(1) if b[0] == 0x03);
+        let _ = self.conn.setNonblocking(false);
+        hit
+    }
+
+    fn resume(&mut self, cpu: &mut CPU, single: bool) -> Stop {
+        let mut n: u32 = 0;
+        loop {
+            if let Some(status) = cpu.step() {
+                return Stop::Exited(status);
+            }
+            if let Some((kind, addr)) = cpu.bus().takeWatchHit() {
+                return Stop::Watchpoint(kind, addr);
+            }
+            if single {
+                return Stop::Step;
+            }
+            if self.breakpoints.contains(&cpu.register().getPC()) {
+                return Stop::Breakpoint;
+            }
+            n = n.wrapping_add(1);
+            if n.is_multiple_of(POLL_INTERVAL) && self.interrupted() {
+                return Stop::Interrupt;
+            }
+        }
+    }
+
+    fn stopReply(stop: Stop) -> String {
+        match stop {
+            Stop::Step                      => format!("S{:02x}", SIGTRAP),
+            Stop::Breakpoint                => format!("T{:02x}swbreak:;", SIGTRAP),
+            Stop::Watchpoint(kind, addr)    => {
+                let name = match kind {
+                    Watch::Write    => "watch",
+                    Watch::Read     => "rwatch",
+                    Watch::Access   => "awatch",
+                };
+                format!("T{:02x}{}:{:x};", SIGTRAP, name, addr)
+            },
+            Stop::Interrupt                 => format!("S{:02x}", SIGINT),
+            Stop::Exited(status)            => format!("W{:02x}", status & 0xFF),
+        }
+    }
+
+    fn readRegisters(cpu: &mut CPU) -> String {
+        let r = cpu.register();
+        let mut s: String = (0..32).map(|i| hex(&r.getReg(i).to_le_bytes())).collect();
+        s += &hex(&r.getPC().to_le_bytes());
+        s
+    }
+
+    fn writeRegister(cpu: &mut CPU, n: u32, v: u32) -> bool {
+        match n {
+            0..=31          => cpu.register().setReg(n, v),
+            PC_REGNUM       => cpu.register().setPC(v),
+            _               => return false,
+        }
+        true
+    }
+
+    // Z/z packets: "type,addr,kind"
+    fn breakpoint(&mut self, cpu: &mut CPU, args: &[u8], insert: bool) -> Option<&'static str> {
+        let fields: Vec<&[u8]> = args.split(|&c| c == b',').collect();
+        if fields.len() < 3 {
+            return Some("E01");
+        }
+        let addr = number(fields[1])?;
+        let len = number(fields[2])?;
+        let kind = match fields[0] {
+            b"0" | b"1" => {
+                if insert {
+                    self.breakpoints.push(addr);
+                } else if let Some(i) = self.breakpoints.iter().position(|&b| b == addr) {
+                    self.breakpoints.remove(i);
+                }
+                return Some("OK");
+            },
+            b"2"        => Watch::Write,
+            b"3"        => Watch::Read,
+            b"4"        => Watch::Access,
+            _           => return None,
+        };
+        if insert {
+            cpu.bus().addWatchpoint(addr, len, kind);
+        } else {
+            cpu.bus().removeWatchpoint(addr, len, kind);
+        }
+        Some("OK")
+    }
+
+    // Answer one packet. Returns the exit status once the session is over.
+    fn handle(&mut self, cpu: &mut CPU, p: &[u8]) -> io::Result<Option<u32>> {
+        if p == b"QStartNoAckMode" {
+            // takes effect once this reply has been acknowledged
+            self.sendPacket(b"OK")?;
+            self.no_ack = true;
+            return Ok(None);
+        }
+        let (cmd, args) = match p.split_first() {
+            Some((c, a))    => (*c, a),
+            None            => return self.sendPacket(b"").map(|_| None),
+        };
+        let reply: String = match cmd {
+            b'?'    => Stub::stopReply(self.last),
+            b'g'    => Stub::readRegisters(cpu),
+            b'G'    => match unhex(args) {
+                Some(d) if d.len() >= 33 * 4 => {
+                    for (i, w) in d.chunks(4).take(33).enumerate() {
+                        Stub::writeRegister(cpu, i as u32, u32::from_le_bytes([w[0], w[1], w[2], w[3]]));
+                    }
+                    "OK".to_string()
+                },
+                _       => "E01".to_string(),
+            },
+            b'p'    => match number(args) {
+                Some(n) if n < 32       => hex(&cpu.register().getReg(n).to_le_bytes()),
+                Some(PC_REGNUM)         => hex(&cpu.register().getPC().to_le_bytes()),
+                _                       => "E01".to_string(),
+            },
+            b'P'    => {
+                let eq = args.iter().position(|&c| c == b'=').unwrap_or(args.len());
+                let v = args.get(eq + 1..).and_then(unhex).filter(|v| v.len() == 4);
+                match (number(&args[..eq]), v) {
+                    (Some(n), Some(v)) if Stub::writeRegister(cpu, n, u32::from_le_bytes([v[0], v[1], v[2], v[3]])) => "OK".to_string(),
+                    _                   => "E01".to_string(),
+                }
+            },
+            b'm'    => match addrLen(args) {
+                Some((addr, len, _))    => cpu.bus().peek(addr, (len as usize).min(PACKET_SIZE / 2)).map(|d| hex(&d)).unwrap_or("E01".to_string()),
+                None                    => "E01".to_string(),
+            },
+            b'M'    => match addrLen(args).and_then(|(a, l, d)| unhex(d).filter(|d| d.len() == l as usize).map(|d| (a, d))) {
+                Some((addr, data))      => if cpu.bus().poke(addr, &data).is_ok() { "OK" } else { "E01" }.to_string(),
+                None                    => "E01".to_string(),
+            },
+            b'X'    => match addrLen(args) {
+                Some((addr, len, data)) if data.len() == len as usize => if cpu.bus().poke(addr, data).is_ok() { "OK" } else { "E01" }.to_string(),
+                _                       => "E01".to_string(),
+            },
+            b'c' | b's' => {
+                if let Some(addr) = number(args) {
+                    cpu.register().setPC(addr);
+                }
+                self.last = self.resume(cpu, cmd == b's');
+                self.sendPacket(Stub::stopReply(self.last).as_bytes())?;
+                return Ok(match self.last {
+                    Stop::Exited(status)    => Some(status),
+                    _                       => None,
+                });
+            },
+            b'Z' | b'z' => self.breakpoint(cpu, args, cmd == b'Z').unwrap_or("").to_string(),
+            b'H' | b'T' => "OK".to_string(),
+            b'k'    => return Ok(Some(0)),
+            b'D'    => {
+                // detach: the program runs on without the debugger
+                self.sendPacket(b"OK")?;
+                return Ok(Some(cpu.run()));
+            },
+            b'q' | b'Q' => self.query(p),
+            _       => String::new(),
+        };
+        self.sendPacket(reply.as_bytes())?;
+        Ok(None)
+    }
+
+    // General query packets. The target looks like a single thread to gdb.
+    fn query(&self, p: &[u8]) -> String {
+        let s = String::from_utf8_lossy(p);
+        if s.starts_with("qSupported") {
+            return format!("PacketSize={:x};qXfer:features:read+;swbreak+;QStartNoAckMode+", PACKET_SIZE);
+        }
+        if let Some(range) = s.strip_prefix("qXfer:features:read:target.xml:") {
+            let xml = targetXml();
+            let (off, len) = match addrLen(range.as_bytes()) {
+                Some((o, l, _)) => (o as usize, l as usize),
+                None            => return "E01".to_string(),
+            };
+            let start = off.min(xml.len());
+            let end = (off + len).min(xml.len());
+            return format!("{}{}", if end == xml.len() { "l" } else { "m" }, &xml[start..end]);
+        }
+        match s.as_ref() {
+            "qAttached"     => "1".to_string(),
+            "qC"            => "QC1".to_string(),
+            "qfThreadInfo"  => "m1".to_string(),
+            "qsThreadInfo"  => "l".to_string(),
+            _               => String::new(),
+        }
+    }
+
+    // Serve gdb until the program exits, gdb kills it or detaches (the program then runs to completion).
+    // Returns the exit status.
+    pub fn serve(&mut self, cpu: &mut CPU) -> io::Result<u32> {
+        loop {
+            let p = self.readPacket()?;
+            if let Some(status) = self.handle(cpu, &p)? {
+                return Ok(status);
+            }
+        }
+    }
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut it = data.iter();
+    while let Some(&c) = it.next() {
+        if c == b'}' {
+            if let Some(&n) = it.next() {
+                out.push(n ^ 0x20);
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    use crate::asm;
+    use crate::bus::Bus;
+    use crate::gdb::*;
+    use crate::memory::{self, Memory};
+
+    // gdb's side of the connection: what it sends is queued up front, what the stub sends is kept.
+    struct Pipe {
+        input       : Cursor<Vec<u8>>,
+        output      : Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Stream for Pipe {
+        fn setNonblocking(&self, _on: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(data: &[u8]) -> Vec<u8> {
+        let sum = data.iter().fold(0u8, |s, &c| s.wrapping_add(c));
+        [b"$", data, format!("#{:02x}", sum).as_bytes()].concat()
+    }
+
+    fn stub(input: Vec<u8>) -> (Stub, Arc<Mutex<Vec<u8>>>) {
+        let output = Arc::new(Mutex::new(vec![]));
+        (Stub::new(Box::new(Pipe { input: Cursor::new(input), output: output.clone() })), output)
+    }
+
+    // Send each packet (acknowledging every reply) until the input runs out; returns the replies.
+    fn session(cpu: &mut CPU, packets: &[&[u8]]) -> Vec<String> {
+        let input = packets.iter().flat_map(|p| [frame(p), b"+".to_vec()].concat()).collect();
+        let (mut stub, output) = stub(input);
+        assert_eq!(stub.serve(cpu).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        let out = output.lock().unwrap().clone();
+        let mut replies = vec![];
+        let mut i = 0;
+        while i < out.len() {
+            if out[i] == b'$' {
+                let end = i + out[i..].iter().position(|&c| c == b'#').unwrap();
+                replies.push(String::from_utf8(unescape(&out[i + 1..end])).unwrap());
+                i = end + 3;
+            } else {
+                assert_eq!(out[i], b'+');
+                i += 1;
+            }
+        }
+        replies
+    }
+
+    fn cpu(src: &str) -> CPU {
+        let p = asm::assemble(src).unwrap();
+        let mut bus = Bus::new();
+        bus.attach(memory::RAM_BASE, Box::new(Memory::withSize(0x10000))).unwrap();
+        bus.poke(p.origin, &p.image).unwrap();
+        CPU::new(bus, p.entry)
+    }
+
+    #[test]
+    fn test_framing() {
+        // noise and a bad checksum before the packet: nak, then ack the retransmission
+        let mut input = b"+\x03".to_vec();
+        input.extend(b"$m0,4#00");
+        input.extend(frame(b"m0,4"));
+        input.extend(b"-+");
+        let (mut stub, output) = stub(input);
+        assert_eq!(stub.readPacket().unwrap(), b"m0,4");
+        assert_eq!(output.lock().unwrap().as_slice(), b"-+");
+
+        // '#', '$', '}' and '*' are escaped, the checksum covers the escaped bytes; resent after a '-'
+        output.lock().unwrap().clear();
+        stub.sendPacket(b"a#$}*").unwrap();
+        let sent = frame(b"a}\x03}\x04}]}\x0a");
+        assert_eq!(output.lock().unwrap().as_slice(), [sent.clone(), sent].concat().as_slice());
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut cpu = cpu("nop\n");
+        cpu.register().setReg(10, 0x1234_5678);
+        let mut g = "0".repeat(33 * 8);
+        g.replace_range(8..16, "efbeadde");
+        g.replace_range(32 * 8.., "00010080");
+        let g = format!("G{}", g);
+        let r = session(&mut cpu, &[b"g", b"p0a", b"p20", b"p21", b"P5=78563412", b"P21=00000000", g.as_bytes(), b"p1", b"p5", b"p20"]);
+        assert_eq!(r[0].len(), 33 * 8);
+        assert_eq!(&r[0][80..88], "78563412");
+        assert_eq!(&r[0][256..], "00000080");
+        assert_eq!(r[1..], ["78563412", "00000080", "E01", "OK", "E01", "OK", "efbeadde", "00000000", "00010080"]);
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut cpu = cpu("nop\n");
+        // X data is binary: '}' escapes the bytes '#', '$' and '}'
+        let x = [&b"X80000100,4:"[..], b"}\x03}\x04}]\x01"].concat();
+        let r = session(&mut cpu, &[&x, b"m80000100,4", b"M80000104,2:abcd", b"m80000104,2", b"X80000100,2:a", b"m0,4"]);
+        assert_eq!(r, ["OK", "23247d01", "OK", "abcd", "E01", "E01"]);
+
+        // a read is capped at what fits in a packet
+        let r = session(&mut cpu, &[b"m80000000,ffffffff"]);
+        assert_eq!(r[0].len(), PACKET_SIZE);
+    }
+
+    #[test]
+    fn test_breakpoints_and_watchpoints() {
+        let src = "
+            li   a1, 0x80008000
+        loop:
+            addi a0, a0, 1
+            sw   a0, 0(a1)
+        here:
+            j    loop
+        ";
+        let mut cpu = cpu(src);
+        let here = asm::assemble(src).unwrap().symbols["here"];
+        let r = session(&mut cpu, &[
+            format!("Z0,{:x},4", here).as_bytes(), b"c", b"p20",
+            format!("z0,{:x},4", here).as_bytes(), b"Z2,80008000,4", b"c", b"p0a",
+            b"z2,80008000,4", b"Z3,80008000,4", b"s", b"s", b"s", b"z3,80008000,4", b"Z9,0,0",
+        ]);
+        assert_eq!(r[..3], ["OK", "T05swbreak:;", &hex(&here.to_le_bytes())]);
+        assert_eq!(r[3..7], ["OK", "OK", "T05watch:80008000;", "02000000"]);
+        // nothing reads the word: the steps run without a hit
+        assert_eq!(r[7..], ["OK", "OK", "S05", "S05", "S05", "OK", ""]);
+    }
+
+    #[test]
+    fn test_target_xml() {
+        let mut cpu = cpu("nop\n");
+        let xml = targetXml();
+        let r = session(&mut cpu, &[
+            b"qSupported:swbreak+", b"qXfer:features:read:target.xml:0,100",
+            format!("qXfer:features:read:target.xml:100,{:x}", xml.len()).as_bytes(),
+            format!("qXfer:features:read:target.xml:{:x},10", xml.len()).as_bytes(),
+        ]);
+        assert_eq!(r[0], "PacketSize=4000;qXfer:features:read+;swbreak+;QStartNoAckMode+");
+        assert_eq!(r[1], format!("m{}", &xml[..0x100]));
+        assert_eq!(r[2], format!("l{}", &xml[0x100..]));
+        assert_eq!(r[3], "l");
+        assert!(xml.contains("<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"32\"/>"));
+    }
+}
@@ -0,0 +1,268 @@
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::bus::Bus;
+use crate::elf;
+
+// HTIF (Host-Target Interface) as implemented by riscv-fesvr: the target writes a 64-bit command to
+// `tohost` and the host answers through `fromhost`.
+//   tohost[63:56] = device, tohost[55:48] = command, tohost[47:0] = payload
+const DEV_SYSCALL       : u32 = 0;
+const DEV_CONSOLE       : u32 = 1;
+const CMD_GETCHAR       : u32 = 0;
+const CMD_PUTCHAR       : u32 = 1;
+
+// Frontend syscalls proxied for the benchmark/newlib runtimes (riscv-pk numbering)
+const SYS_READ          : u64 = 63;
+const SYS_WRITE         : u64 = 64;
+const SYS_EXIT          : u64 = 93;
+const EFAULT            : i64 = 14;
+const ENOSYS            : i64 = 38;
+
+// Longest transfer of one SYS_READ / SYS_WRITE; larger requests complete partially.
+const MAX_TRANSFER      : usize = 64 * 1024;
+
+#[derive(Debug)]
+pub struct Htif {
+    tohost      : u32,
+    fromhost    : Option<u32>,
+    stdin       : Option<Receiver<u8>>,
+    getchar     : bool,     // a console read is waiting for input
+}
+
+impl Htif {
+    pub fn new(tohost: u32, fromhost: Option<u32>) -> Htif {
+        Htif {
+            tohost      : tohost,
+            fromhost    : fromhost,
+            stdin       : None,
+            getchar     : false,
+        }
+    }
+
+    // HTIF is used when the program exports a `tohost` symbol.
+    pub fn fromElf(e: &elf::Elf) -> Option<Htif> {
+        e.symbol("tohost").map(|t| Htif::new(t, e.symbol("fromhost")))
+    }
+
+    // The host's accesses go through peek / poke, which do not trigger the debugger's watchpoints.
+    fn read64(bus: &mut Bus, addr: u32) -> u64 {
+        bus.peek(addr, 8).map(|b| u64::from_le_bytes(b.try_into().unwrap())).unwrap_or(0)
+    }
+
+    fn write64(bus: &mut Bus, addr: u32, v: u64) {
+        let _ = bus.poke(addr, &v.to_le_bytes());
+    }
+
+    fn respond(&self, bus: &mut Bus, device: u32, cmd: u32, payload: u64) {
+        if let Some(fromhost) = self.fromhost {
+            Htif::write64(bus, fromhost, ((device as u64) << 56) | ((cmd as u64) << 48) | (payload & 0xFFFF_FFFF_FFFF));
+        }
+    }
+
+    // stdin is read a byte at a time on purpose: the guest sees keys as they are typed.
+    #[allow(clippy::unbuffered_bytes)]
+    fn stdinChar(&mut self) -> Option<u8> {
+        let rx = self.stdin.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                for b in io::stdin().bytes().flatten() {
+                    if sender.send(b).is_err() {
+                        break;
+                    }
+                }
+            });
+            receiver
+        });
+        rx.try_recv().ok()
+    }
+
+    // Proxy a frontend syscall described by the 8-doubleword magic_mem block at `addr`. The return
+    // value is written back into magic_mem[0].
+    fn syscall(&mut self, bus: &mut Bus, addr: u32) -> Option<u32> {
+        let args: Vec<u64> = (0..8).map(|i| Htif::read64(bus, addr + 8 * i)).collect();
+        let ret: i64 = match args[0] {
+            SYS_EXIT    => return Some(args[1] as u32),
+            SYS_WRITE   => {
+                let buf = match bus.peek(args[2] as u32, (args[3] as usize).min(MAX_TRANSFER)) {
+                    Ok(buf) => buf,
+                    Err(_)  => return Htif::ret(bus, addr, -EFAULT),
+                };
+                let written = match args[1] {
+                    1   => io::stdout().write_all(&buf).and_then(|_| io::stdout().flush()),
+                    2   => io::stderr().write_all(&buf),
+                    _   => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+                };
+                if written.is_ok() { buf.len() as i64 } else { -9 }  // EBADF
+            },
+            SYS_READ if args[1] == 0 => Htif::readFrom(bus, &mut io::stdin(), args[2] as u32, args[3]),
+            _           => -ENOSYS,
+        };
+        Htif::ret(bus, addr, ret)
+    }
+
+    fn ret(bus: &mut Bus, addr: u32, v: i64) -> Option<u32> {
+        Htif::write64(bus, addr, v as u64);
+        None
+    }
+
+    // The destination is checked before reading, so input is not consumed by a call that faults.
+    fn readFrom(bus: &mut Bus, input: &mut impl Read, addr: u32, len: u64) -> i64 {
+        let len = (len as usize).min(MAX_TRANSFER);
+        if bus.peek(addr, len).is_err() {
+            return -EFAULT;
+        }
+        let mut buf = vec![0u8; len];
+        match input.read(&mut buf) {
+            Ok(n)   => {
+                if bus.poke(addr, &buf[..n]).is_err() {
+                    return -EFAULT;
+                }
+                n as i64
+            },
+            Err(_)  => -5,  // EIO
+        }
+    }
+
+    // Called once per instruction. Returns the exit status once the target has finished.
+    pub fn poll(&mut self, bus: &mut Bus) -> Option<u32> {
+        if self.getchar {
+            if let Some(c) = self.stdinChar() {
+                self.getchar = false;
+                self.respond(bus, DEV_CONSOLE, CMD_GETCHAR, c as u64);
+            }
+        }
+
+        let cmd = Htif::read64(bus, self.tohost);
+        if cmd == 0 {
+            return None;
+        }
+        Htif::write64(bus, self.tohost, 0);
+
+        let device = (cmd >> 56) as u32;
+        let command = ((cmd >> 48) & 0xFF) as u32;
+        let payload = cmd & 0xFFFF_FFFF_FFFF;
+        match (device, command) {
+            (DEV_SYSCALL, 0)            => {
+                // riscv-tests: tohost = (code << 1) | 1, where code 0 means pass
+                if payload & 1 != 0 {
+                    let code = (payload >> 1) as u32;
+                    if code != 0 {
+                        eprintln!("*** FAILED *** (tohost = {})", code);
+                    }
+                    return Some(code);
+                }
+                if let Some(status) = self.syscall(bus, payload as u32) {
+                    return Some(status);
+                }
+                self.respond(bus, DEV_SYSCALL, 0, 1);
+            },
+            (DEV_CONSOLE, CMD_PUTCHAR)  => {
+                let _ = io::stdout().write_all(&[payload as u8]);
+                let _ = io::stdout().flush();
+                self.respond(bus, DEV_CONSOLE, CMD_PUTCHAR, 0);
+            },
+            (DEV_CONSOLE, CMD_GETCHAR)  => self.getchar = true,
+            _                           => eprintln!("htif: unsupported command 0x{:016x}", cmd),
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bus::Watch;
+    use crate::htif::*;
+    use crate::memory::{self, Memory};
+
+    fn bus() -> Bus {
+        let mut bus = Bus::new();
+        bus.attach(memory::RAM_BASE, Box::new(Memory::withSize(4096))).unwrap();
+        bus
+    }
+
+    #[test]
+    fn test_poll_skips_watchpoints() {
+        let (tohost, fromhost) = (memory::RAM_BASE + 0x100, memory::RAM_BASE + 0x108);
+        let mut bus = bus();
+        bus.addWatchpoint(tohost, 8, Watch::Access);
+        bus.addWatchpoint(fromhost, 8, Watch::Access);
+        let mut h = Htif::new(tohost, Some(fromhost));
+        assert_eq!(h.poll(&mut bus), None);
+
+        // a console write is answered through fromhost, still without a hit
+        bus.poke(tohost, &(((DEV_CONSOLE as u64) << 56) | ((CMD_PUTCHAR as u64) << 48) | b'\n' as u64).to_le_bytes()).unwrap();
+        assert_eq!(h.poll(&mut bus), None);
+        assert_eq!(bus.peek(tohost, 8).unwrap(), [0; 8]);
+        assert_eq!(bus.peek(fromhost + 4, 4).unwrap(), [0, 0, CMD_PUTCHAR as u8, DEV_CONSOLE as u8]);
+        assert_eq!(bus.takeWatchHit(), None);
+    }
+
+    #[test]
+    fn test_riscv_tests_exit() {
+        let tohost = memory::RAM_BASE + 0x100;
+        let mut bus = bus();
+        let mut h = Htif::new(tohost, None);
+        bus.poke(tohost, &1u64.to_le_bytes()).unwrap();
+        assert_eq!(h.poll(&mut bus), Some(0));
+        bus.poke(tohost, &((5u64 << 1) | 1).to_le_bytes()).unwrap();
+        assert_eq!(h.poll(&mut bus), Some(5));
+
+        // unknown devices are reported and ignored
+        bus.poke(tohost, &(0x7Fu64 << 56).to_le_bytes()).unwrap();
+        assert_eq!(h.poll(&mut bus), None);
+        assert_eq!(bus.peek(tohost, 8).unwrap(), [0; 8]);
+    }
+
+    #[test]
+    fn test_syscall_proxy() {
+        let (tohost, fromhost) = (memory::RAM_BASE + 0x100, memory::RAM_BASE + 0x108);
+        let magic = memory::RAM_BASE + 0x200;
+        let mut bus = bus();
+        let mut h = Htif::new(tohost, Some(fromhost));
+        let mut call = |bus: &mut Bus, args: &[u64]| {
+            let mem: Vec<u8> = (0..8).flat_map(|i| args.get(i).copied().unwrap_or(0).to_le_bytes()).collect();
+            bus.poke(magic, &mem).unwrap();
+            bus.poke(fromhost, &[0; 8]).unwrap();
+            bus.poke(tohost, &(magic as u64).to_le_bytes()).unwrap();
+            let status = h.poll(bus);
+            (status, Htif::read64(bus, magic) as i64)
+        };
+
+        // the return value goes to magic_mem[0], and fromhost acknowledges the request
+        assert_eq!(call(&mut bus, &[SYS_WRITE, 2, magic as u64, 0]), (None, 0));
+        assert_eq!(Htif::read64(&mut bus, fromhost), 1);
+        assert_eq!(call(&mut bus, &[SYS_WRITE, 7, magic as u64, 4]), (None, -9));
+        assert_eq!(call(&mut bus, &[SYS_READ, 3, magic as u64, 4]), (None, -ENOSYS));
+        assert_eq!(call(&mut bus, &[1234]), (None, -ENOSYS));
+        assert_eq!(call(&mut bus, &[SYS_EXIT, 42]).0, Some(42));
+        assert_eq!(Htif::read64(&mut bus, fromhost), 0);
+    }
+
+    #[test]
+    fn test_transfer_faults() {
+        let (tohost, fromhost) = (memory::RAM_BASE + 0x100, memory::RAM_BASE + 0x108);
+        let magic = memory::RAM_BASE + 0x200;
+        let mut bus = bus();
+        let mut h = Htif::new(tohost, Some(fromhost));
+        let args: Vec<u8> = [SYS_WRITE, 2, 0x1000, 4, 0, 0, 0, 0].iter().flat_map(|a| a.to_le_bytes()).collect();
+        bus.poke(magic, &args).unwrap();
+        bus.poke(tohost, &(magic as u64).to_le_bytes()).unwrap();
+        assert_eq!(h.poll(&mut bus), None);
+        assert_eq!(Htif::read64(&mut bus, magic) as i64, -EFAULT);
+
+        // reads land in guest memory; a bad buffer faults without consuming input or allocating
+        // the requested length
+        let mut input = &b"hello"[..];
+        let buf = memory::RAM_BASE + 0x300;
+        assert_eq!(Htif::readFrom(&mut bus, &mut input, buf, 3), 3);
+        assert_eq!(bus.peek(buf, 4).unwrap(), b"hel\0");
+        assert_eq!(Htif::readFrom(&mut bus, &mut input, 0x1000, 2), -EFAULT);
+        assert_eq!(Htif::readFrom(&mut bus, &mut input, buf, u64::MAX), -EFAULT);
+        assert_eq!(Htif::readFrom(&mut bus, &mut input, buf + 0xC00, u64::MAX >> 1), -EFAULT);
+        assert_eq!(Htif::readFrom(&mut bus, &mut input, buf, 16), 2);
+        assert_eq!(bus.peek(buf, 2).unwrap(), b"lo");
+        assert_eq!(Htif::readFrom(&mut bus, &mut input, buf, 16), 0);
+    }
+}
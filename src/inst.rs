@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+
+use crate::{BitFields, Encode};
+
+// Typed view of an instruction word: the operation and its operands. Instructions are recognized
+// with a MATCH/MASK table, as in the riscv-opcodes repository: `word & mask == match` identifies the
+// operation, and the bits outside the mask are the operands, which are extracted according to the
+// operand format. Encoding goes the other way: the operands are placed with the field layouts of the
+// R/I/S/B/U/J formats (RTypeBitField etc.) and the fixed bits come from the table.
+
+// Operand layout of an operation. Every bit an instruction does not fix is held by one of the
+// operands, so a decoded instruction carries the whole word.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    R,          // rd, rs1, rs2
+    I,          // rd, rs1, imm[11:0] (sign-extended)
+    Shift,      // rd, rs1, shamt (in imm)
+    S,          // rs1, rs2, imm[11:0]
+    B,          // rs1, rs2, imm[12:1]
+    U,          // rd, imm[31:12] (imm holds the value with the low 12 bits clear)
+    J,          // rd, imm[20:1]
+    Amo,        // rd, rs1, rs2, aq/rl (in imm: aq << 1 | rl)
+    Lr,         // rd, rs1, aq/rl
+    Csr,        // rd, rs1, csr (in imm)
+    CsrI,       // rd, uimm (in rs1), csr (in imm)
+    Fence,      // rd, rs1, fm/pred/succ (in imm, unsigned)
+    Cbo,        // rs1
+    None,       // no operands
+}
+
+// c.f., Chapter 34: RV32/64G Instruction Set Listings, plus Zicsr, Zifencei, Zicbom/Zicboz and the
+// privileged instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Op {
+    LUI, AUIPC, JAL, JALR,
+    BEQ, BNE, BLT, BGE, BLTU, BGEU,
+    LB, LH, LW, LBU, LHU,
+    SB, SH, SW,
+    ADDI, SLTI, SLTIU, XORI, ORI, ANDI, SLLI, SRLI, SRAI,
+    ADD, SUB, SLL, SLT, SLTU, XOR, SRL, SRA, OR, AND,
+    FENCE, FENCE_I,
+    ECALL, EBREAK, SRET, MRET, WFI,
+    CSRRW, CSRRS, CSRRC, CSRRWI, CSRRSI, CSRRCI,
+    MUL, MULH, MULHSU, MULHU, DIV, DIVU, REM, REMU,
+    LR_W, SC_W, AMOSWAP_W, AMOADD_W, AMOXOR_W, AMOAND_W, AMOOR_W, AMOMIN_W, AMOMAX_W, AMOMINU_W, AMOMAXU_W,
+    CBO_INVAL, CBO_CLEAN, CBO_FLUSH, CBO_ZERO,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Inst {
+    pub op      : Op,
+    pub rd      : u32,
+    pub rs1     : u32,
+    pub rs2     : u32,
+    pub imm     : i32,
+}
+
+pub struct OpInfo {
+    pub op      : Op,
+    pub name    : &'static str,
+    pub format  : Format,
+    pub mask    : u32,
+    pub value   : u32,
+}
+
+// Fields an operation may fix: opcode [6:0], funct3 [14:12], funct7 [31:25] (funct5 [31:27] for AMOs),
+// funct12 [31:20], and rs2 / rd when they must be zero.
+const OPCODE        : u32 = 0x0000_007F;
+const FUNCT3        : u32 = 0x0000_7000;
+const FUNCT7        : u32 = 0xFE00_0000;
+const FUNCT5        : u32 = 0xF800_0000;
+const FUNCT12       : u32 = 0xFFF0_0000;
+const RS2           : u32 = 0x01F0_0000;
+const RD            : u32 = 0x0000_0F80;
+
+const fn info(op: Op, name: &'static str, format: Format, mask: u32, value: u32) -> OpInfo {
+    OpInfo { op: op, name: name, format: format, mask: mask, value: value }
+}
+
+const fn f3(opcode: u32, funct3: u32) -> u32 {
+    opcode | funct3 << 12
+}
+
+const fn f7(opcode: u32, funct3: u32, funct7: u32) -> u32 {
+    opcode | funct3 << 12 | funct7 << 25
+}
+
+const fn amo(funct5: u32) -> u32 {
+    0b0101111 | 0b010 << 12 | funct5 << 27
+}
+
+const fn sys(funct12: u32) -> u32 {
+    0b1110011 | funct12 << 20
+}
+
+const fn cbo(funct12: u32) -> u32 {
+    0b0001111 | 0b010 << 12 | funct12 << 20
+}
+
+pub const OPS: [OpInfo; 73] = [
+    info(Op::LUI,       "lui",       Format::U,     OPCODE,                         0b0110111),
+    info(Op::AUIPC,     "auipc",     Format::U,     OPCODE,                         0b0010111),
+    info(Op::JAL,       "jal",       Format::J,     OPCODE,                         0b1101111),
+    info(Op::JALR,      "jalr",      Format::I,     OPCODE | FUNCT3,                f3(0b1100111, 0b000)),
+    info(Op::BEQ,       "beq",       Format::B,     OPCODE | FUNCT3,                f3(0b1100011, 0b000)),
+    info(Op::BNE,       "bne",       Format::B,     OPCODE | FUNCT3,                f3(0b1100011, 0b001)),
+    info(Op::BLT,       "blt",       Format::B,     OPCODE | FUNCT3,                f3(0b1100011, 0b100)),
+    info(Op::BGE,       "bge",       Format::B,     OPCODE | FUNCT3,                f3(0b1100011, 0b101)),
+    info(Op::BLTU,      "bltu",      Format::B,     OPCODE | FUNCT3,                f3(0b1100011, 0b110)),
+    info(Op::BGEU,      "bgeu",      Format::B,     OPCODE | FUNCT3,                f3(0b1100011, 0b111)),
+    info(Op::LB,        "lb",        Format::I,     OPCODE | FUNCT3,                f3(0b0000011, 0b000)),
+    info(Op::LH,        "lh",        Format::I,     OPCODE | FUNCT3,                f3(0b0000011, 0b001)),
+    info(Op::LW,        "lw",        Format::I,     OPCODE | FUNCT3,                f3(0b0000011, 0b010)),
+    info(Op::LBU,       "lbu",       Format::I,     OPCODE | FUNCT3,                f3(0b0000011, 0b100)),
+    info(Op::LHU,       "lhu",       Format::I,     OPCODE | FUNCT3,                f3(0b0000011, 0b101)),
+    info(Op::SB,        "sb",        Format::S,     OPCODE | FUNCT3,                f3(0b0100011, 0b000)),
+    info(Op::SH,        "sh",        Format::S,     OPCODE | FUNCT3,                f3(0b0100011, 0b001)),
+    info(Op::SW,        "sw",        Format::S,     OPCODE | FUNCT3,                f3(0b0100011, 0b010)),
+    info(Op::ADDI,      "addi",      Format::I,     OPCODE | FUNCT3,                f3(0b0010011, 0b000)),
+    info(Op::SLTI,      "slti",      Format::I,     OPCODE | FUNCT3,                f3(0b0010011, 0b010)),
+    info(Op::SLTIU,     "sltiu",     Format::I,     OPCODE | FUNCT3,                f3(0b0010011, 0b011)),
+    info(Op::XORI,      "xori",      Format::I,     OPCODE | FUNCT3,                f3(0b0010011, 0b100)),
+    info(Op::ORI,       "ori",       Format::I,     OPCODE | FUNCT3,                f3(0b0010011, 0b110)),
+    info(Op::ANDI,      "andi",      Format::I,     OPCODE | FUNCT3,                f3(0b0010011, 0b111)),
+    info(Op::SLLI,      "slli",      Format::Shift, OPCODE | FUNCT3 | FUNCT7,       f7(0b0010011, 0b001, 0b000_0000)),
+    info(Op::SRLI,      "srli",      Format::Shift, OPCODE | FUNCT3 | FUNCT7,       f7(0b0010011, 0b101, 0b000_0000)),
+    info(Op::SRAI,      "srai",      Format::Shift, OPCODE | FUNCT3 | FUNCT7,       f7(0b0010011, 0b101, 0b010_0000)),
+    info(Op::ADD,       "add",       Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b000, 0b000_0000)),
+    info(Op::SUB,       "sub",       Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b000, 0b010_0000)),
+    info(Op::SLL,       "sll",       Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b001, 0b000_0000)),
+    info(Op::SLT,       "slt",       Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b010, 0b000_0000)),
+    info(Op::SLTU,      "sltu",      Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b011, 0b000_0000)),
+    info(Op::XOR,       "xor",       Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b100, 0b000_0000)),
+    info(Op::SRL,       "srl",       Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b101, 0b000_0000)),
+    info(Op::SRA,       "sra",       Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b101, 0b010_0000)),
+    info(Op::OR,        "or",        Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b110, 0b000_0000)),
+    info(Op::AND,       "and",       Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b111, 0b000_0000)),
+    info(Op::FENCE,     "fence",     Format::Fence, OPCODE | FUNCT3,                f3(0b0001111, 0b000)),
+    info(Op::FENCE_I,   "fence.i",   Format::Fence, OPCODE | FUNCT3,                f3(0b0001111, 0b001)),
+    info(Op::ECALL,     "ecall",     Format::None,  !0,                             sys(0x000)),
+    info(Op::EBREAK,    "ebreak",    Format::None,  !0,                             sys(0x001)),
+    info(Op::SRET,      "sret",      Format::None,  !0,                             sys(0x102)),
+    info(Op::MRET,      "mret",      Format::None,  !0,                             sys(0x302)),
+    info(Op::WFI,       "wfi",       Format::None,  !0,                             sys(0x105)),
+    info(Op::CSRRW,     "csrrw",     Format::Csr,   OPCODE | FUNCT3,                f3(0b1110011, 0b001)),
+    info(Op::CSRRS,     "csrrs",     Format::Csr,   OPCODE | FUNCT3,                f3(0b1110011, 0b010)),
+    info(Op::CSRRC,     "csrrc",     Format::Csr,   OPCODE | FUNCT3,                f3(0b1110011, 0b011)),
+    info(Op::CSRRWI,    "csrrwi",    Format::CsrI,  OPCODE | FUNCT3,                f3(0b1110011, 0b101)),
+    info(Op::CSRRSI,    "csrrsi",    Format::CsrI,  OPCODE | FUNCT3,                f3(0b1110011, 0b110)),
+    info(Op::CSRRCI,    "csrrci",    Format::CsrI,  OPCODE | FUNCT3,                f3(0b1110011, 0b111)),
+    info(Op::MUL,       "mul",       Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b000, 0b000_0001)),
+    info(Op::MULH,      "mulh",      Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b001, 0b000_0001)),
+    info(Op::MULHSU,    "mulhsu",    Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b010, 0b000_0001)),
+    info(Op::MULHU,     "mulhu",     Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b011, 0b000_0001)),
+    info(Op::DIV,       "div",       Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b100, 0b000_0001)),
+    info(Op::DIVU,      "divu",      Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b101, 0b000_0001)),
+    info(Op::REM,       "rem",       Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b110, 0b000_0001)),
+    info(Op::REMU,      "remu",      Format::R,     OPCODE | FUNCT3 | FUNCT7,       f7(0b0110011, 0b111, 0b000_0001)),
+    info(Op::LR_W,      "lr.w",      Format::Lr,    OPCODE | FUNCT3 | FUNCT5 | RS2, amo(0b00010)),
+    info(Op::SC_W,      "sc.w",      Format::Amo,   OPCODE | FUNCT3 | FUNCT5,       amo(0b00011)),
+    info(Op::AMOSWAP_W, "amoswap.w", Format::Amo,   OPCODE | FUNCT3 | FUNCT5,       amo(0b00001)),
+    info(Op::AMOADD_W,  "amoadd.w",  Format::Amo,   OPCODE | FUNCT3 | FUNCT5,       amo(0b00000)),
+    info(Op::AMOXOR_W,  "amoxor.w",  Format::Amo,   OPCODE | FUNCT3 | FUNCT5,       amo(0b00100)),
+    info(Op::AMOAND_W,  "amoand.w",  Format::Amo,   OPCODE | FUNCT3 | FUNCT5,       amo(0b01100)),
+    info(Op::AMOOR_W,   "amoor.w",   Format::Amo,   OPCODE | FUNCT3 | FUNCT5,       amo(0b01000)),
+    info(Op::AMOMIN_W,  "amomin.w",  Format::Amo,   OPCODE | FUNCT3 | FUNCT5,       amo(0b10000)),
+    info(Op::AMOMAX_W,  "amomax.w",  Format::Amo,   OPCODE | FUNCT3 | FUNCT5,       amo(0b10100)),
+    info(Op::AMOMINU_W, "amominu.w", Format::Amo,   OPCODE | FUNCT3 | FUNCT5,       amo(0b11000)),
+    info(Op::AMOMAXU_W, "amomaxu.w", Format::Amo,   OPCODE | FUNCT3 | FUNCT5,       amo(0b11100)),
+    info(Op::CBO_INVAL, "cbo.inval", Format::Cbo,   OPCODE | FUNCT3 | FUNCT12 | RD, cbo(0b000)),
+    info(Op::CBO_CLEAN, "cbo.clean", Format::Cbo,   OPCODE | FUNCT3 | FUNCT12 | RD, cbo(0b001)),
+    info(Op::CBO_FLUSH, "cbo.flush", Format::Cbo,   OPCODE | FUNCT3 | FUNCT12 | RD, cbo(0b010)),
+    info(Op::CBO_ZERO,  "cbo.zero",  Format::Cbo,   OPCODE | FUNCT3 | FUNCT12 | RD, cbo(0b100)),
+];
+
+fn sext(v: u32, bits: u32) -> i32 {
+    ((v << (32 - bits)) as i32) >> (32 - bits)
+}
+
+impl Op {
+    pub fn info(&self) -> &'static OpInfo {
+        // OPS is in the same order as Op
+        &OPS[*self as usize]
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.info().name
+    }
+
+    pub fn format(&self) -> Format {
+        self.info().format
+    }
+
+    pub fn parse(name: &str) -> Option<Op> {
+        OPS.iter().find(|i| i.name == name).map(|i| i.op)
+    }
+
+    // Extension that defines the operation, as named in ISA strings (c.f., isa.rs). MRET, WFI, ECALL
+    // and EBREAK are there in every hart and count as base ISA; SRET needs S-mode.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Op::FENCE_I                                         => "zifencei",
+            Op::SRET                                            => "s",
+            Op::CSRRW | Op::CSRRS | Op::CSRRC |
+            Op::CSRRWI | Op::CSRRSI | Op::CSRRCI                => "zicsr",
+            Op::MUL | Op::MULH | Op::MULHSU | Op::MULHU |
+            Op::DIV | Op::DIVU | Op::REM | Op::REMU             => "m",
+            Op::LR_W | Op::SC_W | Op::AMOSWAP_W | Op::AMOADD_W | Op::AMOXOR_W | Op::AMOAND_W |
+            Op::AMOOR_W | Op::AMOMIN_W | Op::AMOMAX_W | Op::AMOMINU_W | Op::AMOMAXU_W => "a",
+            Op::CBO_INVAL | Op::CBO_CLEAN | Op::CBO_FLUSH       => "zicbom",
+            Op::CBO_ZERO                                        => "zicboz",
+            _                                                   => "i",
+        }
+    }
+}
+
+impl Inst {
+    pub fn new(op: Op, rd: u32, rs1: u32, rs2: u32, imm: i32) -> Inst {
+        Inst { op: op, rd: rd, rs1: rs1, rs2: rs2, imm: imm }
+    }
+
+    // None for words that are not a (32-bit) instruction of the implemented extensions.
+    pub fn decode(w: u32) -> Option<Inst> {
+        let info = OPS.iter().find(|i| w & i.mask == i.value)?;
+        let rd = (w >> 7) & 0x1F;
+        let rs1 = (w >> 15) & 0x1F;
+        let rs2 = (w >> 20) & 0x1F;
+        let inst = match info.format {
+            Format::R       => Inst::new(info.op, rd, rs1, rs2, 0),
+            Format::I       => Inst::new(info.op, rd, rs1, 0, sext(w >> 20, 12)),
+            Format::Shift   => Inst::new(info.op, rd, rs1, 0, rs2 as i32),
+            Format::S       => Inst::new(info.op, 0, rs1, rs2, sext((w >> 25) << 5 | rd, 12)),
+            Format::B       => Inst::new(info.op, 0, rs1, rs2, sext((w >> 31) << 12 | ((w >> 7) & 1) << 11 | ((w >> 25) & 0x3F) << 5 | ((w >> 8) & 0xF) << 1, 13)),
+            Format::U       => Inst::new(info.op, rd, 0, 0, (w & 0xFFFF_F000) as i32),
+            Format::J       => Inst::new(info.op, rd, 0, 0, sext((w >> 31) << 20 | ((w >> 12) & 0xFF) << 12 | ((w >> 20) & 1) << 11 | ((w >> 21) & 0x3FF) << 1, 21)),
+            Format::Amo     => Inst::new(info.op, rd, rs1, rs2, ((w >> 25) & 0b11) as i32),
+            Format::Lr      => Inst::new(info.op, rd, rs1, 0, ((w >> 25) & 0b11) as i32),
+            Format::Csr     => Inst::new(info.op, rd, rs1, 0, (w >> 20) as i32),
+            Format::CsrI    => Inst::new(info.op, rd, rs1, 0, (w >> 20) as i32),
+            Format::Fence   => Inst::new(info.op, rd, rs1, 0, (w >> 20) as i32),
+            Format::Cbo     => Inst::new(info.op, 0, rs1, 0, 0),
+            Format::None    => Inst::new(info.op, 0, 0, 0, 0),
+        };
+        Some(inst)
+    }
+
+    // The instruction word. Fails if an operand does not fit its field (a register above x31, an
+    // out-of-range immediate, or a misaligned branch/jump offset). There is no C extension, so every
+    // instruction is a 32-bit word.
+    pub fn encode(&self) -> Result<u32, String> {
+        let info = self.op.info();
+        let bf = BitFields::new();
+        for (name, r) in [("rd", self.rd), ("rs1", self.rs1), ("rs2", self.rs2)] {
+            if r >= 32 && !(name == "rs1" && info.format == Format::CsrI) {
+                return Err(format!("{}: register x{} out of range", info.name, r));
+            }
+        }
+        let (imm, u) = (self.imm as i64, self.imm as u32);
+        let check = |ok: bool, what: &str| {
+            if ok { Ok(()) } else { Err(format!("{}: {} out of range: {}", info.name, what, self.imm)) }
+        };
+        let mut f: HashMap<&str, u32> = HashMap::new();
+        f.insert("rd", self.rd);
+        f.insert("rs1", self.rs1);
+        f.insert("rs2", self.rs2);
+        let fields = match info.format {
+            Format::R           => bf.RTYPE.writeFields(&f),
+            // aq/rl are the low bits of funct7 (funct5 comes from the table)
+            Format::Amo | Format::Lr => {
+                f.insert("funct7", u & 0b11);
+                bf.RTYPE.writeFields(&f)
+            },
+            Format::I           => {
+                check((-0x800..0x800).contains(&imm), "immediate")?;
+                f.insert("imm_11_0", u);
+                bf.ITYPE.writeFields(&f)
+            },
+            Format::Shift       => {
+                check((0..32).contains(&imm), "shift amount")?;
+                f.insert("imm_4_0", u);
+                bf.ITYPE.writeFields(&f)
+            },
+            // the CSR number / fence bits take the whole I-immediate
+            Format::Csr | Format::CsrI | Format::Fence => {
+                check((0..0x1000).contains(&imm), "immediate")?;
+                if self.rs1 >= 32 {
+                    return Err(format!("{}: immediate out of range: {}", info.name, self.rs1));
+                }
+                f.insert("imm_11_0", u);
+                bf.ITYPE.writeFields(&f)
+            },
+            Format::Cbo         => {
+                f.insert("rd", 0);
+                bf.ITYPE.writeFields(&f)
+            },
+            Format::None        => 0,
+            Format::S           => {
+                check((-0x800..0x800).contains(&imm), "offset")?;
+                f.insert("imm_4_0", u & 0x1F);
+                f.insert("imm_11_5", u >> 5);
+                bf.STYPE.writeFields(&f)
+            },
+            Format::B           => {
+                check((-0x1000..0x1000).contains(&imm) && imm & 1 == 0, "branch offset")?;
+                f.insert("imm_11", u >> 11 & 1);
+                f.insert("imm_4_1", u >> 1);
+                f.insert("imm_10_5", u >> 5);
+                f.insert("imm_12", u >> 12 & 1);
+                bf.BTYPE.writeFields(&f)
+            },
+            Format::U           => {
+                check(u & 0xFFF == 0, "immediate")?;
+                f.insert("imm_31_12", u >> 12);
+                bf.UTYPE.writeFields(&f)
+            },
+            Format::J           => {
+                check((-0x10_0000..0x10_0000).contains(&imm) && imm & 1 == 0, "jump offset")?;
+                f.insert("imm_19_12", u >> 12);
+                f.insert("imm_11", u >> 11 & 1);
+                f.insert("imm_10_1", u >> 1);
+                f.insert("imm_20", u >> 20 & 1);
+                bf.JTYPE.writeFields(&f)
+            },
+        };
+        Ok(info.value | fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::inst::*;
+
+    // xorshift32, so that the property tests need nothing beyond std and are reproducible.
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_round_trip_every_op() {
+        // random operand bits under each operation's fixed bits
+        let mut rng = Rng(0x2545_F491);
+        for info in OPS.iter() {
+            for _ in 0..4096 {
+                let w = rng.next() & !info.mask | info.value;
+                let inst = Inst::decode(w).unwrap();
+                assert_eq!(inst.op, info.op, "{:08x}", w);
+                assert_eq!(inst.encode(), Ok(w), "{} {:08x}", info.name, w);
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip_random_words() {
+        let mut rng = Rng(0x9E37_79B9);
+        for _ in 0..1 << 20 {
+            let w = rng.next();
+            if let Some(inst) = Inst::decode(w) {
+                assert_eq!(inst.encode(), Ok(w), "{:08x}", w);
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_out_of_range() {
+        assert!(Inst::new(Op::ADDI, 1, 2, 0, 2048).encode().is_err());
+        assert!(Inst::new(Op::ADD, 32, 0, 0, 0).encode().is_err());
+        assert!(Inst::new(Op::SLLI, 1, 1, 0, 32).encode().is_err());
+        assert!(Inst::new(Op::BEQ, 0, 1, 2, 3).encode().is_err());
+        assert!(Inst::new(Op::JAL, 1, 0, 0, 1 << 20).encode().is_err());
+        assert!(Inst::new(Op::LUI, 1, 0, 0, 0x123).encode().is_err());
+        assert_eq!(Inst::new(Op::JAL, 0, 0, 0, -12).encode(), Ok(0xff5f_f06f));
+    }
+}
@@ -0,0 +1,135 @@
+use crate::csr;
+
+// ISA strings (c.f., The RISC-V Instruction Set Manual, Volume I, Chapter 36: ISA Extension Naming
+// Conventions): "rv32" followed by single-letter extensions and then multi-letter ones (Z*, S*, X*)
+// separated by underscores, each optionally versioned ("2p1"). Case does not matter, so the strings of
+// riscv-config ("RV32IMAZicsr_Zifencei") and of the toolchains ("rv32ima_zicsr_zifencei") are both
+// accepted. "g" stands for imafd_zicsr_zifencei.
+
+// Extensions the simulator implements.
+pub const SUPPORTED: [&str; 9] = ["i", "m", "a", "u", "zicsr", "zifencei", "zicntr", "zicbom", "zicboz"];
+
+// All of them.
+pub const DEFAULT: &str = "rv32imau_zicsr_zifencei_zicntr_zicbom_zicboz";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Isa {
+    pub xlen        : u32,
+    pub extensions  : Vec<String>,  // lower case, without versions, in the order of the string
+}
+
+// Drops a version suffix ("2", "2p0") from the end of an extension name.
+fn unversioned(name: &str) -> &str {
+    let name = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    match name.strip_suffix('p') {
+        Some(n) if n.ends_with(|c: char| c.is_ascii_digit()) => n.trim_end_matches(|c: char| c.is_ascii_digit()),
+        _       => name,
+    }
+}
+
+impl Isa {
+    pub fn parse(s: &str) -> Result<Isa, String> {
+        let lower = s.to_ascii_lowercase();
+        let (xlen, rest) = if let Some(r) = lower.strip_prefix("rv32") {
+            (32, r)
+        } else if let Some(r) = lower.strip_prefix("rv64") {
+            (64, r)
+        } else {
+            return Err(format!("{}: ISA string must start with rv32 or rv64", s));
+        };
+        if !rest.starts_with(['i', 'e', 'g']) {
+            return Err(format!("{}: base ISA must be i, e or g", s));
+        }
+
+        let mut extensions: Vec<String> = vec![];
+        let mut add = |e: &str| {
+            if !extensions.iter().any(|x| x == e) {
+                extensions.push(e.to_string());
+            }
+        };
+        for (k, part) in rest.split('_').enumerate() {
+            let chars: Vec<char> = part.chars().collect();
+            let mut i = 0;
+            while i < chars.len() {
+                let c = chars[i];
+                // multi-letter extensions run to the next underscore; so does an S* one when it starts
+                // a part (a single S elsewhere is supervisor mode, as in "rv32imasu")
+                if c == 'z' || c == 'x' || (c == 's' && i == 0 && k > 0) {
+                    let name: String = chars[i..].iter().collect();
+                    add(unversioned(&name));
+                    break;
+                }
+                if !c.is_ascii_alphabetic() {
+                    return Err(format!("{}: unexpected '{}'", s, c));
+                }
+                if c == 'g' {
+                    for e in ["i", "m", "a", "f", "d", "zicsr", "zifencei"] {
+                        add(e);
+                    }
+                } else {
+                    add(&c.to_string());
+                }
+                // version of a single-letter extension
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_digit() || (chars[i] == 'p' && i + 1 < chars.len() && chars[i + 1].is_ascii_digit())) {
+                    i += 1;
+                }
+            }
+        }
+        Ok(Isa { xlen: xlen, extensions: extensions })
+    }
+
+    pub fn has(&self, ext: &str) -> bool {
+        self.extensions.iter().any(|e| e == ext)
+    }
+
+    // c.f., Section 3.1.1: misa has MXL and a bit for each single-letter extension.
+    pub fn misa(&self) -> u32 {
+        let letters = self.extensions.iter().filter(|e| e.len() == 1).map(|e| e.as_bytes()[0] - b'a');
+        letters.fold(csr::MISA_MXL_32, |misa, bit| misa | 1 << bit)
+    }
+
+    // Whether the simulator can run code for this ISA.
+    pub fn check(&self) -> Result<(), String> {
+        if self.xlen != 32 {
+            return Err(format!("RV{} is not supported", self.xlen));
+        }
+        match self.extensions.iter().find(|e| !SUPPORTED.contains(&e.as_str())) {
+            Some(e) if e.len() == 1 => Err(format!("the {} extension is not supported", e.to_ascii_uppercase())),
+            Some(e)                 => Err(format!("the {} extension is not supported", e)),
+            None                    => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::isa::*;
+
+    #[test]
+    fn test_parse() {
+        let isa = Isa::parse("RV32IMAZicsr_Zifencei").unwrap();
+        assert_eq!(isa.extensions, vec!["i", "m", "a", "zicsr", "zifencei"]);
+        assert!(isa.check().is_ok());
+
+        let isa = Isa::parse("rv32i2p1_m2p0_zicsr2p0_zicbom").unwrap();
+        assert_eq!(isa.extensions, vec!["i", "m", "zicsr", "zicbom"]);
+
+        let isa = Isa::parse("rv32imasu_sstc").unwrap();
+        assert_eq!(isa.extensions, vec!["i", "m", "a", "s", "u", "sstc"]);
+
+        assert_eq!(Isa::parse(DEFAULT).unwrap().misa(), csr::MISA_DEFAULT);
+        assert_eq!(Isa::parse("rv32i_zicsr").unwrap().misa(), 0x4000_0100);
+
+        assert_eq!(Isa::parse("rv32gc").unwrap().extensions, vec!["i", "m", "a", "f", "d", "zicsr", "zifencei", "c"]);
+        assert!(Isa::parse("rv128i").is_err());
+        assert!(Isa::parse("rv32m").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(Isa::parse("rv32imc").unwrap().check(), Err("the C extension is not supported".to_string()));
+        assert_eq!(Isa::parse("rv64i").unwrap().check(), Err("RV64 is not supported".to_string()));
+        assert_eq!(Isa::parse("rv32i_zba").unwrap().check(), Err("the zba extension is not supported".to_string()));
+    }
+}
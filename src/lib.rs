@@ -0,0 +1,1518 @@
+// RV32IMA instruction set simulator: the hart (CPU) with its bus and devices, the loaders, and the
+// tools built on them. simulator::Simulator is the interface for embedding; the command line is in
+// main.rs.
+// The naming follows the specification (behaviorADD, Opcode::OP_IMM, Op::LR_W).
+#![allow(non_snake_case, non_camel_case_types)]
+// Struct literals spell out every field, and functions return explicitly where it reads better.
+#![allow(clippy::redundant_field_names, clippy::needless_return)]
+
+pub mod core;
+pub mod register;
+pub mod memory;
+pub mod cache;
+pub mod csr;
+pub mod bus;
+pub mod uart;
+pub mod finisher;
+pub mod elf;
+pub mod htif;
+pub mod syscall;
+pub mod semihost;
+pub mod vfs;
+pub mod signal;
+pub mod gdb;
+pub mod inst;
+pub mod disasm;
+pub mod debugger;
+pub mod asm;
+pub mod trace;
+pub mod lockstep;
+pub mod rvfi;
+pub mod isa;
+pub mod signature;
+pub mod runner;
+pub mod vectors;
+pub mod fuzz;
+pub mod classify;
+pub mod simulator;
+
+#[cfg(test)]
+mod testutil;
+
+use std::collections::HashMap;
+
+trait Decode {
+    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>);
+}
+
+// The inverse of Decode: put the fields back in their places. Fields that are not given are zero.
+trait Encode {
+    fn writeFields(&self, fields: &HashMap<&str, u32>) -> u32;
+}
+
+// A field value at the position of its mask.
+fn place(mask: u32, fields: &HashMap<&str, u32>, name: &str) -> u32 {
+    (fields.get(name).copied().unwrap_or(0) << mask.trailing_zeros()) & mask
+}
+
+#[derive(Debug)]
+pub struct BitFields {
+    OPCODE  : OpcodeBitField,
+    RTYPE   : RTypeBitField,
+    ITYPE   : ITypeBitField,
+    STYPE   : STypeBitField,
+    BTYPE   : BTypeBitField,
+    UTYPE   : UTypeBitField,
+    JTYPE   : JTypeBitField,
+}
+
+impl Default for BitFields {
+    fn default() -> BitFields {
+        BitFields::new()
+    }
+}
+
+impl BitFields {
+    pub fn new() -> BitFields {
+        BitFields {
+            OPCODE  : OpcodeBitField::new(),
+            RTYPE   : RTypeBitField::new(),
+            ITYPE   : ITypeBitField::new(),
+            STYPE   : STypeBitField::new(),
+            BTYPE   : BTypeBitField::new(),
+            UTYPE   : UTypeBitField::new(),
+            JTYPE   : JTypeBitField::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OpcodeBitField {
+    opcode      : u32,
+}
+
+impl Default for OpcodeBitField {
+    fn default() -> OpcodeBitField {
+        OpcodeBitField::new()
+    }
+}
+
+impl OpcodeBitField {
+    pub fn new() -> OpcodeBitField {
+        OpcodeBitField {
+            opcode      : 0x0000_007F,
+        }
+    }
+}
+
+impl Decode for OpcodeBitField {
+    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>){
+        fields.insert("opcode", inst & self.opcode);
+    }
+}
+
+#[derive(Debug)]
+pub struct RTypeBitField {
+    rd          : u32,
+    funct3      : u32,
+    rs1         : u32,
+    rs2         : u32,
+    funct7      : u32,
+}
+
+impl Default for RTypeBitField {
+    fn default() -> RTypeBitField {
+        RTypeBitField::new()
+    }
+}
+
+// The behavior functions of all formats share one signature, whether or not they use the bus.
+#[allow(unused_variables)]
+impl RTypeBitField {
+    pub fn new() -> RTypeBitField {
+        RTypeBitField {
+            rd          : 0x0000_0F80,
+            funct3      : 0x0000_7000,
+            rs1         : 0x000F_8000,
+            rs2         : 0x01F0_0000,
+            funct7      : 0xFE00_0000,
+        }
+    }
+
+    // LR.W loads a word from the address in rs1 and registers a reservation set on it. SC.W writes rs2
+    // to the address in rs1 only if a valid reservation still exists on it, writing zero to rd on
+    // success and a nonzero code (1) on failure. Either way the reservation is invalidated.
+    pub fn behaviorLRW(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus, reservation: &mut Option<u32>) -> Result<(), core::Exception> {
+        let addr = r.getReg(f["rs1"]);
+        if addr & 0b11 != 0 {
+            return Err(core::Exception::LoadAddressMisaligned);
+        }
+        let t = m.read(addr, bus::Width::Word).map_err(|_| core::Exception::LoadAccessFault)?;
+        *reservation = Some(addr);
+        r.setReg(f["rd"], t);
+        Ok(())
+    }
+
+    pub fn behaviorSCW(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus, reservation: &mut Option<u32>) -> Result<(), core::Exception> {
+        let addr = r.getReg(f["rs1"]);
+        if addr & 0b11 != 0 {
+            return Err(core::Exception::StoreAddressMisaligned);
+        }
+        if reservation.take() != Some(addr) {
+            r.setReg(f["rd"], 1);
+            return Ok(());
+        }
+        m.write(addr, bus::Width::Word, r.getReg(f["rs2"])).map_err(|_| core::Exception::StoreAccessFault)?;
+        r.setReg(f["rd"], 0);
+        Ok(())
+    }
+
+    // AMOs atomically load a word from the address in rs1 into rd, apply the operation to it and rs2,
+    // and store the result back to the same address.
+    fn amo(&self, f: &HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus, op: fn(u32, u32) -> u32) -> Result<(), core::Exception> {
+        let addr = r.getReg(f["rs1"]);
+        if addr & 0b11 != 0 {
+            return Err(core::Exception::StoreAddressMisaligned);
+        }
+        let t = m.read(addr, bus::Width::Word).map_err(|_| core::Exception::StoreAccessFault)?;
+        m.write(addr, bus::Width::Word, op(t, r.getReg(f["rs2"]))).map_err(|_| core::Exception::StoreAccessFault)?;
+        r.setReg(f["rd"], t);
+        Ok(())
+    }
+
+    pub fn behaviorAMOSWAP(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        self.amo(&f, r, m, |_, b| b)
+    }
+
+    pub fn behaviorAMOADD(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        self.amo(&f, r, m, |a, b| a.wrapping_add(b))
+    }
+
+    pub fn behaviorAMOXOR(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        self.amo(&f, r, m, |a, b| a ^ b)
+    }
+
+    pub fn behaviorAMOAND(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        self.amo(&f, r, m, |a, b| a & b)
+    }
+
+    pub fn behaviorAMOOR(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        self.amo(&f, r, m, |a, b| a | b)
+    }
+
+    pub fn behaviorAMOMIN(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        self.amo(&f, r, m, |a, b| (a as i32).min(b as i32) as u32)
+    }
+
+    pub fn behaviorAMOMAX(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        self.amo(&f, r, m, |a, b| (a as i32).max(b as i32) as u32)
+    }
+
+    pub fn behaviorAMOMINU(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        self.amo(&f, r, m, |a, b| a.min(b))
+    }
+
+    pub fn behaviorAMOMAXU(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        self.amo(&f, r, m, |a, b| a.max(b))
+    }
+
+    // ADD performs the addition of rs1 and rs2. SUB performs the subtraction of rs2 from rs1. Overflows
+    // are ignored and the low XLEN bits of results are written to the destination rd.
+    pub fn behaviorADD(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t = r.getReg(f["rs1"]).wrapping_add(r.getReg(f["rs2"]));
+        r.setReg(f["rd"], t);
+    }
+
+    pub fn behaviorSUB(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t = r.getReg(f["rs1"]).wrapping_sub(r.getReg(f["rs2"]));
+        r.setReg(f["rd"], t);
+    }
+    
+    // SLT and SLTU perform signed and unsigned compares respectively, writing 1 to rd if rs1 < rs2, 0 otherwise. Note,
+    // SLTU rd, x0, rs2 sets rd to 1 if rs2 is not equal to zero, otherwise sets rd to zero (assembler
+    // pseudoinstruction SNEZ rd, rs).
+    pub fn behaviorSLT(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        if (r.getReg(f["rs1"]) as i32) < (r.getReg(f["rs2"]) as i32) {
+            r.setReg(f["rd"], 1);
+        } else {
+            r.setReg(f["rd"], 0);
+        }
+    }
+    
+    pub fn behaviorSLTU(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        if r.getReg(f["rs1"]) < r.getReg(f["rs2"]) {
+            r.setReg(f["rd"], 1);
+        } else {
+            r.setReg(f["rd"], 0);
+        }
+    }
+    
+    // AND, OR, and XOR perform bitwise logical operations.
+    pub fn behaviorAND(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t = r.getReg(f["rs1"]) & r.getReg(f["rs2"]);
+        r.setReg(f["rd"], t);
+    }
+    
+    pub fn behaviorOR(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t = r.getReg(f["rs1"]) | r.getReg(f["rs2"]);
+        r.setReg(f["rd"], t);
+    }
+
+    pub fn behaviorXOR(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t = r.getReg(f["rs1"]) ^ r.getReg(f["rs2"]);
+        r.setReg(f["rd"], t);
+    }
+
+    // SLL, SRL, and SRA perform logical left, logical right, and arithmetic right shifts on the value in
+    // register rs1 by the shift amount held in the lower 5 bits of register rs2.
+    pub fn behaviorSLL(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t = r.getReg(f["rs1"]) << (r.getReg(f["rs2"]) & 0x1F);
+        r.setReg(f["rd"], t);
+    }
+        
+    pub fn behaviorSRL(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t = r.getReg(f["rs1"]) >> (r.getReg(f["rs2"]) & 0x1F);
+        r.setReg(f["rd"], t);
+    }
+    
+    pub fn behaviorSRA(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t = ((r.getReg(f["rs1"]) as i32) >> (r.getReg(f["rs2"]) & 0x1F)) as u32;
+        r.setReg(f["rd"], t);
+    }
+
+    // MUL performs an XLEN-bit x XLEN-bit multiplication of rs1 by rs2 and places the lower XLEN bits in
+    // the destination register. MULH, MULHU, and MULHSU perform the same multiplication but return the
+    // upper XLEN bits of the full 2 x XLEN-bit product, for signed x signed, unsigned x unsigned, and
+    // signed rs1 x unsigned rs2 multiplication, respectively.
+    pub fn behaviorMUL(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t = r.getReg(f["rs1"]).wrapping_mul(r.getReg(f["rs2"]));
+        r.setReg(f["rd"], t);
+    }
+
+    pub fn behaviorMULH(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t = (r.getReg(f["rs1"]) as i32 as i64) * (r.getReg(f["rs2"]) as i32 as i64);
+        r.setReg(f["rd"], (t >> 32) as u32);
+    }
+
+    pub fn behaviorMULHSU(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t = (r.getReg(f["rs1"]) as i32 as i64).wrapping_mul(r.getReg(f["rs2"]) as i64);
+        r.setReg(f["rd"], (t >> 32) as u32);
+    }
+
+    pub fn behaviorMULHU(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t = (r.getReg(f["rs1"]) as u64) * (r.getReg(f["rs2"]) as u64);
+        r.setReg(f["rd"], (t >> 32) as u32);
+    }
+
+    // DIV and DIVU perform signed and unsigned integer division of rs1 by rs2, rounding towards zero.
+    // REM and REMU provide the remainder of the corresponding division operation. The quotient of
+    // division by zero has all bits set, and the remainder of division by zero equals the dividend.
+    // Signed division overflow (-2^(XLEN-1) / -1) yields the dividend and a remainder of zero.
+    pub fn behaviorDIV(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let (a, b) = (r.getReg(f["rs1"]) as i32, r.getReg(f["rs2"]) as i32);
+        let t = if b == 0 { -1 } else { a.wrapping_div(b) };
+        r.setReg(f["rd"], t as u32);
+    }
+
+    pub fn behaviorDIVU(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let (a, b) = (r.getReg(f["rs1"]), r.getReg(f["rs2"]));
+        let t = a.checked_div(b).unwrap_or(0xFFFF_FFFF);
+        r.setReg(f["rd"], t);
+    }
+
+    pub fn behaviorREM(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let (a, b) = (r.getReg(f["rs1"]) as i32, r.getReg(f["rs2"]) as i32);
+        let t = if b == 0 { a } else { a.wrapping_rem(b) };
+        r.setReg(f["rd"], t as u32);
+    }
+
+    pub fn behaviorREMU(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let (a, b) = (r.getReg(f["rs1"]), r.getReg(f["rs2"]));
+        let t = if b == 0 { a } else { a % b };
+        r.setReg(f["rd"], t);
+    }
+
+    pub fn behavior(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        
+    }
+}
+
+impl Decode for RTypeBitField {
+    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>) {
+        fields.insert("rd", (inst & self.rd) >> 7);
+        fields.insert("funct3", (inst & self.funct3) >> 12);
+        fields.insert("rs1", (inst & self.rs1) >> 15);
+        fields.insert("rs2", (inst & self.rs2) >> 20);
+        fields.insert("funct7", (inst & self.funct7) >> 25);
+    }
+}
+
+impl Encode for RTypeBitField {
+    fn writeFields(&self, f: &HashMap<&str, u32>) -> u32 {
+        place(self.rd, f, "rd") | place(self.funct3, f, "funct3") | place(self.rs1, f, "rs1") | place(self.rs2, f, "rs2") | place(self.funct7, f, "funct7")
+    }
+}
+
+#[derive(Debug)]
+pub struct ITypeBitField {
+    rd          : u32,
+    funct3      : u32,
+    rs1         : u32,
+    imm_11_0    : u32,
+    imm_4_0     : u32,
+    imm_11_5    : u32,
+}
+
+impl Default for ITypeBitField {
+    fn default() -> ITypeBitField {
+        ITypeBitField::new()
+    }
+}
+
+#[allow(unused_variables)]
+impl ITypeBitField {
+    pub fn new() -> ITypeBitField {
+        ITypeBitField {
+            rd          : 0x0000_0F80,
+            funct3      : 0x0000_7000,
+            rs1         : 0x000F_8000,
+            imm_11_0    : 0xFFF0_0000,
+            imm_4_0     : 0x01F0_0000,
+            imm_11_5    : 0xFE00_0000,
+        }
+    }
+
+    // ADDI adds the sign-extended 12-bit immediate to register rs1. Arithmetic overflow is ignored and
+    // the result is simply the low XLEN bits of the result. ADDI rd, rs1, 0 is used to implement the MV
+    // rd, rs1 assembler pseudoinpub struction.
+    pub fn behaviorADDI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t = f["imm_11_0"].wrapping_add(r.getReg(f["rs1"]));
+        r.setReg(f["rd"], t);
+    }
+
+    // SLTI (set less than immediate) places the value 1 in register rd if register rs1 is less than the signextended
+    // immediate when both are treated as signed numbers, else 0 is written to rd. SLTIU is
+    // similar but compares the values as unsigned numbers (i.e., the immediate is first sign-extended to
+    // XLEN bits then treated as an unsigned number). Note, SLTIU rd, rs1, 1 sets rd to 1 if rs1 equals
+    // zero, otherwise sets rd to 0 (assembler pseudoinpub struction SEQZ rd, rs).
+    pub fn behaviorSLTI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        if (r.getReg(f["rs1"]) as i32) < (f["imm_11_0"] as i32) {
+            r.setReg(f["rd"], 1);
+        } else {
+            r.setReg(f["rd"], 0);
+        }
+    }
+
+    pub fn behaviorSLTIU(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        if r.getReg(f["rs1"]) < f["imm_11_0"] {
+            r.setReg(f["rd"], 1);
+        } else {
+            r.setReg(f["rd"], 0);
+        }
+    }
+
+    // ANDI, ORI, XORI are logical operations that perform bitwise AND, OR, and XOR on register rs1
+    // and the sign-extended 12-bit immediate and place the result in rd. Note, XORI rd, rs1, -1 performs
+    // a bitwise logical inversion of register rs1 (assembler pseudoinpub struction NOT rd, rs).
+    pub fn behaviorXORI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t: u32 = f["imm_11_0"] ^ r.getReg(f["rs1"]);
+        r.setReg(f["rd"], t);
+    }
+
+    pub fn behaviorORI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t: u32 = f["imm_11_0"] | r.getReg(f["rs1"]);
+        r.setReg(f["rd"], t);
+    }
+
+    pub fn behaviorANDI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t: u32 = f["imm_11_0"] & r.getReg(f["rs1"]);
+        r.setReg(f["rd"], t);
+    }
+    
+    // Shifts by a constant are encoded as a specialization of the I-type format. The operand to be shifted
+    // is in rs1, and the shift amount is encoded in the lower 5 bits of the I-immediate field. The right
+    // shift type is encoded in bit 30. SLLI is a logical left shift (zeros are shifted into the lower bits);
+    // SRLI is a logical right shift (zeros are shifted into the upper bits); and SRAI is an arithmetic right
+    // shift (the original sign bit is copied into the vacated upper bits).
+    pub fn behaviorSLLI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t: u32 = r.getReg(f["rs1"]) << f["imm_4_0"];
+        r.setReg(f["rd"], t);
+    }
+    
+    pub fn behaviorSRLI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t: u32 = r.getReg(f["rs1"]) >> f["imm_4_0"];
+        r.setReg(f["rd"], t);
+    }
+
+    pub fn behaviorSRAI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t: u32 = ((r.getReg(f["rs1"]) as i32) >> f["imm_4_0"]) as u32;
+        r.setReg(f["rd"], t);
+    }
+
+    // The indirect jump instruction JALR uses the I-type encoding. The target address is obtained by
+    // adding the sign-extended 12-bit I-immediate to the register rs1, then setting the least-significant
+    // bit of the result to zero. The address of the instruction following the jump (pc+4) is written to
+    // register rd.
+    pub fn behaviorJALR(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<u32, core::Exception> {
+        let target = r.getReg(f["rs1"]).wrapping_add(f["imm_11_0"]) & !1;
+        if target & 0b11 != 0 {
+            return Err(core::Exception::InstructionAddressMisaligned);
+        }
+        r.setReg(f["rd"], r.getPC().wrapping_add(4));
+        Ok(target)
+    }
+
+    // CSRRW (Atomic Read/Write CSR) swaps values in the CSRs and integer registers. If rd=x0, then the
+    // instruction shall not read the CSR. CSRRS / CSRRC set / clear the bits given by rs1 in the CSR; if
+    // rs1=x0 the instruction shall not write to the CSR at all. The immediate forms use a 5-bit
+    // zero-extended immediate (uimm[4:0]) encoded in the rs1 field instead of a register.
+    pub fn behaviorCSRRW(&self, f: HashMap<&str, u32>, r: &mut register::Register, c: &mut csr::Csr, mode: core::Privilege) -> Result<(), core::Exception> {
+        let addr = f["imm_11_0"] & 0xFFF;
+        let src = r.getReg(f["rs1"]);
+        let old = if f["rd"] != 0 { c.access(addr, mode, false)? } else { 0 };
+        c.access(addr, mode, true)?;
+        c.writeWarl(addr, src);
+        r.setReg(f["rd"], old);
+        Ok(())
+    }
+
+    pub fn behaviorCSRRS(&self, f: HashMap<&str, u32>, r: &mut register::Register, c: &mut csr::Csr, mode: core::Privilege) -> Result<(), core::Exception> {
+        let addr = f["imm_11_0"] & 0xFFF;
+        let old = c.access(addr, mode, f["rs1"] != 0)?;
+        if f["rs1"] != 0 {
+            c.writeWarl(addr, old | r.getReg(f["rs1"]));
+        }
+        r.setReg(f["rd"], old);
+        Ok(())
+    }
+
+    pub fn behaviorCSRRC(&self, f: HashMap<&str, u32>, r: &mut register::Register, c: &mut csr::Csr, mode: core::Privilege) -> Result<(), core::Exception> {
+        let addr = f["imm_11_0"] & 0xFFF;
+        let old = c.access(addr, mode, f["rs1"] != 0)?;
+        if f["rs1"] != 0 {
+            c.writeWarl(addr, old & !r.getReg(f["rs1"]));
+        }
+        r.setReg(f["rd"], old);
+        Ok(())
+    }
+
+    pub fn behaviorCSRRWI(&self, f: HashMap<&str, u32>, r: &mut register::Register, c: &mut csr::Csr, mode: core::Privilege) -> Result<(), core::Exception> {
+        let addr = f["imm_11_0"] & 0xFFF;
+        let old = if f["rd"] != 0 { c.access(addr, mode, false)? } else { 0 };
+        c.access(addr, mode, true)?;
+        c.writeWarl(addr, f["rs1"]);
+        r.setReg(f["rd"], old);
+        Ok(())
+    }
+
+    pub fn behaviorCSRRSI(&self, f: HashMap<&str, u32>, r: &mut register::Register, c: &mut csr::Csr, mode: core::Privilege) -> Result<(), core::Exception> {
+        let addr = f["imm_11_0"] & 0xFFF;
+        let old = c.access(addr, mode, f["rs1"] != 0)?;
+        if f["rs1"] != 0 {
+            c.writeWarl(addr, old | f["rs1"]);
+        }
+        r.setReg(f["rd"], old);
+        Ok(())
+    }
+
+    pub fn behaviorCSRRCI(&self, f: HashMap<&str, u32>, r: &mut register::Register, c: &mut csr::Csr, mode: core::Privilege) -> Result<(), core::Exception> {
+        let addr = f["imm_11_0"] & 0xFFF;
+        let old = c.access(addr, mode, f["rs1"] != 0)?;
+        if f["rs1"] != 0 {
+            c.writeWarl(addr, old & !f["rs1"]);
+        }
+        r.setReg(f["rd"], old);
+        Ok(())
+    }
+
+    // Loads copy a value from memory to register rd. The effective address is obtained by adding register
+    // rs1 to the sign-extended 12-bit offset. LW loads a 32-bit value; LH loads a 16-bit value and then
+    // sign-extends it, while LHU zero-extends; LB and LBU are defined analogously for 8-bit values.
+    pub fn behaviorLB(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        let addr = r.getReg(f["rs1"]).wrapping_add(f["imm_11_0"]);
+        let t = m.read(addr, bus::Width::Byte).map_err(|_| core::Exception::LoadAccessFault)?;
+        r.setReg(f["rd"], t as u8 as i8 as i32 as u32);
+        Ok(())
+    }
+
+    pub fn behaviorLH(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        let addr = r.getReg(f["rs1"]).wrapping_add(f["imm_11_0"]);
+        let t = m.read(addr, bus::Width::Half).map_err(|_| core::Exception::LoadAccessFault)?;
+        r.setReg(f["rd"], t as u16 as i16 as i32 as u32);
+        Ok(())
+    }
+
+    pub fn behaviorLW(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        let addr = r.getReg(f["rs1"]).wrapping_add(f["imm_11_0"]);
+        let t = m.read(addr, bus::Width::Word).map_err(|_| core::Exception::LoadAccessFault)?;
+        r.setReg(f["rd"], t);
+        Ok(())
+    }
+
+    pub fn behaviorLBU(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        let addr = r.getReg(f["rs1"]).wrapping_add(f["imm_11_0"]);
+        let t = m.read(addr, bus::Width::Byte).map_err(|_| core::Exception::LoadAccessFault)?;
+        r.setReg(f["rd"], t);
+        Ok(())
+    }
+
+    pub fn behaviorLHU(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        let addr = r.getReg(f["rs1"]).wrapping_add(f["imm_11_0"]);
+        let t = m.read(addr, bus::Width::Half).map_err(|_| core::Exception::LoadAccessFault)?;
+        r.setReg(f["rd"], t);
+        Ok(())
+    }
+
+    // CMO instructions operate on the cache block containing the address in rs1. When the data cache is
+    // disabled, clean/flush/inval have nothing to act on and complete as no-ops, while cbo.zero still
+    // has an architecturally visible effect on memory. Blocks outside memory-like regions raise a
+    // store/AMO access fault.
+    pub fn behaviorCBOCLEAN(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        m.cboClean(r.getReg(f["rs1"])).map_err(|_| core::Exception::StoreAccessFault)
+    }
+
+    pub fn behaviorCBOFLUSH(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        m.cboFlush(r.getReg(f["rs1"])).map_err(|_| core::Exception::StoreAccessFault)
+    }
+
+    pub fn behaviorCBOINVAL(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        m.cboInval(r.getReg(f["rs1"])).map_err(|_| core::Exception::StoreAccessFault)
+    }
+
+    pub fn behaviorCBOZERO(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        m.cboZero(r.getReg(f["rs1"])).map_err(|_| core::Exception::StoreAccessFault)
+    }
+
+    pub fn behavior(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        
+    }
+}
+
+impl Decode for ITypeBitField {
+    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>) {
+        fields.insert("rd", (inst & self.rd) >> 7);
+        fields.insert("funct3", (inst & self.funct3) >> 12);
+        fields.insert("rs1", (inst & self.rs1) >> 15);
+        fields.insert("imm_11_0", ((inst & self.imm_11_0) as i32 >> 20) as u32);  // 符号拡張
+        fields.insert("imm_4_0", (inst & self.imm_4_0) >> 20);
+        fields.insert("imm_11_5", (inst & self.imm_11_5) >> 25);
+    }
+}
+
+impl Encode for ITypeBitField {
+    fn writeFields(&self, f: &HashMap<&str, u32>) -> u32 {
+        place(self.rd, f, "rd") | place(self.funct3, f, "funct3") | place(self.rs1, f, "rs1")
+            | place(self.imm_11_0, f, "imm_11_0") | place(self.imm_4_0, f, "imm_4_0") | place(self.imm_11_5, f, "imm_11_5")
+    }
+}
+
+#[derive(Debug)]
+pub struct STypeBitField {
+    imm_4_0     : u32,
+    funct3      : u32,
+    rs1         : u32,
+    rs2         : u32,
+    imm_11_5    : u32,
+}
+
+impl Default for STypeBitField {
+    fn default() -> STypeBitField {
+        STypeBitField::new()
+    }
+}
+
+#[allow(unused_variables)]
+impl STypeBitField {
+    pub fn new() -> STypeBitField {
+        STypeBitField {
+            imm_4_0     : 0x0000_0F80,
+            funct3      : 0x0000_7000,
+            rs1         : 0x000F_8000,
+            rs2         : 0x01F0_0000,
+            imm_11_5    : 0xFE00_0000,
+        }
+    }
+
+    // The S-type immediate is split into imm[11:5] and imm[4:0].
+    fn imm(&self, f: &HashMap<&str, u32>) -> u32 {
+        (((f["imm_11_5"] << 25) as i32 >> 20) as u32) | f["imm_4_0"]
+    }
+
+    // SW, SH, and SB store 32-bit, 16-bit, and 8-bit values from the low bits of register rs2 to memory.
+    pub fn behaviorSB(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        let addr = r.getReg(f["rs1"]).wrapping_add(self.imm(&f));
+        m.write(addr, bus::Width::Byte, r.getReg(f["rs2"])).map_err(|_| core::Exception::StoreAccessFault)
+    }
+
+    pub fn behaviorSH(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        let addr = r.getReg(f["rs1"]).wrapping_add(self.imm(&f));
+        m.write(addr, bus::Width::Half, r.getReg(f["rs2"])).map_err(|_| core::Exception::StoreAccessFault)
+    }
+
+    pub fn behaviorSW(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<(), core::Exception> {
+        let addr = r.getReg(f["rs1"]).wrapping_add(self.imm(&f));
+        m.write(addr, bus::Width::Word, r.getReg(f["rs2"])).map_err(|_| core::Exception::StoreAccessFault)
+    }
+}
+
+impl Decode for STypeBitField {
+    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>) {
+        fields.insert("imm_4_0", (inst & self.imm_4_0) >> 7);
+        fields.insert("funct3", (inst & self.funct3) >> 12);
+        fields.insert("rs1", (inst & self.rs1) >> 15);
+        fields.insert("rs2", (inst & self.rs2) >> 20);
+        fields.insert("imm_11_5", (inst & self.imm_11_5) >> 25);
+    }
+}
+
+impl Encode for STypeBitField {
+    fn writeFields(&self, f: &HashMap<&str, u32>) -> u32 {
+        place(self.imm_4_0, f, "imm_4_0") | place(self.funct3, f, "funct3") | place(self.rs1, f, "rs1") | place(self.rs2, f, "rs2") | place(self.imm_11_5, f, "imm_11_5")
+    }
+}
+
+#[derive(Debug)]
+pub struct BTypeBitField {
+    imm_11      : u32,
+    imm_4_1     : u32,
+    funct3      : u32,
+    rs1         : u32,
+    rs2         : u32,
+    imm_10_5    : u32,
+    imm_12      : u32,
+}
+
+impl Default for BTypeBitField {
+    fn default() -> BTypeBitField {
+        BTypeBitField::new()
+    }
+}
+
+#[allow(unused_variables)]
+impl BTypeBitField {
+    pub fn new() -> BTypeBitField {
+        BTypeBitField {
+            imm_11      : 0x0000_0080,
+            imm_4_1     : 0x0000_0F00,
+            funct3      : 0x0000_7000,
+            rs1         : 0x000F_8000,
+            rs2         : 0x01F0_0000,
+            imm_10_5    : 0x7E00_0000,
+            imm_12      : 0x8000_0000,
+        }
+    }
+
+    // The 12-bit B-immediate encodes signed offsets in multiples of 2 bytes.
+    fn imm(&self, f: &HashMap<&str, u32>) -> u32 {
+        let imm = (f["imm_12"] << 12) | (f["imm_11"] << 11) | (f["imm_10_5"] << 5) | (f["imm_4_1"] << 1);
+        ((imm << 19) as i32 >> 19) as u32
+    }
+
+    // The branch target is formed by adding the sign-extended B-immediate to the address of the branch
+    // instruction. Returns the address of the next instruction, whether the branch is taken or not.
+    // An instruction-address-misaligned exception is generated only if the branch is taken.
+    fn branch(&self, f: &HashMap<&str, u32>, r: &register::Register, taken: bool) -> Result<u32, core::Exception> {
+        if !taken {
+            return Ok(r.getPC().wrapping_add(4));
+        }
+        let target = r.getPC().wrapping_add(self.imm(f));
+        if target & 0b11 != 0 {
+            return Err(core::Exception::InstructionAddressMisaligned);
+        }
+        Ok(target)
+    }
+
+    // BEQ and BNE take the branch if registers rs1 and rs2 are equal or unequal respectively. BLT and
+    // BLTU take the branch if rs1 is less than rs2, using signed and unsigned comparison respectively.
+    // BGE and BGEU take the branch if rs1 is greater than or equal to rs2, using signed and unsigned
+    // comparison respectively.
+    pub fn behaviorBEQ(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<u32, core::Exception> {
+        let taken = r.getReg(f["rs1"]) == r.getReg(f["rs2"]);
+        self.branch(&f, r, taken)
+    }
+
+    pub fn behaviorBNE(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<u32, core::Exception> {
+        let taken = r.getReg(f["rs1"]) != r.getReg(f["rs2"]);
+        self.branch(&f, r, taken)
+    }
+
+    pub fn behaviorBLT(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<u32, core::Exception> {
+        let taken = (r.getReg(f["rs1"]) as i32) < (r.getReg(f["rs2"]) as i32);
+        self.branch(&f, r, taken)
+    }
+
+    pub fn behaviorBGE(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<u32, core::Exception> {
+        let taken = (r.getReg(f["rs1"]) as i32) >= (r.getReg(f["rs2"]) as i32);
+        self.branch(&f, r, taken)
+    }
+
+    pub fn behaviorBLTU(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<u32, core::Exception> {
+        let taken = r.getReg(f["rs1"]) < r.getReg(f["rs2"]);
+        self.branch(&f, r, taken)
+    }
+
+    pub fn behaviorBGEU(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<u32, core::Exception> {
+        let taken = r.getReg(f["rs1"]) >= r.getReg(f["rs2"]);
+        self.branch(&f, r, taken)
+    }
+}
+
+impl Decode for BTypeBitField {
+    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>) {
+        fields.insert("imm_11", (inst & self.imm_11) >> 7);
+        fields.insert("imm_4_1", (inst & self.imm_4_1) >> 8);
+        fields.insert("funct3", (inst & self.funct3) >> 12);
+        fields.insert("rs1", (inst & self.rs1) >> 15);
+        fields.insert("rs2", (inst & self.rs2) >> 20);
+        fields.insert("imm_10_5", (inst & self.imm_10_5) >> 25);
+        fields.insert("imm_12", (inst & self.imm_12) >> 31);
+    }
+}
+
+impl Encode for BTypeBitField {
+    fn writeFields(&self, f: &HashMap<&str, u32>) -> u32 {
+        place(self.imm_11, f, "imm_11") | place(self.imm_4_1, f, "imm_4_1") | place(self.funct3, f, "funct3") | place(self.rs1, f, "rs1")
+            | place(self.rs2, f, "rs2") | place(self.imm_10_5, f, "imm_10_5") | place(self.imm_12, f, "imm_12")
+    }
+}
+
+#[derive(Debug)]
+pub struct UTypeBitField {
+    rd          : u32,
+    imm_31_12   : u32,
+}
+
+impl Default for UTypeBitField {
+    fn default() -> UTypeBitField {
+        UTypeBitField::new()
+    }
+}
+
+#[allow(unused_variables)]
+impl UTypeBitField {
+    pub fn new() -> UTypeBitField {
+        UTypeBitField {
+            rd          : 0x0000_0F80,
+            imm_31_12   : 0xFFFF_F000,
+        }
+    }
+
+    // LUI (load upper immediate) is used to build 32-bit constants and uses the U-type format. LUI
+    // places the 32-bit U-immediate value into the destination register rd, filling in the lowest 12 bits
+    // with zeros.
+    pub fn behaviorLUI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let t: u32 = f["imm_31_12"] << 12;
+        r.setReg(f["rd"], t);
+    }
+
+    // AUIPC (add upper immediate to pc) is used to build pc-relative addresses and uses the U-type
+    // format. AUIPC forms a 32-bit offset from the U-immediate, filling in the lowest 12 bits with zeros,
+    // adds this offset to the address of the AUIPC inpub struction, then places the result in register rd.
+    pub fn behaviorAUIPC(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) {
+        let mut t: u32 = f["imm_31_12"] << 12;
+        t = t.wrapping_add(r.getPC());
+        r.setReg(f["rd"], t);
+    }
+}
+
+impl Decode for UTypeBitField {
+    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>) {
+        fields.insert("rd", (inst & self.rd) >> 7);
+        fields.insert("imm_31_12", (inst & self.imm_31_12) >> 12);
+    }
+}
+
+impl Encode for UTypeBitField {
+    fn writeFields(&self, f: &HashMap<&str, u32>) -> u32 {
+        place(self.rd, f, "rd") | place(self.imm_31_12, f, "imm_31_12")
+    }
+}
+
+#[derive(Debug)]
+pub struct JTypeBitField {
+    rd          : u32,
+    imm_19_12   : u32,
+    imm_11      : u32,
+    imm_10_1    : u32,
+    imm_20      : u32,
+}
+
+impl Default for JTypeBitField {
+    fn default() -> JTypeBitField {
+        JTypeBitField::new()
+    }
+}
+
+#[allow(unused_variables)]
+impl JTypeBitField {
+    pub fn new() -> JTypeBitField {
+        JTypeBitField {
+            rd          : 0x0000_0F80,
+            imm_19_12   : 0x000F_F000,
+            imm_11      : 0x0010_0000,
+            imm_10_1    : 0x7FE0_0000,
+            imm_20      : 0x8000_0000,
+        }
+    }
+
+    // The J-immediate encodes a signed offset in multiples of 2 bytes.
+    fn imm(&self, f: &HashMap<&str, u32>) -> u32 {
+        let imm = (f["imm_20"] << 20) | (f["imm_19_12"] << 12) | (f["imm_11"] << 11) | (f["imm_10_1"] << 1);
+        ((imm << 11) as i32 >> 11) as u32
+    }
+
+    // The jump and link (JAL) instruction uses the J-type format. The offset is sign-extended and added
+    // to the address of the jump instruction to form the jump target address. JAL stores the address of
+    // the instruction following the jump (pc+4) into register rd.
+    pub fn behaviorJAL(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut bus::Bus) -> Result<u32, core::Exception> {
+        let target = r.getPC().wrapping_add(self.imm(&f));
+        if target & 0b11 != 0 {
+            return Err(core::Exception::InstructionAddressMisaligned);
+        }
+        r.setReg(f["rd"], r.getPC().wrapping_add(4));
+        Ok(target)
+    }
+}
+
+impl Decode for JTypeBitField {
+    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>) {
+        fields.insert("rd", (inst & self.rd) >> 7);
+        fields.insert("imm_19_12", (inst & self.imm_19_12) >> 12);
+        fields.insert("imm_11", (inst & self.imm_11) >> 20);
+        fields.insert("imm_10_1", (inst & self.imm_10_1) >> 21);
+        fields.insert("imm_20", (inst & self.imm_20) >> 31);
+    }
+}
+
+impl Encode for JTypeBitField {
+    fn writeFields(&self, f: &HashMap<&str, u32>) -> u32 {
+        place(self.rd, f, "rd") | place(self.imm_19_12, f, "imm_19_12") | place(self.imm_11, f, "imm_11") | place(self.imm_10_1, f, "imm_10_1") | place(self.imm_20, f, "imm_20")
+    }
+}
+
+
+#[derive(Debug)]
+pub struct CPU {
+    reg: register::Register,
+    bus: bus::Bus,
+    csr: csr::Csr,
+    mode: core::Privilege,
+    reservation: Option<u32>,
+    htif: Option<htif::Htif>,
+    linux: Option<syscall::Linux>,
+    semihost: Option<semihost::Semihost>,
+    commit_log: Option<trace::CommitLog>,
+    lockstep: Option<lockstep::Lockstep>,
+    rvfi: Option<rvfi::RvfiTrace>,
+    isa: isa::Isa,
+    last_trap: Option<(u32, u32, u32)>,
+    exit: Option<u32>,
+    reset_pc: u32,
+}
+
+impl CPU {
+    // A hart with every extension the simulator implements (isa::DEFAULT).
+    pub fn new(bus: bus::Bus, reset_pc: u32) -> CPU {
+        CPU::withIsa(bus, reset_pc, isa::Isa::parse(isa::DEFAULT).unwrap())
+    }
+
+    // A hart with the extensions of `isa`, which must pass Isa::check. Instructions of the other
+    // extensions raise an illegal instruction exception.
+    pub fn withIsa(bus: bus::Bus, reset_pc: u32, isa: isa::Isa) -> CPU {
+        let mut reg = register::Register::new();
+        reg.setPC(reset_pc);
+        let mut csr = csr::Csr::new();
+        csr.setIsa(&isa);
+        CPU {
+            reg: reg,
+            bus: bus,
+            csr: csr,
+            mode: core::Privilege::Machine,
+            reservation: None,
+            htif: None,
+            linux: None,
+            semihost: None,
+            commit_log: None,
+            lockstep: None,
+            rvfi: None,
+            isa: isa,
+            last_trap: None,
+            exit: None,
+            reset_pc: reset_pc,
+        }
+    }
+
+    pub fn isa(&self) -> &isa::Isa {
+        &self.isa
+    }
+
+    // Back to the reset state: M-mode at `pc` with cleared registers and CSRs and no reservation. The
+    // bus and the devices are left as they are.
+    pub fn reset(&mut self, pc: u32) {
+        self.reg = register::Register::new();
+        self.reg.setPC(pc);
+        self.csr = csr::Csr::new();
+        self.csr.setIsa(&self.isa);
+        self.mode = core::Privilege::Machine;
+        self.reservation = None;
+        self.last_trap = None;
+        self.exit = None;
+    }
+
+    // c.f., Section 3.3.1: the pc of the excepting instruction is written to mepc, the cause to mcause
+    // and the faulting value to mtval, then control transfers to the address in mtvec.
+    fn raiseException(&mut self, e: core::Exception, tval: u32) {
+        // an emulated user process has no trap handler to go to: the fault becomes a signal
+        if let Some(l) = self.linux.as_mut() {
+            self.reservation = None;
+            self.exit = l.exception(&mut self.reg, &mut self.bus, e, tval);
+            return;
+        }
+        self.trap(e as u32, tval);
+    }
+
+    // Interrupts are taken at an instruction boundary; mepc points at the instruction not yet executed.
+    fn raiseInterrupt(&mut self, code: u32) {
+        self.trap(0x8000_0000 | code, 0);
+    }
+
+    fn trap(&mut self, cause: u32, tval: u32) {
+        let mstatus = self.csr.readCsr(csr::MSTATUS);
+        let mpp = (self.mode as u32) << 11;
+        let mpie = (mstatus & csr::MSTATUS_MIE) << 4;
+        self.csr.writeCsr(csr::MSTATUS, (mstatus & !(0b11 << 11 | csr::MSTATUS_MPIE | csr::MSTATUS_MIE)) | mpp | mpie);
+        self.csr.writeCsr(csr::MEPC, self.reg.getPC());
+        self.csr.writeCsr(csr::MCAUSE, cause);
+        self.csr.writeCsr(csr::MTVAL, tval);
+        self.mode = core::Privilege::Machine;
+        self.reservation = None;
+        self.last_trap = Some((cause, tval, self.reg.getPC()));
+        self.reg.setPC(self.csr.readCsr(csr::MTVEC) & !0b11);
+    }
+
+    // Cause, value and pc of the last trap taken, if there was one since the last call.
+    pub fn takeTrap(&mut self) -> Option<(u32, u32, u32)> {
+        self.last_trap.take()
+    }
+
+    // Device interrupt lines are ORed into mip.MEIP (there is no interrupt controller model, so the
+    // handler finds the source by polling the devices).
+    fn checkInterrupts(&mut self) -> bool {
+        let mut mip = self.csr.readCsr(csr::MIP) & !csr::MIP_MEIP;
+        if self.bus.pendingIrqs() != 0 {
+            mip |= csr::MIP_MEIP;
+        }
+        self.csr.writeCsr(csr::MIP, mip);
+
+        let enabled = self.mode < core::Privilege::Machine || self.csr.readCsr(csr::MSTATUS) & csr::MSTATUS_MIE != 0;
+        if enabled && mip & self.csr.readCsr(csr::MIE) & csr::MIP_MEIP != 0 {
+            self.raiseInterrupt(11);
+            return true;
+        }
+        return false;
+    }
+
+    pub fn setHtif(&mut self, h: htif::Htif) {
+        self.htif = Some(h);
+    }
+
+    // Service the RISC-V semihosting sequence around EBREAK instead of raising a breakpoint exception.
+    pub fn setSemihost(&mut self, s: semihost::Semihost) {
+        self.semihost = Some(s);
+    }
+
+    // Run as a Linux user process: the hart starts in U-mode with the given stack pointer and ECALLs
+    // are serviced by the syscall emulation instead of trapping.
+    pub fn setLinux(&mut self, l: syscall::Linux, sp: u32) {
+        self.linux = Some(l);
+        self.mode = core::Privilege::User;
+        self.reg.setReg(2, sp);
+        // rdcycle / rdtime / rdinstret are available to user programs
+        self.csr.writeCsr(csr::MCOUNTEREN, 0b111);
+    }
+
+    // Write a Spike-style commit log of every step.
+    pub fn setCommitLog(&mut self, log: trace::CommitLog) {
+        self.commit_log = Some(log);
+        self.bus.recordAccesses(true);
+    }
+
+    // Write RVFI records of every instruction.
+    pub fn setRvfi(&mut self, r: rvfi::RvfiTrace) {
+        self.rvfi = Some(r);
+        self.bus.recordAccesses(true);
+    }
+
+    // Called once the run is over.
+    pub fn flushTrace(&mut self) {
+        if let Some(log) = self.commit_log.as_mut() {
+            log.flush();
+        }
+        if let Some(r) = self.rvfi.as_mut() {
+            r.finish();
+        }
+    }
+
+    // Check every retired instruction against a reference commit log; the run stops with
+    // lockstep::DIVERGED at the first instruction that disagrees.
+    pub fn setLockstep(&mut self, l: lockstep::Lockstep) {
+        self.lockstep = Some(l);
+        self.bus.recordAccesses(true);
+    }
+
+    // Number of instructions checked against the reference so far.
+    pub fn lockstepChecked(&self) -> Option<u64> {
+        self.lockstep.as_ref().map(|l| l.checked())
+    }
+
+    // Source operands of an instruction about to execute, with their values.
+    fn sourceValues(&self, inst: u32) -> [Option<(u32, u32)>; 2] {
+        let sources = inst::Inst::decode(inst).map(|i| trace::sources(&i)).unwrap_or([None, None]);
+        sources.map(|r| r.map(|r| (r, self.reg.getReg(r))))
+    }
+
+    // Pass what the step just executed at `pc` in `mode` to the tracers. `inst` is None for an
+    // interrupt; `trap` is the cause and value of the exception the instruction raised, if any; `rs`
+    // are the source operands as they were before the instruction.
+    fn traceStep(&mut self, mode: core::Privilege, pc: u32, inst: Option<u32>, trap: Option<(u32, u32)>, rs: [Option<(u32, u32)>; 2]) {
+        let mut mem = self.bus.takeAccesses();
+        let decoded = inst.and_then(inst::Inst::decode);
+        let (mut rd, mut csrs) = (None, vec![]);
+        if trap.is_none() {
+            if let Some(i) = decoded {
+                rd = trace::destination(&i).map(|r| (r, self.reg.getReg(r)));
+                csrs = trace::csrWritten(&i).map(|a| (a, self.csr.readCsr(a))).into_iter().collect();
+                // host-side services (syscall emulation, semihosting) are not the instruction's accesses
+                if matches!(i.op, inst::Op::ECALL | inst::Op::EBREAK) {
+                    mem.clear();
+                }
+            }
+        }
+        let c = trace::Commit {
+            mode    : mode,
+            pc      : pc,
+            inst    : inst,
+            trap    : trap,
+            rs      : rs,
+            rd      : rd,
+            csrs    : csrs,
+            mem     : mem,
+            next_pc : self.reg.getPC(),
+        };
+        if let Some(log) = self.commit_log.as_mut() {
+            log.commit(&c);
+        }
+        if let Some(r) = self.rvfi.as_mut() {
+            r.commit(&c);
+        }
+        if let Some(l) = self.lockstep.as_mut() {
+            if let Err(report) = l.check(&c, &self.reg) {
+                eprint!("{}", report);
+                self.exit = Some(lockstep::DIVERGED);
+            }
+        }
+    }
+
+    // Hart state for debuggers.
+    pub fn register(&mut self) -> &mut register::Register {
+        &mut self.reg
+    }
+
+    pub fn bus(&mut self) -> &mut bus::Bus {
+        &mut self.bus
+    }
+
+    pub fn csr(&mut self) -> &mut csr::Csr {
+        &mut self.csr
+    }
+
+    // The same, read-only.
+    pub fn registers(&self) -> &register::Register {
+        &self.reg
+    }
+
+    pub fn csrs(&self) -> &csr::Csr {
+        &self.csr
+    }
+
+    // MRET: the privilege mode is set to MPP, MIE is set to MPIE, MPIE is set to 1 and MPP is set to
+    // the least-privileged supported mode (U, or M without U-mode). The pc is set to mepc.
+    fn mret(&mut self) -> Result<u32, core::Exception> {
+        if self.mode != core::Privilege::Machine {
+            return Err(core::Exception::IllegalInstruction);
+        }
+        let mstatus = self.csr.readCsr(csr::MSTATUS);
+        self.mode = match (mstatus & csr::MSTATUS_MPP) >> 11 {
+            0b11    => core::Privilege::Machine,
+            0b01    => core::Privilege::Supervisor,
+            _       => core::Privilege::User,
+        };
+        let mie = (mstatus & csr::MSTATUS_MPIE) >> 4;
+        let mpp = (self.csr.leastPrivileged() as u32) << 11;
+        self.csr.writeCsr(csr::MSTATUS, (mstatus & !(csr::MSTATUS_MPP | csr::MSTATUS_MIE)) | mpp | mie | csr::MSTATUS_MPIE);
+        Ok(self.csr.readCsr(csr::MEPC))
+    }
+
+    // ECALL / EBREAK / xRET / WFI
+    fn executePriv(&mut self, fields: &HashMap<&str, u32>) -> Result<Option<u32>, core::Exception> {
+        if fields["rd"] != 0 || fields["rs1"] != 0 {
+            return Err(core::Exception::IllegalInstruction);
+        }
+        match core::Funct12Priv::decode(fields["imm_11_0"] & 0xFFF) {
+            Some(core::Funct12Priv::ECALL) if self.mode == core::Privilege::User && self.linux.is_some() => {
+                // the syscall layer advances the pc itself and may switch to another thread
+                if let Some(l) = self.linux.as_mut() {
+                    self.exit = l.dispatch(&mut self.reg, &mut self.bus);
+                }
+                self.reservation = None;
+                Ok(Some(self.reg.getPC()))
+            },
+            Some(core::Funct12Priv::ECALL)  => Err(match self.mode {
+                core::Privilege::User       => core::Exception::EcallFromUMode,
+                core::Privilege::Supervisor => core::Exception::EcallFromSMode,
+                core::Privilege::Machine    => core::Exception::EcallFromMMode,
+            }),
+            Some(core::Funct12Priv::EBREAK) if self.semihost.is_some() && semihost::isSemihostingCall(&mut self.bus, self.reg.getPC()) => {
+                if let Some(s) = self.semihost.as_mut() {
+                    self.exit = s.call(&mut self.reg, &mut self.bus);
+                }
+                Ok(None)
+            },
+            Some(core::Funct12Priv::EBREAK) => Err(core::Exception::Breakpoint),
+            Some(core::Funct12Priv::MRET)   => self.mret().map(Some),
+            // memo: 割り込みはステップ毎に確認しているので、WFIはNOPで良い
+            Some(core::Funct12Priv::WFI)    => Ok(None),
+            _                               => Err(core::Exception::IllegalInstruction),
+        }
+    }
+
+    // Runs until a device (e.g. the test finisher), the HTIF or an emulated user process requests the
+    // simulation to stop, and returns the exit status that was reported.
+    pub fn run(&mut self) -> u32 {
+        loop {
+            if let Some(status) = self.step() {
+                return status;
+            }
+        }
+    }
+
+    // One step of the hart: take a pending interrupt or execute one instruction. Returns the exit
+    // status once the simulation has been asked to stop.
+    pub fn step(&mut self) -> Option<u32> {
+        let bf = BitFields::new();
+
+        if let Some(status) = self.exit {
+            return Some(status);
+        }
+        if let Some(status) = self.bus.exitStatus() {
+            return Some(status);
+        }
+        if self.bus.takeResetRequest() {
+            // a machine reset (e.g. through the test finisher) restarts the hart where it first
+            // started; memory and the devices keep their state
+            self.reset(self.reset_pc);
+        }
+        if let Some(h) = self.htif.as_mut() {
+            if let Some(status) = h.poll(&mut self.bus) {
+                return Some(status);
+            }
+        }
+
+        self.bus.tick();
+        let tracing = self.commit_log.is_some() || self.lockstep.is_some() || self.rvfi.is_some();
+        let (mode, pc) = (self.mode, self.reg.getPC());
+        if self.checkInterrupts() {
+            if tracing {
+                let cause = self.csr.readCsr(csr::MCAUSE);
+                self.traceStep(mode, pc, None, Some((cause, 0)), [None, None]);
+            }
+            return None;
+        }
+        if tracing {
+            // drop the accesses of host-side polling (HTIF), which are not the instruction's
+            self.bus.takeAccesses();
+        }
+
+        let inst: u32 = match self.bus.fetch(pc) {
+            Ok(inst)    => inst,
+            Err(_)      => {
+                self.raiseException(core::Exception::InstructionAccessFault, pc);
+                if tracing {
+                    self.traceStep(mode, pc, None, Some((core::Exception::InstructionAccessFault as u32, pc)), [None, None]);
+                }
+                return None;
+            },
+        };
+        let rs = if tracing { self.sourceValues(inst) } else { [None, None] };
+        let mut fields: HashMap<&str, u32> = HashMap::new();
+        let mut trap: Option<core::Exception> = None;
+        let mut next_pc: Option<u32> = None;
+
+        bf.OPCODE.readFields(inst, &mut fields);
+
+        // OpcodeからTypeを特定して他フィールドを読み出し
+        match core::Opcode::decode(fields["opcode"]) {
+            Some(core::Opcode::LOAD)        => {
+                bf.ITYPE.readFields(inst, &mut fields);
+                trap = match core::Funct3Load::decode(fields["funct3"]) {
+                    Some(core::Funct3Load::LB)          => bf.ITYPE.behaviorLB(fields, &mut self.reg, &mut self.bus).err(),
+                    Some(core::Funct3Load::LH)          => bf.ITYPE.behaviorLH(fields, &mut self.reg, &mut self.bus).err(),
+                    Some(core::Funct3Load::LW)          => bf.ITYPE.behaviorLW(fields, &mut self.reg, &mut self.bus).err(),
+                    Some(core::Funct3Load::LBU)         => bf.ITYPE.behaviorLBU(fields, &mut self.reg, &mut self.bus).err(),
+                    Some(core::Funct3Load::LHU)         => bf.ITYPE.behaviorLHU(fields, &mut self.reg, &mut self.bus).err(),
+                    None                                => Some(core::Exception::IllegalInstruction),
+                };
+            },
+            Some(core::Opcode::MISC_MEM)    => {
+                bf.ITYPE.readFields(inst, &mut fields);
+                match core::Funct3MiscMem::decode(fields["funct3"]) {
+                    Some(core::Funct3MiscMem::FENCE)    => {},
+                    Some(core::Funct3MiscMem::FENCE_I) if self.isa.has("zifencei") => self.bus.fenceI(),
+                    Some(core::Funct3MiscMem::CBO)      => {
+                        if fields["rd"] != 0 {
+                            trap = Some(core::Exception::IllegalInstruction);
+                        } else {
+                            trap = self.executeCbo(&bf, fields).err();
+                        }
+                    },
+                    _                                   => trap = Some(core::Exception::IllegalInstruction),
+                }
+            },
+            Some(core::Opcode::OP_IMM)      => {
+                bf.ITYPE.readFields(inst, &mut fields);
+                match core::Funct3OpImm::decode(fields["funct3"]) {
+                    Some(core::Funct3OpImm::ADDI)       => bf.ITYPE.behaviorADDI(fields, &mut self.reg, &mut self.bus),
+                    Some(core::Funct3OpImm::SLTI)       => bf.ITYPE.behaviorSLTI(fields, &mut self.reg, &mut self.bus),
+                    Some(core::Funct3OpImm::SLTIU)      => bf.ITYPE.behaviorSLTIU(fields, &mut self.reg, &mut self.bus),
+                    Some(core::Funct3OpImm::XORI)       => bf.ITYPE.behaviorXORI(fields, &mut self.reg, &mut self.bus),
+                    Some(core::Funct3OpImm::ORI)        => bf.ITYPE.behaviorORI(fields, &mut self.reg, &mut self.bus),
+                    Some(core::Funct3OpImm::ANDI)       => bf.ITYPE.behaviorANDI(fields, &mut self.reg, &mut self.bus),
+                    Some(core::Funct3OpImm::SLLI)       => {
+                        match fields["imm_11_5"] {
+                            0b000_0000      => bf.ITYPE.behaviorSLLI(fields, &mut self.reg, &mut self.bus),
+                            _               => trap = Some(core::Exception::IllegalInstruction),
+                        }
+                    }
+                    Some(core::Funct3OpImm::SRLISRAI)   => {
+                        match fields["imm_11_5"] {
+                            0b000_0000      => bf.ITYPE.behaviorSRLI(fields, &mut self.reg, &mut self.bus),
+                            0b010_0000      => bf.ITYPE.behaviorSRAI(fields, &mut self.reg, &mut self.bus),
+                            _               => trap = Some(core::Exception::IllegalInstruction),
+                        }
+                    }
+                    None                                => trap = Some(core::Exception::IllegalInstruction),
+                }
+            },
+            Some(core::Opcode::AUIPC)       => {
+                bf.UTYPE.readFields(inst, &mut fields);
+                bf.UTYPE.behaviorAUIPC(fields, &mut self.reg, &mut self.bus);
+            },
+            Some(core::Opcode::STORE)       => {
+                bf.STYPE.readFields(inst, &mut fields);
+                trap = match core::Funct3Store::decode(fields["funct3"]) {
+                    Some(core::Funct3Store::SB)         => bf.STYPE.behaviorSB(fields, &mut self.reg, &mut self.bus).err(),
+                    Some(core::Funct3Store::SH)         => bf.STYPE.behaviorSH(fields, &mut self.reg, &mut self.bus).err(),
+                    Some(core::Funct3Store::SW)         => bf.STYPE.behaviorSW(fields, &mut self.reg, &mut self.bus).err(),
+                    None                                => Some(core::Exception::IllegalInstruction),
+                };
+            },
+            Some(core::Opcode::OP)          => {
+                bf.RTYPE.readFields(inst, &mut fields);
+                match (fields["funct7"], core::Funct3Op::decode(fields["funct3"])) {
+                    (0b000_0001, _) if self.isa.has("m")    => {
+                        match core::Funct3M::decode(fields["funct3"]) {
+                            Some(core::Funct3M::MUL)        => bf.RTYPE.behaviorMUL(fields, &mut self.reg, &mut self.bus),
+                            Some(core::Funct3M::MULH)       => bf.RTYPE.behaviorMULH(fields, &mut self.reg, &mut self.bus),
+                            Some(core::Funct3M::MULHSU)     => bf.RTYPE.behaviorMULHSU(fields, &mut self.reg, &mut self.bus),
+                            Some(core::Funct3M::MULHU)      => bf.RTYPE.behaviorMULHU(fields, &mut self.reg, &mut self.bus),
+                            Some(core::Funct3M::DIV)        => bf.RTYPE.behaviorDIV(fields, &mut self.reg, &mut self.bus),
+                            Some(core::Funct3M::DIVU)       => bf.RTYPE.behaviorDIVU(fields, &mut self.reg, &mut self.bus),
+                            Some(core::Funct3M::REM)        => bf.RTYPE.behaviorREM(fields, &mut self.reg, &mut self.bus),
+                            Some(core::Funct3M::REMU)       => bf.RTYPE.behaviorREMU(fields, &mut self.reg, &mut self.bus),
+                            None                            => trap = Some(core::Exception::IllegalInstruction),
+                        }
+                    },
+                    (0b000_0000, Some(core::Funct3Op::ADDSUB))  => bf.RTYPE.behaviorADD(fields, &mut self.reg, &mut self.bus),
+                    (0b010_0000, Some(core::Funct3Op::ADDSUB))  => bf.RTYPE.behaviorSUB(fields, &mut self.reg, &mut self.bus),
+                    (0b000_0000, Some(core::Funct3Op::SLL))     => bf.RTYPE.behaviorSLL(fields, &mut self.reg, &mut self.bus),
+                    (0b000_0000, Some(core::Funct3Op::SLT))     => bf.RTYPE.behaviorSLT(fields, &mut self.reg, &mut self.bus),
+                    (0b000_0000, Some(core::Funct3Op::SLTU))    => bf.RTYPE.behaviorSLTU(fields, &mut self.reg, &mut self.bus),
+                    (0b000_0000, Some(core::Funct3Op::XOR))     => bf.RTYPE.behaviorXOR(fields, &mut self.reg, &mut self.bus),
+                    (0b000_0000, Some(core::Funct3Op::SRLSRA))  => bf.RTYPE.behaviorSRL(fields, &mut self.reg, &mut self.bus),
+                    (0b010_0000, Some(core::Funct3Op::SRLSRA))  => bf.RTYPE.behaviorSRA(fields, &mut self.reg, &mut self.bus),
+                    (0b000_0000, Some(core::Funct3Op::OR))      => bf.RTYPE.behaviorOR(fields, &mut self.reg, &mut self.bus),
+                    (0b000_0000, Some(core::Funct3Op::AND))     => bf.RTYPE.behaviorAND(fields, &mut self.reg, &mut self.bus),
+                    _                                           => trap = Some(core::Exception::IllegalInstruction),
+                }
+            },
+            Some(core::Opcode::AMO) if !self.isa.has("a") => trap = Some(core::Exception::IllegalInstruction),
+            Some(core::Opcode::AMO)         => {
+                bf.RTYPE.readFields(inst, &mut fields);
+                // funct7 = funct5 | aq | rl. Every access is sequentially consistent here, so the
+                // ordering bits need no handling.
+                let funct5 = core::Funct5Amo::decode(fields["funct7"] >> 2);
+                trap = match (fields["funct3"], funct5) {
+                    (0b010, Some(core::Funct5Amo::LR)) if fields["rs2"] == 0 => bf.RTYPE.behaviorLRW(fields, &mut self.reg, &mut self.bus, &mut self.reservation).err(),
+                    (0b010, Some(core::Funct5Amo::SC))      => bf.RTYPE.behaviorSCW(fields, &mut self.reg, &mut self.bus, &mut self.reservation).err(),
+                    (0b010, Some(core::Funct5Amo::AMOSWAP)) => bf.RTYPE.behaviorAMOSWAP(fields, &mut self.reg, &mut self.bus).err(),
+                    (0b010, Some(core::Funct5Amo::AMOADD))  => bf.RTYPE.behaviorAMOADD(fields, &mut self.reg, &mut self.bus).err(),
+                    (0b010, Some(core::Funct5Amo::AMOXOR))  => bf.RTYPE.behaviorAMOXOR(fields, &mut self.reg, &mut self.bus).err(),
+                    (0b010, Some(core::Funct5Amo::AMOAND))  => bf.RTYPE.behaviorAMOAND(fields, &mut self.reg, &mut self.bus).err(),
+                    (0b010, Some(core::Funct5Amo::AMOOR))   => bf.RTYPE.behaviorAMOOR(fields, &mut self.reg, &mut self.bus).err(),
+                    (0b010, Some(core::Funct5Amo::AMOMIN))  => bf.RTYPE.behaviorAMOMIN(fields, &mut self.reg, &mut self.bus).err(),
+                    (0b010, Some(core::Funct5Amo::AMOMAX))  => bf.RTYPE.behaviorAMOMAX(fields, &mut self.reg, &mut self.bus).err(),
+                    (0b010, Some(core::Funct5Amo::AMOMINU)) => bf.RTYPE.behaviorAMOMINU(fields, &mut self.reg, &mut self.bus).err(),
+                    (0b010, Some(core::Funct5Amo::AMOMAXU)) => bf.RTYPE.behaviorAMOMAXU(fields, &mut self.reg, &mut self.bus).err(),
+                    _                                       => Some(core::Exception::IllegalInstruction),
+                };
+            },
+            Some(core::Opcode::LUI)         => {
+                bf.UTYPE.readFields(inst, &mut fields);
+                bf.UTYPE.behaviorLUI(fields, &mut self.reg, &mut self.bus);
+            },
+            Some(core::Opcode::BRANCH)      => {
+                bf.BTYPE.readFields(inst, &mut fields);
+                let result = match core::Funct3Branch::decode(fields["funct3"]) {
+                    Some(core::Funct3Branch::BEQ)       => bf.BTYPE.behaviorBEQ(fields, &mut self.reg, &mut self.bus),
+                    Some(core::Funct3Branch::BNE)       => bf.BTYPE.behaviorBNE(fields, &mut self.reg, &mut self.bus),
+                    Some(core::Funct3Branch::BLT)       => bf.BTYPE.behaviorBLT(fields, &mut self.reg, &mut self.bus),
+                    Some(core::Funct3Branch::BGE)       => bf.BTYPE.behaviorBGE(fields, &mut self.reg, &mut self.bus),
+                    Some(core::Funct3Branch::BLTU)      => bf.BTYPE.behaviorBLTU(fields, &mut self.reg, &mut self.bus),
+                    Some(core::Funct3Branch::BGEU)      => bf.BTYPE.behaviorBGEU(fields, &mut self.reg, &mut self.bus),
+                    None                                => Err(core::Exception::IllegalInstruction),
+                };
+                match result {
+                    Ok(target)  => next_pc = Some(target),
+                    Err(e)      => trap = Some(e),
+                }
+            },
+            Some(core::Opcode::JALR)        => {
+                bf.ITYPE.readFields(inst, &mut fields);
+                let result = match fields["funct3"] {
+                    0b000       => bf.ITYPE.behaviorJALR(fields, &mut self.reg, &mut self.bus),
+                    _           => Err(core::Exception::IllegalInstruction),
+                };
+                match result {
+                    Ok(target)  => next_pc = Some(target),
+                    Err(e)      => trap = Some(e),
+                }
+            },
+            Some(core::Opcode::JAL)         => {
+                bf.JTYPE.readFields(inst, &mut fields);
+                match bf.JTYPE.behaviorJAL(fields, &mut self.reg, &mut self.bus) {
+                    Ok(target)  => next_pc = Some(target),
+                    Err(e)      => trap = Some(e),
+                }
+            },
+            Some(core::Opcode::SYSTEM)      => {
+                bf.ITYPE.readFields(inst, &mut fields);
+                let mode = self.mode;
+                let result = match core::Funct3System::decode(fields["funct3"]) {
+                    Some(core::Funct3System::PRIV)      => self.executePriv(&fields).map(|pc| next_pc = pc),
+                    Some(_) if !self.isa.has("zicsr")   => Err(core::Exception::IllegalInstruction),
+                    Some(core::Funct3System::CSRRW)     => bf.ITYPE.behaviorCSRRW(fields, &mut self.reg, &mut self.csr, mode),
+                    Some(core::Funct3System::CSRRS)     => bf.ITYPE.behaviorCSRRS(fields, &mut self.reg, &mut self.csr, mode),
+                    Some(core::Funct3System::CSRRC)     => bf.ITYPE.behaviorCSRRC(fields, &mut self.reg, &mut self.csr, mode),
+                    Some(core::Funct3System::CSRRWI)    => bf.ITYPE.behaviorCSRRWI(fields, &mut self.reg, &mut self.csr, mode),
+                    Some(core::Funct3System::CSRRSI)    => bf.ITYPE.behaviorCSRRSI(fields, &mut self.reg, &mut self.csr, mode),
+                    Some(core::Funct3System::CSRRCI)    => bf.ITYPE.behaviorCSRRCI(fields, &mut self.reg, &mut self.csr, mode),
+                    None                                => Err(core::Exception::IllegalInstruction),
+                };
+                trap = result.err();
+            },
+            // F/D and RV64-only opcodes are not implemented
+            _                               => trap = Some(core::Exception::IllegalInstruction),
+        }
+
+        match (trap, next_pc) {
+            (Some(e), _)        => {
+                let tval = match e {
+                    core::Exception::IllegalInstruction => inst,
+                    core::Exception::Breakpoint         => pc,
+                    core::Exception::LoadAccessFault | core::Exception::StoreAccessFault |
+                    core::Exception::LoadAddressMisaligned | core::Exception::StoreAddressMisaligned => self.faultAddress(&bf, inst),
+                    _                                   => 0,
+                };
+                self.raiseException(e, tval);
+                if tracing {
+                    self.traceStep(mode, pc, Some(inst), Some((e as u32, tval)), rs);
+                }
+            },
+            (None, Some(target)) => {
+                self.reg.setPC(target);
+                self.csr.retire();
+            },
+            (None, None)        => {
+                self.reg.incPC();
+                self.csr.retire();
+            },
+        }
+        if tracing && trap.is_none() {
+            self.traceStep(mode, pc, Some(inst), None, rs);
+        }
+
+        if let Some(l) = self.linux.as_mut() {
+            if l.tick(&mut self.reg) {
+                self.reservation = None;
+                if let Some(status) = l.deliverPending(&mut self.reg, &mut self.bus) {
+                    self.exit = Some(status);
+                }
+            }
+        }
+
+        self.exit
+    }
+
+    // Effective address of a faulting load, store or AMO, for mtval (c.f., 3.1.16 Machine Trap Value
+    // Register). rs1 is still intact: a faulting access never writes rd.
+    fn faultAddress(&self, bf: &BitFields, inst: u32) -> u32 {
+        let mut fields: HashMap<&str, u32> = HashMap::new();
+        bf.OPCODE.readFields(inst, &mut fields);
+        match core::Opcode::decode(fields["opcode"]) {
+            Some(core::Opcode::LOAD)    => {
+                bf.ITYPE.readFields(inst, &mut fields);
+                self.reg.getReg(fields["rs1"]).wrapping_add(fields["imm_11_0"])
+            },
+            Some(core::Opcode::STORE)   => {
+                bf.STYPE.readFields(inst, &mut fields);
+                self.reg.getReg(fields["rs1"]).wrapping_add(bf.STYPE.imm(&fields))
+            },
+            _                           => {
+                // AMO: the address is rs1 itself
+                bf.RTYPE.readFields(inst, &mut fields);
+                self.reg.getReg(fields["rs1"])
+            },
+        }
+    }
+
+    // Zicbom / Zicboz, if enabled. The envcfg CSRs decide whether the instruction may execute in the current
+    // privilege mode (and, for cbo.inval, whether it is performed as a flush instead).
+    fn executeCbo(&mut self, bf: &BitFields, fields: HashMap<&str, u32>) -> Result<(), core::Exception> {
+        let op = core::CboFunct12::decode(fields["imm_11_0"] & 0xFFF);
+        let ext = if op == Some(core::CboFunct12::CBO_ZERO) { "zicboz" } else { "zicbom" };
+        if !self.isa.has(ext) {
+            return Err(core::Exception::IllegalInstruction);
+        }
+        match op {
+            Some(core::CboFunct12::CBO_CLEAN)   => {
+                self.csr.checkCboCleanFlush(self.mode)?;
+                bf.ITYPE.behaviorCBOCLEAN(fields, &mut self.reg, &mut self.bus)
+            },
+            Some(core::CboFunct12::CBO_FLUSH)   => {
+                self.csr.checkCboCleanFlush(self.mode)?;
+                bf.ITYPE.behaviorCBOFLUSH(fields, &mut self.reg, &mut self.bus)
+            },
+            Some(core::CboFunct12::CBO_INVAL)   => {
+                match self.csr.checkCboInval(self.mode)? {
+                    csr::InvalAction::Flush => bf.ITYPE.behaviorCBOFLUSH(fields, &mut self.reg, &mut self.bus),
+                    csr::InvalAction::Inval => bf.ITYPE.behaviorCBOINVAL(fields, &mut self.reg, &mut self.bus),
+                }
+            },
+            Some(core::CboFunct12::CBO_ZERO)    => {
+                self.csr.checkCboZero(self.mode)?;
+                bf.ITYPE.behaviorCBOZERO(fields, &mut self.reg, &mut self.bus)
+            },
+            None                                => Err(core::Exception::IllegalInstruction),
+        }
+    }
+}
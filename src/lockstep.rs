@@ -0,0 +1,273 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use crate::disasm;
+use crate::register::Register;
+use crate::trace::Commit;
+
+// Lockstep differential testing: every instruction the hart retires is checked against the next
+// commit line of a reference trace in the format of Spike's `-l --log-commits` (Spike itself, or an
+// RTL simulation logging the same way, see trace.rs). The pc, the instruction, the value written to rd
+// and the memory writes must agree; the run stops at the first divergence with a report of the
+// instructions around it and the register state.
+//
+// Reference lines other than commit lines (disassembly, exceptions) are skipped, as are the commits
+// before the first one at the hart's reset pc (Spike's boot ROM). Values wider than 32 bits (an
+// RV64 reference) are truncated.
+
+// Exit status of a run stopped by a divergence.
+pub const DIVERGED: u32 = 255;
+
+// Commits shown before the divergent one, and reference commits shown after it.
+const CONTEXT_BEFORE: usize = 8;
+const CONTEXT_AFTER: usize = 2;
+
+// A commit line of the reference trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefCommit {
+    pub pc          : u32,
+    pub inst        : u32,
+    pub rd          : Option<(u32, u32)>,
+    pub writes      : Vec<(u32, u32, u32)>,     // address, value, size in bytes
+}
+
+fn hex(s: &str) -> Option<u32> {
+    let digits = s.strip_prefix("0x")?;
+    // the low 32 bits of a 64-bit value
+    let low = &digits[digits.len().saturating_sub(8)..];
+    u32::from_str_radix(low, 16).ok()
+}
+
+// `core   0: 3 0x80000004 (0x00a12623) x10 0x00000005 mem 0x80001ffc 0x00000005`; None for other lines.
+pub fn parseLine(line: &str) -> Option<RefCommit> {
+    let rest = line.trim_start().strip_prefix("core")?;
+    let (_, rest) = rest.split_once(':')?;
+    let tokens: Vec<&str> = rest.split_whitespace().collect();
+    if tokens.len() < 3 || !matches!(tokens[0], "0" | "1" | "2" | "3") {
+        return None;
+    }
+    let pc = hex(tokens[1])?;
+    let inst = hex(tokens[2].strip_prefix('(')?.strip_suffix(')')?)?;
+    let mut c = RefCommit { pc: pc, inst: inst, rd: None, writes: vec![] };
+    let mut i = 3;
+    while i < tokens.len() {
+        let t = tokens[i];
+        if t == "mem" {
+            let addr = hex(tokens.get(i + 1)?)?;
+            i += 2;
+            // a store has the data after the address, a load has none
+            if let Some(v) = tokens.get(i).filter(|v| v.starts_with("0x")) {
+                let bytes = ((v.len() - 2) / 2).min(4) as u32;
+                c.writes.push((addr, hex(v)?, bytes));
+                i += 1;
+            }
+            continue;
+        }
+        // xN, fN, vN and cNNN_name are all followed by the value written
+        let value = hex(tokens.get(i + 1)?)?;
+        if let Some(r) = t.strip_prefix('x').and_then(|r| r.parse::<u32>().ok()) {
+            // Spike also logs writes to x0, which are not writes
+            if r != 0 {
+                c.rd = Some((r, value));
+            }
+        }
+        i += 2;
+    }
+    Some(c)
+}
+
+fn refText(c: &RefCommit) -> String {
+    let mut out = format!("0x{:08x} (0x{:08x})", c.pc, c.inst);
+    if let Some((rd, v)) = c.rd {
+        out += &format!(" x{:<2} 0x{:08x}", rd, v);
+    }
+    for (addr, v, bytes) in &c.writes {
+        out += &format!(" mem 0x{:08x} 0x{:0w$x}", addr, v, w = 2 * *bytes as usize);
+    }
+    out
+}
+
+// What the hart retired, in the terms of the reference.
+fn retired(c: &Commit) -> RefCommit {
+    RefCommit {
+        pc      : c.pc,
+        inst    : c.inst.unwrap_or(0),
+        rd      : c.rd,
+        writes  : c.mem.iter().filter(|a| a.write).map(|a| (a.addr, a.value, a.width.bytes())).collect(),
+    }
+}
+
+// Why a retired instruction disagrees with the reference commit, if it does.
+fn compare(ours: &RefCommit, theirs: &RefCommit) -> Option<String> {
+    if ours.pc != theirs.pc {
+        return Some(format!("pc differs: 0x{:08x} vs 0x{:08x}", ours.pc, theirs.pc));
+    }
+    if ours.inst != theirs.inst {
+        return Some(format!("instruction differs: 0x{:08x} vs 0x{:08x}", ours.inst, theirs.inst));
+    }
+    if ours.rd != theirs.rd {
+        let show = |rd: Option<(u32, u32)>| match rd {
+            Some((r, v))    => format!("x{} = 0x{:08x}", r, v),
+            None            => "no register write".to_string(),
+        };
+        return Some(format!("rd differs: {} vs {}", show(ours.rd), show(theirs.rd)));
+    }
+    if ours.writes != theirs.writes {
+        let show = |w: &[(u32, u32, u32)]| {
+            if w.is_empty() {
+                return "no memory write".to_string();
+            }
+            w.iter().map(|(a, v, b)| format!("{}-byte write of 0x{:x} to 0x{:08x}", b, v, a)).collect::<Vec<_>>().join(", ")
+        };
+        return Some(format!("memory writes differ: {} vs {}", show(&ours.writes), show(&theirs.writes)));
+    }
+    None
+}
+
+pub struct Lockstep {
+    reference   : Box<dyn BufRead>,
+    line        : usize,                    // lines of the reference read so far
+    synced      : bool,
+    checked     : u64,
+    history     : VecDeque<(String, String)>,
+}
+
+impl fmt::Debug for Lockstep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Lockstep").field("line", &self.line).field("checked", &self.checked).finish()
+    }
+}
+
+impl Lockstep {
+    pub fn open(path: &str) -> io::Result<Lockstep> {
+        Ok(Lockstep::new(Box::new(BufReader::new(File::open(path)?))))
+    }
+
+    pub fn new(reference: Box<dyn BufRead>) -> Lockstep {
+        Lockstep { reference: reference, line: 0, synced: false, checked: 0, history: VecDeque::new() }
+    }
+
+    // The next commit of the reference, skipping other lines.
+    fn next(&mut self) -> Option<RefCommit> {
+        let mut text = String::new();
+        loop {
+            text.clear();
+            match self.reference.read_line(&mut text) {
+                Ok(0) | Err(_)  => return None,
+                Ok(_)           => self.line += 1,
+            }
+            if let Some(c) = parseLine(&text) {
+                return Some(c);
+            }
+        }
+    }
+
+    // Check one step of the hart, with the register state after it. Interrupts and instructions
+    // that trapped have no commit line and are not checked. Returns the divergence report.
+    pub fn check(&mut self, c: &Commit, reg: &Register) -> Result<(), String> {
+        if c.inst.is_none() || c.trap.is_some() {
+            return Ok(());
+        }
+        let mut theirs = self.next();
+        if !self.synced {
+            while theirs.as_ref().is_some_and(|t| t.pc != c.pc) {
+                theirs = self.next();
+            }
+            self.synced = true;
+        }
+        let mine = retired(c);
+        let reason = match &theirs {
+            Some(t) => compare(&mine, t),
+            None    => Some("the reference trace ends here".to_string()),
+        };
+        let (ours, theirs) = (refText(&mine), theirs.as_ref().map(refText).unwrap_or_default());
+        if let Some(reason) = reason {
+            return Err(self.report(c, &reason, &ours, &theirs, reg));
+        }
+        self.checked += 1;
+        self.history.push_back((ours, theirs));
+        if self.history.len() > CONTEXT_BEFORE {
+            self.history.pop_front();
+        }
+        Ok(())
+    }
+
+    // Side-by-side listing of the last commits that agreed, the divergent one (marked with '>')
+    // and the reference commits that follow it.
+    fn report(&mut self, c: &Commit, reason: &str, ours: &str, theirs: &str, reg: &Register) -> String {
+        let line = self.line;
+        let mut rows: Vec<(char, String, String)> = self.history.iter().map(|(o, t)| (' ', o.clone(), t.clone())).collect();
+        rows.push(('>', ours.to_string(), theirs.to_string()));
+        for _ in 0..CONTEXT_AFTER {
+            match self.next() {
+                Some(t) => rows.push((' ', String::new(), refText(&t))),
+                None    => break,
+            }
+        }
+        let width = rows.iter().map(|(_, o, _)| o.len()).max().unwrap_or(0).max("simulator".len());
+
+        let mut out = format!("lockstep: divergence after {} matching instructions, at reference line {}\n", self.checked, line);
+        out += &format!("  {}: {}\n", reason, disasm::disassemble(c.inst.unwrap_or(0), c.pc));
+        out += &format!("\n  {:<w$} | {}\n", "simulator", "reference", w = width);
+        for (mark, o, t) in rows {
+            out += &format!("{} {:<w$} | {}\n", mark, o, t, w = width);
+        }
+        out += "\nregisters (simulator, after the instruction):\n";
+        out += &reg.dump();
+        out
+    }
+
+    // Number of instructions that agreed with the reference.
+    pub fn checked(&self) -> u64 {
+        self.checked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bus::{Access, Width};
+    use crate::core::Privilege;
+    use crate::lockstep::*;
+
+    fn commit(pc: u32, inst: u32, rd: Option<(u32, u32)>, mem: Vec<Access>) -> Commit {
+        Commit { mode: Privilege::Machine, pc: pc, inst: Some(inst), trap: None, rs: [None, None], rd: rd, csrs: vec![], mem: mem, next_pc: pc + 4 }
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parseLine("core   0: 0x80000000 (0x00500513) li      a0, 5"), None);
+        assert_eq!(parseLine("core   0: exception trap_illegal_instruction, epc 0x80000000"), None);
+        assert_eq!(parseLine("core   0: 3 0x80000000 (0x00500513) x10 0x00000005"),
+            Some(RefCommit { pc: 0x8000_0000, inst: 0x0050_0513, rd: Some((10, 5)), writes: vec![] }));
+        assert_eq!(parseLine("core   0: 3 0x0000000080000008 (0x00b52023) mem 0x0000000080001000 0x00000007"),
+            Some(RefCommit { pc: 0x8000_0008, inst: 0x00b5_2023, rd: None, writes: vec![(0x8000_1000, 7, 4)] }));
+        assert_eq!(parseLine("core   0: 3 0x80000004 (0x0080006f) x0  0x80000008 c768_mstatus 0x00000080 mem 0x80001000"),
+            Some(RefCommit { pc: 0x8000_0004, inst: 0x0080_006f, rd: None, writes: vec![] }));
+    }
+
+    #[test]
+    fn test_divergence() {
+        let reference = "\
+core   0: 3 0x00001000 (0x00000297) x5  0x00001000
+core   0: 0x80000000 (0x00500513) li      a0, 5
+core   0: 3 0x80000000 (0x00500513) x10 0x00000005
+core   0: 3 0x80000004 (0x00a58023) mem 0x80001000 0x05
+core   0: 3 0x80000008 (0x00158593) x11 0x80001001
+core   0: 3 0x8000000c (0x00000013)
+";
+        let mut l = Lockstep::new(Box::new(Cursor::new(reference.as_bytes().to_vec())));
+        let reg = Register::new();
+        assert!(l.check(&commit(0x8000_0000, 0x0050_0513, Some((10, 5)), vec![]), &reg).is_ok());
+        let store = Access { addr: 0x8000_1000, width: Width::Byte, value: 5, write: true };
+        assert!(l.check(&commit(0x8000_0004, 0x00a5_8023, None, vec![store]), &reg).is_ok());
+        let report = l.check(&commit(0x8000_0008, 0x0015_8593, Some((11, 0x8000_1002)), vec![]), &reg).unwrap_err();
+        assert!(report.contains("after 2 matching instructions"), "{}", report);
+        assert!(report.contains("rd differs: x11 = 0x80001002 vs x11 = 0x80001001"), "{}", report);
+        assert!(report.contains("| 0x8000000c (0x00000013)"), "{}", report);
+        assert_eq!(l.checked(), 2);
+    }
+}
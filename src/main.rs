@@ -1,550 +1,347 @@
-pub mod core;
-pub mod register;
-pub mod memory;
+#![allow(non_snake_case, clippy::redundant_field_names)]
 
 use std::collections::HashMap;
 
-trait Decode {
-    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>);
-}
-
-#[derive(Debug)]
-pub struct BitFields {
-    OPCODE  : OpcodeBitField,
-    RTYPE   : RTypeBitField,
-    ITYPE   : ITypeBitField,
-    STYPE   : STypeBitField,
-    BTYPE   : BTypeBitField,
-    UTYPE   : UTypeBitField,
-    JTYPE   : JTypeBitField,
-}
-
-impl BitFields {
-    pub fn new() -> BitFields {
-        BitFields {
-            OPCODE  : OpcodeBitField::new(),
-            RTYPE   : RTypeBitField::new(),
-            ITYPE   : ITypeBitField::new(),
-            STYPE   : STypeBitField::new(),
-            BTYPE   : BTypeBitField::new(),
-            UTYPE   : UTypeBitField::new(),
-            JTYPE   : JTypeBitField::new(),
-        }
-    }
-}
+use rvsim::simulator::Simulator;
+use rvsim::{bus, classify, debugger, disasm, elf, finisher, gdb, isa, lockstep, memory, runner, rvfi};
+use rvsim::{semihost, signature, syscall, trace, uart, vectors, vfs, CPU};
 
+// Command line options of the simulator binary.
 #[derive(Debug)]
-pub struct OpcodeBitField {
-    opcode      : u32,
-}
-
-impl OpcodeBitField {
-    pub fn new() -> OpcodeBitField {
-        OpcodeBitField {
-            opcode      : 0x0000_007F,
-        }
-    }
-}
-
-impl Decode for OpcodeBitField {
-    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>){
-        fields.insert("opcode", inst & self.opcode);
-    }
+struct Options {
+    image       : String,
+    uart        : uart::HostPort,
+    uart_base   : u32,
+    user        : bool,
+    strace      : bool,
+    semihosting : bool,
+    root        : Option<(String, bool)>,
+    host_fs     : bool,
+    mounts      : Vec<vfs::Mount>,
+    overlay     : bool,
+    gdb         : Option<String>,
+    debug       : bool,
+    log_commits : Option<String>,
+    lockstep    : Option<String>,
+    rvfi        : Option<String>,
+    signature   : Option<String>,
+    isa         : isa::Isa,
+    guest_args  : Vec<String>,
 }
 
-#[derive(Debug)]
-pub struct RTypeBitField {
-    rd          : u32,
-    funct3      : u32,
-    rs1         : u32,
-    rs2         : u32,
-    funct7      : u32,
-}
-
-impl RTypeBitField {
-    pub fn new() -> RTypeBitField {
-        RTypeBitField {
-            rd          : 0x0000_0F80,
-            funct3      : 0x0000_7000,
-            rs1         : 0x000F_8000,
-            rs2         : 0x01F0_0000,
-            funct7      : 0xFE00_0000,
-        }
-    }
-
-    // ADD performs the addition of rs1 and rs2. SUB performs the subtraction of rs2 from rs1. Overflows
-    // are ignored and the low XLEN bits of results are written to the destination rd.
-    pub fn behaviorADD(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let t = f["rs1"] + f["rs2"];
-        r.setReg(f["rd"], t);
-    }
-
-    pub fn behaviorSUB(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let t = f["rs1"] - f["rs2"];
-        r.setReg(f["rd"], t);
-    }
-    
-    // SLT and SLTU perform signed and unsigned compares respectively, writing 1 to rd if rs1 < rs2, 0 otherwise. Note,
-    // SLTU rd, x0, rs2 sets rd to 1 if rs2 is not equal to zero, otherwise sets rd to zero (assembler
-    // pseudoinstruction SNEZ rd, rs).
-    pub fn behaviorSLT(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        if f["rs1"] < f["rs2"] {
-            r.setReg(f["rd"], 1);
-        } else {
-            r.setReg(f["rd"], 0);
-        }
-    }
-    
-    pub fn behaviorSLTU(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        if (f["rs1"] as u32) < (f["rs2"] as u32) {
-            r.setReg(f["rd"], 1);
-        } else {
-            r.setReg(f["rd"], 0);
-        }
-    }
-    
-    // AND, OR, and XOR perform bitwise logical operations.
-    pub fn behaviorAND(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let t = f["rs1"] & f["rs2"];
-        r.setReg(f["rd"], t);
-    }
-    
-    pub fn behaviorOR(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let t = f["rs1"] | f["rs2"];
-        r.setReg(f["rd"], t);
-    }
-
-    pub fn behaviorXOR(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let t = f["rs1"] ^ f["rs2"];
-        r.setReg(f["rd"], t);
-    }
-
-    // SLL, SRL, and SRA perform logical left, logical right, and arithmetic right shifts on the value in
-    // register rs1 by the shift amount held in the lower 5 bits of register rs2.
-    pub fn behaviorSLL(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let t = f["rs1"] << f["rs2"];
-        r.setReg(f["rd"], t);
-    }
-        
-    pub fn behaviorSRL(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let t = f["rs1"] >> f["rs2"];
-        r.setReg(f["rd"], t);
-    }
-    
-    pub fn behaviorSRA(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let sign_bit: u32 = f["rs1"] >> 4;
-        let start_pos: u32 = if f["rs2"] >= 5 { 0 } else { 4 - f["rs2"] };
-        let end_pos: u32 = 32;
-        let mut vacated_upper_bits: u32 = 0;
-        for i in start_pos..end_pos {
-            vacated_upper_bits |= sign_bit << i;
-        }
-
-        let t = vacated_upper_bits | f["rs1"] >> f["rs2"];
-        r.setReg(f["rd"], t);
-    }
-
-    pub fn behavior(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        
-    }
+fn usage(prog: &str) -> ! {
+    eprintln!("usage: {} [options] <program.elf|image.bin|source.s> [-- <args for the program>]", prog);
+    eprintln!("       {} objdump <program.elf>                  disassemble the code sections of an ELF file", prog);
+    eprintln!("       {} test [--jobs N] [--timeout N] [--junit FILE] [--isa S] <tests>...  run regression tests in parallel", prog);
+    eprintln!("       {} vectors <file.toml|directory>...       run instruction test vectors", prog);
+    eprintln!("       {} classify [--isa S] [--from W] [--to W] [--jobs N]  check which encodings are legal against the spec", prog);
+    eprintln!("  --uart <stdio|stdout|file:PATH|unix:PATH|pty|none>  host side of the UART (default: stdio)");
+    eprintln!("  --uart-base <addr>                            UART base address (default: 0x{:08x})", uart::UART_BASE);
+    eprintln!("  --user                                        run a static riscv32 Linux binary in U-mode with syscall emulation");
+    eprintln!("  --strace                                      trace emulated system calls to stderr");
+    eprintln!("  --semihosting                                 enable RISC-V semihosting (host file I/O and exit)");
+    eprintln!("  --root <dir>[:ro]                             host directory the guest sees as / (default: the working directory)");
+    eprintln!("  --host-fs                                     give the guest the whole host filesystem instead of a sandboxed root");
+    eprintln!("  --mount <host>:<guest>[:ro]                   additional host directory visible to the guest");
+    eprintln!("  --overlay                                     keep guest file modifications in memory");
+    eprintln!("  --gdb <port|host:port|unix:PATH>              wait for a gdb remote connection before running");
+    eprintln!("  --debug                                       start in the interactive debugger (the UART does not read stdin)");
+    eprintln!("  --log-commits <file|->                        write a Spike-style (-l --log-commits) instruction trace");
+    eprintln!("  --lockstep <file>                             check every retired instruction against a reference commit log (exit status {} on divergence)", lockstep::DIVERGED);
+    eprintln!("  --rvfi <file>                                 write RVFI records of the retired instructions (JSON lines if named *.json[l], else RVFI-DII packets)");
+    eprintln!("  --signature <file>                            write the memory from begin_signature to end_signature at halt (riscv-arch-test)");
+    eprintln!("  --isa <string>                                extensions of the hart, e.g. rv32imazicsr_zifencei (default: {}); instructions of the others are illegal", isa::DEFAULT);
+    std::process::exit(1);
 }
 
-impl Decode for RTypeBitField {
-    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>) {
-        fields.insert("rd", inst & self.rd);
-        fields.insert("funct3", inst & self.funct3);
-        fields.insert("rs1", inst & self.rs1);
-        fields.insert("rs2", inst & self.rs2);
-        fields.insert("funct7", inst & self.funct7);
+fn parseNumber(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex)   => u32::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None        => s.parse().ok(),
     }
 }
 
-#[derive(Debug)]
-pub struct ITypeBitField {
-    rd          : u32,
-    funct3      : u32,
-    rs1         : u32,
-    imm_11_0    : u32,
-    imm_4_0     : u32,
-    imm_11_5    : u32,
-}
-
-impl ITypeBitField {
-    pub fn new() -> ITypeBitField {
-        ITypeBitField {
-            rd          : 0x0000_0F80,
-            funct3      : 0x0000_7000,
-            rs1         : 0x000F_8000,
-            imm_11_0    : 0xFFF0_0000,
-            imm_4_0     : 0x01F0_0000,
-            imm_11_5    : 0xFE00_0000,
-        }
-    }
-
-    // ADDI adds the sign-extended 12-bit immediate to register rs1. Arithmetic overflow is ignored and
-    // the result is simply the low XLEN bits of the result. ADDI rd, rs1, 0 is used to implement the MV
-    // rd, rs1 assembler pseudoinpub struction.
-    pub fn behaviorADDI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let t = f["imm_11_0"] + r.getReg(f["rs1"]);
-        r.setReg(f["rd"], t);
-    }
-
-    // SLTI (set less than immediate) places the value 1 in register rd if register rs1 is less than the signextended
-    // immediate when both are treated as signed numbers, else 0 is written to rd. SLTIU is
-    // similar but compares the values as unsigned numbers (i.e., the immediate is first sign-extended to
-    // XLEN bits then treated as an unsigned number). Note, SLTIU rd, rs1, 1 sets rd to 1 if rs1 equals
-    // zero, otherwise sets rd to 0 (assembler pseudoinpub struction SEQZ rd, rs).
-    pub fn behaviorSLTI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        if f["rs1"] < f["imm_11_0"] {
-            r.setReg(f["rd"], 1);
-        } else {
-            r.setReg(f["rd"], 0);
-        }
-    }
-
-    pub fn behaviorSLTIU(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        // todo: unsigned intに直したい
-        if (f["rs1"] as i32) < (f["imm_11_0"] as i32) {
-            r.setReg(f["rd"], 1);
-        } else {
-            r.setReg(f["rd"], 0);
-        }
-    }
-
-    // ANDI, ORI, XORI are logical operations that perform bitwise AND, OR, and XOR on register rs1
-    // and the sign-extended 12-bit immediate and place the result in rd. Note, XORI rd, rs1, -1 performs
-    // a bitwise logical inversion of register rs1 (assembler pseudoinpub struction NOT rd, rs).
-    pub fn behaviorXORI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let t: u32 = f["imm_11_0"] ^ r.getReg(f["rs1"]);
-        r.setReg(f["rd"], t);
-    }
-
-    pub fn behaviorORI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let t: u32 = f["imm_11_0"] | r.getReg(f["rs1"]);
-        r.setReg(f["rd"], t);
-    }
-
-    pub fn behaviorANDI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let t: u32 = f["imm_11_0"] & r.getReg(f["rs1"]);
-        r.setReg(f["rd"], t);
-    }
-    
-    // Shifts by a constant are encoded as a specialization of the I-type format. The operand to be shifted
-    // is in rs1, and the shift amount is encoded in the lower 5 bits of the I-immediate field. The right
-    // shift type is encoded in bit 30. SLLI is a logical left shift (zeros are shifted into the lower bits);
-    // SRLI is a logical right shift (zeros are shifted into the upper bits); and SRAI is an arithmetic right
-    // shift (the original sign bit is copied into the vacated upper bits).
-    pub fn behaviorSLLI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let t: u32 = f["rs1"] << f["imm_4_0"];
-        r.setReg(f["rd"], t);
-    }
-    
-    pub fn behaviorSRLI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let t: u32 = f["rs1"] >> f["imm_4_0"];
-        r.setReg(f["rd"], t);
-    }
-
-    // todo: 動作検証が必須
-    pub fn behaviorSRAI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let sign_bit: u32 = f["rs1"] >> 4;
-        let start_pos: u32 = if f["imm_4_0"] >= 5 { 0 } else { 4 - f["imm_4_0"] };
-        let end_pos: u32 = 32;
-        let mut vacated_upper_bits: u32 = 0;
-        for i in start_pos..end_pos {
-            vacated_upper_bits |= sign_bit << i;
+fn parseArgs(args: &[String]) -> Options {
+    let mut image = None;
+    let mut uart = uart::HostPort::Stdio;
+    let mut uart_base = uart::UART_BASE;
+    let mut user = false;
+    let mut strace = false;
+    let mut semihosting = false;
+    let mut root = None;
+    let mut host_fs = false;
+    let mut mounts = vec![];
+    let mut overlay = false;
+    let mut gdb = None;
+    let mut debug = false;
+    let mut log_commits = None;
+    let mut lockstep = None;
+    let mut rvfi = None;
+    let mut signature = None;
+    let mut isa = isa::Isa::parse(isa::DEFAULT).unwrap();
+    let mut guest_args = vec![];
+
+    let mut i = 1;
+    while i < args.len() {
+        let value = args.get(i + 1).cloned().unwrap_or_default();
+        match args[i].as_str() {
+            "--uart"        => {
+                uart = uart::HostPort::parse(&value).unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    usage(&args[0]);
+                });
+                i += 1;
+            },
+            "--uart-base"   => {
+                uart_base = parseNumber(&value).unwrap_or_else(|| usage(&args[0]));
+                i += 1;
+            },
+            "--user"        => user = true,
+            "--strace"      => strace = true,
+            "--semihosting" => semihosting = true,
+            "--root"        => {
+                root = Some(match value.strip_suffix(":ro") {
+                    Some(dir)   => (dir.to_string(), true),
+                    None        => (value.clone(), false),
+                });
+                i += 1;
+            },
+            "--host-fs"     => host_fs = true,
+            "--mount"       => {
+                mounts.push(vfs::Mount::parse(&value).unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    usage(&args[0]);
+                }));
+                i += 1;
+            },
+            "--overlay"     => overlay = true,
+            "--debug"       => debug = true,
+            "--gdb"         => {
+                gdb = Some(value.clone());
+                i += 1;
+            },
+            "--log-commits" => {
+                log_commits = Some(value.clone());
+                i += 1;
+            },
+            "--lockstep"    => {
+                lockstep = Some(value.clone());
+                i += 1;
+            },
+            "--rvfi"        => {
+                rvfi = Some(value.clone());
+                i += 1;
+            },
+            "--signature"   => {
+                signature = Some(value.clone());
+                i += 1;
+            },
+            "--isa"         => {
+                isa = isa::Isa::parse(&value).and_then(|isa| isa.check().map(|_| isa)).unwrap_or_else(|e| {
+                    eprintln!("{}: {}", value, e);
+                    std::process::exit(1);
+                });
+                i += 1;
+            },
+            "--"            => {
+                guest_args = args[i + 1..].to_vec();
+                break;
+            },
+            a if a.starts_with("--") => usage(&args[0]),
+            a               => image = Some(a.to_string()),
         }
-
-        let t: u32 = vacated_upper_bits | f["rs1"] >> f["imm_4_0"];
-        r.setReg(f["rd"], t);
-    }
-
-    pub fn behavior(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        
+        i += 1;
+    }
+
+    Options {
+        image       : image.unwrap_or_else(|| usage(&args[0])),
+        uart        : uart,
+        uart_base   : uart_base,
+        user        : user,
+        strace      : strace,
+        semihosting : semihosting,
+        root        : root,
+        host_fs     : host_fs,
+        mounts      : mounts,
+        overlay     : overlay,
+        gdb         : gdb,
+        debug       : debug,
+        log_commits : log_commits,
+        lockstep    : lockstep,
+        rvfi        : rvfi,
+        signature   : signature,
+        isa         : isa,
+        guest_args  : guest_args,
     }
 }
 
-impl Decode for ITypeBitField {
-    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>) {
-        fields.insert("rd", inst & self.rd);
-        fields.insert("funct3", inst & self.funct3);
-        fields.insert("rs1", inst & self.rs1);
-        fields.insert("imm_11_0", inst & self.imm_11_0);
-        fields.insert("imm_4_0", inst & self.imm_4_0);
-        fields.insert("imm_11_5", inst & self.imm_11_5);
-    }
-}
-
-#[derive(Debug)]
-pub struct STypeBitField {
-    imm_4_0     : u32,
-    funct3      : u32,
-    rs1         : u32,
-    rs2         : u32,
-    imm_11_5    : u32,
-}
-
-impl STypeBitField {
-    pub fn new() -> STypeBitField {
-        STypeBitField {
-            imm_4_0     : 0x0000_0F80,
-            funct3      : 0x0000_7000,
-            rs1         : 0x000F_8000,
-            rs2         : 0x01F0_0000,
-            imm_11_5    : 0xFE00_0000,
+// Filesystem seen by guest file operations (syscall emulation and semihosting). Unless --host-fs is
+// given, the guest is confined to --root, by default the working directory.
+fn makeVfs(opts: &Options) -> vfs::Vfs {
+    let mut v = if opts.host_fs {
+        vfs::Vfs::host()
+    } else {
+        let (dir, readonly) = opts.root.clone().unwrap_or_else(|| (".".to_string(), false));
+        if !std::path::Path::new(&dir).is_dir() {
+            eprintln!("{}: not a directory", dir);
+            std::process::exit(1);
         }
+        let mut v = vfs::Vfs::new(std::path::Path::new(&dir), std::path::PathBuf::from("/"));
+        v.setReadonlyRoot(readonly);
+        v
+    };
+    for m in &opts.mounts {
+        v.mount(m.clone());
     }
-}
-
-impl Decode for STypeBitField {
-    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>) {
-        fields.insert("imm_4_0", inst & self.imm_4_0);
-        fields.insert("funct3", inst & self.funct3);
-        fields.insert("rs1", inst & self.rs1);
-        fields.insert("rs2", inst & self.rs2);
-        fields.insert("imm_11_5", inst & self.imm_11_5);
+    if opts.overlay {
+        v.enableOverlay();
     }
+    v
 }
 
-#[derive(Debug)]
-pub struct BTypeBitField {
-    imm_11      : u32,
-    imm_4_1     : u32,
-    funct3      : u32,
-    rs1         : u32,
-    rs2         : u32,
-    imm_10_5    : u32,
-    imm_12      : u32,
-}
-
-impl BTypeBitField {
-    pub fn new() -> BTypeBitField {
-        BTypeBitField {
-            imm_11      : 0x0000_0080,
-            imm_4_1     : 0x0000_0F00,
-            funct3      : 0x0000_7000,
-            rs1         : 0x000F_8000,
-            rs2         : 0x01F0_0000,
-            imm_10_5    : 0x7C00_0000,
-            imm_12      : 0x8000_0000,
+// Run the program to completion, under the gdb stub or the debugger if one was requested. `symbols`
+// are those of the ELF file, if the program is one.
+fn execute(cpu: &mut CPU, opts: &Options, symbols: HashMap<String, u32>) -> u32 {
+    if let Some(path) = &opts.log_commits {
+        let log = trace::CommitLog::create(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        });
+        cpu.setCommitLog(log);
+    }
+    if let Some(path) = &opts.lockstep {
+        let l = lockstep::Lockstep::open(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        });
+        cpu.setLockstep(l);
+    }
+    if let Some(path) = &opts.rvfi {
+        let r = rvfi::RvfiTrace::create(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        });
+        cpu.setRvfi(r);
+    }
+    let signature = opts.signature.as_ref().map(|path| signature::Signature::new(path, &symbols).unwrap_or_else(|e| {
+        eprintln!("{}: {}", opts.image, e);
+        std::process::exit(1);
+    }));
+    let status = if opts.debug {
+        debugger::Debugger::new(symbols).repl(cpu)
+    } else if let Some(addr) = &opts.gdb {
+        let result = gdb::listen(addr).and_then(|mut stub| stub.serve(cpu));
+        result.unwrap_or_else(|e| {
+            eprintln!("gdb: {}", e);
+            std::process::exit(1);
+        })
+    } else {
+        cpu.run()
+    };
+    cpu.flushTrace();
+    if let Some(s) = signature {
+        if let Err(e) = s.write(cpu.bus()) {
+            eprintln!("signature: {}", e);
         }
     }
-}
-
-impl Decode for BTypeBitField {
-    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>) {
-        fields.insert("imm_11", inst & self.imm_11);
-        fields.insert("imm_4_1", inst & self.imm_4_1);
-        fields.insert("funct3", inst & self.funct3);
-        fields.insert("rs1", inst & self.rs1);
-        fields.insert("rs2", inst & self.rs2);
-        fields.insert("imm_10_5", inst & self.imm_10_5);
-        fields.insert("imm_12", inst & self.imm_12);
-    }
-}
-
-#[derive(Debug)]
-pub struct UTypeBitField {
-    rd          : u32,
-    imm_31_12   : u32,
-}
-
-impl UTypeBitField {
-    pub fn new() -> UTypeBitField {
-        UTypeBitField {
-            rd          : 0x0000_0F80,
-            imm_31_12   : 0xFFFF_F000,
+    if let Some(n) = cpu.lockstepChecked() {
+        if status != lockstep::DIVERGED {
+            eprintln!("lockstep: {} instructions matched the reference", n);
         }
     }
-
-    // LUI (load upper immediate) is used to build 32-bit constants and uses the U-type format. LUI
-    // places the 32-bit U-immediate value into the destination register rd, filling in the lowest 12 bits
-    // with zeros.
-    pub fn behaviorLUI(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let t: u32 = f["imm_31_12"] << 12;
-        r.setReg(f["rd"], t);
-    }
-
-    // AUIPC (add upper immediate to pc) is used to build pc-relative addresses and uses the U-type
-    // format. AUIPC forms a 32-bit offset from the U-immediate, filling in the lowest 12 bits with zeros,
-    // adds this offset to the address of the AUIPC inpub struction, then places the result in register rd.
-    pub fn behaviorAUIPC(&self, f: HashMap<&str, u32>, r: &mut register::Register, m: &mut memory::Memory) {
-        let mut t: u32 = f["imm_31_12"] << 12;
-        t += r.getPC();
-        r.setReg(f["rd"], t);
-    }
-}
-
-impl Decode for UTypeBitField {
-    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>) {
-        fields.insert("rd", self.rd);
-        fields.insert("imm_31_12", self.imm_31_12);
-    }
+    status
 }
 
-#[derive(Debug)]
-pub struct JTypeBitField {
-    rd          : u32,
-    imm_19_12   : u32,
-    imm_11      : u32,
-    imm_10_1    : u32,
-    imm_20      : u32,
+// Linux user-mode emulation: the whole user address space is RAM, the program is loaded at its virtual
+// addresses and no devices are attached.
+fn runUser(opts: &Options, image: Vec<u8>) -> u32 {
+    if !opts.isa.has("u") {
+        eprintln!("--user needs U-mode (the U extension)");
+        std::process::exit(1);
+    }
+    let e = elf::Elf::parse(image).unwrap_or_else(|e| {
+        eprintln!("{}: {}", opts.image, e);
+        std::process::exit(1);
+    });
+    // argv[0] is the program itself and the process sees the simulator's environment
+    let mut argv = vec![opts.image.clone()];
+    argv.extend(opts.guest_args.iter().cloned());
+    let envp: Vec<String> = std::env::vars().map(|(k, v)| format!("{}={}", k, v)).collect();
+
+    let mut ram = memory::Memory::withSize((syscall::USER_END - syscall::USER_BASE) as usize);
+    let sp = ram.initStack(syscall::USER_BASE, syscall::STACK_TOP, &argv, &envp, &syscall::auxv(&e, &opts.isa), &syscall::random());
+    let mut bus = bus::Bus::new();
+    bus.attach(syscall::USER_BASE, Box::new(ram)).unwrap();
+    e.loadVirtual(&mut bus).unwrap_or_else(|err| {
+        eprintln!("{}: {}", opts.image, err);
+        std::process::exit(1);
+    });
+
+    let mut linux = syscall::Linux::new(e.end(), makeVfs(opts));
+    linux.setStrace(opts.strace);
+    let mut cpu = CPU::withIsa(bus, e.entry, opts.isa.clone());
+    cpu.setLinux(linux, sp);
+    execute(&mut cpu, opts, e.symbols.clone())
 }
 
-impl JTypeBitField {
-    pub fn new() -> JTypeBitField {
-        JTypeBitField {
-            rd          : 0x0000_0F80,
-            imm_19_12   : 0x0000_F000,
-            imm_11      : 0x0001_0000,
-            imm_10_1    : 0x07FE_0000,
-            imm_20      : 0x8000_0000,
-        }
+// `objdump <elf>`: print a disassembly listing instead of running the program.
+fn objdump(path: &str) -> i32 {
+    let e = std::fs::read(path).map_err(|e| e.to_string()).and_then(elf::Elf::parse);
+    match e {
+        Ok(e)   => {
+            print!("{}", disasm::listing(path, &e));
+            0
+        },
+        Err(e)  => {
+            eprintln!("{}: {}", path, e);
+            1
+        },
     }
 }
 
-impl Decode for JTypeBitField {
-    fn readFields(&self, inst: u32, fields: &mut HashMap<&str, u32>) {
-        fields.insert("rd", inst & self.rd);
-        fields.insert("imm_19_12", inst & self.imm_19_12);
-        fields.insert("imm_11", inst & self.imm_11);
-        fields.insert("imm_10_1", inst & self.imm_10_1);
-        fields.insert("imm_20", inst & self.imm_20);
-    }
-}
-
-#[derive(Debug)]
-pub struct CPU {
-    pc: usize,
-    reg: [u32; 32],
-    ram: [u32; 256],
-    rom: [u32; 256],
-}
-
-impl CPU {
-    // fn readOpcode(&self, inst: u32, op_bf: OpBitField) -> u32 {
-    //     let opcode = inst & op_bf.opcode;
-    //     return opcode;
-    // }
-
-    // fn readOtherFields(&self, inst: u32, xtype_bf: BitFields) -> HashMap {
-    //     let mut fields: HashMap = HashMap::new();
-    //     for 
-    //     return fields;
-    // }
-
-    /*
-    fn read_funct3(self, inst: u32) -> u8 {
-        let funct3 = ((inst & 0x0000_7000) >> 12) as u8;
-        return funct3;
-    }
-
-    fn read_funct7(self, inst: u32) -> u8 {
-        let funct7 = ((inst & 0xFE00_0000) >> 25) as u8;
-        return funct7;
-    }
-    */
-
-    fn run(&mut self) {
-        let bf = BitFields::new();
-        let mut reg = register::Register::new();
-        let mut mem = memory::Memory::new();
-
-        loop {
-            let inst: u32 = self.rom[self.pc] as u32;
-            let mut fields: HashMap<&str, u32> = HashMap::new();
-
-            bf.OPCODE.readFields(inst, &mut fields);
-
-            // OpcodeからTypeを特定して他フィールドを読み出し
-            match fields["opcode"] {
-                Opcode::LOAD        => ,
-                Opcode::LOAD_FP     => ,
-                Opcode::MISC_MEM    => ,
-                Opcode::OP_IMM      => {
-                    bf.ITYPE.readFields(inst, &mut fields);
-                    match f["funct3"] {
-                        Funct3OpImm::ADDI       => bf.ITYPE.behaviorADDI(fields, &mut reg, &mut mem);
-                        Funct3OpImm::SLTI       => bf.ITYPE.behaviorSLTI(fields, &mut reg, &mut mem);
-                        Funct3OpImm::SLLI       => bf.ITYPE.behaviorSLLI(fields, &mut reg, &mut mem);
-                        Funct3OpImm::SLTIU      => bf.ITYPE.behaviorSLTIU(fields, &mut reg, &mut mem);
-                        Funct3OpImm::XORI       => bf.ITYPE.behaviorXORI(fields, &mut reg, &mut mem);
-                        Funct3OpImm::SRLISRAI   => {
-                            match f["imm_11_5"] {
-                                0b000_0000      => bf.ITYPE.behaviorSRLI(fields, &mut reg, &mut mem);
-                                0b000_0001      => bf.ITYPE.behaviorSRAI(fields, &mut reg, &mut mem);
-                                _               => ,
-                            }
-                        }
-                        Funct3OpImm::ORI        => bf.ITYPE.behaviorORI(fields, &mut reg, &mut mem);
-                        Funct3OpImm::ANDI       => bf.ITYPE.behaviorANDI(fields, &mut reg, &mut mem);
-                        _                       => ,
-                    }
-                },
-                Opcode::AUIPC       => {
-                    bf.UTYPE.readFields(inst, &mut fields);
-                    bf.UTYPE.behaviorAUIPC(fields, &mut reg, &mut mem);
-                },
-                Opcode::OP_IMM_32   => ,
-                Opcode::STORE       => ,
-                Opcode::STORE_FP    => ,
-                Opcode::AMO         => ,
-                Opcode::OP          => {
-                    bf.RTYPE.readFields(inst, &mut fields)
-                    match f["funct3"] {
-                        Funct3Op::ADDSUB        => {
-                            match f["funct7"] {
-                                0b000_0000      => // ADD
-                                0b010_0000      => // SUB
-                                _               => ,
-                            }
-                        }
-                        Funct3Op::SLT           => 
-                        Funct3Op::SLTU          => 
-                        Funct3Op::XOR           => 
-                        Funct3Op::SRLSRA        => {
-                            match f["funct7"] {
-                                0b000_0000      => // SRL
-                                0b010_0000      => // SRA
-                                _               => ,
-                            }
-                        }
-                        Funct3Op::OR            => 
-                        Funct3Op::AND           => 
-                        _                       => ,
-                    }
-                },
-                Opcode::LUI         => ,
-                    bf.UTYPE.readFields(inst, &mut fields);
-                    bf.UTYPE.behaviorLUI(fields, r: &mut reg, &mut mem);
-                Opcode::OP_32       => ,
-                Opcode::MADD        => ,
-                Opcode::MSUB        => ,
-                Opcode::MMSUB       => ,
-                Opcode::NMADD       => ,
-                Opcode::OP_FP       => ,
-                Opcode::BRANCH      => ,
-                Opcode::JALR        => ,
-                Opcode::JAL         => ,
-                Opcode::SYSTEM      => ,
-                _                   => ,
-            }
-            
-            // memo: いつでもPCを4インクリメント？
-            reg.incPC();
-
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(|s| s.as_str()) == Some("objdump") {
+        match args.get(2) {
+            Some(path)  => std::process::exit(objdump(path)),
+            None        => usage(&args[0]),
         }
     }
+    if args.get(1).map(|s| s.as_str()) == Some("test") {
+        std::process::exit(runner::main(&args));
+    }
+    if args.get(1).map(|s| s.as_str()) == Some("vectors") {
+        std::process::exit(vectors::main(&args));
+    }
+    if args.get(1).map(|s| s.as_str()) == Some("classify") {
+        std::process::exit(classify::main(&args));
+    }
+    let opts = parseArgs(&args);
+
+    // memo: とりあえずQEMU virtと同じくRAMを0x8000_0000に置き、そこから実行を開始する
+    let image = std::fs::read(&opts.image).unwrap_or_else(|e| {
+        eprintln!("{}: {}", opts.image, e);
+        std::process::exit(1);
+    });
+    if opts.user {
+        std::process::exit(runUser(&opts, image) as i32);
+    }
+
+    // the debugger reads its commands from stdin
+    let port = if opts.debug && opts.uart == uart::HostPort::Stdio { uart::HostPort::Stdout } else { opts.uart.clone() };
+    // a bare-metal machine: RAM, the UART and the test finisher
+    let builder = Simulator::builder().isa(opts.isa.clone()).uart(opts.uart_base, port).finisher(finisher::FINISHER_BASE);
+    let mut sim = builder.image(&opts.image, image).build().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let symbols = sim.symbols().clone();
+    let cpu = sim.cpu();
+    if opts.semihosting {
+        let mut cmdline = vec![opts.image.clone()];
+        cmdline.extend(opts.guest_args.iter().cloned());
+        cpu.setSemihost(semihost::Semihost::new(cmdline.join(" "), makeVfs(&opts)));
+    }
+    let status = execute(cpu, &opts, symbols);
+    std::process::exit(status as i32);
 }
-
-fn main() {
-    let bf = BitFields::new();  
-    println!("{:b}", bf.OPCODE.opcode);
-
-    let inst: u32 = 0x8485_4744;
-    println!("inst: {:x}", inst);
-
-    let mut fields: HashMap<&str, u32> = HashMap::new();
-    bf.OPCODE.readFields(inst, &mut fields);
-    println!("op: {:x}", fields["opcode"]);
-}
-
@@ -1,20 +1,250 @@
+use crate::bus::{BusError, Device, Width};
+
+// Default RAM size and cache-block size (c.f., CMO spec: the cache block size is implementation defined)
+pub const RAM_BASE: u32 = 0x8000_0000;
+pub const MEM_SIZE: usize = 16 * 1024 * 1024;
+pub const CACHE_BLOCK_SIZE: u32 = 64;
+
+// Auxiliary vector entry types (c.f., include/uapi/linux/auxvec.h). The values of AT_RANDOM and
+// AT_EXECFN are filled in by initStack.
+pub const AT_NULL: u32 = 0;
+pub const AT_PHDR: u32 = 3;
+pub const AT_PHENT: u32 = 4;
+pub const AT_PHNUM: u32 = 5;
+pub const AT_PAGESZ: u32 = 6;
+pub const AT_ENTRY: u32 = 9;
+pub const AT_UID: u32 = 11;
+pub const AT_EUID: u32 = 12;
+pub const AT_GID: u32 = 13;
+pub const AT_EGID: u32 = 14;
+pub const AT_HWCAP: u32 = 16;
+pub const AT_CLKTCK: u32 = 17;
+pub const AT_SECURE: u32 = 23;
+pub const AT_RANDOM: u32 = 25;
+pub const AT_EXECFN: u32 = 31;
+
 #[derive(Debug)]
 pub struct Memory {
-    mem: [i32; 256],
+    mem: Vec<u8>,
+    block_size: u32,
+}
+
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
 }
 
 impl Memory {
     pub fn new() -> Memory {
+        Memory::withSize(MEM_SIZE)
+    }
+
+    pub fn withSize(size: usize) -> Memory {
         Memory {
-            mem: [0; 256],  // ゼロ初期化
+            mem: vec![0; size],  // ゼロ初期化
+            block_size: CACHE_BLOCK_SIZE,
         }
     }
 
-    pub fn readMem(&self, addr: u32) -> i32 {
+    pub fn size(&self) -> usize {
+        return self.mem.len();
+    }
+
+    pub fn getBlockSize(&self) -> u32 {
+        return self.block_size;
+    }
+
+    // memo: CBOの対象ブロックは2のべき乗でないと整列できない
+    pub fn setBlockSize(&mut self, size: u32) {
+        assert!(size.is_power_of_two(), "cache block size must be a power of two");
+        self.block_size = size;
+    }
+
+    pub fn readByte(&self, addr: u32) -> u8 {
         return self.mem[addr as usize];
     }
 
-    pub fn writeMem(&mut self, addr: u32, imm: i32) {  // memo: 多分mutを付けないと書き込みできない
+    pub fn writeByte(&mut self, addr: u32, imm: u8) {
         self.mem[addr as usize] = imm;
     }
-}
\ No newline at end of file
+
+    pub fn readHalf(&self, addr: u32) -> u16 {
+        let a = addr as usize;
+        return u16::from_le_bytes([self.mem[a], self.mem[a + 1]]);
+    }
+
+    pub fn writeHalf(&mut self, addr: u32, imm: u16) {
+        let a = addr as usize;
+        self.mem[a..a + 2].copy_from_slice(&imm.to_le_bytes());
+    }
+
+    pub fn readMem(&self, addr: u32) -> i32 {
+        let a = addr as usize;
+        return i32::from_le_bytes([self.mem[a], self.mem[a + 1], self.mem[a + 2], self.mem[a + 3]]);
+    }
+
+    pub fn writeMem(&mut self, addr: u32, imm: i32) {  // memo: 多分mutを付けないと書き込みできない
+        let a = addr as usize;
+        self.mem[a..a + 4].copy_from_slice(&imm.to_le_bytes());
+    }
+
+    // cbo.zero: store zeros to the full set of bytes corresponding to the cache block that contains
+    // the effective address.
+    pub fn zeroBlock(&mut self, addr: u32) {
+        let base = (addr & !(self.block_size - 1)) as usize;
+        let end = base + self.block_size as usize;
+        self.mem[base..end].fill(0);
+    }
+
+    pub fn loadImage(&mut self, addr: u32, image: &[u8]) {
+        let a = addr as usize;
+        self.mem[a..a + image.len()].copy_from_slice(image);
+    }
+
+    // Initial stack of a Linux process (c.f., System V ABI, Process Initialization). From sp upwards:
+    // argc, argv[], NULL, envp[], NULL and the auxiliary vector terminated by AT_NULL. The strings and
+    // the AT_RANDOM bytes are stored above it, just below `top`. `base` is the address this memory is
+    // mapped at. Returns the initial sp, which is 16-byte aligned as the psABI requires.
+    pub fn initStack(&mut self, base: u32, top: u32, argv: &[String], envp: &[String], auxv: &[(u32, u32)], random: &[u8; 16]) -> u32 {
+        let mut sp = top;
+        let mut push = |m: &mut Memory, data: &[u8]| -> u32 {
+            sp -= data.len() as u32;
+            m.loadImage(sp - base, data);
+            sp
+        };
+
+        let mut strings = |m: &mut Memory, list: &[String]| -> Vec<u32> {
+            list.iter().map(|s| push(m, format!("{}\0", s).as_bytes())).collect()
+        };
+        let argv_ptrs = strings(self, argv);
+        let envp_ptrs = strings(self, envp);
+        let execfn = argv_ptrs.first().copied().unwrap_or(0);
+        let random_ptr = push(self, random);
+
+        let mut words = vec![argv.len() as u32];
+        words.extend(&argv_ptrs);
+        words.push(0);
+        words.extend(&envp_ptrs);
+        words.push(0);
+        for &(key, value) in auxv {
+            let value = match key {
+                AT_RANDOM   => random_ptr,
+                AT_EXECFN   => execfn,
+                _           => value,
+            };
+            words.extend([key, value]);
+        }
+        words.extend([AT_NULL, 0]);
+
+        let sp = (random_ptr - 4 * words.len() as u32) & !0xF;
+        for (i, w) in words.iter().enumerate() {
+            self.writeMem(sp - base + 4 * i as u32, *w as i32);
+        }
+        sp
+    }
+}
+
+// RAM is just another device on the bus.
+impl Device for Memory {
+    fn name(&self) -> &str {
+        "ram"
+    }
+
+    fn size(&self) -> u32 {
+        self.mem.len() as u32
+    }
+
+    fn read(&mut self, offset: u32, width: Width) -> Result<u32, BusError> {
+        match width {
+            Width::Byte => Ok(self.readByte(offset) as u32),
+            Width::Half => Ok(self.readHalf(offset) as u32),
+            Width::Word => Ok(self.readMem(offset) as u32),
+        }
+    }
+
+    fn write(&mut self, offset: u32, width: Width, value: u32) -> Result<(), BusError> {
+        match width {
+            Width::Byte => self.writeByte(offset, value as u8),
+            Width::Half => self.writeHalf(offset, value as u16),
+            Width::Word => self.writeMem(offset, value as i32),
+        }
+        Ok(())
+    }
+
+    fn blockSize(&self) -> Option<u32> {
+        Some(self.block_size)
+    }
+}
+
+// Read-only memory initialised from an image, e.g. a boot ROM.
+#[derive(Debug)]
+pub struct Rom {
+    mem: Memory,
+}
+
+impl Rom {
+    pub fn new(image: &[u8]) -> Rom {
+        // memo: サイズは4バイト境界に切り上げておく
+        let mut mem = Memory::withSize((image.len() + 3) & !3);
+        mem.loadImage(0, image);
+        Rom { mem: mem }
+    }
+}
+
+impl Device for Rom {
+    fn name(&self) -> &str {
+        "rom"
+    }
+
+    fn size(&self) -> u32 {
+        self.mem.size() as u32
+    }
+
+    fn read(&mut self, offset: u32, width: Width) -> Result<u32, BusError> {
+        self.mem.read(offset, width)
+    }
+
+    fn write(&mut self, offset: u32, _width: Width, _value: u32) -> Result<(), BusError> {
+        Err(BusError::ReadOnly(offset))
+    }
+
+    fn blockSize(&self) -> Option<u32> {
+        self.mem.blockSize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::*;
+
+    #[test]
+    fn test_init_stack() {
+        let base = 0x7000_0000;
+        let mut m = Memory::withSize(0x1000);
+        let argv = vec!["prog".to_string(), "-x".to_string()];
+        let envp = vec!["HOME=/".to_string()];
+        let auxv = [(AT_PAGESZ, 4096), (AT_RANDOM, 0), (AT_EXECFN, 0)];
+        let random: [u8; 16] = core::array::from_fn(|i| i as u8 + 1);
+        let top = base + 0x1000 - 3;
+        let sp = m.initStack(base, top, &argv, &envp, &auxv, &random);
+        assert_eq!(sp % 16, 0);
+
+        let word = |a: u32| m.readMem(a - base) as u32;
+        let string = |a: u32| {
+            let s: Vec<u8> = (a - base..top - base).map(|i| m.readByte(i)).take_while(|b| *b != 0).collect();
+            String::from_utf8(s).unwrap()
+        };
+        let words: Vec<u32> = (0..13).map(|i| word(sp + 4 * i)).collect();
+        assert_eq!(words[0], 2);
+        assert_eq!((string(words[1]), string(words[2]), words[3]), ("prog".to_string(), "-x".to_string(), 0));
+        assert_eq!((string(words[4]), words[5]), ("HOME=/".to_string(), 0));
+        assert_eq!(words[6..8], [AT_PAGESZ, 4096]);
+        assert_eq!((words[8], words[10], words[11], words[12]), (AT_RANDOM, AT_EXECFN, words[1], AT_NULL));
+
+        // the strings and the random bytes sit between the vectors and the top
+        let r = words[9];
+        assert_eq!((0..16).map(|i| m.readByte(r - base + i)).collect::<Vec<u8>>(), random);
+        assert!(sp + 4 * 14 <= r && r + 16 <= words[4] && words[1] + 5 == top);
+    }
+}
@@ -1,6 +1,6 @@
-// todo: 実際のアセンブリの表記()と論理レジスタ番号を対応づけ
-#[derive(Debug)]
-enum Reg {
+// ABI mnemonics of the integer registers (c.f., RISC-V ELF psABI, Table 1: Integer register convention)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reg {
     ZERO,
     RA,
     SP,
@@ -35,12 +35,41 @@ enum Reg {
     T6,
 }
 
-#[derive(Debug)]
+const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+impl Reg {
+    // ABI name of register x<idx>.
+    pub fn name(idx: u32) -> &'static str {
+        ABI_NAMES[(idx & 31) as usize]
+    }
+
+    // Register number for an ABI name, "fp" (an alias of s0) or "x0".."x31".
+    pub fn parse(s: &str) -> Option<u32> {
+        if s == "fp" {
+            return Some(Reg::S0 as u32);
+        }
+        if let Some(n) = s.strip_prefix('x').and_then(|n| n.parse::<u32>().ok()) {
+            return if n < 32 { Some(n) } else { None };
+        }
+        ABI_NAMES.iter().position(|&n| n == s).map(|i| i as u32)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Register {
     pc: u32,
     reg: [u32; 32],
 }
 
+impl Default for Register {
+    fn default() -> Register {
+        Register::new()
+    }
+}
+
 impl Register {
     pub fn new() -> Self {
         return Self {
@@ -60,15 +89,31 @@ impl Register {
 
     pub fn incPC(&mut self) {
         // todo: オーバーフローを検出
-        self.pc += 4;
+        self.pc = self.pc.wrapping_add(4);
     }
 
     pub fn getReg(&self, idx: u32) -> u32 {
         return self.reg[idx as usize];
     }
 
+    // x0 is hardwired with all bits equal to 0, so writes to it are discarded.
     pub fn setReg(&mut self, idx: u32, imm: u32) {
-        self.reg[idx as usize] = imm;
+        if idx != 0 {
+            self.reg[idx as usize] = imm;
+        }
+    }
+
+    // pc and x0..x31, four registers per line.
+    pub fn dump(&self) -> String {
+        let mut out = format!("pc   0x{:08x}\n", self.pc);
+        for row in 0..8 {
+            let line: Vec<String> = (0..4).map(|col| {
+                let i = row * 4 + col;
+                format!("{:<4} 0x{:08x}", Reg::name(i), self.reg[i as usize])
+            }).collect();
+            out += &(line.join("   ") + "\n");
+        }
+        out
     }
 }
 
@@ -84,10 +129,14 @@ mod tests {
         reg.setPC(1);
         assert_eq!(reg.getPC(), 1);
         reg.incPC();
-        assert_eq!(reg.getPC(), 2);
+        assert_eq!(reg.getPC(), 5);
+
+        assert_eq!(reg.getReg(Reg::T1 as u32), 0);
+        reg.setReg(Reg::T1 as u32, 1);
+        assert_eq!(reg.getReg(Reg::T1 as u32), 1);
 
-        assert_eq!(reg.getReg(Reg::T1), 0);
-        reg.setReg(Reg::T1, 1);
-        assert_eq!(reg.getReg(Reg::T1), 1);
+        // x0 is hardwired to zero
+        reg.setReg(Reg::ZERO as u32, 1);
+        assert_eq!(reg.getReg(Reg::ZERO as u32), 0);
     }
 }
\ No newline at end of file
@@ -0,0 +1,310 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::elf;
+use crate::finisher;
+use crate::isa::{self, Isa};
+use crate::simulator::{Simulator, StopReason};
+use crate::uart;
+
+// Regression runner (the `test` subcommand): every ELF file is run in a machine of its own, several at
+// a time on worker threads, with a limit on the number of instructions. A test passes when it stops
+// with exit status 0, reported through HTIF (tohost), the test finisher or an ECALL exit. The results
+// are printed as a table and can be written as a JUnit XML report for CI.
+//
+// The machines are not Send (devices are plain trait objects), so each worker builds the machine for
+// its test itself.
+
+pub const DEFAULT_TIMEOUT: u64 = 100_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Pass,
+    Fail(u32),          // exit status
+    Timeout,
+    Error(String),      // the test could not be loaded, or the simulator panicked
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub path        : PathBuf,
+    pub outcome     : Outcome,
+    pub steps       : u64,
+    pub time        : Duration,
+}
+
+// Shell-style match of a file name against a pattern with * and ?.
+fn wildcard(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None)            => true,
+        (Some(b'*'), _)         => wildcard(&pattern[1..], name) || (!name.is_empty() && wildcard(pattern, &name[1..])),
+        (Some(b'?'), Some(_))   => wildcard(&pattern[1..], &name[1..]),
+        (Some(p), Some(n))      => p == n && wildcard(&pattern[1..], &name[1..]),
+        _                       => false,
+    }
+}
+
+fn isElfFile(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    std::fs::File::open(path).and_then(|mut f| std::io::Read::read_exact(&mut f, &mut magic)).is_ok() && elf::isElf(&magic)
+}
+
+// Test files named by an argument: a file, the ELF files of a directory, or a pattern with * or ? in
+// its last component (for when the shell has not expanded it), in name order.
+pub fn collect(arg: &str) -> Result<Vec<PathBuf>, String> {
+    let path = Path::new(arg);
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let (dir, pattern) = if name.contains(['*', '?']) {
+        let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        (dir, Some(name))
+    } else if path.is_dir() {
+        (path, None)
+    } else if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    } else {
+        return Err(format!("{}: no such file or directory", arg));
+    };
+
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let mut files: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path())
+        .filter(|p| p.is_file())
+        .filter(|p| match &pattern {
+            Some(pat)   => wildcard(pat.as_bytes(), p.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default().as_bytes()),
+            None        => isElfFile(p),
+        })
+        .collect();
+    files.sort();
+    if files.is_empty() {
+        return Err(format!("{}: no tests", arg));
+    }
+    Ok(files)
+}
+
+// Run one test until it stops or has executed `timeout` steps. A panic anywhere, loading the test
+// included, makes it an error.
+pub fn runOne(path: &Path, isa: &Isa, timeout: u64) -> TestResult {
+    let start = Instant::now();
+    let name = path.to_string_lossy().to_string();
+    let mut sim = None;
+    let run = panic::catch_unwind(AssertUnwindSafe(|| -> Result<StopReason, String> {
+        let image = std::fs::read(path).map_err(|e| format!("{}: {}", name, e))?;
+        let builder = Simulator::builder().isa(isa.clone()).uart(uart::UART_BASE, uart::HostPort::Null).finisher(finisher::FINISHER_BASE);
+        Ok(sim.insert(builder.image(&name, image).build()?).run(timeout))
+    }));
+    let outcome = match run {
+        Ok(Ok(StopReason::Halted(0)))       => Outcome::Pass,
+        Ok(Ok(StopReason::Halted(status)))  => Outcome::Fail(status),
+        Ok(Ok(_))                           => Outcome::Timeout,
+        Ok(Err(e))                          => Outcome::Error(e),
+        Err(_)                              => Outcome::Error("the simulator panicked".to_string()),
+    };
+    let steps = sim.map(|s: Simulator| s.steps()).unwrap_or(0);
+    TestResult { path: path.to_path_buf(), outcome: outcome, steps: steps, time: start.elapsed() }
+}
+
+// Run the tests on `jobs` threads, on harts with the extensions of `isa`; the results are in the order
+// of `paths`. A test whose worker died without a result is an error.
+pub fn runAll(paths: &[PathBuf], isa: &Isa, jobs: usize, timeout: u64) -> Vec<TestResult> {
+    let paths = Arc::new(paths.to_vec());
+    let next = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = mpsc::channel();
+    let workers: Vec<_> = (0..jobs.max(1).min(paths.len())).map(|_| {
+        let (paths, next, tx, isa) = (Arc::clone(&paths), Arc::clone(&next), tx.clone(), isa.clone());
+        thread::spawn(move || loop {
+            let i = next.fetch_add(1, Ordering::SeqCst);
+            match paths.get(i) {
+                Some(p) => {
+                    let _ = tx.send((i, runOne(p, &isa, timeout)));
+                },
+                None    => break,
+            }
+        })
+    }).collect();
+    drop(tx);
+
+    let mut results: Vec<Option<TestResult>> = vec![None; paths.len()];
+    for (i, r) in rx {
+        results[i] = Some(r);
+    }
+    for w in workers {
+        let _ = w.join();
+    }
+    results.into_iter().zip(paths.iter()).map(|(r, p)| r.unwrap_or_else(|| TestResult {
+        path        : p.clone(),
+        outcome     : Outcome::Error("no result (the worker thread died)".to_string()),
+        steps       : 0,
+        time        : Duration::ZERO,
+    })).collect()
+}
+
+fn describe(o: &Outcome) -> String {
+    match o {
+        Outcome::Pass       => "pass".to_string(),
+        Outcome::Fail(s)    => format!("fail ({})", s),
+        Outcome::Timeout    => "timeout".to_string(),
+        Outcome::Error(_)   => "error".to_string(),
+    }
+}
+
+pub fn summary(results: &[TestResult]) -> String {
+    let mut out = format!("{:<12} {:>12} {:>9}  {}\n", "result", "instructions", "time", "test");
+    for r in results {
+        out += &format!("{:<12} {:>12} {:>8.3}s  {}\n", describe(&r.outcome), r.steps, r.time.as_secs_f64(), r.path.display());
+        if let Outcome::Error(e) = &r.outcome {
+            out += &format!("{:<12} {}\n", "", e);
+        }
+    }
+    let count = |f: fn(&Outcome) -> bool| results.iter().filter(|r| f(&r.outcome)).count();
+    out += &format!("\n{} tests: {} passed, {} failed, {} timed out, {} errors\n", results.len(),
+        count(|o| *o == Outcome::Pass), count(|o| matches!(o, Outcome::Fail(_))),
+        count(|o| *o == Outcome::Timeout), count(|o| matches!(o, Outcome::Error(_))));
+    out
+}
+
+fn xmlEscape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+// JUnit XML report: one testcase per file, classname being its directory. Failed tests and timeouts
+// are failures, tests that could not run are errors.
+pub fn junit(results: &[TestResult], timeout: u64) -> String {
+    let failures = results.iter().filter(|r| matches!(r.outcome, Outcome::Fail(_) | Outcome::Timeout)).count();
+    let errors = results.iter().filter(|r| matches!(r.outcome, Outcome::Error(_))).count();
+    let total: f64 = results.iter().map(|r| r.time.as_secs_f64()).sum();
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    out += &format!("  <testsuite name=\"tests\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n", results.len(), failures, errors, total);
+    for r in results {
+        let class = r.path.parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+        let name = r.path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let head = format!("    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"", xmlEscape(&class), xmlEscape(&name), r.time.as_secs_f64());
+        out += &match &r.outcome {
+            Outcome::Pass       => format!("{}/>\n", head),
+            Outcome::Fail(s)    => format!("{}>\n      <failure message=\"exit status {}\" type=\"fail\"/>\n    </testcase>\n", head, s),
+            Outcome::Timeout    => format!("{}>\n      <failure message=\"no exit after {} instructions\" type=\"timeout\"/>\n    </testcase>\n", head, timeout),
+            Outcome::Error(e)   => format!("{}>\n      <error message=\"{}\"/>\n    </testcase>\n", head, xmlEscape(e)),
+        };
+    }
+    out += "  </testsuite>\n</testsuites>\n";
+    out
+}
+
+fn usage(prog: &str) -> ! {
+    eprintln!("usage: {} test [options] <file|directory|pattern>...", prog);
+    eprintln!("  --jobs <n>                                    tests run at the same time (default: the number of CPUs)");
+    eprintln!("  --timeout <instructions>                      fail a test that has not exited after this many instructions (default: {})", DEFAULT_TIMEOUT);
+    eprintln!("  --junit <file>                                write a JUnit XML report");
+    eprintln!("  --isa <string>                                extensions of the hart (default: {})", isa::DEFAULT);
+    std::process::exit(1);
+}
+
+// `test` subcommand; returns the exit status: 0 if every test passed.
+pub fn main(args: &[String]) -> i32 {
+    let mut jobs = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let mut timeout = DEFAULT_TIMEOUT;
+    let mut junit_path = None;
+    let mut isa = Isa::parse(isa::DEFAULT).unwrap();
+    let mut paths = vec![];
+
+    let mut i = 2;
+    while i < args.len() {
+        let value = args.get(i + 1).cloned().unwrap_or_default();
+        match args[i].as_str() {
+            "--jobs"        => {
+                jobs = value.parse().unwrap_or_else(|_| usage(&args[0]));
+                i += 1;
+            },
+            "--timeout"     => {
+                timeout = value.parse().unwrap_or_else(|_| usage(&args[0]));
+                i += 1;
+            },
+            "--junit"       => {
+                junit_path = Some(value);
+                i += 1;
+            },
+            "--isa"         => {
+                isa = match Isa::parse(&value).and_then(|isa| isa.check().map(|_| isa)) {
+                    Ok(isa) => isa,
+                    Err(e)  => {
+                        eprintln!("{}: {}", value, e);
+                        return 1;
+                    },
+                };
+                i += 1;
+            },
+            a if a.starts_with("--") => usage(&args[0]),
+            a               => match collect(a) {
+                Ok(files)   => paths.extend(files),
+                Err(e)      => {
+                    eprintln!("{}", e);
+                    return 1;
+                },
+            },
+        }
+        i += 1;
+    }
+    if paths.is_empty() {
+        usage(&args[0]);
+    }
+
+    let results = runAll(&paths, &isa, jobs, timeout);
+    print!("{}", summary(&results));
+    if let Some(path) = junit_path {
+        if let Err(e) = std::fs::write(&path, junit(&results, timeout)) {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    }
+    if results.iter().all(|r| r.outcome == Outcome::Pass) { 0 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runner::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_wildcard() {
+        assert!(wildcard(b"rv32ui-p-*", b"rv32ui-p-add"));
+        assert!(wildcard(b"*.elf", b"a.elf"));
+        assert!(wildcard(b"t?.elf", b"t1.elf"));
+        assert!(!wildcard(b"*.elf", b"a.elf.dump"));
+        assert!(!wildcard(b"t?.elf", b"t.elf"));
+    }
+
+    #[test]
+    fn test_reports() {
+        let result = |path: &str, outcome| TestResult { path: PathBuf::from(path), outcome: outcome, steps: 10, time: Duration::from_millis(5) };
+        let results = vec![
+            result("t/add", Outcome::Pass),
+            result("t/sub", Outcome::Fail(3)),
+            result("t/loop", Outcome::Timeout),
+            result("t/bad<1>", Outcome::Error("t/bad<1>: not an ELF file".to_string())),
+        ];
+        let xml = junit(&results, 10);
+        assert!(xml.contains("<testsuite name=\"tests\" tests=\"4\" failures=\"2\" errors=\"1\" time=\"0.020\">"), "{}", xml);
+        assert!(xml.contains("<testcase classname=\"t\" name=\"add\" time=\"0.005\"/>"), "{}", xml);
+        assert!(xml.contains("<failure message=\"exit status 3\" type=\"fail\"/>"), "{}", xml);
+        assert!(xml.contains("name=\"bad&lt;1&gt;\""), "{}", xml);
+
+        let table = summary(&results);
+        assert!(table.contains("fail (3)"), "{}", table);
+        assert!(table.ends_with("4 tests: 1 passed, 1 failed, 1 timed out, 1 errors\n"), "{}", table);
+    }
+
+    #[test]
+    fn test_errors_keep_their_place() {
+        let dir = TempDir::new("runner");
+        std::fs::write(dir.join("bad.elf"), b"\x7fELF").unwrap();
+        let paths = vec![dir.join("missing.elf"), dir.join("bad.elf")];
+        let isa = Isa::parse(isa::DEFAULT).unwrap();
+        let results = runAll(&paths, &isa, 2, 1000);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results.iter().map(|r| r.path.clone()).collect::<Vec<_>>(), paths);
+        assert!(results.iter().all(|r| matches!(r.outcome, Outcome::Error(_)) && r.steps == 0));
+    }
+}
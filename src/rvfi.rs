@@ -0,0 +1,230 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::trace::Commit;
+
+// RVFI (RISC-V Formal Interface, c.f., riscv-formal docs/rvfi.md) records of the retired
+// instructions, so that the simulator can be compared with RTL cores field by field. There is one
+// record per instruction, whether it retired or trapped (trap = 1). Interrupts have no record of
+// their own: the first instruction of the handler has intr = 1, as after an exception. The last
+// record of a run has halt = 1.
+//
+// Memory fields follow the unaligned convention (mem_addr is the byte address, the masks and data
+// start at bit 0). Registers that are not read or written have address 0 and data 0.
+//
+// Records are written as JSON lines (files named *.json or *.jsonl) or as the 88-byte little-endian
+// RVFI-DII execution packets of TestRIG, which have no mode field.
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Packet {
+    pub order       : u64,
+    pub insn        : u32,
+    pub trap        : bool,
+    pub halt        : bool,
+    pub intr        : bool,
+    pub mode        : u32,
+    pub rs1_addr    : u32,
+    pub rs2_addr    : u32,
+    pub rs1_rdata   : u32,
+    pub rs2_rdata   : u32,
+    pub rd_addr     : u32,
+    pub rd_wdata    : u32,
+    pub pc_rdata    : u32,
+    pub pc_wdata    : u32,
+    pub mem_addr    : u32,
+    pub mem_rmask   : u32,
+    pub mem_wmask   : u32,
+    pub mem_rdata   : u32,
+    pub mem_wdata   : u32,
+}
+
+impl Packet {
+    // The record of one instruction; None for an interrupt.
+    pub fn new(order: u64, intr: bool, c: &Commit) -> Option<Packet> {
+        // interrupts have no record of their own; a fetch fault has one, without an instruction word
+        if c.inst.is_none() && c.trap.is_none_or(|(cause, _)| cause & 0x8000_0000 != 0) {
+            return None;
+        }
+        let mut p = Packet {
+            order       : order,
+            insn        : c.inst.unwrap_or(0),
+            trap        : c.trap.is_some(),
+            intr        : intr,
+            mode        : c.mode as u32,
+            pc_rdata    : c.pc,
+            pc_wdata    : c.next_pc,
+            ..Packet::default()
+        };
+        if let Some((r, v)) = c.rs[0] {
+            (p.rs1_addr, p.rs1_rdata) = if r == 0 { (0, 0) } else { (r, v) };
+        }
+        if let Some((r, v)) = c.rs[1] {
+            (p.rs2_addr, p.rs2_rdata) = if r == 0 { (0, 0) } else { (r, v) };
+        }
+        if p.trap {
+            return Some(p);
+        }
+        if let Some((r, v)) = c.rd {
+            (p.rd_addr, p.rd_wdata) = (r, v);
+        }
+        let mask = |bytes: u32| ((1u64 << bytes) - 1) as u32;
+        // an AMO both reads and writes its address
+        if let Some(a) = c.mem.iter().find(|a| !a.write) {
+            (p.mem_addr, p.mem_rmask, p.mem_rdata) = (a.addr, mask(a.width.bytes()), a.value);
+        }
+        if let Some(a) = c.mem.iter().find(|a| a.write) {
+            (p.mem_addr, p.mem_wmask, p.mem_wdata) = (a.addr, mask(a.width.bytes()), a.value);
+        }
+        Some(p)
+    }
+
+    // c.f., TestRIG RVFI_DII_Execution_Packet
+    pub fn bytes(&self) -> [u8; 88] {
+        let mut b = [0u8; 88];
+        let words = [self.order, self.pc_rdata as u64, self.pc_wdata as u64, self.insn as u64, self.rs1_rdata as u64,
+                     self.rs2_rdata as u64, self.rd_wdata as u64, self.mem_addr as u64, self.mem_rdata as u64, self.mem_wdata as u64];
+        for (k, w) in words.iter().enumerate() {
+            b[8 * k..8 * k + 8].copy_from_slice(&w.to_le_bytes());
+        }
+        let small = [self.mem_rmask, self.mem_wmask, self.rs1_addr, self.rs2_addr, self.rd_addr,
+                     self.trap as u32, self.halt as u32, self.intr as u32];
+        for (k, v) in small.iter().enumerate() {
+            b[80 + k] = *v as u8;
+        }
+        b
+    }
+
+    pub fn json(&self) -> String {
+        format!("{{\"order\":{},\"insn\":{},\"trap\":{},\"halt\":{},\"intr\":{},\"mode\":{},\
+                 \"rs1_addr\":{},\"rs2_addr\":{},\"rs1_rdata\":{},\"rs2_rdata\":{},\"rd_addr\":{},\"rd_wdata\":{},\
+                 \"pc_rdata\":{},\"pc_wdata\":{},\"mem_addr\":{},\"mem_rmask\":{},\"mem_wmask\":{},\"mem_rdata\":{},\"mem_wdata\":{}}}",
+            self.order, self.insn, self.trap as u32, self.halt as u32, self.intr as u32, self.mode,
+            self.rs1_addr, self.rs2_addr, self.rs1_rdata, self.rs2_rdata, self.rd_addr, self.rd_wdata,
+            self.pc_rdata, self.pc_wdata, self.mem_addr, self.mem_rmask, self.mem_wmask, self.mem_rdata, self.mem_wdata)
+    }
+}
+
+// Writes the RVFI records of a run to a file.
+pub struct RvfiTrace {
+    out         : BufWriter<Box<dyn Write>>,
+    json        : bool,
+    order       : u64,
+    intr        : bool,             // the next instruction is the first of a trap handler
+    pending     : Option<Packet>,   // held back until it is known whether it is the last one
+}
+
+impl fmt::Debug for RvfiTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RvfiTrace").field("json", &self.json).field("order", &self.order).finish()
+    }
+}
+
+impl RvfiTrace {
+    pub fn create(path: &str) -> io::Result<RvfiTrace> {
+        let json = path.ends_with(".json") || path.ends_with(".jsonl");
+        Ok(RvfiTrace::new(Box::new(File::create(path)?), json))
+    }
+
+    pub fn new(out: Box<dyn Write>, json: bool) -> RvfiTrace {
+        RvfiTrace { out: BufWriter::new(out), json: json, order: 0, intr: false, pending: None }
+    }
+
+    fn write(&mut self, p: &Packet) {
+        let _ = if self.json {
+            writeln!(self.out, "{}", p.json())
+        } else {
+            self.out.write_all(&p.bytes())
+        };
+    }
+
+    pub fn commit(&mut self, c: &Commit) {
+        if let Some(p) = Packet::new(self.order, self.intr, c) {
+            if let Some(prev) = self.pending.replace(p) {
+                self.write(&prev);
+            }
+            self.order += 1;
+        }
+        self.intr = c.trap.is_some();
+    }
+
+    // The run is over: the last record is written with halt set.
+    pub fn finish(&mut self) {
+        if let Some(mut p) = self.pending.take() {
+            p.halt = true;
+            self.write(&p);
+        }
+        let _ = self.out.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::bus::{Access, Width};
+    use crate::core::Privilege;
+    use crate::rvfi::*;
+
+    // A Write whose contents can be looked at after RvfiTrace is done with it.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn commit(pc: u32, inst: u32) -> Commit {
+        Commit { mode: Privilege::Machine, pc: pc, inst: Some(inst), trap: None, rs: [None, None], rd: None, csrs: vec![], mem: vec![], next_pc: pc + 4 }
+    }
+
+    #[test]
+    fn test_packet_fields() {
+        // amoadd.w a0, a1, (a2)
+        let mut c = commit(0x8000_0000, 0x00b6_252f);
+        c.rs = [Some((12, 0x8000_1000)), Some((11, 2))];
+        c.rd = Some((10, 5));
+        c.mem = vec![Access { addr: 0x8000_1000, width: Width::Word, value: 5, write: false },
+                     Access { addr: 0x8000_1000, width: Width::Word, value: 7, write: true }];
+        let p = Packet::new(3, false, &c).unwrap();
+        assert_eq!((p.rs1_addr, p.rs1_rdata, p.rs2_addr, p.rs2_rdata, p.rd_addr, p.rd_wdata), (12, 0x8000_1000, 11, 2, 10, 5));
+        assert_eq!((p.mem_addr, p.mem_rmask, p.mem_wmask, p.mem_rdata, p.mem_wdata), (0x8000_1000, 0xF, 0xF, 5, 7));
+
+        let b = p.bytes();
+        assert_eq!(b[0], 3);
+        assert_eq!(u32::from_le_bytes([b[24], b[25], b[26], b[27]]), 0x00b6_252f);
+        assert_eq!(&b[80..88], &[0xF, 0xF, 12, 11, 10, 0, 0, 0]);
+
+        // a trapping instruction writes nothing
+        c.trap = Some((7, 0x8000_1000));
+        let p = Packet::new(3, false, &c).unwrap();
+        assert!(p.trap && p.rd_addr == 0 && p.mem_wmask == 0);
+    }
+
+    #[test]
+    fn test_trace_order_intr_halt() {
+        let out = Shared::default();
+        let mut t = RvfiTrace::new(Box::new(out.clone()), true);
+        t.commit(&commit(0x8000_0000, 0x0000_0013));
+        let mut ecall = commit(0x8000_0004, 0x0000_0073);
+        ecall.trap = Some((11, 0));
+        ecall.next_pc = 0x8000_0100;
+        t.commit(&ecall);
+        t.commit(&commit(0x8000_0100, 0x0000_0013));
+        t.finish();
+
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("{\"order\":0,\"insn\":19,\"trap\":0,\"halt\":0,\"intr\":0,\"mode\":3,"), "{}", lines[0]);
+        assert!(lines[1].contains("\"trap\":1,") && lines[1].contains("\"pc_wdata\":2147483904,"), "{}", lines[1]);
+        assert!(lines[2].starts_with("{\"order\":2,\"insn\":19,\"trap\":0,\"halt\":1,\"intr\":1,"), "{}", lines[2]);
+    }
+}
@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use std::io::{self, IsTerminal, Read, SeekFrom, Write};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::bus::{Bus, Width};
+use crate::register::Register;
+use crate::vfs::{OpenFlags, VFile, Vfs};
+
+// c.f., RISC-V Semihosting: the semihosting call is an EBREAK surrounded by these two hint
+// instructions, which are NOPs for any other debugger or emulator.
+pub const ENTRY_NOP     : u32 = 0x01F0_1013;    // slli x0, x0, 0x1f
+pub const EXIT_NOP      : u32 = 0x4070_5013;    // srai x0, x0, 7
+
+// c.f., Semihosting for AArch32 and AArch64, Semihosting operations. The operation number is passed
+// in a0 and a pointer to the parameter block (or the single parameter) in a1; the result goes to a0.
+const SYS_OPEN          : u32 = 0x01;
+const SYS_CLOSE         : u32 = 0x02;
+const SYS_WRITEC        : u32 = 0x03;
+const SYS_WRITE0        : u32 = 0x04;
+const SYS_WRITE         : u32 = 0x05;
+const SYS_READ          : u32 = 0x06;
+const SYS_READC         : u32 = 0x07;
+const SYS_ISERROR       : u32 = 0x08;
+const SYS_ISTTY         : u32 = 0x09;
+const SYS_SEEK          : u32 = 0x0A;
+const SYS_FLEN          : u32 = 0x0C;
+const SYS_TMPNAM        : u32 = 0x0D;
+const SYS_REMOVE        : u32 = 0x0E;
+const SYS_RENAME        : u32 = 0x0F;
+const SYS_CLOCK         : u32 = 0x10;
+const SYS_TIME          : u32 = 0x11;
+const SYS_SYSTEM        : u32 = 0x12;
+const SYS_ERRNO         : u32 = 0x13;
+const SYS_GET_CMDLINE   : u32 = 0x15;
+const SYS_HEAPINFO      : u32 = 0x16;
+const SYS_EXIT          : u32 = 0x18;
+const SYS_EXIT_EXTENDED : u32 = 0x20;
+const SYS_ELAPSED       : u32 = 0x30;
+const SYS_TICKFREQ      : u32 = 0x31;
+
+const ADP_STOPPED_APPLICATION_EXIT : u32 = 0x20026;
+
+// The ":semihosting-features" pseudo file: magic "SHFB" followed by the feature bits
+// (bit 0: SYS_EXIT_EXTENDED, bit 1: ":tt" opened for append is stderr).
+const FEATURES          : [u8; 5] = [b'S', b'H', b'F', b'B', 0b11];
+
+// SYS_READ and SYS_WRITE move the guest's buffer through a host buffer of at most this size.
+const CHUNK             : u32 = 64 * 1024;
+
+const EIO               : i32 = 5;
+const EBADF             : u32 = 9;
+const ENOSYS            : u32 = 38;
+
+#[derive(Debug)]
+enum Handle {
+    Stdin,
+    Stdout,
+    Stderr,
+    Features(usize),
+    File(VFile),
+}
+
+#[derive(Debug)]
+pub struct Semihost {
+    vfs         : Vfs,
+    handles     : HashMap<u32, Handle>,
+    errno       : u32,
+    start       : Instant,
+    cmdline     : String,
+}
+
+// True if the EBREAK at pc is a semihosting call.
+pub fn isSemihostingCall(bus: &mut Bus, pc: u32) -> bool {
+    bus.fetch(pc.wrapping_sub(4)).ok() == Some(ENTRY_NOP) && bus.fetch(pc.wrapping_add(4)).ok() == Some(EXIT_NOP)
+}
+
+impl Semihost {
+    pub fn new(cmdline: String, vfs: Vfs) -> Semihost {
+        Semihost {
+            vfs         : vfs,
+            handles     : HashMap::new(),
+            errno       : 0,
+            start       : Instant::now(),
+            cmdline     : cmdline,
+        }
+    }
+
+    fn param(bus: &mut Bus, block: u32, i: u32) -> u32 {
+        bus.read(block + 4 * i, Width::Word).unwrap_or(0)
+    }
+
+    fn fail(&mut self, e: io::Error) -> u32 {
+        self.errno = e.raw_os_error().unwrap_or(EIO) as u32;
+        u32::MAX
+    }
+
+    // Perform the operation selected by a0. Returns the exit status if the program has exited.
+    pub fn call(&mut self, r: &mut Register, bus: &mut Bus) -> Option<u32> {
+        let op = r.getReg(10);
+        let arg = r.getReg(11);
+
+        let ret = match op {
+            SYS_EXIT            => {
+                // on RV32 the reason code is passed directly
+                let _ = io::stdout().flush();
+                return Some(if arg == ADP_STOPPED_APPLICATION_EXIT { 0 } else { 1 });
+            },
+            SYS_EXIT_EXTENDED   => {
+                let _ = io::stdout().flush();
+                let reason = Semihost::param(bus, arg, 0);
+                let code = Semihost::param(bus, arg, 1);
+                return Some(if reason == ADP_STOPPED_APPLICATION_EXIT { code } else { 1 });
+            },
+            SYS_OPEN            => self.open(bus, arg),
+            SYS_CLOSE           => {
+                match self.handles.remove(&Semihost::param(bus, arg, 0)) {
+                    Some(_) => 0,
+                    None    => { self.errno = EBADF; u32::MAX },
+                }
+            },
+            SYS_WRITEC          => {
+                let c = bus.read(arg, Width::Byte).unwrap_or(0) as u8;
+                let _ = io::stdout().write_all(&[c]).and_then(|_| io::stdout().flush());
+                0
+            },
+            SYS_WRITE0          => {
+                let s = bus.readCString(arg).unwrap_or_default();
+                let _ = io::stdout().write_all(&s).and_then(|_| io::stdout().flush());
+                0
+            },
+            SYS_WRITE           => {
+                let (h, buf, len) = (Semihost::param(bus, arg, 0), Semihost::param(bus, arg, 1), Semihost::param(bus, arg, 2));
+                // returns the number of bytes that were *not* written
+                let mut done = 0;
+                while done < len {
+                    let data = match bus.readBytes(buf.wrapping_add(done), (len - done).min(CHUNK) as usize) {
+                        Ok(data)    => data,
+                        Err(_)      => break,
+                    };
+                    match self.write(h, &data) {
+                        Ok(n)   => {
+                            done += n as u32;
+                            if n < data.len() {
+                                break;
+                            }
+                        },
+                        Err(e)  => { self.fail(e); break },
+                    }
+                }
+                len - done
+            },
+            SYS_READ            => {
+                let (h, buf, len) = (Semihost::param(bus, arg, 0), Semihost::param(bus, arg, 1), Semihost::param(bus, arg, 2));
+                // a short read (end of file, or a line from the console) ends the transfer
+                let mut data = vec![0u8; len.min(CHUNK) as usize];
+                let mut done = 0;
+                while done < len {
+                    let want = (len - done).min(CHUNK) as usize;
+                    match self.read(h, &mut data[..want]) {
+                        Ok(n)   => {
+                            if bus.writeBytes(buf.wrapping_add(done), &data[..n]).is_err() {
+                                break;
+                            }
+                            done += n as u32;
+                            if n < want {
+                                break;
+                            }
+                        },
+                        Err(e)  => { self.fail(e); break },
+                    }
+                }
+                len - done
+            },
+            SYS_READC           => {
+                let mut c = [0u8];
+                match io::stdin().read(&mut c) {
+                    Ok(1)   => c[0] as u32,
+                    _       => u32::MAX,
+                }
+            },
+            SYS_ISERROR         => ((Semihost::param(bus, arg, 0) as i32) < 0) as u32,
+            SYS_ISTTY           => {
+                match self.handles.get(&Semihost::param(bus, arg, 0)) {
+                    Some(Handle::Stdin)     => io::stdin().is_terminal() as u32,
+                    Some(Handle::Stdout)    => io::stdout().is_terminal() as u32,
+                    Some(Handle::Stderr)    => io::stderr().is_terminal() as u32,
+                    _                       => 0,
+                }
+            },
+            SYS_SEEK            => {
+                let (h, pos) = (Semihost::param(bus, arg, 0), Semihost::param(bus, arg, 1));
+                let result = match self.handles.get_mut(&h) {
+                    Some(Handle::File(f))       => f.seek(SeekFrom::Start(pos as u64)).map(|_| ()),
+                    Some(Handle::Features(p))   => { *p = pos as usize; Ok(()) },
+                    _                           => Err(io::Error::from_raw_os_error(EBADF as i32)),
+                };
+                match result {
+                    Ok(())  => 0,
+                    Err(e)  => self.fail(e),
+                }
+            },
+            SYS_FLEN            => {
+                match self.handles.get(&Semihost::param(bus, arg, 0)) {
+                    Some(Handle::File(f))       => match f.stat() {
+                        Ok(m)   => m.size as u32,
+                        Err(e)  => self.fail(e),
+                    },
+                    Some(Handle::Features(_))   => FEATURES.len() as u32,
+                    _                           => { self.errno = EBADF; u32::MAX },
+                }
+            },
+            SYS_TMPNAM          => {
+                let (buf, id, len) = (Semihost::param(bus, arg, 0), Semihost::param(bus, arg, 1), Semihost::param(bus, arg, 2));
+                let name = format!("{}/semihost-{}-{}\0", std::env::temp_dir().display(), std::process::id(), id & 0xFF);
+                if name.len() > len as usize || bus.writeBytes(buf, name.as_bytes()).is_err() { u32::MAX } else { 0 }
+            },
+            SYS_REMOVE          => {
+                let name = Semihost::string(bus, arg, 0);
+                match self.vfs.unlink(&name) {
+                    Ok(())  => 0,
+                    Err(e)  => self.fail(e),
+                }
+            },
+            SYS_RENAME          => {
+                let from = Semihost::string(bus, arg, 0);
+                let to = Semihost::string(bus, arg, 2);
+                match self.vfs.rename(&from, &to) {
+                    Ok(())  => 0,
+                    Err(e)  => self.fail(e),
+                }
+            },
+            SYS_CLOCK           => (self.start.elapsed().as_millis() / 10) as u32,  // centiseconds
+            SYS_TIME            => SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as u32).unwrap_or(0),
+            SYS_SYSTEM          => { self.errno = ENOSYS; u32::MAX },  // memo: ゲストからホストのコマンドは実行させない
+            SYS_ERRNO           => self.errno,
+            SYS_GET_CMDLINE     => {
+                let (buf, len) = (Semihost::param(bus, arg, 0), Semihost::param(bus, arg, 1));
+                let s = format!("{}\0", self.cmdline);
+                if s.len() > len as usize || bus.writeBytes(buf, s.as_bytes()).is_err() {
+                    u32::MAX
+                } else {
+                    let _ = bus.write(arg + 4, Width::Word, s.len() as u32 - 1);
+                    0
+                }
+            },
+            SYS_HEAPINFO        => {
+                // heap base/limit and stack base/limit: 0 lets the runtime use its linker symbols
+                let block = Semihost::param(bus, arg, 0);
+                let _ = bus.writeBytes(block, &[0u8; 16]);
+                0
+            },
+            SYS_ELAPSED         => {
+                let t = self.start.elapsed().as_micros() as u64;
+                let _ = bus.write(arg, Width::Word, t as u32);
+                let _ = bus.write(arg + 4, Width::Word, (t >> 32) as u32);
+                0
+            },
+            SYS_TICKFREQ        => 1_000_000,
+            _                   => {
+                eprintln!("semihosting: unsupported operation 0x{:x}", op);
+                u32::MAX
+            },
+        };
+        r.setReg(10, ret);
+        None
+    }
+
+    // Reads a name given as the (pointer, length) pair at parameter i.
+    fn string(bus: &mut Bus, block: u32, i: u32) -> String {
+        let addr = Semihost::param(bus, block, i);
+        let len = Semihost::param(bus, block, i + 1);
+        String::from_utf8_lossy(&bus.readBytes(addr, len as usize).unwrap_or_default()).into_owned()
+    }
+
+    // SYS_OPEN: parameter block { name, mode, length }. The mode is an index into the fopen() modes
+    // "r", "rb", "r+", "r+b", "w", "wb", "w+", "w+b", "a", "ab", "a+", "a+b". ":tt" is the console.
+    fn open(&mut self, bus: &mut Bus, arg: u32) -> u32 {
+        let (addr, mode, len) = (Semihost::param(bus, arg, 0), Semihost::param(bus, arg, 1), Semihost::param(bus, arg, 2));
+        let name = String::from_utf8_lossy(&bus.readBytes(addr, len as usize).unwrap_or_default()).into_owned();
+        let handle = match (name.as_str(), mode / 4) {
+            (":tt", 0)                  => Handle::Stdin,
+            (":tt", 1)                  => Handle::Stdout,
+            (":tt", _)                  => Handle::Stderr,
+            (":semihosting-features", 0) => Handle::Features(0),
+            (_, kind)                   => {
+                let plus = mode & 0b10 != 0;
+                let flags = match kind {
+                    0   => OpenFlags { read: true, write: plus, ..Default::default() },
+                    1   => OpenFlags { read: plus, write: true, create: true, truncate: true, ..Default::default() },
+                    _   => OpenFlags { read: plus, append: true, create: true, ..Default::default() },
+                };
+                match self.vfs.open(&name, &flags, 0o644) {
+                    Ok(f)   => Handle::File(f),
+                    Err(e)  => return self.fail(e),
+                }
+            },
+        };
+        let h = (1..).find(|h| !self.handles.contains_key(h)).unwrap();
+        self.handles.insert(h, handle);
+        h
+    }
+
+    fn write(&mut self, h: u32, data: &[u8]) -> io::Result<usize> {
+        match self.handles.get_mut(&h) {
+            Some(Handle::Stdout)    => io::stdout().write_all(data).and_then(|_| io::stdout().flush()).map(|_| data.len()),
+            Some(Handle::Stderr)    => io::stderr().write_all(data).map(|_| data.len()),
+            Some(Handle::File(f))   => f.write(data),
+            _                       => Err(io::Error::from_raw_os_error(EBADF as i32)),
+        }
+    }
+
+    fn read(&mut self, h: u32, buf: &mut [u8]) -> io::Result<usize> {
+        match self.handles.get_mut(&h) {
+            Some(Handle::Stdin)         => io::stdin().read(buf),
+            Some(Handle::File(f))       => f.read(buf),
+            Some(Handle::Features(p))   => {
+                let start = (*p).min(FEATURES.len());
+                let n = (FEATURES.len() - start).min(buf.len());
+                buf[..n].copy_from_slice(&FEATURES[start..start + n]);
+                *p = start + n;
+                Ok(n)
+            },
+            _                           => Err(io::Error::from_raw_os_error(EBADF as i32)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::memory::{self, Memory};
+    use crate::semihost::*;
+    use crate::testutil::TempDir;
+
+    const BLOCK : u32 = memory::RAM_BASE + 0x100;    // parameter block
+    const DATA  : u32 = memory::RAM_BASE + 0x200;
+
+    fn bus() -> Bus {
+        let mut bus = Bus::new();
+        bus.attach(memory::RAM_BASE, Box::new(Memory::withSize(4096))).unwrap();
+        bus
+    }
+
+    // Operation `op` with the given parameter block; returns a0.
+    fn call(s: &mut Semihost, bus: &mut Bus, op: u32, params: &[u32]) -> u32 {
+        for (i, p) in params.iter().enumerate() {
+            bus.write(BLOCK + 4 * i as u32, Width::Word, *p).unwrap();
+        }
+        let mut r = Register::new();
+        r.setReg(10, op);
+        r.setReg(11, BLOCK);
+        assert_eq!(s.call(&mut r, bus), None);
+        r.getReg(10)
+    }
+
+    fn open(s: &mut Semihost, bus: &mut Bus, name: &str, mode: u32) -> u32 {
+        bus.writeBytes(DATA, name.as_bytes()).unwrap();
+        call(s, bus, SYS_OPEN, &[DATA, mode, name.len() as u32])
+    }
+
+    #[test]
+    fn test_detect_and_exit() {
+        let mut bus = bus();
+        let base = memory::RAM_BASE;
+        bus.writeBytes(base, &[ENTRY_NOP.to_le_bytes(), 0x0010_0073u32.to_le_bytes(), EXIT_NOP.to_le_bytes()].concat()).unwrap();
+        assert!(isSemihostingCall(&mut bus, base + 4));
+        assert!(!isSemihostingCall(&mut bus, base + 8));
+        assert!(!isSemihostingCall(&mut bus, base));
+
+        let mut s = Semihost::new(String::new(), Vfs::host());
+        let mut r = Register::new();
+        r.setReg(10, SYS_EXIT);
+        r.setReg(11, ADP_STOPPED_APPLICATION_EXIT);
+        assert_eq!(s.call(&mut r, &mut bus), Some(0));
+        r.setReg(11, 0x20023);  // ADP_Stopped_RunTimeErrorUnknown
+        assert_eq!(s.call(&mut r, &mut bus), Some(1));
+        bus.writeBytes(BLOCK, &[ADP_STOPPED_APPLICATION_EXIT.to_le_bytes(), 7u32.to_le_bytes()].concat()).unwrap();
+        r.setReg(10, SYS_EXIT_EXTENDED);
+        r.setReg(11, BLOCK);
+        assert_eq!(s.call(&mut r, &mut bus), Some(7));
+    }
+
+    #[test]
+    fn test_files() {
+        let dir = TempDir::new("semihost");
+        let mut s = Semihost::new("prog arg".to_string(), Vfs::new(&dir, PathBuf::from("/")));
+        let mut bus = bus();
+
+        // "w" creates the file; SYS_WRITE and SYS_READ return the number of bytes *not* transferred
+        let h = open(&mut s, &mut bus, "out.txt", 4);
+        assert_eq!(h, 1);
+        bus.writeBytes(DATA + 0x80, b"hello").unwrap();
+        assert_eq!(call(&mut s, &mut bus, SYS_WRITE, &[h, DATA + 0x80, 5]), 0);
+        assert_eq!(call(&mut s, &mut bus, SYS_CLOSE, &[h]), 0);
+        assert_eq!(std::fs::read(dir.join("out.txt")).unwrap(), b"hello");
+
+        let h = open(&mut s, &mut bus, "out.txt", 0);
+        assert_eq!(call(&mut s, &mut bus, SYS_FLEN, &[h]), 5);
+        assert_eq!(call(&mut s, &mut bus, SYS_SEEK, &[h, 1]), 0);
+        assert_eq!(call(&mut s, &mut bus, SYS_READ, &[h, DATA + 0x100, 8]), 4);
+        assert_eq!(bus.readBytes(DATA + 0x100, 4).unwrap(), b"ello");
+        assert_eq!(call(&mut s, &mut bus, SYS_WRITE, &[h, DATA + 0x80, 5]), 5);
+
+        // a buffer outside memory transfers nothing, and a huge length is not allocated up front
+        assert_eq!(call(&mut s, &mut bus, SYS_SEEK, &[h, 0]), 0);
+        assert_eq!(call(&mut s, &mut bus, SYS_READ, &[h, 0x1000, 4]), 4);
+        assert_eq!(call(&mut s, &mut bus, SYS_SEEK, &[h, 0]), 0);
+        assert_eq!(call(&mut s, &mut bus, SYS_READ, &[h, DATA + 0x100, u32::MAX]), u32::MAX - 5);
+        assert_eq!(bus.readBytes(DATA + 0x100, 5).unwrap(), b"hello");
+        assert_eq!(call(&mut s, &mut bus, SYS_CLOSE, &[h]), 0);
+
+        // failures set the errno reported by SYS_ERRNO
+        assert_eq!(call(&mut s, &mut bus, SYS_CLOSE, &[h]), u32::MAX);
+        assert_eq!(call(&mut s, &mut bus, SYS_ERRNO, &[]), EBADF);
+        assert_eq!(open(&mut s, &mut bus, "missing", 0), u32::MAX);
+        assert_eq!(call(&mut s, &mut bus, SYS_ERRNO, &[]), 2);  // ENOENT
+        assert_eq!(call(&mut s, &mut bus, SYS_ISERROR, &[u32::MAX]), 1);
+        assert_eq!(call(&mut s, &mut bus, SYS_ISERROR, &[3]), 0);
+        assert_eq!(call(&mut s, &mut bus, SYS_SYSTEM, &[]), u32::MAX);
+        assert_eq!(call(&mut s, &mut bus, SYS_ERRNO, &[]), ENOSYS);
+        assert_eq!(call(&mut s, &mut bus, 0xFF, &[]), u32::MAX);
+
+        bus.writeBytes(DATA, b"out.txtnew.txt").unwrap();
+        assert_eq!(call(&mut s, &mut bus, SYS_RENAME, &[DATA, 7, DATA + 7, 7]), 0);
+        assert_eq!(call(&mut s, &mut bus, SYS_REMOVE, &[DATA + 7, 7]), 0);
+        assert!(!dir.join("new.txt").exists());
+    }
+
+    #[test]
+    fn test_console_features_and_cmdline() {
+        let mut s = Semihost::new("prog arg".to_string(), Vfs::host());
+        let mut bus = bus();
+        assert_eq!(open(&mut s, &mut bus, ":tt", 0), 1);
+        assert_eq!(open(&mut s, &mut bus, ":tt", 4), 2);
+        assert_eq!(open(&mut s, &mut bus, ":tt", 8), 3);
+        assert_eq!(call(&mut s, &mut bus, SYS_WRITE, &[3, DATA, 0]), 0);
+        assert_eq!(call(&mut s, &mut bus, SYS_WRITE, &[1, DATA, 1]), 1);
+
+        let h = open(&mut s, &mut bus, ":semihosting-features", 0);
+        assert_eq!(call(&mut s, &mut bus, SYS_FLEN, &[h]), 5);
+        assert_eq!(call(&mut s, &mut bus, SYS_READ, &[h, DATA + 0x100, 8]), 3);
+        assert_eq!(bus.readBytes(DATA + 0x100, 5).unwrap(), FEATURES);
+        assert_eq!(call(&mut s, &mut bus, SYS_SEEK, &[h, 4]), 0);
+        assert_eq!(call(&mut s, &mut bus, SYS_READ, &[h, DATA + 0x100, 1]), 0);
+        assert_eq!(bus.read(DATA + 0x100, Width::Byte), Ok(0b11));
+
+        assert_eq!(call(&mut s, &mut bus, SYS_GET_CMDLINE, &[DATA, 4]), u32::MAX);
+        assert_eq!(call(&mut s, &mut bus, SYS_GET_CMDLINE, &[DATA, 64]), 0);
+        assert_eq!(bus.readCString(DATA).unwrap(), b"prog arg");
+        assert_eq!(bus.read(BLOCK + 4, Width::Word), Ok(8));
+        assert_eq!(call(&mut s, &mut bus, SYS_TICKFREQ, &[]), 1_000_000);
+    }
+}
@@ -0,0 +1,279 @@
+use crate::bus::{Bus, BusError, Width};
+use crate::register::Register;
+
+// c.f., include/uapi/asm-generic/signal.h
+pub const SIGILL            : u32 = 4;
+pub const SIGTRAP           : u32 = 5;
+pub const SIGABRT           : u32 = 6;
+pub const SIGBUS            : u32 = 7;
+pub const SIGKILL           : u32 = 9;
+pub const SIGSEGV           : u32 = 11;
+pub const SIGCHLD           : u32 = 17;
+pub const SIGCONT           : u32 = 18;
+pub const SIGSTOP           : u32 = 19;
+pub const SIGTSTP           : u32 = 20;
+pub const SIGTTIN           : u32 = 21;
+pub const SIGTTOU           : u32 = 22;
+pub const SIGURG            : u32 = 23;
+pub const SIGWINCH          : u32 = 28;
+pub const NSIG              : u32 = 64;
+
+pub const SIG_DFL           : u32 = 0;
+pub const SIG_IGN           : u32 = 1;
+
+pub const SA_ONSTACK        : u32 = 0x0800_0000;
+pub const SA_NODEFER        : u32 = 0x4000_0000;
+pub const SA_RESETHAND      : u32 = 0x8000_0000;
+
+pub const SS_ONSTACK        : u32 = 1;
+pub const SS_DISABLE        : u32 = 2;
+
+// si_code values (c.f., include/uapi/asm-generic/siginfo.h)
+pub const SI_USER           : i32 = 0;
+pub const SI_TKILL          : i32 = -6;
+pub const ILL_ILLOPC        : i32 = 1;
+pub const SEGV_MAPERR       : i32 = 1;
+pub const BUS_ADRALN        : i32 = 1;
+pub const TRAP_BRKPT        : i32 = 1;
+
+// rt_sigreturn, called by the trampoline the handler returns to.
+pub const SYS_RT_SIGRETURN  : u32 = 139;
+
+// struct rt_sigframe { siginfo_t info; struct ucontext uc; } for riscv32 (c.f., arch/riscv/kernel/signal.c,
+// include/uapi/asm/ucontext.h). uc_mcontext is 16-byte aligned; its fp state is left zero since F/D
+// are not implemented.
+const SIGINFO_SIZE          : u32 = 128;
+const UC_SIGMASK            : u32 = 20;
+const UC_STACK              : u32 = 8;
+const UC_MCONTEXT           : u32 = 160;
+const UCONTEXT_SIZE         : u32 = 816;
+const FRAME_SIZE            : u32 = SIGINFO_SIZE + UCONTEXT_SIZE;
+// Linux returns through the vDSO; without one, the frame carries its own `li a7, 139; ecall`.
+const TRAMPOLINE            : [u32; 2] = [0x08B0_0893, 0x0000_0073];
+
+// struct sigaction without sa_restorer: { handler, flags, mask (64 bits) }
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SigAction {
+    pub handler     : u32,
+    pub flags       : u32,
+    pub mask        : u64,
+}
+
+impl SigAction {
+    pub fn read(bus: &mut Bus, addr: u32) -> Result<SigAction, BusError> {
+        Ok(SigAction {
+            handler : bus.read(addr, Width::Word)?,
+            flags   : bus.read(addr + 4, Width::Word)?,
+            mask    : bus.read(addr + 8, Width::Word)? as u64 | (bus.read(addr + 12, Width::Word)? as u64) << 32,
+        })
+    }
+
+    pub fn write(&self, bus: &mut Bus, addr: u32) -> Result<(), BusError> {
+        bus.write(addr, Width::Word, self.handler)?;
+        bus.write(addr + 4, Width::Word, self.flags)?;
+        bus.write(addr + 8, Width::Word, self.mask as u32)?;
+        bus.write(addr + 12, Width::Word, (self.mask >> 32) as u32)
+    }
+}
+
+// stack_t { ss_sp, ss_flags, ss_size }
+#[derive(Debug, Clone, Copy)]
+pub struct AltStack {
+    pub sp          : u32,
+    pub flags       : u32,
+    pub size        : u32,
+}
+
+impl Default for AltStack {
+    fn default() -> AltStack {
+        AltStack { sp: 0, flags: SS_DISABLE, size: 0 }
+    }
+}
+
+impl AltStack {
+    pub fn contains(&self, sp: u32) -> bool {
+        self.flags & SS_DISABLE == 0 && sp > self.sp && sp <= self.sp.wrapping_add(self.size)
+    }
+}
+
+// What the handler finds in its siginfo_t.
+#[derive(Debug, Clone, Copy)]
+pub struct SigInfo {
+    pub signo       : u32,
+    pub code        : i32,
+    pub addr        : u32,      // si_addr for faults, si_pid for kill
+}
+
+pub fn bit(sig: u32) -> u64 {
+    1 << (sig - 1)
+}
+
+// Signals whose default action is to be ignored (the job control stops are treated the same way,
+// there being nothing to stop the process for).
+pub fn ignoredByDefault(sig: u32) -> bool {
+    matches!(sig, SIGCHLD | SIGCONT | SIGURG | SIGWINCH | SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU) || sig > 31
+}
+
+pub fn name(sig: u32) -> &'static str {
+    const NAMES: [&str; 32] = [
+        "", "SIGHUP", "SIGINT", "SIGQUIT", "SIGILL", "SIGTRAP", "SIGABRT", "SIGBUS", "SIGFPE", "SIGKILL",
+        "SIGUSR1", "SIGSEGV", "SIGUSR2", "SIGPIPE", "SIGALRM", "SIGTERM", "SIGSTKFLT", "SIGCHLD", "SIGCONT",
+        "SIGSTOP", "SIGTSTP", "SIGTTIN", "SIGTTOU", "SIGURG", "SIGXCPU", "SIGXFSZ", "SIGVTALRM", "SIGPROF",
+        "SIGWINCH", "SIGIO", "SIGPWR", "SIGSYS",
+    ];
+    NAMES.get(sig as usize).copied().unwrap_or("SIGRT")
+}
+
+// Push an rt_sigframe and enter the handler with a0 = signo, a1 = &info, a2 = &uc and ra pointing
+// at the trampoline. `mask` is the signal mask to restore on sigreturn.
+pub fn setupFrame(r: &mut Register, bus: &mut Bus, info: &SigInfo, act: &SigAction, mask: u64, alt: &AltStack) -> Result<(), BusError> {
+    let sp = r.getReg(2);
+    let top = if act.flags & SA_ONSTACK != 0 && alt.flags & SS_DISABLE == 0 && !alt.contains(sp) {
+        alt.sp.wrapping_add(alt.size)
+    } else {
+        sp
+    };
+    let frame = top.wrapping_sub(FRAME_SIZE + 4 * TRAMPOLINE.len() as u32) & !0xF;
+    if frame.checked_add(FRAME_SIZE + 4 * TRAMPOLINE.len() as u32).is_none() {
+        return Err(BusError::Unmapped(frame));
+    }
+    let uc = frame + SIGINFO_SIZE;
+
+    let mut f = vec![0u8; (FRAME_SIZE + 4 * TRAMPOLINE.len() as u32) as usize];
+    let mut put = |off: u32, v: u32| f[off as usize..off as usize + 4].copy_from_slice(&v.to_le_bytes());
+    put(0, info.signo);
+    put(8, info.code as u32);
+    put(12, info.addr);
+    put(SIGINFO_SIZE + UC_STACK, alt.sp);
+    put(SIGINFO_SIZE + UC_STACK + 4, if alt.contains(sp) { SS_ONSTACK } else { alt.flags });
+    put(SIGINFO_SIZE + UC_STACK + 8, alt.size);
+    put(SIGINFO_SIZE + UC_SIGMASK, mask as u32);
+    put(SIGINFO_SIZE + UC_SIGMASK + 4, (mask >> 32) as u32);
+    // sc_regs: pc, then x1..x31
+    put(SIGINFO_SIZE + UC_MCONTEXT, r.getPC());
+    for i in 1..32 {
+        put(SIGINFO_SIZE + UC_MCONTEXT + 4 * i, r.getReg(i));
+    }
+    for (i, w) in TRAMPOLINE.iter().enumerate() {
+        put(FRAME_SIZE + 4 * i as u32, *w);
+    }
+    bus.writeBytes(frame, &f)?;
+
+    r.setReg(1, frame + FRAME_SIZE);
+    r.setReg(2, frame);
+    r.setReg(10, info.signo);
+    r.setReg(11, frame);
+    r.setReg(12, uc);
+    r.setPC(act.handler);
+    Ok(())
+}
+
+// rt_sigreturn: reload the registers saved (and possibly modified by the handler) in the frame at sp.
+// Returns the signal mask to restore; a frame that cannot be read is an error (the caller's SIGSEGV).
+pub fn restoreFrame(r: &mut Register, bus: &mut Bus) -> Result<u64, BusError> {
+    let sp = r.getReg(2);
+    if sp.checked_add(FRAME_SIZE).is_none() {
+        return Err(BusError::Unmapped(sp));
+    }
+    let uc = sp + SIGINFO_SIZE;
+    let mask = bus.read(uc + UC_SIGMASK, Width::Word)? as u64 | (bus.read(uc + UC_SIGMASK + 4, Width::Word)? as u64) << 32;
+    let mut saved = [0u32; 32];
+    for (i, v) in saved.iter_mut().enumerate() {
+        *v = bus.read(uc + UC_MCONTEXT + 4 * i as u32, Width::Word)?;
+    }
+    r.setPC(saved[0]);
+    for i in 1..32 {
+        r.setReg(i, saved[i as usize]);
+    }
+    Ok(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::Memory;
+    use crate::signal::*;
+
+    fn bus() -> Bus {
+        let mut bus = Bus::new();
+        bus.attach(0x10000, Box::new(Memory::withSize(0x10000))).unwrap();
+        bus
+    }
+
+    fn word(bus: &mut Bus, addr: u32) -> u32 {
+        bus.read(addr, Width::Word).unwrap()
+    }
+
+    #[test]
+    fn test_frame_layout() {
+        let mut bus = bus();
+        let mut r = Register::new();
+        for i in 1..32 {
+            r.setReg(i, 0x100 + i);
+        }
+        r.setReg(2, 0x1F7F3);
+        r.setPC(0x10400);
+        let info = SigInfo { signo: SIGSEGV, code: SEGV_MAPERR, addr: 0xDEAD };
+        let act = SigAction { handler: 0x10800, flags: 0, mask: 0 };
+        setupFrame(&mut r, &mut bus, &info, &act, 0x1_0000_0002, &AltStack::default()).unwrap();
+
+        // the frame is 16-byte aligned below sp, and so is uc_mcontext within it
+        let frame = r.getReg(2);
+        assert_eq!(frame % 16, 0);
+        assert_eq!((frame + SIGINFO_SIZE + UC_MCONTEXT) % 16, 0);
+        assert!(frame + FRAME_SIZE + 8 <= 0x1F7F3 && 0x1F7F3 - frame < FRAME_SIZE + 8 + 16);
+        assert_eq!((r.getPC(), r.getReg(10), r.getReg(11), r.getReg(12)), (0x10800, SIGSEGV, frame, frame + SIGINFO_SIZE));
+        assert_eq!(r.getReg(1), frame + FRAME_SIZE);
+        assert_eq!((word(&mut bus, frame + FRAME_SIZE), word(&mut bus, frame + FRAME_SIZE + 4)), (TRAMPOLINE[0], TRAMPOLINE[1]));
+
+        // siginfo, the saved mask, the disabled altstack and the registers (pc first)
+        assert_eq!((word(&mut bus, frame), word(&mut bus, frame + 8), word(&mut bus, frame + 12)), (SIGSEGV, 1, 0xDEAD));
+        let uc = frame + SIGINFO_SIZE;
+        assert_eq!((word(&mut bus, uc + UC_SIGMASK), word(&mut bus, uc + UC_SIGMASK + 4)), (2, 1));
+        assert_eq!(word(&mut bus, uc + UC_STACK + 4), SS_DISABLE);
+        assert_eq!(word(&mut bus, uc + UC_MCONTEXT), 0x10400);
+        assert_eq!(word(&mut bus, uc + UC_MCONTEXT + 4 * 2), 0x1F7F3);
+        assert_eq!(word(&mut bus, uc + UC_MCONTEXT + 4 * 31), 0x11F);
+
+        // sigreturn restores what the handler left in the frame
+        bus.write(uc + UC_MCONTEXT, Width::Word, 0x10404).unwrap();
+        bus.write(uc + UC_MCONTEXT + 4 * 10, Width::Word, 42).unwrap();
+        assert_eq!(restoreFrame(&mut r, &mut bus), Ok(0x1_0000_0002));
+        assert_eq!((r.getPC(), r.getReg(2), r.getReg(10), r.getReg(11)), (0x10404, 0x1F7F3, 42, 0x10B));
+
+        // a frame that would wrap around the address space is never read or written
+        r.setReg(2, 0xFFFF_FF00);
+        assert_eq!(restoreFrame(&mut r, &mut bus), Err(BusError::Unmapped(0xFFFF_FF00)));
+        assert_eq!(r.getPC(), 0x10404);
+        r.setReg(2, 0x40);
+        assert!(setupFrame(&mut r, &mut bus, &info, &act, 0, &AltStack::default()).is_err());
+        assert_eq!((r.getPC(), r.getReg(2)), (0x10404, 0x40));
+    }
+
+    #[test]
+    fn test_altstack() {
+        let mut bus = bus();
+        let mut r = Register::new();
+        let info = SigInfo { signo: SIGBUS, code: BUS_ADRALN, addr: 0 };
+        let alt = AltStack { sp: 0x18000, flags: 0, size: 0x2000 };
+
+        // without SA_ONSTACK the frame goes on the current stack
+        r.setReg(2, 0x1F000);
+        setupFrame(&mut r, &mut bus, &info, &SigAction { handler: 0x10800, flags: 0, mask: 0 }, 0, &alt).unwrap();
+        assert!(r.getReg(2) < 0x1F000 && r.getReg(2) > 0x1E000);
+
+        // with it, on top of the alternate stack, unless the thread is already running on it
+        let act = SigAction { handler: 0x10800, flags: SA_ONSTACK, mask: 0 };
+        r.setReg(2, 0x1F000);
+        setupFrame(&mut r, &mut bus, &info, &act, 0, &alt).unwrap();
+        let frame = r.getReg(2);
+        assert!(frame < 0x1A000 && frame > 0x19000);
+        assert_eq!(word(&mut bus, frame + SIGINFO_SIZE + UC_STACK + 4), 0);
+        setupFrame(&mut r, &mut bus, &info, &act, 0, &alt).unwrap();
+        assert!(r.getReg(2) < frame);
+        assert_eq!(word(&mut bus, r.getReg(2) + SIGINFO_SIZE + UC_STACK + 4), SS_ONSTACK);
+
+        // a frame that does not fit is an error
+        r.setReg(2, 0x10100);
+        assert!(setupFrame(&mut r, &mut bus, &info, &SigAction::default(), 0, &alt).is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+use crate::bus::Bus;
+
+// Signatures of the RISC-V architectural tests (riscv-arch-test, run through RISCOF): the memory from
+// `begin_signature` up to `end_signature`, written at halt as one 32-bit word per line in hex, most
+// significant digit first, as Spike's +signature does. RISCOF compares it with the reference model's.
+
+#[derive(Debug)]
+pub struct Signature {
+    path        : String,
+    begin       : u32,
+    end         : u32,
+}
+
+impl Signature {
+    pub fn new(path: &str, symbols: &HashMap<String, u32>) -> Result<Signature, String> {
+        let symbol = |name: &str| symbols.get(name).copied().ok_or(format!("no {} symbol", name));
+        let (begin, end) = (symbol("begin_signature")?, symbol("end_signature")?);
+        if end < begin {
+            return Err(format!("end_signature (0x{:08x}) is below begin_signature (0x{:08x})", end, begin));
+        }
+        Ok(Signature { path: path.to_string(), begin: begin, end: end })
+    }
+
+    // The signature lines; a partial last word is written whole.
+    pub fn format(&self, bus: &mut Bus) -> Result<String, String> {
+        let len = ((self.end - self.begin + 3) & !3) as usize;
+        let data = bus.peek(self.begin, len).map_err(|e| format!("0x{:08x}: {}", self.begin, e))?;
+        Ok(data.chunks(4).map(|w| format!("{:08x}\n", u32::from_le_bytes([w[0], w[1], w[2], w[3]]))).collect())
+    }
+
+    pub fn write(&self, bus: &mut Bus) -> Result<(), String> {
+        let text = self.format(bus)?;
+        std::fs::write(&self.path, text).map_err(|e| format!("{}: {}", self.path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::{self, Memory};
+    use crate::signature::*;
+
+    #[test]
+    fn test_format() {
+        let mut bus = Bus::new();
+        bus.attach(memory::RAM_BASE, Box::new(Memory::new())).unwrap();
+        bus.poke(memory::RAM_BASE + 0x100, &[0xef, 0xbe, 0xad, 0xde, 0x01, 0x00]).unwrap();
+
+        let mut symbols = HashMap::new();
+        symbols.insert("begin_signature".to_string(), memory::RAM_BASE + 0x100);
+        symbols.insert("end_signature".to_string(), memory::RAM_BASE + 0x106);
+        let sig = Signature::new("-", &symbols).unwrap();
+        assert_eq!(sig.format(&mut bus).unwrap(), "deadbeef\n00000001\n");
+
+        symbols.remove("end_signature");
+        assert_eq!(Signature::new("-", &symbols).unwrap_err(), "no end_signature symbol");
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+
+use crate::asm;
+use crate::bus::{Bus, Device};
+use crate::elf;
+use crate::finisher;
+use crate::htif;
+use crate::isa::{self, Isa};
+use crate::memory::{self, Memory};
+use crate::uart;
+use crate::CPU;
+
+// Embedding API. A Simulator is a hart and its bus, put together by a SimulatorBuilder from a memory
+// map, an ISA and the programs to load, and driven with step() / run(limit), which say why execution
+// stopped:
+//
+//     let mut sim = Simulator::builder()
+//         .isa(Isa::parse("rv32im_zicsr")?)
+//         .ram(0x8000_0000, 1 << 20)
+//         .load("program.elf")
+//         .build()?;
+//     match sim.run(1_000_000) {
+//         StopReason::Halted(status)  => println!("exit {}", status),
+//         reason                      => println!("{:?} at 0x{:08x}", reason, sim.pc()),
+//     }
+//
+// The hart itself (tracing, semihosting, the debugger and the gdb stub) is reached through cpu().
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Halted(u32),                                // the program exited with this status
+    Breakpoint(u32),                            // the pc reached a breakpoint; that instruction has not executed
+    Trap { cause: u32, tval: u32, pc: u32 },    // a trap was taken at pc (only with stopOnTrap)
+    LimitReached,                               // the given number of steps ran
+}
+
+// A program to load, in the order given.
+enum Program {
+    File(String),               // ELF, assembly source (.s) or a raw image at the start of RAM
+    Image(String, Vec<u8>),     // the same, already read from the named file
+    Binary(u32, Vec<u8>),       // raw bytes at an address
+}
+
+pub struct SimulatorBuilder {
+    isa         : Isa,
+    ram         : Vec<(u32, usize)>,
+    devices     : Vec<(u32, Option<u32>, Box<dyn Device>)>,
+    uart        : Option<(u32, uart::HostPort)>,
+    finisher    : Option<u32>,
+    programs    : Vec<Program>,
+    entry       : Option<u32>,
+    stop_on_trap: bool,
+}
+
+impl SimulatorBuilder {
+    // The extensions of the hart (default: isa::DEFAULT).
+    pub fn isa(mut self, isa: Isa) -> Self {
+        self.isa = isa;
+        self
+    }
+
+    // RAM of `size` bytes at `base`. Without any, there is RAM_BASE with MEM_SIZE bytes.
+    pub fn ram(mut self, base: u32, size: usize) -> Self {
+        self.ram.push((base, size));
+        self
+    }
+
+    pub fn device(mut self, base: u32, dev: Box<dyn Device>) -> Self {
+        self.devices.push((base, None, dev));
+        self
+    }
+
+    // A device whose interrupt line is ORed into mip.MEIP.
+    pub fn deviceWithIrq(mut self, base: u32, irq: u32, dev: Box<dyn Device>) -> Self {
+        self.devices.push((base, Some(irq), dev));
+        self
+    }
+
+    // The 16550 UART at `base`, connected to `port` on the host.
+    pub fn uart(mut self, base: u32, port: uart::HostPort) -> Self {
+        self.uart = Some((base, port));
+        self
+    }
+
+    // The test finisher at `base`: a write to it halts the simulation.
+    pub fn finisher(mut self, base: u32) -> Self {
+        self.finisher = Some(base);
+        self
+    }
+
+    // A program file. ELF files are loaded segment by segment (and use HTIF if they have a tohost
+    // symbol); assembly source (.s) is assembled; anything else is a raw image at the start of RAM.
+    pub fn load(mut self, path: &str) -> Self {
+        self.programs.push(Program::File(path.to_string()));
+        self
+    }
+
+    // The same, with the contents of the file already read.
+    pub fn image(mut self, name: &str, image: Vec<u8>) -> Self {
+        self.programs.push(Program::Image(name.to_string(), image));
+        self
+    }
+
+    pub fn binary(mut self, addr: u32, data: Vec<u8>) -> Self {
+        self.programs.push(Program::Binary(addr, data));
+        self
+    }
+
+    // Where the hart starts (default: the entry point of the last program that has one, else the
+    // start of RAM).
+    pub fn entry(mut self, pc: u32) -> Self {
+        self.entry = Some(pc);
+        self
+    }
+
+    // Make run() / step() return StopReason::Trap whenever a trap is taken, instead of carrying on in
+    // the trap handler.
+    pub fn stopOnTrap(mut self, on: bool) -> Self {
+        self.stop_on_trap = on;
+        self
+    }
+
+    pub fn build(self) -> Result<Simulator, String> {
+        self.isa.check()?;
+        let ram = if self.ram.is_empty() { vec![(memory::RAM_BASE, memory::MEM_SIZE)] } else { self.ram };
+        let mut bus = Bus::new();
+        for (base, size) in &ram {
+            bus.attach(*base, Box::new(Memory::withSize(*size))).map_err(|e| format!("ram: {}", e))?;
+        }
+        for (base, irq, dev) in self.devices {
+            let name = dev.name().to_string();
+            let attached = match irq {
+                Some(irq)   => bus.attachWithIrq(base, irq, dev),
+                None        => bus.attach(base, dev),
+            };
+            attached.map_err(|e| format!("{}: {}", name, e))?;
+        }
+        if let Some((base, port)) = &self.uart {
+            let uart = uart::Uart::new(port).map_err(|e| format!("uart: {}", e))?;
+            bus.attachWithIrq(*base, uart::UART_IRQ, Box::new(uart)).map_err(|e| format!("uart: {}", e))?;
+        }
+        if let Some(base) = self.finisher {
+            bus.attach(base, Box::new(finisher::Finisher::new())).map_err(|e| format!("finisher: {}", e))?;
+        }
+
+        let mut entry = None;
+        let mut htif = None;
+        let mut symbols = HashMap::new();
+        for p in self.programs {
+            let (path, image) = match p {
+                Program::File(path)         => {
+                    let image = std::fs::read(&path).map_err(|e| format!("{}: {}", path, e))?;
+                    (path, image)
+                },
+                Program::Image(path, image) => (path, image),
+                Program::Binary(addr, data) => {
+                    bus.loadImage(addr, &data).map_err(|e| format!("0x{:08x}: {}", addr, e))?;
+                    continue;
+                },
+            };
+            if elf::isElf(&image) {
+                let e = elf::Elf::parse(image).map_err(|e| format!("{}: {}", path, e))?;
+                e.load(&mut bus).map_err(|e| format!("{}: {}", path, e))?;
+                entry = Some(e.entry);
+                htif = htif::Htif::fromElf(&e).or(htif);
+                symbols.extend(e.symbols.clone());
+            } else if path.ends_with(".s") || path.ends_with(".S") {
+                let p = asm::assemble(&String::from_utf8_lossy(&image)).map_err(|e| format!("{}: {}", path, e))?;
+                bus.loadImage(p.origin, &p.image).map_err(|e| format!("{}: {}", path, e))?;
+                entry = Some(p.entry);
+                symbols.extend(p.symbols);
+            } else {
+                bus.loadImage(ram[0].0, &image).map_err(|e| format!("{}: {}", path, e))?;
+            }
+        }
+
+        let mut cpu = CPU::withIsa(bus, self.entry.or(entry).unwrap_or(ram[0].0), self.isa);
+        if let Some(h) = htif {
+            cpu.setHtif(h);
+        }
+        Ok(Simulator {
+            cpu         : cpu,
+            symbols     : symbols,
+            breakpoints : vec![],
+            stop_on_trap: self.stop_on_trap,
+            halted      : None,
+            steps       : 0,
+        })
+    }
+}
+
+pub struct Simulator {
+    cpu         : CPU,
+    symbols     : HashMap<String, u32>,
+    breakpoints : Vec<u32>,
+    stop_on_trap: bool,
+    halted      : Option<u32>,
+    steps       : u64,
+}
+
+impl Simulator {
+    pub fn builder() -> SimulatorBuilder {
+        SimulatorBuilder {
+            isa         : Isa::parse(isa::DEFAULT).unwrap(),
+            ram         : vec![],
+            devices     : vec![],
+            uart        : None,
+            finisher    : None,
+            programs    : vec![],
+            entry       : None,
+            stop_on_trap: false,
+        }
+    }
+
+    // One step: an instruction, or taking an interrupt. LimitReached if nothing else happened.
+    pub fn step(&mut self) -> StopReason {
+        self.run(1)
+    }
+
+    // Up to `limit` steps. Once the program has halted, it stays halted.
+    pub fn run(&mut self, limit: u64) -> StopReason {
+        if let Some(status) = self.halted {
+            return StopReason::Halted(status);
+        }
+        for _ in 0..limit {
+            if let Some(status) = self.cpu.step() {
+                self.halted = Some(status);
+                return StopReason::Halted(status);
+            }
+            self.steps += 1;
+            if let Some((cause, tval, pc)) = self.cpu.takeTrap() {
+                if self.stop_on_trap {
+                    return StopReason::Trap { cause: cause, tval: tval, pc: pc };
+                }
+            }
+            let pc = self.cpu.registers().getPC();
+            if self.breakpoints.contains(&pc) {
+                return StopReason::Breakpoint(pc);
+            }
+        }
+        StopReason::LimitReached
+    }
+
+    // Steps run so far (not counting the one that found the program halted).
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.cpu.registers().getPC()
+    }
+
+    pub fn setPC(&mut self, pc: u32) {
+        self.cpu.register().setPC(pc);
+    }
+
+    pub fn reg(&self, idx: u32) -> u32 {
+        self.cpu.registers().getReg(idx)
+    }
+
+    pub fn setReg(&mut self, idx: u32, value: u32) {
+        self.cpu.register().setReg(idx, value);
+    }
+
+    // CSRs as they are held, without permission checks or WARL legalisation.
+    pub fn csr(&self, addr: u32) -> u32 {
+        self.cpu.csrs().readCsr(addr)
+    }
+
+    pub fn setCsr(&mut self, addr: u32, value: u32) {
+        self.cpu.csr().writeCsr(addr, value);
+    }
+
+    // Memory as the hart sees it; no watchpoints trigger and written code is seen by instruction
+    // fetch.
+    pub fn readMemory(&mut self, addr: u32, len: usize) -> Result<Vec<u8>, String> {
+        self.cpu.bus().peek(addr, len).map_err(|e| e.to_string())
+    }
+
+    pub fn writeMemory(&mut self, addr: u32, data: &[u8]) -> Result<(), String> {
+        self.cpu.bus().poke(addr, data).map_err(|e| e.to_string())
+    }
+
+    // Symbols of the loaded ELF files and assembly sources.
+    pub fn symbols(&self) -> &HashMap<String, u32> {
+        &self.symbols
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<u32> {
+        self.symbols.get(name).copied()
+    }
+
+    pub fn addBreakpoint(&mut self, addr: u32) {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
+    }
+
+    pub fn removeBreakpoint(&mut self, addr: u32) -> bool {
+        let before = self.breakpoints.len();
+        self.breakpoints.retain(|&b| b != addr);
+        self.breakpoints.len() != before
+    }
+
+    pub fn cpu(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::csr;
+    use crate::simulator::*;
+
+    fn machine(src: &str) -> SimulatorBuilder {
+        Simulator::builder().ram(memory::RAM_BASE, 0x10000).image("test.s", src.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_run() {
+        let src = "
+            li   a0, 0
+            li   a1, 10
+        loop:
+            addi a0, a0, 1
+            bne  a0, a1, loop
+        done:
+            li   t0, 0x5555
+            li   t1, 0x100000
+            sw   t0, 0(t1)
+        ";
+        let mut sim = machine(src).finisher(finisher::FINISHER_BASE).build().unwrap();
+        assert_eq!(sim.step(), StopReason::LimitReached);
+        assert_eq!(sim.pc(), memory::RAM_BASE + 4);
+
+        let done = sim.symbol("done").unwrap();
+        sim.addBreakpoint(done);
+        assert_eq!(sim.run(1000), StopReason::Breakpoint(done));
+        assert_eq!(sim.reg(10), 10);
+        assert!(sim.removeBreakpoint(done));
+
+        assert_eq!(sim.run(2), StopReason::LimitReached);
+        assert_eq!(sim.run(1000), StopReason::Halted(0));
+        assert_eq!(sim.run(1000), StopReason::Halted(0));
+        assert_eq!(sim.steps(), 2 + 2 * 10 + 3 + 1);
+    }
+
+    #[test]
+    fn test_trap_and_memory() {
+        let mut sim = machine("mul a0, a1, a2\nlw a0, 0(a3)\n").isa(Isa::parse("rv32i_zicsr").unwrap()).stopOnTrap(true).build().unwrap();
+        assert_eq!(sim.csr(csr::MISA), 0x4000_0100);
+        assert_eq!(sim.step(), StopReason::Trap { cause: 2, tval: 0x02c5_8533, pc: memory::RAM_BASE });
+        // the read-only accessors need no mutable borrow
+        let state = |s: &Simulator| (s.pc(), s.reg(10), s.csr(csr::MEPC));
+        assert_eq!(state(&sim), (0, 0, memory::RAM_BASE));
+
+        sim.setPC(memory::RAM_BASE + 4);
+        sim.setReg(13, memory::RAM_BASE + 0x100);
+        sim.writeMemory(memory::RAM_BASE + 0x100, &[0x78, 0x56, 0x34, 0x12]).unwrap();
+        assert_eq!(sim.step(), StopReason::LimitReached);
+        assert_eq!(sim.reg(10), 0x1234_5678);
+        assert_eq!(sim.readMemory(memory::RAM_BASE + 0x102, 2).unwrap(), vec![0x34, 0x12]);
+        assert!(sim.readMemory(0, 4).is_err());
+
+        assert!(Simulator::builder().isa(Isa::parse("rv32imc").unwrap()).build().is_err());
+    }
+}
@@ -0,0 +1,1377 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, IsTerminal, Read, SeekFrom, Write};
+use std::os::unix::ffi::OsStringExt;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::bus::{Bus, BusError, Width};
+use crate::core::Exception;
+use crate::csr;
+use crate::elf::Elf;
+use crate::isa::Isa;
+use crate::memory;
+use crate::register::Register;
+use crate::signal::{self, AltStack, SigAction, SigInfo};
+use crate::vfs::{OpenFlags, Stat, VFile, Vfs};
+
+// Address space of an emulated user process. There is no MMU, so guest virtual addresses are bus
+// addresses: one RAM region covers [USER_BASE, USER_END). Page 0 is left unmapped so that NULL
+// dereferences fault.
+pub const USER_BASE         : u32 = 0x0000_1000;
+pub const USER_END          : u32 = 0x8000_0000;
+pub const STACK_TOP         : u32 = 0x7FFF_0000;
+pub const STACK_SIZE        : u32 = 8 << 20;
+pub const PAGE_SIZE         : u32 = 4096;
+const MMAP_TOP              : u32 = STACK_TOP - STACK_SIZE;
+
+// Largest buffer the host allocates for one transfer. read, write and getrandom return short
+// counts beyond it, and file mappings are copied in pieces of this size.
+const IO_CHUNK              : u32 = 64 * 1024;
+
+// c.f., include/uapi/asm-generic/unistd.h. riscv32 only has the 64-bit off_t / time64 variants, so
+// e.g. lseek is _llseek, mmap is mmap2 and clock_gettime is clock_gettime64.
+const SYS_GETCWD            : u32 = 17;
+const SYS_IOCTL             : u32 = 29;
+const SYS_MKDIRAT           : u32 = 34;
+const SYS_UNLINKAT          : u32 = 35;
+const SYS_FACCESSAT         : u32 = 48;
+const SYS_CHDIR             : u32 = 49;
+const SYS_OPENAT            : u32 = 56;
+const SYS_CLOSE             : u32 = 57;
+const SYS_LLSEEK            : u32 = 62;
+const SYS_READ              : u32 = 63;
+const SYS_WRITE             : u32 = 64;
+const SYS_READV             : u32 = 65;
+const SYS_WRITEV            : u32 = 66;
+const SYS_READLINKAT        : u32 = 78;
+const SYS_FSTATAT64         : u32 = 79;
+const SYS_FSTAT64           : u32 = 80;
+const SYS_EXIT              : u32 = 93;
+const SYS_EXIT_GROUP        : u32 = 94;
+const SYS_SET_TID_ADDRESS   : u32 = 96;
+const SYS_FUTEX             : u32 = 98;
+const SYS_SET_ROBUST_LIST   : u32 = 99;
+const SYS_SCHED_YIELD       : u32 = 124;
+const SYS_KILL              : u32 = 129;
+const SYS_TKILL             : u32 = 130;
+const SYS_TGKILL            : u32 = 131;
+const SYS_SIGALTSTACK       : u32 = 132;
+const SYS_RT_SIGACTION      : u32 = 134;
+const SYS_RT_SIGPROCMASK    : u32 = 135;
+const SYS_UNAME             : u32 = 160;
+const SYS_GETPID            : u32 = 172;
+const SYS_GETPPID           : u32 = 173;
+const SYS_GETUID            : u32 = 174;
+const SYS_GETEUID           : u32 = 175;
+const SYS_GETGID            : u32 = 176;
+const SYS_GETEGID           : u32 = 177;
+const SYS_GETTID            : u32 = 178;
+const SYS_BRK               : u32 = 214;
+const SYS_MUNMAP            : u32 = 215;
+const SYS_CLONE             : u32 = 220;
+const SYS_MMAP2             : u32 = 222;
+const SYS_MPROTECT          : u32 = 226;
+const SYS_MADVISE           : u32 = 233;
+const SYS_PRLIMIT64         : u32 = 261;
+const SYS_GETRANDOM         : u32 = 278;
+const SYS_STATX             : u32 = 291;
+const SYS_CLOCK_GETTIME64   : u32 = 403;
+const SYS_CLOCK_NANOSLEEP64 : u32 = 407;
+const SYS_FUTEX_TIME64      : u32 = 422;
+const SYS_CLONE3            : u32 = 435;
+
+// c.f., include/uapi/asm-generic/errno-base.h
+const EPERM                 : i32 = 1;
+const ESRCH                 : i32 = 3;
+const EINTR                 : i32 = 4;
+const EIO                   : i32 = 5;
+const EBADF                 : i32 = 9;
+const EAGAIN                : i32 = 11;
+const ENOMEM                : i32 = 12;
+const EFAULT                : i32 = 14;
+const EINVAL                : i32 = 22;
+const ENOTTY                : i32 = 25;
+const ESPIPE                : i32 = 29;
+const ERANGE                : i32 = 34;
+const ENOSYS                : i32 = 38;
+const ETIMEDOUT             : i32 = 110;
+
+// c.f., include/uapi/asm-generic/fcntl.h
+const O_ACCMODE             : u32 = 0o3;
+const O_WRONLY              : u32 = 0o1;
+const O_RDWR                : u32 = 0o2;
+const O_CREAT               : u32 = 0o100;
+const O_EXCL                : u32 = 0o200;
+const O_TRUNC               : u32 = 0o1000;
+const O_APPEND              : u32 = 0o2000;
+const AT_FDCWD              : i32 = -100;
+const AT_SYMLINK_NOFOLLOW   : u32 = 0x100;
+const AT_REMOVEDIR          : u32 = 0x200;
+const AT_EMPTY_PATH         : u32 = 0x1000;
+
+// c.f., include/uapi/asm-generic/mman-common.h
+const MAP_FIXED             : u32 = 0x10;
+const MAP_ANONYMOUS         : u32 = 0x20;
+
+// c.f., include/uapi/linux/sched.h
+const CLONE_VM              : u32 = 0x0000_0100;
+const CLONE_THREAD          : u32 = 0x0001_0000;
+const CLONE_SETTLS          : u32 = 0x0008_0000;
+const CLONE_PARENT_SETTID   : u32 = 0x0010_0000;
+const CLONE_CHILD_CLEARTID  : u32 = 0x0020_0000;
+const CLONE_CHILD_SETTID    : u32 = 0x0100_0000;
+
+// c.f., include/uapi/linux/futex.h (the PRIVATE and CLOCK_REALTIME flags make no difference here)
+const FUTEX_CMD_MASK        : u32 = 0x7F;
+const FUTEX_WAIT            : u32 = 0;
+const FUTEX_WAKE            : u32 = 1;
+const FUTEX_REQUEUE         : u32 = 3;
+const FUTEX_CMP_REQUEUE     : u32 = 4;
+const FUTEX_WAIT_BITSET     : u32 = 9;
+const FUTEX_WAKE_BITSET     : u32 = 10;
+
+// Instructions a thread runs before the scheduler switches to the next runnable one. Switching only
+// happens at fixed instruction counts and blocking syscalls, so runs are reproducible.
+const QUANTUM               : u32 = 10_000;
+
+const TCGETS                : u32 = 0x5401;
+const TIOCGWINSZ            : u32 = 0x5413;
+const RLIMIT_STACK          : u32 = 3;
+
+// rt_sigprocmask
+const SIG_BLOCK             : u32 = 0;
+const SIG_UNBLOCK           : u32 = 1;
+const SIG_SETMASK           : u32 = 2;
+const MINSIGSTKSZ           : u32 = 2048;
+
+// Process id, which is also the thread id of the main thread.
+const PID                   : u32 = 1000;
+
+#[derive(Debug)]
+enum FileDesc {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(VFile, String),    // with its guest path, for *at() calls relative to a directory fd
+}
+
+// A thread blocked in FUTEX_WAIT.
+#[derive(Debug)]
+struct Wait {
+    addr        : u32,
+    bitset      : u32,
+    timeout     : bool,
+}
+
+// Guest thread. All threads share the bus; the registers of the running thread live in the CPU and
+// `reg` holds them while the thread is switched out.
+#[derive(Debug)]
+struct Thread {
+    tid             : u32,
+    reg             : Register,
+    clear_child_tid : u32,
+    wait            : Option<Wait>,
+    mask            : u64,              // blocked signals
+    pending         : Vec<SigInfo>,
+    altstack        : AltStack,
+}
+
+impl Thread {
+    fn new(tid: u32, reg: Register, mask: u64) -> Thread {
+        Thread {
+            tid             : tid,
+            reg             : reg,
+            clear_child_tid : 0,
+            wait            : None,
+            mask            : mask,
+            pending         : vec![],
+            altstack        : AltStack::default(),
+        }
+    }
+}
+
+// Linux system call emulation for statically linked riscv32 programs running in U-mode. Guest file
+// descriptors are backed by files of the guest filesystem (see vfs.rs) and failures are reported as
+// -errno in a0, like the kernel.
+#[derive(Debug)]
+pub struct Linux {
+    vfs         : Vfs,
+    fds         : HashMap<i32, FileDesc>,
+    brk_start   : u32,
+    brk         : u32,
+    mmap_top    : u32,
+    start       : Instant,
+    strace      : bool,
+    threads     : Vec<Thread>,
+    current     : usize,
+    next_tid    : u32,
+    slice       : u32,
+    resched     : bool,
+    actions     : [SigAction; signal::NSIG as usize],  // indexed by signo - 1, shared by all threads
+}
+
+// Auxiliary vector passed to a new process. AT_HWCAP has one bit per single-letter extension of the
+// hart, as in misa.
+pub fn auxv(e: &Elf, isa: &Isa) -> Vec<(u32, u32)> {
+    let mut v = vec![];
+    if let Some(phdr) = e.phdrAddr() {
+        v.push((memory::AT_PHDR, phdr));
+    }
+    v.extend([
+        (memory::AT_PHENT,  e.phentsize as u32),
+        (memory::AT_PHNUM,  e.phnum as u32),
+        (memory::AT_PAGESZ, PAGE_SIZE),
+        (memory::AT_ENTRY,  e.entry),
+        (memory::AT_UID,    0),
+        (memory::AT_EUID,   0),
+        (memory::AT_GID,    0),
+        (memory::AT_EGID,   0),
+        (memory::AT_HWCAP,  isa.misa() & csr::MISA_EXT),
+        (memory::AT_CLKTCK, 100),
+        (memory::AT_SECURE, 0),
+        (memory::AT_RANDOM, 0),
+        (memory::AT_EXECFN, 0),
+    ]);
+    v
+}
+
+// Seed for AT_RANDOM (used by libc for the stack protector and pointer mangling).
+pub fn random() -> [u8; 16] {
+    let mut r = [0u8; 16];
+    let _ = fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut r));
+    r
+}
+
+fn fault(_: BusError) -> i32 {
+    EFAULT
+}
+
+// Host errno values are the asm-generic ones on Linux, so they can be passed through unchanged.
+fn errno(e: io::Error) -> i32 {
+    e.raw_os_error().unwrap_or(EIO)
+}
+
+// The guest buffer [addr, addr + len) must lie in user memory. Checked before the host allocates
+// anything for it.
+fn userRange(addr: u32, len: u32) -> Result<(), i32> {
+    if addr < USER_BASE || addr.checked_add(len).is_none_or(|end| end > USER_END) {
+        return Err(EFAULT);
+    }
+    Ok(())
+}
+
+fn pageAlign(v: u32) -> u32 {
+    (v.wrapping_add(PAGE_SIZE - 1)) & !(PAGE_SIZE - 1)
+}
+
+fn writeWord(bus: &mut Bus, addr: u32, v: u32) -> Result<(), i32> {
+    bus.write(addr, Width::Word, v).map_err(fault)
+}
+
+fn readWord(bus: &mut Bus, addr: u32) -> Result<u32, i32> {
+    bus.read(addr, Width::Word).map_err(fault)
+}
+
+impl Linux {
+    // `end` is the end of the loaded program, where the heap (program break) starts.
+    pub fn new(end: u32, vfs: Vfs) -> Linux {
+        let mut fds = HashMap::new();
+        fds.insert(0, FileDesc::Stdin);
+        fds.insert(1, FileDesc::Stdout);
+        fds.insert(2, FileDesc::Stderr);
+        Linux {
+            vfs         : vfs,
+            fds         : fds,
+            brk_start   : pageAlign(end),
+            brk         : pageAlign(end),
+            mmap_top    : MMAP_TOP,
+            start       : Instant::now(),
+            strace      : false,
+            threads     : vec![Thread::new(PID, Register::new(), 0)],
+            current     : 0,
+            next_tid    : PID + 1,
+            slice       : 0,
+            resched     : false,
+            actions     : [SigAction::default(); signal::NSIG as usize],
+        }
+    }
+
+    // Print every system call and its result to stderr.
+    pub fn setStrace(&mut self, on: bool) {
+        self.strace = on;
+    }
+
+    // Handle an ECALL from U-mode: the syscall number is in a7, the arguments in a0-a5 and the result
+    // is returned in a0. The pc is advanced past the ECALL here, because the call may switch to another
+    // thread (r then holds that thread's registers). Returns the exit status once the process has
+    // exited.
+    pub fn dispatch(&mut self, r: &mut Register, bus: &mut Bus) -> Option<u32> {
+        let nr = r.getReg(17);
+        let a: Vec<u32> = (10..16).map(|i| r.getReg(i)).collect();
+        let tid = self.threads[self.current].tid;
+
+        let result = match nr {
+            SYS_EXIT_GROUP              => {
+                if self.strace {
+                    eprintln!("[strace {}] exit_group({})", tid, a[0] as i32);
+                }
+                let _ = io::stdout().flush();
+                return Some(a[0] & 0xFF);
+            },
+            SYS_EXIT                    => {
+                if self.strace {
+                    eprintln!("[strace {}] exit({})", tid, a[0] as i32);
+                }
+                return self.exitThread(r, bus, a[0]);
+            },
+            signal::SYS_RT_SIGRETURN    => {
+                if self.strace {
+                    eprintln!("[strace {}] rt_sigreturn()", tid);
+                }
+                return match signal::restoreFrame(r, bus) {
+                    Ok(mask)    => {
+                        self.threads[self.current].mask = mask & !(signal::bit(signal::SIGKILL) | signal::bit(signal::SIGSTOP));
+                        self.deliverPending(r, bus)
+                    },
+                    Err(_)      => self.terminate(r, signal::SIGSEGV),
+                };
+            },
+            SYS_CLONE                   => self.clone(r, bus, a[0], a[1], a[2], a[3], a[4]),
+            SYS_CLONE3                  => Err(ENOSYS),  // libc falls back to clone
+            SYS_FUTEX | SYS_FUTEX_TIME64 => self.futex(bus, a[0], a[1], a[2], a[3], a[4], a[5]),
+            SYS_SET_TID_ADDRESS         => {
+                self.threads[self.current].clear_child_tid = a[0];
+                Ok(tid)
+            },
+            SYS_GETTID                  => Ok(tid),
+            SYS_SCHED_YIELD             => {
+                self.resched = true;
+                Ok(0)
+            },
+            SYS_READ                    => self.read(bus, a[0] as i32, a[1], a[2]),
+            SYS_WRITE                   => self.write(bus, a[0] as i32, a[1], a[2]),
+            SYS_READV                   => self.readv(bus, a[0] as i32, a[1], a[2]),
+            SYS_WRITEV                  => self.writev(bus, a[0] as i32, a[1], a[2]),
+            SYS_OPENAT                  => self.openat(bus, a[0] as i32, a[1], a[2], a[3]),
+            SYS_CLOSE                   => self.close(a[0] as i32),
+            SYS_LLSEEK                  => self.llseek(bus, a[0] as i32, ((a[1] as u64) << 32) | a[2] as u64, a[3], a[4]),
+            SYS_FSTAT64                 => self.fstatat(bus, a[0] as i32, 0, a[1], AT_EMPTY_PATH, false),
+            SYS_FSTATAT64               => self.fstatat(bus, a[0] as i32, a[1], a[2], a[3], false),
+            SYS_STATX                   => self.fstatat(bus, a[0] as i32, a[1], a[4], a[2], true),
+            SYS_IOCTL                   => self.ioctl(bus, a[0] as i32, a[1], a[2]),
+            SYS_GETCWD                  => self.getcwd(bus, a[0], a[1]),
+            SYS_READLINKAT              => self.readlinkat(bus, a[0] as i32, a[1], a[2], a[3]),
+            SYS_CHDIR                   => self.path(bus, AT_FDCWD, a[0]).and_then(|p| self.vfs.chdir(&p).map(|_| 0).map_err(errno)),
+            SYS_FACCESSAT               => self.path(bus, a[0] as i32, a[1]).and_then(|p| self.vfs.stat(&p, true).map(|_| 0).map_err(errno)),
+            SYS_MKDIRAT                 => self.path(bus, a[0] as i32, a[1]).and_then(|p| self.vfs.mkdir(&p, a[2]).map(|_| 0).map_err(errno)),
+            SYS_UNLINKAT                => self.path(bus, a[0] as i32, a[1]).and_then(|p| {
+                if a[2] & AT_REMOVEDIR != 0 { self.vfs.rmdir(&p) } else { self.vfs.unlink(&p) }.map(|_| 0).map_err(errno)
+            }),
+            SYS_BRK                     => Ok(self.brk(bus, a[0])),
+            SYS_MMAP2                   => self.mmap(bus, a[0], a[1], a[3], a[4] as i32, a[5]),
+            SYS_MUNMAP                  => Ok(0),  // see mmap()
+            SYS_MPROTECT | SYS_MADVISE  => Ok(0),
+            SYS_CLOCK_GETTIME64         => self.clockGettime(bus, a[0], a[1]),
+            SYS_CLOCK_NANOSLEEP64       => self.nanosleep(bus, a[2]),
+            SYS_GETRANDOM               => self.getrandom(bus, a[0], a[1]),
+            SYS_UNAME                   => self.uname(bus, a[0]),
+            SYS_PRLIMIT64               => self.prlimit(bus, a[1], a[3]),
+            SYS_GETPID                  => Ok(PID),
+            SYS_GETPPID                 => Ok(1),
+            SYS_GETUID | SYS_GETEUID | SYS_GETGID | SYS_GETEGID => Ok(0),
+            SYS_RT_SIGACTION            => self.sigaction(bus, a[0], a[1], a[2], a[3]),
+            SYS_RT_SIGPROCMASK          => self.sigprocmask(bus, a[0], a[1], a[2], a[3]),
+            SYS_SIGALTSTACK             => self.sigaltstack(r, bus, a[0], a[1]),
+            SYS_KILL                    => match a[0] as i32 {
+                0 | -1                  => self.kill(PID, a[1], signal::SI_USER),
+                pid                     => self.kill(pid as u32, a[1], signal::SI_USER),
+            },
+            SYS_TKILL                   => self.kill(a[0], a[1], signal::SI_TKILL),
+            SYS_TGKILL if a[0] == PID   => self.kill(a[1], a[2], signal::SI_TKILL),
+            SYS_TGKILL                  => Err(ESRCH),
+            SYS_SET_ROBUST_LIST         => Ok(0),
+            _                           => Err(ENOSYS),
+        };
+
+        let ret = match result {
+            Ok(v)   => v,
+            Err(e)  => (-e) as u32,
+        };
+        if self.strace {
+            eprintln!("[strace {}] syscall {}({:#x}, {:#x}, {:#x}, {:#x}) = {}", tid, nr, a[0], a[1], a[2], a[3], ret as i32);
+        }
+        r.setReg(10, ret);
+        r.incPC();
+        if std::mem::take(&mut self.resched) {
+            if let Some(status) = self.switchThread(r) {
+                return Some(status);
+            }
+        }
+        self.deliverPending(r, bus)
+    }
+
+    // A synchronous exception in the guest becomes the matching signal (c.f., arch/riscv/kernel/traps.c).
+    // The saved pc is that of the faulting instruction, so a handler that returns retries it.
+    pub fn exception(&mut self, r: &mut Register, bus: &mut Bus, e: Exception, tval: u32) -> Option<u32> {
+        let (signo, code, addr) = match e {
+            Exception::IllegalInstruction       => (signal::SIGILL, signal::ILL_ILLOPC, r.getPC()),
+            Exception::Breakpoint               => (signal::SIGTRAP, signal::TRAP_BRKPT, r.getPC()),
+            Exception::InstructionAddressMisaligned |
+            Exception::LoadAddressMisaligned |
+            Exception::StoreAddressMisaligned   => (signal::SIGBUS, signal::BUS_ADRALN, tval),
+            _                                   => (signal::SIGSEGV, signal::SEGV_MAPERR, tval),
+        };
+        if self.strace {
+            eprintln!("[strace {}] --- {} {{si_code={}, si_addr={:#x}}} ---", self.threads[self.current].tid, signal::name(signo), code, addr);
+        }
+        self.deliver(r, bus, SigInfo { signo: signo, code: code, addr: addr }, true)
+    }
+
+    // Deliver the first pending signal the running thread does not block. Called whenever control
+    // returns to the guest after a syscall or a thread switch.
+    pub fn deliverPending(&mut self, r: &mut Register, bus: &mut Bus) -> Option<u32> {
+        let t = &mut self.threads[self.current];
+        let i = t.pending.iter().position(|s| t.mask & signal::bit(s.signo) == 0)?;
+        let info = t.pending.remove(i);
+        self.deliver(r, bus, info, false)
+    }
+
+    // Run the disposition of a signal: ignore it, terminate the process, or enter the handler. A
+    // `forced` (fault) signal that is blocked or ignored kills the process, as the kernel does.
+    fn deliver(&mut self, r: &mut Register, bus: &mut Bus, info: SigInfo, forced: bool) -> Option<u32> {
+        let act = self.actions[info.signo as usize - 1];
+        let mask = self.threads[self.current].mask;
+        if forced && (mask & signal::bit(info.signo) != 0 || act.handler == signal::SIG_IGN) {
+            return self.terminate(r, info.signo);
+        }
+        match act.handler {
+            signal::SIG_DFL if signal::ignoredByDefault(info.signo) => None,
+            signal::SIG_DFL                 => self.terminate(r, info.signo),
+            signal::SIG_IGN                 => None,
+            _                               => {
+                let alt = self.threads[self.current].altstack;
+                if signal::setupFrame(r, bus, &info, &act, mask, &alt).is_err() {
+                    return self.terminate(r, signal::SIGSEGV);
+                }
+                let mut block = act.mask;
+                if act.flags & signal::SA_NODEFER == 0 {
+                    block |= signal::bit(info.signo);
+                }
+                self.threads[self.current].mask = (mask | block) & !(signal::bit(signal::SIGKILL) | signal::bit(signal::SIGSTOP));
+                if act.flags & signal::SA_RESETHAND != 0 {
+                    self.actions[info.signo as usize - 1] = SigAction::default();
+                }
+                None
+            },
+        }
+    }
+
+    // Death by signal. The exit status is the one a shell would report, 128 + signo.
+    fn terminate(&mut self, r: &Register, signo: u32) -> Option<u32> {
+        let _ = io::stdout().flush();
+        eprintln!("uncaught signal {} ({}) at pc 0x{:08x}", signo, signal::name(signo), r.getPC());
+        Some(128 + signo)
+    }
+
+    // Queue a signal for thread `tid` (or, for the process id, the main thread). A thread sleeping in
+    // futex wait is interrupted with EINTR if it does not block the signal.
+    fn kill(&mut self, tid: u32, signo: u32, code: i32) -> Result<u32, i32> {
+        if signo > signal::NSIG {
+            return Err(EINVAL);
+        }
+        let t = match self.threads.iter_mut().find(|t| t.tid == tid) {
+            Some(t) => t,
+            None    => return Err(ESRCH),
+        };
+        if signo == 0 {
+            return Ok(0);
+        }
+        // standard signals do not queue up
+        if signo >= 32 || !t.pending.iter().any(|s| s.signo == signo) {
+            t.pending.push(SigInfo { signo: signo, code: code, addr: PID });
+        }
+        if t.mask & signal::bit(signo) == 0 && t.wait.take().is_some() {
+            t.reg.setReg(10, (-EINTR) as u32);
+        }
+        Ok(0)
+    }
+
+    // rt_sigaction(signum, act, oldact, sigsetsize)
+    fn sigaction(&mut self, bus: &mut Bus, signo: u32, act: u32, oldact: u32, size: u32) -> Result<u32, i32> {
+        if size != 8 || signo == 0 || signo > signal::NSIG {
+            return Err(EINVAL);
+        }
+        let new = if act != 0 {
+            if signo == signal::SIGKILL || signo == signal::SIGSTOP {
+                return Err(EINVAL);
+            }
+            Some(SigAction::read(bus, act).map_err(fault)?)
+        } else {
+            None
+        };
+        if oldact != 0 {
+            self.actions[signo as usize - 1].write(bus, oldact).map_err(fault)?;
+        }
+        if let Some(a) = new {
+            self.actions[signo as usize - 1] = a;
+            // setting SIG_IGN discards what is already pending
+            if a.handler == signal::SIG_IGN || (a.handler == signal::SIG_DFL && signal::ignoredByDefault(signo)) {
+                for t in self.threads.iter_mut() {
+                    t.pending.retain(|s| s.signo != signo);
+                }
+            }
+        }
+        Ok(0)
+    }
+
+    // rt_sigprocmask(how, set, oldset, sigsetsize). SIGKILL and SIGSTOP cannot be blocked.
+    fn sigprocmask(&mut self, bus: &mut Bus, how: u32, set: u32, oldset: u32, size: u32) -> Result<u32, i32> {
+        if size != 8 {
+            return Err(EINVAL);
+        }
+        let old = self.threads[self.current].mask;
+        let new = if set != 0 {
+            let v = readWord(bus, set)? as u64 | (readWord(bus, set + 4)? as u64) << 32;
+            match how {
+                SIG_BLOCK       => old | v,
+                SIG_UNBLOCK     => old & !v,
+                SIG_SETMASK     => v,
+                _               => return Err(EINVAL),
+            }
+        } else {
+            old
+        };
+        if oldset != 0 {
+            writeWord(bus, oldset, old as u32)?;
+            writeWord(bus, oldset + 4, (old >> 32) as u32)?;
+        }
+        self.threads[self.current].mask = new & !(signal::bit(signal::SIGKILL) | signal::bit(signal::SIGSTOP));
+        Ok(0)
+    }
+
+    // sigaltstack(ss, old_ss). The stack cannot be changed while a handler is running on it.
+    fn sigaltstack(&mut self, r: &Register, bus: &mut Bus, ss: u32, old: u32) -> Result<u32, i32> {
+        let sp = r.getReg(2);
+        let cur = self.threads[self.current].altstack;
+        let new = if ss != 0 {
+            let a = AltStack {
+                sp      : readWord(bus, ss)?,
+                flags   : readWord(bus, ss + 4)?,
+                size    : readWord(bus, ss + 8)?,
+            };
+            if cur.contains(sp) {
+                return Err(EPERM);
+            }
+            match a.flags {
+                0 | signal::SS_ONSTACK if a.size < MINSIGSTKSZ => return Err(ENOMEM),
+                0 | signal::SS_ONSTACK  => Some(AltStack { flags: 0, ..a }),
+                signal::SS_DISABLE      => Some(AltStack::default()),
+                _                       => return Err(EINVAL),
+            }
+        } else {
+            None
+        };
+        if old != 0 {
+            writeWord(bus, old, cur.sp)?;
+            writeWord(bus, old + 4, if cur.contains(sp) { signal::SS_ONSTACK } else { cur.flags })?;
+            writeWord(bus, old + 8, cur.size)?;
+        }
+        if let Some(a) = new {
+            self.threads[self.current].altstack = a;
+        }
+        Ok(0)
+    }
+
+    // Called after every instruction: preempts the running thread once its time slice is used up.
+    // Returns true if another thread was switched in.
+    pub fn tick(&mut self, r: &mut Register) -> bool {
+        self.slice += 1;
+        if self.slice < QUANTUM || self.threads.len() < 2 {
+            return false;
+        }
+        let before = self.current;
+        self.switchThread(r);
+        self.current != before
+    }
+
+    // The first runnable thread at or after `from` (round-robin). If every thread is blocked, a
+    // waiter with a timeout is woken with ETIMEDOUT, as if its time had run out.
+    fn pick(&mut self, from: usize) -> Option<usize> {
+        let n = self.threads.len();
+        let order: Vec<usize> = (0..n).map(|i| (from + i) % n).collect();
+        if let Some(&i) = order.iter().find(|&&i| self.threads[i].wait.is_none()) {
+            return Some(i);
+        }
+        let i = *order.iter().find(|&&i| self.threads[i].wait.as_ref().is_some_and(|w| w.timeout))?;
+        self.threads[i].wait = None;
+        self.threads[i].reg.setReg(10, (-ETIMEDOUT) as u32);
+        Some(i)
+    }
+
+    // Save the running thread and continue with the next runnable one. If none is left the process
+    // could never make progress again, so it is terminated.
+    fn switchThread(&mut self, r: &mut Register) -> Option<u32> {
+        self.threads[self.current].reg = r.clone();
+        let next = (self.current + 1) % self.threads.len();
+        self.load(r, next)
+    }
+
+    fn load(&mut self, r: &mut Register, from: usize) -> Option<u32> {
+        match self.pick(from) {
+            Some(i) => {
+                self.current = i;
+                self.slice = 0;
+                *r = self.threads[i].reg.clone();
+                None
+            },
+            None    => {
+                eprintln!("deadlock: all {} threads are blocked in futex wait", self.threads.len());
+                Some(1)
+            },
+        }
+    }
+
+    // clone(flags, newsp, parent_tid, tls, child_tid). Only threads (CLONE_VM | CLONE_THREAD) are
+    // supported; there is a single address space.
+    #[allow(clippy::too_many_arguments)]
+    fn clone(&mut self, r: &Register, bus: &mut Bus, flags: u32, sp: u32, ptid: u32, tls: u32, ctid: u32) -> Result<u32, i32> {
+        if flags & (CLONE_VM | CLONE_THREAD) != (CLONE_VM | CLONE_THREAD) {
+            return Err(ENOSYS);
+        }
+        let tid = self.next_tid;
+        let mut child = r.clone();
+        child.setReg(10, 0);
+        child.setPC(r.getPC().wrapping_add(4));
+        if sp != 0 {
+            child.setReg(2, sp);
+        }
+        if flags & CLONE_SETTLS != 0 {
+            child.setReg(4, tls);
+        }
+        if flags & CLONE_PARENT_SETTID != 0 {
+            writeWord(bus, ptid, tid)?;
+        }
+        if flags & CLONE_CHILD_SETTID != 0 {
+            writeWord(bus, ctid, tid)?;
+        }
+        let clear = if flags & CLONE_CHILD_CLEARTID != 0 { ctid } else { 0 };
+        self.next_tid += 1;
+        let mut thread = Thread::new(tid, child, self.threads[self.current].mask);
+        thread.clear_child_tid = clear;
+        self.threads.push(thread);
+        Ok(tid)
+    }
+
+    // Thread exit: clear_child_tid is zeroed and woken (this is how pthread_join waits), then the
+    // next thread runs. The process ends with the last thread.
+    fn exitThread(&mut self, r: &mut Register, bus: &mut Bus, status: u32) -> Option<u32> {
+        let clear = self.threads[self.current].clear_child_tid;
+        if clear != 0 && writeWord(bus, clear, 0).is_ok() {
+            self.wake(clear, 1, u32::MAX);
+        }
+        self.threads.remove(self.current);
+        if self.threads.is_empty() {
+            let _ = io::stdout().flush();
+            return Some(status & 0xFF);
+        }
+        let next = self.current % self.threads.len();
+        self.load(r, next)
+    }
+
+    fn wake(&mut self, addr: u32, count: u32, bitset: u32) -> u32 {
+        let mut woken = 0;
+        for t in self.threads.iter_mut() {
+            if woken == count {
+                break;
+            }
+            if t.wait.as_ref().is_some_and(|w| w.addr == addr && w.bitset & bitset != 0) {
+                t.wait = None;
+                woken += 1;
+            }
+        }
+        woken
+    }
+
+    // futex(uaddr, op, val, timeout | val2, uaddr2, val3)
+    #[allow(clippy::too_many_arguments)]
+    fn futex(&mut self, bus: &mut Bus, addr: u32, op: u32, val: u32, timeout: u32, addr2: u32, val3: u32) -> Result<u32, i32> {
+        match op & FUTEX_CMD_MASK {
+            cmd @ (FUTEX_WAIT | FUTEX_WAIT_BITSET) => {
+                let bitset = if cmd == FUTEX_WAIT_BITSET { val3 } else { u32::MAX };
+                if bitset == 0 {
+                    return Err(EINVAL);
+                }
+                if readWord(bus, addr)? != val {
+                    return Err(EAGAIN);
+                }
+                // the thread sleeps with a0 = 0, which is what it sees when woken
+                self.threads[self.current].wait = Some(Wait { addr: addr, bitset: bitset, timeout: timeout != 0 });
+                self.resched = true;
+                Ok(0)
+            },
+            FUTEX_WAKE                  => Ok(self.wake(addr, val, u32::MAX)),
+            FUTEX_WAKE_BITSET           => Ok(self.wake(addr, val, val3)),
+            cmd @ (FUTEX_REQUEUE | FUTEX_CMP_REQUEUE) => {
+                if cmd == FUTEX_CMP_REQUEUE && readWord(bus, addr)? != val3 {
+                    return Err(EAGAIN);
+                }
+                let woken = self.wake(addr, val, u32::MAX);
+                // val2 (passed in the timeout slot) limits how many of the remaining waiters move
+                let mut moved = 0;
+                for t in self.threads.iter_mut() {
+                    if moved == timeout {
+                        break;
+                    }
+                    if let Some(w) = t.wait.as_mut().filter(|w| w.addr == addr) {
+                        w.addr = addr2;
+                        moved += 1;
+                    }
+                }
+                Ok(if cmd == FUTEX_CMP_REQUEUE { woken + moved } else { woken })
+            },
+            _                           => Err(ENOSYS),
+        }
+    }
+
+    fn file(&mut self, fd: i32) -> Result<&mut FileDesc, i32> {
+        self.fds.get_mut(&fd).ok_or(EBADF)
+    }
+
+    // Guest path relative to dirfd (AT_FDCWD being the guest's working directory, which the VFS
+    // resolves).
+    fn path(&mut self, bus: &mut Bus, dirfd: i32, addr: u32) -> Result<String, i32> {
+        let name = String::from_utf8_lossy(&bus.readCString(addr).map_err(fault)?).into_owned();
+        if name.starts_with('/') || dirfd == AT_FDCWD {
+            return Ok(name);
+        }
+        match self.file(dirfd)? {
+            FileDesc::File(_, dir)  => Ok(format!("{}/{}", dir, name)),
+            _                       => Err(EBADF),
+        }
+    }
+
+    fn readInto(&mut self, fd: i32, buf: &mut [u8]) -> Result<usize, i32> {
+        match self.file(fd)? {
+            FileDesc::Stdin         => io::stdin().read(buf).map_err(errno),
+            FileDesc::File(f, _)    => f.read(buf).map_err(errno),
+            _                       => Err(EBADF),
+        }
+    }
+
+    fn writeFrom(&mut self, fd: i32, buf: &[u8]) -> Result<usize, i32> {
+        match self.file(fd)? {
+            FileDesc::Stdout        => io::stdout().write_all(buf).and_then(|_| io::stdout().flush()).map(|_| buf.len()).map_err(errno),
+            FileDesc::Stderr        => io::stderr().write_all(buf).map(|_| buf.len()).map_err(errno),
+            FileDesc::File(f, _)    => f.write(buf).map_err(errno),
+            _                       => Err(EBADF),
+        }
+    }
+
+    fn read(&mut self, bus: &mut Bus, fd: i32, addr: u32, len: u32) -> Result<u32, i32> {
+        userRange(addr, len)?;
+        let mut buf = vec![0u8; len.min(IO_CHUNK) as usize];
+        let n = self.readInto(fd, &mut buf)?;
+        bus.writeBytes(addr, &buf[..n]).map_err(fault)?;
+        Ok(n as u32)
+    }
+
+    fn write(&mut self, bus: &mut Bus, fd: i32, addr: u32, len: u32) -> Result<u32, i32> {
+        userRange(addr, len)?;
+        let buf = bus.readBytes(addr, len.min(IO_CHUNK) as usize).map_err(fault)?;
+        self.writeFrom(fd, &buf).map(|n| n as u32)
+    }
+
+    // struct iovec { void *iov_base; size_t iov_len; }
+    fn iovecs(bus: &mut Bus, iov: u32, count: u32) -> Result<Vec<(u32, u32)>, i32> {
+        (0..count).map(|i| Ok((readWord(bus, iov + 8 * i)?, readWord(bus, iov + 8 * i + 4)?))).collect()
+    }
+
+    fn readv(&mut self, bus: &mut Bus, fd: i32, iov: u32, count: u32) -> Result<u32, i32> {
+        let mut total = 0;
+        for (base, len) in Linux::iovecs(bus, iov, count)? {
+            let n = self.read(bus, fd, base, len)?;
+            total += n;
+            if n < len {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    fn writev(&mut self, bus: &mut Bus, fd: i32, iov: u32, count: u32) -> Result<u32, i32> {
+        let mut buf = vec![];
+        for (base, len) in Linux::iovecs(bus, iov, count)? {
+            userRange(base, len)?;
+            buf.extend(bus.readBytes(base, len as usize).map_err(fault)?);
+        }
+        self.writeFrom(fd, &buf).map(|n| n as u32)
+    }
+
+    fn openat(&mut self, bus: &mut Bus, dirfd: i32, addr: u32, flags: u32, mode: u32) -> Result<u32, i32> {
+        let path = self.path(bus, dirfd, addr)?;
+        let access = flags & O_ACCMODE;
+        let f = OpenFlags {
+            read        : access != O_WRONLY,
+            write       : access == O_WRONLY || access == O_RDWR,
+            append      : flags & O_APPEND != 0,
+            create      : flags & O_CREAT != 0,
+            excl        : flags & O_EXCL != 0,
+            truncate    : flags & O_TRUNC != 0,
+        };
+        let file = self.vfs.open(&path, &f, mode).map_err(errno)?;
+        let fd = (3..).find(|fd| !self.fds.contains_key(fd)).unwrap();
+        self.fds.insert(fd, FileDesc::File(file, path));
+        Ok(fd as u32)
+    }
+
+    fn close(&mut self, fd: i32) -> Result<u32, i32> {
+        self.fds.remove(&fd).map(|_| 0).ok_or(EBADF)
+    }
+
+    // _llseek(fd, offset_high, offset_low, loff_t *result, whence)
+    fn llseek(&mut self, bus: &mut Bus, fd: i32, offset: u64, result: u32, whence: u32) -> Result<u32, i32> {
+        let pos = match whence {
+            0   => SeekFrom::Start(offset),
+            1   => SeekFrom::Current(offset as i64),
+            2   => SeekFrom::End(offset as i64),
+            _   => return Err(EINVAL),
+        };
+        let new = match self.file(fd)? {
+            FileDesc::File(f, _)    => f.seek(pos).map_err(errno)?,
+            _                       => return Err(ESPIPE),
+        };
+        writeWord(bus, result, new as u32)?;
+        writeWord(bus, result + 4, (new >> 32) as u32)?;
+        Ok(0)
+    }
+
+    fn metadata(&mut self, bus: &mut Bus, dirfd: i32, addr: u32, flags: u32) -> Result<Stat, i32> {
+        if flags & AT_EMPTY_PATH != 0 && (addr == 0 || bus.read(addr, Width::Byte).map_err(fault)? == 0) {
+            return match self.file(dirfd)? {
+                FileDesc::File(f, _)    => f.stat().map_err(errno),
+                // report whatever the host's stdio is connected to (a tty, a pipe, ...)
+                _                       => fs::metadata(format!("/proc/self/fd/{}", dirfd)).map(|m| Stat::fromMetadata(&m)).map_err(errno),
+            };
+        }
+        let path = self.path(bus, dirfd, addr)?;
+        self.vfs.stat(&path, flags & AT_SYMLINK_NOFOLLOW == 0).map_err(errno)
+    }
+
+    // fstat64 / fstatat64 fill struct stat64, statx fills struct statx (c.f., include/uapi/asm-generic/stat.h
+    // and include/uapi/linux/stat.h).
+    fn fstatat(&mut self, bus: &mut Bus, dirfd: i32, addr: u32, buf: u32, flags: u32, statx: bool) -> Result<u32, i32> {
+        let m = self.metadata(bus, dirfd, addr, flags)?;
+        let mut s = vec![];
+        if statx {
+            s.extend(0x7FFu32.to_le_bytes());                   // stx_mask = STATX_BASIC_STATS
+            s.extend(m.blksize.to_le_bytes());
+            s.extend(0u64.to_le_bytes());                       // stx_attributes
+            s.extend(m.nlink.to_le_bytes());
+            s.extend(m.uid.to_le_bytes());
+            s.extend(m.gid.to_le_bytes());
+            s.extend((m.mode as u16).to_le_bytes());
+            s.extend(0u16.to_le_bytes());
+            s.extend(m.ino.to_le_bytes());
+            s.extend(m.size.to_le_bytes());
+            s.extend(m.blocks.to_le_bytes());
+            s.extend(0u64.to_le_bytes());                       // stx_attributes_mask
+            for (sec, nsec) in [m.atime, m.ctime, m.ctime, m.mtime] {
+                s.extend(sec.to_le_bytes());
+                s.extend(nsec.to_le_bytes());
+                s.extend(0u32.to_le_bytes());
+            }
+            s.extend(((m.rdev >> 8) as u32 & 0xFFF).to_le_bytes());
+            s.extend((m.rdev as u32 & 0xFF).to_le_bytes());
+            s.extend(((m.dev >> 8) as u32 & 0xFFF).to_le_bytes());
+            s.extend((m.dev as u32 & 0xFF).to_le_bytes());
+            s.resize(256, 0);
+        } else {
+            s.extend(m.dev.to_le_bytes());
+            s.extend(m.ino.to_le_bytes());
+            s.extend(m.mode.to_le_bytes());
+            s.extend(m.nlink.to_le_bytes());
+            s.extend(m.uid.to_le_bytes());
+            s.extend(m.gid.to_le_bytes());
+            s.extend(m.rdev.to_le_bytes());
+            s.extend(0u64.to_le_bytes());
+            s.extend(m.size.to_le_bytes());
+            s.extend(m.blksize.to_le_bytes());
+            s.extend(0u32.to_le_bytes());
+            s.extend(m.blocks.to_le_bytes());
+            for (sec, nsec) in [m.atime, m.mtime, m.ctime] {
+                s.extend((sec as u32).to_le_bytes());
+                s.extend(nsec.to_le_bytes());
+            }
+            s.resize(104, 0);
+        }
+        bus.writeBytes(buf, &s).map_err(fault)?;
+        Ok(0)
+    }
+
+    // Only the terminal queries libc makes to pick the stdio buffering mode are supported.
+    fn ioctl(&mut self, bus: &mut Bus, fd: i32, req: u32, arg: u32) -> Result<u32, i32> {
+        let tty = match self.file(fd)? {
+            FileDesc::Stdin         => io::stdin().is_terminal(),
+            FileDesc::Stdout        => io::stdout().is_terminal(),
+            FileDesc::Stderr        => io::stderr().is_terminal(),
+            FileDesc::File(..)      => false,
+        };
+        if !tty {
+            return Err(ENOTTY);
+        }
+        match req {
+            TCGETS      => bus.writeBytes(arg, &[0u8; 36]).map_err(fault)?,
+            TIOCGWINSZ  => {
+                // struct winsize { ws_row, ws_col, ws_xpixel, ws_ypixel }
+                writeWord(bus, arg, 24 | (80 << 16))?;
+                writeWord(bus, arg + 4, 0)?;
+            },
+            _           => return Err(EINVAL),
+        }
+        Ok(0)
+    }
+
+    fn getcwd(&mut self, bus: &mut Bus, addr: u32, size: u32) -> Result<u32, i32> {
+        let mut s = self.vfs.cwd().to_string_lossy().into_owned().into_bytes();
+        s.push(0);
+        if s.len() > size as usize {
+            return Err(ERANGE);
+        }
+        bus.writeBytes(addr, &s).map_err(fault)?;
+        Ok(s.len() as u32)
+    }
+
+    // readlinkat(dirfd, path, buf, bufsiz): the target is truncated to bufsiz and not NUL-terminated.
+    fn readlinkat(&mut self, bus: &mut Bus, dirfd: i32, addr: u32, buf: u32, size: u32) -> Result<u32, i32> {
+        if size as i32 <= 0 {
+            return Err(EINVAL);
+        }
+        let path = self.path(bus, dirfd, addr)?;
+        let target = self.vfs.readlink(&path).map_err(errno)?.into_os_string().into_vec();
+        let n = target.len().min(size as usize);
+        bus.writeBytes(buf, &target[..n]).map_err(fault)?;
+        Ok(n as u32)
+    }
+
+    // The break can move anywhere between the end of the program and the mmap area. New memory is
+    // zeroed; an invalid request returns the current break.
+    fn brk(&mut self, bus: &mut Bus, addr: u32) -> u32 {
+        if addr < self.brk_start || addr >= self.mmap_top {
+            return self.brk;
+        }
+        if addr > self.brk && bus.writeBytes(self.brk, &vec![0u8; (addr - self.brk) as usize]).is_err() {
+            return self.brk;
+        }
+        self.brk = addr;
+        self.brk
+    }
+
+    // mmap2(addr, length, prot, flags, fd, pgoffset). Mappings are carved top-down below the stack;
+    // file mappings are private copies of the file contents. munmap() does nothing: unmapped ranges
+    // stay accessible and are never handed out again, so a guest that keeps mapping and unmapping
+    // eventually gets ENOMEM.
+    fn mmap(&mut self, bus: &mut Bus, addr: u32, len: u32, flags: u32, fd: i32, pgoff: u32) -> Result<u32, i32> {
+        if len == 0 {
+            return Err(EINVAL);
+        }
+        let size = pageAlign(len);
+        let base = if flags & MAP_FIXED != 0 {
+            if !addr.is_multiple_of(PAGE_SIZE) || addr < USER_BASE || addr.checked_add(size).is_none_or(|end| end > USER_END) {
+                return Err(EINVAL);
+            }
+            for off in (0..size).step_by(IO_CHUNK as usize) {
+                bus.writeBytes(addr + off, &vec![0u8; (size - off).min(IO_CHUNK) as usize]).map_err(fault)?;
+            }
+            addr
+        } else {
+            if self.mmap_top - self.brk < size {
+                return Err(ENOMEM);
+            }
+            self.mmap_top -= size;
+            self.mmap_top
+        };
+
+        if flags & MAP_ANONYMOUS == 0 {
+            let f = match self.file(fd)? {
+                FileDesc::File(f, _)    => f,
+                _                       => return Err(EBADF),
+            };
+            let pos = f.seek(SeekFrom::Current(0)).map_err(errno)?;
+            f.seek(SeekFrom::Start(pgoff as u64 * 4096)).map_err(errno)?;
+            let mut buf = vec![0u8; len.min(IO_CHUNK) as usize];
+            let mut off = 0;
+            while off < len {
+                let want = (len - off).min(IO_CHUNK) as usize;
+                match f.read(&mut buf[..want]).map_err(errno)? {
+                    0   => break,
+                    k   => {
+                        bus.writeBytes(base + off, &buf[..k]).map_err(fault)?;
+                        off += k as u32;
+                    },
+                }
+            }
+            f.seek(SeekFrom::Start(pos)).map_err(errno)?;
+        }
+        Ok(base)
+    }
+
+    // struct __kernel_timespec { s64 tv_sec; s64 tv_nsec; }
+    fn clockGettime(&mut self, bus: &mut Bus, clock: u32, addr: u32) -> Result<u32, i32> {
+        let t = match clock {
+            0   => SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default(),  // CLOCK_REALTIME
+            1..=7 => self.start.elapsed(),
+            _   => return Err(EINVAL),
+        };
+        let mut s = vec![];
+        s.extend(t.as_secs().to_le_bytes());
+        s.extend((t.subsec_nanos() as u64).to_le_bytes());
+        bus.writeBytes(addr, &s).map_err(fault)?;
+        Ok(0)
+    }
+
+    // clock_nanosleep(clockid, flags, request, remain); TIMER_ABSTIME is not supported.
+    fn nanosleep(&mut self, bus: &mut Bus, req: u32) -> Result<u32, i32> {
+        let sec = readWord(bus, req)? as u64 | (readWord(bus, req + 4)? as u64) << 32;
+        let nsec = readWord(bus, req + 8)? as u64 | (readWord(bus, req + 12)? as u64) << 32;
+        if (sec as i64) < 0 || nsec >= 1_000_000_000 {
+            return Err(EINVAL);
+        }
+        let nsec = nsec as u32;
+        std::thread::sleep(std::time::Duration::new(sec, nsec));
+        Ok(0)
+    }
+
+    fn getrandom(&mut self, bus: &mut Bus, addr: u32, len: u32) -> Result<u32, i32> {
+        userRange(addr, len)?;
+        let mut buf = vec![0u8; len.min(IO_CHUNK) as usize];
+        fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut buf)).map_err(errno)?;
+        bus.writeBytes(addr, &buf).map_err(fault)?;
+        Ok(buf.len() as u32)
+    }
+
+    // struct new_utsname: six 65-byte strings
+    fn uname(&mut self, bus: &mut Bus, addr: u32) -> Result<u32, i32> {
+        let mut s = vec![];
+        for field in ["Linux", "rv32im", "6.1.0", "#1", "riscv32", ""] {
+            let mut f = field.as_bytes().to_vec();
+            f.resize(65, 0);
+            s.extend(f);
+        }
+        bus.writeBytes(addr, &s).map_err(fault)?;
+        Ok(0)
+    }
+
+    // prlimit64(pid, resource, new, old): limits cannot be changed, RLIMIT_STACK reports the real
+    // stack size and everything else is unlimited.
+    fn prlimit(&mut self, bus: &mut Bus, resource: u32, old: u32) -> Result<u32, i32> {
+        if old != 0 {
+            let lim = if resource == RLIMIT_STACK { STACK_SIZE as u64 } else { u64::MAX };
+            let mut s = vec![];
+            s.extend(lim.to_le_bytes());
+            s.extend(lim.to_le_bytes());
+            bus.writeBytes(old, &s).map_err(fault)?;
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::memory::Memory;
+    use crate::syscall::*;
+    use crate::testutil::TempDir;
+
+    // 64 KiB of user memory at 0x10000; the program ends at 0x12000 and `dir` is the guest's root.
+    fn process(name: &str) -> (Linux, Register, Bus, TempDir) {
+        let dir = TempDir::new(&format!("syscall-{}", name));
+        let mut bus = Bus::new();
+        bus.attach(0x10000, Box::new(Memory::withSize(0x10000))).unwrap();
+        let mut r = Register::new();
+        r.setPC(0x10000);
+        r.setReg(2, 0x20000);
+        (Linux::new(0x12000, Vfs::new(&dir, PathBuf::from("/"))), r, bus, dir)
+    }
+
+    // Issue a syscall from the running thread; the result is a0 of whichever thread runs next.
+    fn sys(l: &mut Linux, r: &mut Register, bus: &mut Bus, nr: u32, args: &[u32]) -> Option<u32> {
+        r.setReg(17, nr);
+        for (i, a) in args.iter().enumerate() {
+            r.setReg(10 + i as u32, *a);
+        }
+        l.dispatch(r, bus)
+    }
+
+    fn call(l: &mut Linux, r: &mut Register, bus: &mut Bus, nr: u32, args: &[u32]) -> i32 {
+        assert_eq!(sys(l, r, bus, nr, args), None);
+        r.getReg(10) as i32
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let (mut l, mut r, mut bus, dir) = process("dispatch");
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_GETPID, &[]), PID as i32);
+        assert_eq!(r.getPC(), 0x10004);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_GETTID, &[]), PID as i32);
+        assert_eq!(call(&mut l, &mut r, &mut bus, 9999, &[]), -ENOSYS);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_CLONE3, &[]), -ENOSYS);
+        assert_eq!(r.getPC(), 0x10010);
+
+        // argument errors are -errno in a0; host errors pass through unchanged
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_WRITE, &[5, 0x11000, 1]), -EBADF);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_READ, &[1, 0x11000, 1]), -EBADF);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_UNAME, &[0x100]), -EFAULT);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_RT_SIGACTION, &[signal::SIGABRT, 0, 0, 4]), -EINVAL);
+        bus.writeBytes(0x11000, b"/missing\0").unwrap();
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_OPENAT, &[AT_FDCWD as u32, 0x11000, 0, 0]), -2);  // ENOENT
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_MKDIRAT, &[AT_FDCWD as u32, 0x11000, 0o755]), 0);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_MKDIRAT, &[AT_FDCWD as u32, 0x11000, 0o755]), -17);  // EEXIST
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_IOCTL, &[3, TCGETS, 0x11000]), -EBADF);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_GETCWD, &[0x11000, 1]), -ERANGE);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_GETCWD, &[0x11000, 64]), 2);
+
+        // a file round trip through the lowest free descriptor
+        bus.writeBytes(0x11000, b"f.txt\0hello").unwrap();
+        let fd = call(&mut l, &mut r, &mut bus, SYS_OPENAT, &[AT_FDCWD as u32, 0x11000, O_RDWR | O_CREAT, 0o644]);
+        assert_eq!(fd, 3);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_WRITE, &[3, 0x11006, 5]), 5);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_LLSEEK, &[3, 0, 1, 0x11100, 0]), 0);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_READ, &[3, 0x11200, 16]), 4);
+        assert_eq!(bus.readBytes(0x11200, 4).unwrap(), b"ello");
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_LLSEEK, &[1, 0, 0, 0x11100, 0]), -ESPIPE);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_CLOSE, &[3]), 0);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_CLOSE, &[3]), -EBADF);
+        assert_eq!(fs::read(dir.join("f.txt")).unwrap(), b"hello");
+
+        // readlinkat goes through the VFS and truncates to the buffer
+        std::os::unix::fs::symlink("f.txt", dir.join("link")).unwrap();
+        bus.writeBytes(0x11000, b"link\0").unwrap();
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_READLINKAT, &[AT_FDCWD as u32, 0x11000, 0x11200, 64]), 5);
+        assert_eq!(bus.readBytes(0x11200, 5).unwrap(), b"f.txt");
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_READLINKAT, &[AT_FDCWD as u32, 0x11000, 0x11200, 2]), 2);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_READLINKAT, &[AT_FDCWD as u32, 0x11000, 0x11200, 0]), -EINVAL);
+        bus.writeBytes(0x11000, b"f.txt\0").unwrap();
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_READLINKAT, &[AT_FDCWD as u32, 0x11000, 0x11200, 64]), -EINVAL);
+
+        // guest buffers outside user memory fault before anything is allocated for them
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_READ, &[0, 0x11000, 0xFFFF_0000]), -EFAULT);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_READ, &[0, 0x0, 16]), -EFAULT);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_WRITE, &[1, 0x7FFF_FFF0, 0x20]), -EFAULT);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_GETRANDOM, &[0x11000, 0x8000_0000]), -EFAULT);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_GETRANDOM, &[0x11000, 16]), 16);
+
+        // struct __kernel_timespec with tv_nsec out of range
+        bus.writeBytes(0x11100, &[0u8; 16]).unwrap();
+        bus.writeBytes(0x11108, &1_000_000_000u64.to_le_bytes()).unwrap();
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_CLOCK_NANOSLEEP64, &[0, 0, 0x11100, 0]), -EINVAL);
+        bus.writeBytes(0x11100, &(-1i64).to_le_bytes()).unwrap();
+        bus.writeBytes(0x11108, &0u64.to_le_bytes()).unwrap();
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_CLOCK_NANOSLEEP64, &[0, 0, 0x11100, 0]), -EINVAL);
+        bus.writeBytes(0x11100, &[0u8; 16]).unwrap();
+        bus.writeBytes(0x11108, &1000u64.to_le_bytes()).unwrap();
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_CLOCK_NANOSLEEP64, &[0, 0, 0x11100, 0]), 0);
+
+        // brk stays put when it cannot grow
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_BRK, &[0]), 0x12000);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_BRK, &[0x13000]), 0x13000);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_BRK, &[0x40000]), 0x13000);
+
+        assert_eq!(sys(&mut l, &mut r, &mut bus, SYS_EXIT_GROUP, &[0x1FF]), Some(0xFF));
+    }
+
+    const THREAD: u32 = CLONE_VM | CLONE_THREAD | CLONE_CHILD_SETTID | CLONE_CHILD_CLEARTID;
+
+    // Start a thread with its own stack pointer, which is how the tests tell the threads apart.
+    fn spawn(l: &mut Linux, r: &mut Register, bus: &mut Bus, sp: u32, ctid: u32) -> u32 {
+        call(l, r, bus, SYS_CLONE, &[THREAD, sp, 0, 0, ctid]) as u32
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn futex(l: &mut Linux, r: &mut Register, bus: &mut Bus, addr: u32, op: u32, val: u32, val2: u32, addr2: u32, val3: u32) -> i32 {
+        call(l, r, bus, SYS_FUTEX, &[addr, op, val, val2, addr2, val3])
+    }
+
+    #[test]
+    fn test_scheduling() {
+        let (mut l, mut r, mut bus, _dir) = process("sched");
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_CLONE, &[CLONE_VM, 0x18000, 0, 0, 0]), -ENOSYS);
+        assert_eq!(spawn(&mut l, &mut r, &mut bus, 0x18000, 0x11800), PID + 1);
+        assert_eq!(spawn(&mut l, &mut r, &mut bus, 0x19000, 0x11804), PID + 2);
+        assert_eq!(bus.readBytes(0x11800, 8).unwrap(), [(PID + 1).to_le_bytes(), (PID + 2).to_le_bytes()].concat());
+
+        // round robin in creation order, one quantum each
+        let mut order = vec![];
+        for _ in 0..4 {
+            for _ in 0..QUANTUM - 1 {
+                assert!(!l.tick(&mut r));
+            }
+            assert!(l.tick(&mut r));
+            order.push(r.getReg(2));
+        }
+        assert_eq!(order, [0x18000, 0x19000, 0x20000, 0x18000]);
+
+        // a child starts after its clone with a0 = 0; sched_yield hands over to the next thread
+        assert_eq!((r.getReg(10), r.getPC()), (0, 0x10008));
+        call(&mut l, &mut r, &mut bus, SYS_SCHED_YIELD, &[]);
+        assert_eq!(r.getReg(2), 0x19000);
+    }
+
+    #[test]
+    fn test_futex() {
+        let (mut l, mut r, mut bus, _dir) = process("futex");
+        let (f, f2) = (0x11000, 0x11004);
+        writeWord(&mut bus, f, 5).unwrap();
+        spawn(&mut l, &mut r, &mut bus, 0x18000, 0x11800);
+        spawn(&mut l, &mut r, &mut bus, 0x19000, 0x11804);
+
+        // WAIT returns EAGAIN at once if the value has changed, otherwise the next thread runs
+        assert_eq!(futex(&mut l, &mut r, &mut bus, f, FUTEX_WAIT, 4, 0, 0, 0), -EAGAIN);
+        assert_eq!(r.getReg(2), 0x20000);
+        futex(&mut l, &mut r, &mut bus, f, FUTEX_WAIT, 5, 0, 0, 0);
+        assert_eq!(r.getReg(2), 0x18000);
+        futex(&mut l, &mut r, &mut bus, f, FUTEX_WAIT, 5, 0, 0, 0);
+        assert_eq!(r.getReg(2), 0x19000);
+
+        // WAKE wakes at most `val` waiters and returns how many it woke
+        assert_eq!(futex(&mut l, &mut r, &mut bus, f, FUTEX_WAKE, 1, 0, 0, 0), 1);
+        assert_eq!(futex(&mut l, &mut r, &mut bus, f, FUTEX_WAKE, 5, 0, 0, 0), 1);
+        assert_eq!(futex(&mut l, &mut r, &mut bus, f, FUTEX_WAKE, 5, 0, 0, 0), 0);
+        call(&mut l, &mut r, &mut bus, SYS_SCHED_YIELD, &[]);
+        assert_eq!((r.getReg(2), r.getReg(10)), (0x20000, 0));
+
+        // CMP_REQUEUE checks val3, wakes `val` waiters and moves up to val2 of the others to uaddr2
+        futex(&mut l, &mut r, &mut bus, f, FUTEX_WAIT, 5, 0, 0, 0);
+        futex(&mut l, &mut r, &mut bus, f, FUTEX_WAIT, 5, 0, 0, 0);
+        assert_eq!(r.getReg(2), 0x19000);
+        assert_eq!(futex(&mut l, &mut r, &mut bus, f, FUTEX_CMP_REQUEUE, 0, 5, f2, 6), -EAGAIN);
+        assert_eq!(futex(&mut l, &mut r, &mut bus, f, FUTEX_CMP_REQUEUE, 0, 1, f2, 5), 1);
+        assert_eq!(futex(&mut l, &mut r, &mut bus, f, FUTEX_WAKE, 5, 0, 0, 0), 1);
+        call(&mut l, &mut r, &mut bus, SYS_SCHED_YIELD, &[]);
+        assert_eq!(r.getReg(2), 0x18000);
+        assert_eq!(futex(&mut l, &mut r, &mut bus, f2, FUTEX_WAKE, 5, 0, 0, 0), 1);
+        assert_eq!(futex(&mut l, &mut r, &mut bus, f, FUTEX_WAIT_BITSET, 5, 0, 0, 0), -EINVAL);
+    }
+
+    #[test]
+    fn test_thread_exit() {
+        let (mut l, mut r, mut bus, _dir) = process("exit");
+        let tid = spawn(&mut l, &mut r, &mut bus, 0x18000, 0x11800);
+
+        // pthread_join: wait on the child's tid word, which is cleared and woken when it exits
+        futex(&mut l, &mut r, &mut bus, 0x11800, FUTEX_WAIT, tid, 0, 0, 0);
+        assert_eq!(r.getReg(2), 0x18000);
+        assert_eq!(sys(&mut l, &mut r, &mut bus, SYS_EXIT, &[9]), None);
+        assert_eq!((r.getReg(2), r.getReg(10)), (0x20000, 0));
+        assert_eq!(readWord(&mut bus, 0x11800), Ok(0));
+        assert_eq!(sys(&mut l, &mut r, &mut bus, SYS_EXIT, &[0x103]), Some(3));
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let (mut l, mut r, mut bus, _dir) = process("deadlock");
+        spawn(&mut l, &mut r, &mut bus, 0x18000, 0x11800);
+
+        // with every thread waiting, one with a timeout gives up with ETIMEDOUT...
+        futex(&mut l, &mut r, &mut bus, 0x11000, FUTEX_WAIT, 0, 0x11100, 0, 0);
+        assert_eq!(r.getReg(2), 0x18000);
+        futex(&mut l, &mut r, &mut bus, 0x11000, FUTEX_WAIT, 0, 0, 0, 0);
+        assert_eq!((r.getReg(2), r.getReg(10) as i32), (0x20000, -ETIMEDOUT));
+
+        // ...and without one the process is terminated
+        assert_eq!(sys(&mut l, &mut r, &mut bus, SYS_FUTEX, &[0x11000, FUTEX_WAIT, 0, 0, 0, 0]), Some(1));
+    }
+
+    const SIGUSR1: u32 = 10;
+    const SIGUSR2: u32 = 12;
+
+    // rt_sigaction(signo, {handler, flags, mask}, NULL, 8)
+    fn handle(l: &mut Linux, r: &mut Register, bus: &mut Bus, signo: u32, handler: u32, flags: u32, mask: u64) -> i32 {
+        SigAction { handler: handler, flags: flags, mask: mask }.write(bus, 0x11F00).unwrap();
+        call(l, r, bus, SYS_RT_SIGACTION, &[signo, 0x11F00, 0, 8])
+    }
+
+    #[test]
+    fn test_signal_masks() {
+        let (mut l, mut r, mut bus, _dir) = process("sigmask");
+        assert_eq!(handle(&mut l, &mut r, &mut bus, signal::SIGKILL, 0x10800, 0, 0), -EINVAL);
+        assert_eq!(handle(&mut l, &mut r, &mut bus, SIGUSR1, 0x10800, 0, signal::bit(SIGUSR2)), 0);
+
+        // the handler runs with the signal itself and sa_mask blocked
+        call(&mut l, &mut r, &mut bus, SYS_KILL, &[PID, SIGUSR1]);
+        assert_eq!((r.getPC(), r.getReg(10)), (0x10800, SIGUSR1));
+        let frame = r.getReg(2);
+        assert_eq!(l.threads[0].mask, signal::bit(SIGUSR1) | signal::bit(SIGUSR2));
+
+        // a blocked signal stays pending until sigreturn unblocks it, and is then delivered at once
+        call(&mut l, &mut r, &mut bus, SYS_TKILL, &[PID, SIGUSR1]);
+        assert_eq!(r.getPC(), 0x10804);
+        assert_eq!(sys(&mut l, &mut r, &mut bus, signal::SYS_RT_SIGRETURN, &[]), None);
+        assert_eq!((r.getPC(), r.getReg(2)), (0x10800, frame));
+        assert_eq!(sys(&mut l, &mut r, &mut bus, signal::SYS_RT_SIGRETURN, &[]), None);
+        assert_eq!((r.getPC(), l.threads[0].mask), (0x1000C, 0));
+
+        // SA_NODEFER leaves the signal unblocked; SIGKILL cannot be blocked
+        handle(&mut l, &mut r, &mut bus, SIGUSR2, 0x10900, signal::SA_NODEFER, 0);
+        call(&mut l, &mut r, &mut bus, SYS_KILL, &[PID, SIGUSR2]);
+        assert_eq!((r.getPC(), l.threads[0].mask), (0x10900, 0));
+        writeWord(&mut bus, 0x11F00, u32::MAX).unwrap();
+        writeWord(&mut bus, 0x11F04, u32::MAX).unwrap();
+        call(&mut l, &mut r, &mut bus, SYS_RT_SIGPROCMASK, &[SIG_SETMASK, 0x11F00, 0, 8]);
+        assert_eq!(l.threads[0].mask, !(signal::bit(signal::SIGKILL) | signal::bit(signal::SIGSTOP)));
+        assert_eq!(sys(&mut l, &mut r, &mut bus, SYS_KILL, &[PID, signal::SIGKILL]), Some(128 + signal::SIGKILL));
+    }
+
+    #[test]
+    fn test_sigaltstack() {
+        let (mut l, mut r, mut bus, _dir) = process("sigaltstack");
+        let ss = |bus: &mut Bus, sp: u32, flags: u32, size: u32| {
+            bus.writeBytes(0x11F00, &[sp.to_le_bytes(), flags.to_le_bytes(), size.to_le_bytes()].concat()).unwrap();
+        };
+        ss(&mut bus, 0x14000, 0, MINSIGSTKSZ - 1);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_SIGALTSTACK, &[0x11F00, 0]), -ENOMEM);
+        ss(&mut bus, 0x14000, 0, 0x4000);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_SIGALTSTACK, &[0x11F00, 0]), 0);
+
+        // an SA_ONSTACK handler runs on the alternate stack, which cannot be changed from there
+        handle(&mut l, &mut r, &mut bus, SIGUSR1, 0x10800, signal::SA_ONSTACK, 0);
+        call(&mut l, &mut r, &mut bus, SYS_KILL, &[PID, SIGUSR1]);
+        assert!(r.getReg(2) > 0x14000 && r.getReg(2) < 0x18000);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_SIGALTSTACK, &[0x11F00, 0x11F10]), -EPERM);
+        assert_eq!(call(&mut l, &mut r, &mut bus, SYS_SIGALTSTACK, &[0, 0x11F10]), 0);
+        assert_eq!(bus.readBytes(0x11F10, 12).unwrap(), [0x14000u32.to_le_bytes(), signal::SS_ONSTACK.to_le_bytes(), 0x4000u32.to_le_bytes()].concat());
+        sys(&mut l, &mut r, &mut bus, signal::SYS_RT_SIGRETURN, &[]);
+        assert_eq!(r.getReg(2), 0x20000);
+
+        // a sigreturn whose frame is not readable is a SIGSEGV
+        r.setReg(2, 0xFFFF_FFF0);
+        assert_eq!(sys(&mut l, &mut r, &mut bus, signal::SYS_RT_SIGRETURN, &[]), Some(128 + signal::SIGSEGV));
+    }
+
+    #[test]
+    fn test_fault_signals() {
+        // faults with the default action terminate with 128 + signo
+        let (mut l, mut r, mut bus, _dir) = process("faults");
+        assert_eq!(l.exception(&mut r, &mut bus, Exception::IllegalInstruction, 0), Some(128 + signal::SIGILL));
+        assert_eq!(l.exception(&mut r, &mut bus, Exception::LoadAccessFault, 0), Some(128 + signal::SIGSEGV));
+        assert_eq!(l.exception(&mut r, &mut bus, Exception::StoreAddressMisaligned, 0), Some(128 + signal::SIGBUS));
+
+        // a handler gets si_addr and may skip the faulting instruction
+        handle(&mut l, &mut r, &mut bus, signal::SIGSEGV, 0x10800, 0, 0);
+        r.setPC(0x10100);
+        assert_eq!(l.exception(&mut r, &mut bus, Exception::StoreAccessFault, 0x8), None);
+        assert_eq!((r.getPC(), r.getReg(10)), (0x10800, signal::SIGSEGV));
+        assert_eq!(readWord(&mut bus, r.getReg(11) + 12), Ok(0x8));
+
+        // a fault inside the handler, with the signal blocked, is fatal
+        assert_eq!(l.exception(&mut r, &mut bus, Exception::LoadAccessFault, 0x4), Some(128 + signal::SIGSEGV));
+
+        // as is one that is ignored, or whose frame cannot be written
+        let (mut l, mut r, mut bus, _) = process("faults");
+        handle(&mut l, &mut r, &mut bus, signal::SIGILL, signal::SIG_IGN, 0, 0);
+        assert_eq!(l.exception(&mut r, &mut bus, Exception::IllegalInstruction, 0), Some(128 + signal::SIGILL));
+        handle(&mut l, &mut r, &mut bus, signal::SIGSEGV, 0x10800, 0, 0);
+        r.setReg(2, 0x100);
+        assert_eq!(l.exception(&mut r, &mut bus, Exception::LoadAccessFault, 0), Some(128 + signal::SIGSEGV));
+    }
+}
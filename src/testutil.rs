@@ -0,0 +1,32 @@
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+// Scratch directory for tests that need host files. It is removed when the guard is dropped, so a
+// failing test does not leave it behind either.
+pub struct TempDir {
+    path        : PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("rvsim-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);  // memo: 前回の実行が途中で落ちた場合の残骸
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path: path }
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,195 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::bus::Access;
+use crate::core::Privilege;
+use crate::csr;
+use crate::disasm;
+use crate::inst::{Format, Inst, Op};
+
+// Instruction trace in the format of Spike's `-l --log-commits`, so that traces of this simulator,
+// Spike and RTL simulations can be compared with the same scripts. Every instruction gets a line
+// with its disassembly, followed by either its commit line
+//
+//     core   0: 0x80000004 (0x00a12623) sw      a0, 12(sp)
+//     core   0: 3 0x80000004 (0x00a12623) mem 0x80001ffc 0x00000005
+//
+// with the privilege level it executed in, the registers and CSRs it wrote and the memory it
+// accessed (loads: address, stores: address and data), or the trap it raised instead of retiring.
+
+// What one step of the hart did: an instruction that retired or trapped, or an interrupt taken
+// before the next instruction.
+#[derive(Debug, Clone)]
+pub struct Commit {
+    pub mode        : Privilege,
+    pub pc          : u32,
+    pub inst        : Option<u32>,          // None for an interrupt
+    pub trap        : Option<(u32, u32)>,   // mcause and mtval when the step trapped
+    pub rs          : [Option<(u32, u32)>; 2],  // rs1 and rs2 if read, and their values
+    pub rd          : Option<(u32, u32)>,   // register written and its new value
+    pub csrs        : Vec<(u32, u32)>,      // CSRs written and their new values
+    pub mem         : Vec<Access>,
+    pub next_pc     : u32,
+}
+
+// Registers an instruction reads, as rs1 and rs2.
+pub fn sources(i: &Inst) -> [Option<u32>; 2] {
+    match i.op.format() {
+        Format::R | Format::S | Format::B | Format::Amo => [Some(i.rs1), Some(i.rs2)],
+        Format::I | Format::Shift | Format::Lr | Format::Csr | Format::Cbo => [Some(i.rs1), None],
+        _                                           => [None, None],
+    }
+}
+
+// Register an instruction writes, if any (writes to x0 are not writes).
+pub fn destination(i: &Inst) -> Option<u32> {
+    match i.op.format() {
+        Format::R | Format::I | Format::Shift | Format::U | Format::J | Format::Amo | Format::Lr
+            | Format::Csr | Format::CsrI if i.rd != 0 => Some(i.rd),
+        _                                           => None,
+    }
+}
+
+// CSR an instruction writes, if any: CSRRS/CSRRC with x0 (CSRRSI/CSRRCI with 0) only read, and MRET
+// updates mstatus.
+pub fn csrWritten(i: &Inst) -> Option<u32> {
+    match i.op {
+        Op::CSRRW | Op::CSRRWI                      => Some(i.imm as u32),
+        Op::CSRRS | Op::CSRRC | Op::CSRRSI | Op::CSRRCI if i.rs1 != 0 => Some(i.imm as u32),
+        Op::MRET                                    => Some(csr::MSTATUS),
+        _                                           => None,
+    }
+}
+
+// c.f., riscv-isa-sim riscv/encoding.h (the names Spike prints for exceptions)
+fn trapName(cause: u32) -> String {
+    if cause & 0x8000_0000 != 0 {
+        return format!("interrupt #{}", cause & 0x7FFF_FFFF);
+    }
+    let name = match cause {
+        0   => "instruction_address_misaligned",
+        1   => "instruction_access_fault",
+        2   => "illegal_instruction",
+        3   => "breakpoint",
+        4   => "load_address_misaligned",
+        5   => "load_access_fault",
+        6   => "store_address_misaligned",
+        7   => "store_access_fault",
+        8   => "user_ecall",
+        9   => "supervisor_ecall",
+        11  => "machine_ecall",
+        12  => "instruction_page_fault",
+        13  => "load_page_fault",
+        15  => "store_page_fault",
+        _   => "unknown",
+    };
+    format!("trap_{}", name)
+}
+
+// Spike pads the mnemonic to 8 columns.
+fn spikeDisasm(inst: u32, pc: u32) -> String {
+    let text = disasm::disassemble(inst, pc);
+    match text.split_once(' ') {
+        Some((m, ops))  => format!("{:<7} {}", m, ops),
+        None            => text,
+    }
+}
+
+// The trace lines of one step.
+pub fn format(c: &Commit) -> String {
+    let mut out = String::new();
+    if let Some(inst) = c.inst {
+        out += &format!("core   0: 0x{:08x} (0x{:08x}) {}\n", c.pc, inst, spikeDisasm(inst, c.pc));
+    }
+    match (c.trap, c.inst) {
+        (Some((cause, tval)), _)    => {
+            out += &format!("core   0: exception {}, epc 0x{:08x}\n", trapName(cause), c.pc);
+            // ECALLs and interrupts have no trap value
+            if cause & 0x8000_0000 == 0 && !(8..=11).contains(&cause) {
+                out += &format!("core   0:           tval 0x{:08x}\n", tval);
+            }
+        },
+        (None, Some(_))             => out += &(commitLine(c) + "\n"),
+        (None, None)                => {},
+    }
+    out
+}
+
+// The commit line of a retired instruction (without the newline).
+pub fn commitLine(c: &Commit) -> String {
+    let mut out = format!("core   0: {} 0x{:08x} (0x{:08x})", c.mode as u32, c.pc, c.inst.unwrap_or(0));
+    if let Some((rd, v)) = c.rd {
+        out += &format!(" x{:<2} 0x{:08x}", rd, v);
+    }
+    for (addr, v) in &c.csrs {
+        out += &format!(" c{}_{} 0x{:08x}", addr, csr::name(*addr).unwrap_or("unknown"), v);
+    }
+    for a in c.mem.iter().filter(|a| !a.write) {
+        out += &format!(" mem 0x{:08x}", a.addr);
+    }
+    for a in c.mem.iter().filter(|a| a.write) {
+        out += &format!(" mem 0x{:08x} 0x{:0w$x}", a.addr, a.value, w = 2 * a.width.bytes() as usize);
+    }
+    out
+}
+
+// Writes the trace of every step to a file ("-": stderr, where Spike writes it).
+pub struct CommitLog {
+    out         : BufWriter<Box<dyn Write>>,
+}
+
+impl fmt::Debug for CommitLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CommitLog").finish()
+    }
+}
+
+impl CommitLog {
+    pub fn create(path: &str) -> io::Result<CommitLog> {
+        let out: Box<dyn Write> = if path == "-" { Box::new(io::stderr()) } else { Box::new(File::create(path)?) };
+        Ok(CommitLog { out: BufWriter::new(out) })
+    }
+
+    pub fn commit(&mut self, c: &Commit) {
+        let _ = self.out.write_all(format(c).as_bytes());
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bus::Width;
+    use crate::trace::*;
+
+    fn commit(inst: u32) -> Commit {
+        Commit { mode: Privilege::Machine, pc: 0x8000_0000, inst: Some(inst), trap: None, rs: [None, None], rd: None, csrs: vec![], mem: vec![], next_pc: 0x8000_0004 }
+    }
+
+    #[test]
+    fn test_commit_lines() {
+        let mut c = commit(0x0050_0513);
+        c.rd = Some((10, 5));
+        assert_eq!(format(&c), "core   0: 0x80000000 (0x00500513) li      a0, 5\ncore   0: 3 0x80000000 (0x00500513) x10 0x00000005\n");
+
+        let mut c = commit(0x00b5_0023);
+        c.mem = vec![Access { addr: 0x8000_1000, width: Width::Byte, value: 0x12, write: true }];
+        assert!(format(&c).ends_with("core   0: 3 0x80000000 (0x00b50023) mem 0x80001000 0x12\n"));
+
+        let mut c = commit(0x3415_9573);
+        c.mode = Privilege::User;
+        c.rd = Some((10, 0));
+        c.csrs = vec![(csr::MEPC, 4)];
+        assert!(format(&c).ends_with("core   0: 0 0x80000000 (0x34159573) x10 0x00000000 c833_mepc 0x00000004\n"));
+    }
+
+    #[test]
+    fn test_trap_lines() {
+        let mut c = commit(0x0000_0000);
+        c.trap = Some((2, 0));
+        assert_eq!(format(&c), "core   0: 0x80000000 (0x00000000) .word   0x00000000\ncore   0: exception trap_illegal_instruction, epc 0x80000000\ncore   0:           tval 0x00000000\n");
+    }
+}
@@ -0,0 +1,531 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::bus::{BusError, Device, Width};
+
+// Default base address and interrupt number (same as QEMU virt)
+pub const UART_BASE: u32 = 0x1000_0000;
+pub const UART_IRQ: u32 = 10;
+
+// c.f., PC16550D data sheet: register offsets (reg-shift 0)
+const RBR_THR_DLL   : u32 = 0;
+const IER_DLM       : u32 = 1;
+const IIR_FCR       : u32 = 2;
+const LCR           : u32 = 3;
+const MCR           : u32 = 4;
+const LSR           : u32 = 5;
+const MSR           : u32 = 6;
+const SCR           : u32 = 7;
+
+const IER_ERBFI     : u8 = 1 << 0;  // received data available
+const IER_ETBEI     : u8 = 1 << 1;  // transmitter holding register empty
+const IER_ELSI      : u8 = 1 << 2;  // receiver line status
+
+const IIR_NONE      : u8 = 0x01;
+const IIR_RLS       : u8 = 0x06;
+const IIR_RDA       : u8 = 0x04;
+const IIR_THRE      : u8 = 0x02;
+const IIR_FIFO      : u8 = 0xC0;
+
+const FCR_ENABLE    : u8 = 1 << 0;
+const FCR_CLEAR_RX  : u8 = 1 << 1;
+const FCR_CLEAR_TX  : u8 = 1 << 2;
+
+const LCR_DLAB      : u8 = 1 << 7;
+
+const MCR_LOOP      : u8 = 1 << 4;
+
+const LSR_DR        : u8 = 1 << 0;
+const LSR_OE        : u8 = 1 << 1;
+const LSR_THRE      : u8 = 1 << 5;
+const LSR_TEMT      : u8 = 1 << 6;
+
+const FIFO_DEPTH    : usize = 16;
+
+// How many ticks pass between polls of the host side for received characters.
+const POLL_INTERVAL : u32 = 256;
+
+// Where the guest side of the UART is connected on the host.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostPort {
+    Stdio,
+    Stdout,                 // output only: stdin is left to the simulator (e.g. the debugger)
+    File(String),
+    Unix(String),
+    Pty,
+    Null,
+}
+
+impl HostPort {
+    // stdio | stdout | file:PATH | unix:PATH | pty | none
+    pub fn parse(s: &str) -> Result<HostPort, String> {
+        match s {
+            "stdio"     => Ok(HostPort::Stdio),
+            "stdout"    => Ok(HostPort::Stdout),
+            "pty" if cfg!(target_os = "linux") => Ok(HostPort::Pty),
+            "pty"       => Err("the pty uart backend is only supported on Linux".to_string()),
+            "none"      => Ok(HostPort::Null),
+            _           => {
+                if let Some(path) = s.strip_prefix("file:") {
+                    Ok(HostPort::File(path.to_string()))
+                } else if let Some(path) = s.strip_prefix("unix:") {
+                    Ok(HostPort::Unix(path.to_string()))
+                } else {
+                    Err(format!("unknown uart backend '{}' (expected stdio, stdout, file:PATH, unix:PATH, pty or none)", s))
+                }
+            },
+        }
+    }
+}
+
+// Host side of the serial line: bytes written by the guest go to `tx`, bytes typed on the host arrive
+// through `rx` from a reader thread.
+struct Host {
+    tx          : Box<dyn Write>,
+    rx          : Option<Receiver<u8>>,
+}
+
+fn spawnReader<R: Read + Send + 'static>(mut input: R, sender: Sender<u8>) {
+    thread::spawn(move || {
+        let mut buf = [0u8; 64];
+        loop {
+            match input.read(&mut buf) {
+                Ok(0)       => break,
+                Ok(n)       => {
+                    for b in &buf[..n] {
+                        if sender.send(*b).is_err() {
+                            return;
+                        }
+                    }
+                },
+                // memo: PTYのスレーブ側が閉じているとEIOが返るので、接続されるまで待つ
+                Err(_)      => thread::sleep(Duration::from_millis(50)),
+            }
+        }
+    });
+}
+
+// Writes to a Unix socket client once one has connected; output before that is dropped.
+struct SocketWriter {
+    stream      : Arc<Mutex<Option<UnixStream>>>,
+}
+
+impl Write for SocketWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut guard = self.stream.lock().unwrap();
+        if let Some(s) = guard.as_mut() {
+            if s.write_all(buf).is_err() {
+                *guard = None;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn posix_openpt(flags: i32) -> i32;
+    fn grantpt(fd: i32) -> i32;
+    fn unlockpt(fd: i32) -> i32;
+    fn ptsname_r(fd: i32, buf: *mut std::os::raw::c_char, buflen: usize) -> i32;
+}
+
+#[cfg(target_os = "linux")]
+const O_RDWR        : i32 = 0o2;
+#[cfg(target_os = "linux")]
+const O_NOCTTY      : i32 = 0o400;
+
+// Allocate a pseudo-terminal and return the master side plus the path of the slave device.
+#[cfg(target_os = "linux")]
+fn openPty() -> io::Result<(File, String)> {
+    use std::os::unix::io::FromRawFd;
+
+    unsafe {
+        let fd = posix_openpt(O_RDWR | O_NOCTTY);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = File::from_raw_fd(fd);
+        if grantpt(fd) != 0 || unlockpt(fd) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // ptsname() returns a static buffer; the reentrant variant returns the error number instead
+        let mut name = [0 as std::os::raw::c_char; 64];
+        let err = ptsname_r(fd, name.as_mut_ptr(), name.len());
+        if err != 0 {
+            return Err(io::Error::from_raw_os_error(err));
+        }
+        let name = std::ffi::CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned();
+        Ok((master, name))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn openPty() -> io::Result<(File, String)> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "the pty uart backend is only supported on Linux"))
+}
+
+impl Host {
+    fn open(port: &HostPort) -> io::Result<Host> {
+        match port {
+            HostPort::Stdio     => {
+                let (sender, receiver) = mpsc::channel();
+                spawnReader(io::stdin(), sender);
+                Ok(Host { tx: Box::new(io::stdout()), rx: Some(receiver) })
+            },
+            HostPort::Stdout    => {
+                Ok(Host { tx: Box::new(io::stdout()), rx: None })
+            },
+            HostPort::File(path) => {
+                Ok(Host { tx: Box::new(File::create(path)?), rx: None })
+            },
+            HostPort::Unix(path) => {
+                let _ = std::fs::remove_file(path);
+                let listener = UnixListener::bind(path)?;
+                let stream: Arc<Mutex<Option<UnixStream>>> = Arc::new(Mutex::new(None));
+                let (sender, receiver) = mpsc::channel();
+                let accepted = stream.clone();
+                thread::spawn(move || {
+                    for client in listener.incoming().flatten() {
+                        if let Ok(reader) = client.try_clone() {
+                            spawnReader(reader, sender.clone());
+                        }
+                        *accepted.lock().unwrap() = Some(client);
+                    }
+                });
+                Ok(Host { tx: Box::new(SocketWriter { stream: stream }), rx: Some(receiver) })
+            },
+            HostPort::Pty       => {
+                let (master, name) = openPty()?;
+                eprintln!("uart: attached to {}", name);
+                let (sender, receiver) = mpsc::channel();
+                spawnReader(master.try_clone()?, sender);
+                Ok(Host { tx: Box::new(master), rx: Some(receiver) })
+            },
+            HostPort::Null      => {
+                Ok(Host { tx: Box::new(io::sink()), rx: None })
+            },
+        }
+    }
+}
+
+// NS16550A compatible UART. Transmission is instantaneous (the THR is drained into the host as soon as
+// it is written), reception is paced by the host reader thread and the receive FIFO.
+pub struct Uart {
+    host        : Host,
+    rx_fifo     : VecDeque<u8>,
+    ier         : u8,
+    lcr         : u8,
+    mcr         : u8,
+    lsr         : u8,
+    scr         : u8,
+    fcr         : u8,
+    dll         : u8,
+    dlm         : u8,
+    thre_ip     : bool,     // THR empty interrupt pending (cleared by IIR read or THR write)
+    ticks       : u32,
+}
+
+impl Uart {
+    pub fn new(port: &HostPort) -> io::Result<Uart> {
+        Ok(Uart {
+            host        : Host::open(port)?,
+            rx_fifo     : VecDeque::new(),
+            ier         : 0,
+            lcr         : 0x03,  // 8N1
+            mcr         : 0,
+            lsr         : LSR_THRE | LSR_TEMT,
+            scr         : 0,
+            fcr         : 0,
+            dll         : 0x0C,  // 9600 baud @ 1.8432MHz
+            dlm         : 0,
+            thre_ip     : false,
+            ticks       : 0,
+        })
+    }
+
+    fn fifoDepth(&self) -> usize {
+        if self.fcr & FCR_ENABLE != 0 { FIFO_DEPTH } else { 1 }
+    }
+
+    fn receive(&mut self, b: u8) {
+        if self.rx_fifo.len() >= self.fifoDepth() {
+            self.lsr |= LSR_OE;
+            return;
+        }
+        self.rx_fifo.push_back(b);
+        self.lsr |= LSR_DR;
+    }
+
+    fn transmit(&mut self, b: u8) {
+        if self.mcr & MCR_LOOP != 0 {
+            self.receive(b);
+        } else {
+            let _ = self.host.tx.write_all(&[b]);
+            let _ = self.host.tx.flush();
+        }
+        self.thre_ip = true;
+    }
+
+    // Highest priority pending interrupt identification (c.f., Table 4: Interrupt Control Functions)
+    fn iir(&self) -> u8 {
+        let fifo = if self.fcr & FCR_ENABLE != 0 { IIR_FIFO } else { 0 };
+        if self.ier & IER_ELSI != 0 && self.lsr & LSR_OE != 0 {
+            return fifo | IIR_RLS;
+        }
+        if self.ier & IER_ERBFI != 0 && self.lsr & LSR_DR != 0 {
+            return fifo | IIR_RDA;
+        }
+        if self.ier & IER_ETBEI != 0 && self.thre_ip {
+            return fifo | IIR_THRE;
+        }
+        return fifo | IIR_NONE;
+    }
+
+    fn pollHost(&mut self) {
+        let mut received = vec![];
+        if let Some(rx) = &self.host.rx {
+            while received.len() + self.rx_fifo.len() < self.fifoDepth() {
+                match rx.try_recv() {
+                    Ok(b)   => received.push(b),
+                    Err(_)  => break,
+                }
+            }
+        }
+        for b in received {
+            self.receive(b);
+        }
+    }
+}
+
+impl Device for Uart {
+    fn name(&self) -> &str {
+        "ns16550a"
+    }
+
+    fn size(&self) -> u32 {
+        0x100
+    }
+
+    fn read(&mut self, offset: u32, _width: Width) -> Result<u32, BusError> {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        let v = match offset {
+            RBR_THR_DLL if dlab => self.dll,
+            RBR_THR_DLL         => {
+                let b = self.rx_fifo.pop_front().unwrap_or(0);
+                if self.rx_fifo.is_empty() {
+                    self.lsr &= !LSR_DR;
+                }
+                b
+            },
+            IER_DLM if dlab     => self.dlm,
+            IER_DLM             => self.ier,
+            IIR_FCR             => {
+                let iir = self.iir();
+                if iir & 0x0F == IIR_THRE {
+                    self.thre_ip = false;
+                }
+                iir
+            },
+            LCR                 => self.lcr,
+            MCR                 => self.mcr,
+            LSR                 => {
+                // reading LSR clears the error bits
+                let lsr = self.lsr;
+                self.lsr &= !LSR_OE;
+                lsr
+            },
+            MSR                 => 0xB0,  // DCD | DSR | CTS
+            SCR                 => self.scr,
+            _                   => 0,
+        };
+        Ok(v as u32)
+    }
+
+    fn write(&mut self, offset: u32, _width: Width, value: u32) -> Result<(), BusError> {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        let v = value as u8;
+        match offset {
+            RBR_THR_DLL if dlab => self.dll = v,
+            RBR_THR_DLL         => self.transmit(v),
+            IER_DLM if dlab     => self.dlm = v,
+            IER_DLM             => {
+                // enabling ETBEI while the THR is empty raises the interrupt immediately
+                if v & IER_ETBEI != 0 && self.ier & IER_ETBEI == 0 {
+                    self.thre_ip = true;
+                }
+                self.ier = v & 0x0F;
+            },
+            IIR_FCR             => {
+                if v & FCR_CLEAR_RX != 0 {
+                    self.rx_fifo.clear();
+                    self.lsr &= !LSR_DR;
+                }
+                if v & FCR_CLEAR_TX != 0 {
+                    self.lsr |= LSR_THRE | LSR_TEMT;
+                }
+                self.fcr = v & 0xC9;
+            },
+            LCR                 => self.lcr = v,
+            MCR                 => self.mcr = v & 0x1F,
+            SCR                 => self.scr = v,
+            _                   => {},
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self) {
+        self.ticks += 1;
+        if self.ticks.is_multiple_of(POLL_INTERVAL) {
+            self.pollHost();
+        }
+    }
+
+    fn interrupt(&self) -> bool {
+        self.iir() & IIR_NONE == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::TempDir;
+    use crate::uart::*;
+
+    fn read(u: &mut Uart, offset: u32) -> u8 {
+        u.read(offset, Width::Byte).unwrap() as u8
+    }
+
+    fn write(u: &mut Uart, offset: u32, v: u8) {
+        u.write(offset, Width::Byte, v as u32).unwrap();
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(HostPort::parse("stdio"), Ok(HostPort::Stdio));
+        assert_eq!(HostPort::parse("none"), Ok(HostPort::Null));
+        assert_eq!(HostPort::parse("file:/tmp/x"), Ok(HostPort::File("/tmp/x".to_string())));
+        assert_eq!(HostPort::parse("unix:sock"), Ok(HostPort::Unix("sock".to_string())));
+        assert!(HostPort::parse("tcp:1234").is_err());
+        assert_eq!(HostPort::parse("pty").is_ok(), cfg!(target_os = "linux"));
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut u = Uart::new(&HostPort::Null).unwrap();
+        assert_eq!(read(&mut u, LSR), LSR_THRE | LSR_TEMT);
+        assert_eq!(read(&mut u, IIR_FCR), IIR_NONE);
+        write(&mut u, SCR, 0x5A);
+        assert_eq!(read(&mut u, SCR), 0x5A);
+
+        // DLAB switches offsets 0 and 1 to the divisor latch
+        write(&mut u, LCR, LCR_DLAB | 0x03);
+        write(&mut u, RBR_THR_DLL, 0x01);
+        write(&mut u, IER_DLM, 0x02);
+        assert_eq!((read(&mut u, RBR_THR_DLL), read(&mut u, IER_DLM)), (0x01, 0x02));
+        write(&mut u, LCR, 0x03);
+        assert_eq!(read(&mut u, IER_DLM), 0);
+        write(&mut u, IER_DLM, 0xFF);
+        assert_eq!(read(&mut u, IER_DLM), 0x0F);
+    }
+
+    #[test]
+    fn test_loopback_and_interrupts() {
+        let mut u = Uart::new(&HostPort::Null).unwrap();
+        write(&mut u, MCR, MCR_LOOP);
+
+        // enabling ETBEI with an empty THR raises the interrupt, reading IIR clears it
+        write(&mut u, IER_DLM, IER_ETBEI);
+        assert!(u.interrupt());
+        assert_eq!(read(&mut u, IIR_FCR), IIR_THRE);
+        assert!(!u.interrupt());
+
+        // without the FIFO the receiver holds one byte and a second one overruns
+        write(&mut u, IER_DLM, IER_ERBFI | IER_ELSI);
+        write(&mut u, RBR_THR_DLL, b'a');
+        assert_eq!(read(&mut u, LSR) & (LSR_DR | LSR_OE), LSR_DR);
+        write(&mut u, RBR_THR_DLL, b'b');
+        assert_eq!(read(&mut u, IIR_FCR), IIR_RLS);
+        assert_eq!(read(&mut u, LSR) & (LSR_DR | LSR_OE), LSR_DR | LSR_OE);
+        assert_eq!(read(&mut u, IIR_FCR), IIR_RDA);
+        assert_eq!(read(&mut u, RBR_THR_DLL), b'a');
+        assert_eq!(read(&mut u, LSR) & LSR_DR, 0);
+        assert!(!u.interrupt());
+
+        // with the FIFO enabled up to 16 bytes are queued, and FCR can clear them
+        write(&mut u, IIR_FCR, FCR_ENABLE);
+        assert_eq!(read(&mut u, IIR_FCR), IIR_FIFO | IIR_NONE);
+        for b in b"0123456789abcdef" {
+            write(&mut u, RBR_THR_DLL, *b);
+        }
+        assert_eq!(read(&mut u, LSR) & LSR_OE, 0);
+        assert_eq!((read(&mut u, RBR_THR_DLL), read(&mut u, RBR_THR_DLL)), (b'0', b'1'));
+        write(&mut u, IIR_FCR, FCR_ENABLE | FCR_CLEAR_RX);
+        assert_eq!(read(&mut u, LSR) & LSR_DR, 0);
+        assert_eq!(read(&mut u, RBR_THR_DLL), 0);
+    }
+
+    #[test]
+    fn test_host_ports() {
+        let dir = TempDir::new("uart");
+
+        let path = dir.join("out.txt");
+        let mut u = Uart::new(&HostPort::File(path.to_str().unwrap().to_string())).unwrap();
+        for b in b"hi\n" {
+            write(&mut u, RBR_THR_DLL, *b);
+        }
+        assert_eq!(std::fs::read(&path).unwrap(), b"hi\n");
+
+        // bytes sent by a socket client arrive on the next host poll
+        let path = dir.join("sock");
+        let mut u = Uart::new(&HostPort::Unix(path.to_str().unwrap().to_string())).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"ok").unwrap();
+        let mut received = vec![];
+        for _ in 0..200 {
+            for _ in 0..POLL_INTERVAL {
+                u.tick();
+            }
+            while read(&mut u, LSR) & LSR_DR != 0 {
+                received.push(read(&mut u, RBR_THR_DLL));
+            }
+            if received.len() == 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(received, b"ok");
+
+        // and the guest's output goes back to the client (once the listener has recorded it)
+        client.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+        let mut buf = [0u8; 1];
+        for _ in 0..40 {
+            write(&mut u, RBR_THR_DLL, b'!');
+            if client.read(&mut buf).is_ok() {
+                break;
+            }
+        }
+        assert_eq!(&buf, b"!");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pty() {
+        let (mut master, name) = openPty().unwrap();
+        assert!(name.starts_with("/dev/pts/"), "{}", name);
+        let mut slave = std::fs::OpenOptions::new().read(true).write(true).open(&name).unwrap();
+        slave.write_all(b"ok").unwrap();
+        let mut buf = [0u8; 2];
+        master.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ok");
+    }
+}
@@ -0,0 +1,495 @@
+use std::path::Path;
+
+use crate::asm;
+use crate::bus::Bus;
+use crate::csr;
+use crate::isa::{self, Isa};
+use crate::memory::{self, Memory};
+use crate::register::Reg;
+use crate::CPU;
+
+// Instruction test vectors: TOML files describing the state before some instructions, the
+// instructions, and the state expected after them, so that cases can be added without writing Rust.
+//
+//     [[vector]]
+//     name       = "add wraps around"
+//     code       = ["add a0, a1, a2"]          # assembly, or instruction words such as 0x00c58533
+//     init       = { a1 = 0xffffffff, a2 = 2 }
+//     expect     = { a0 = 1, pc = 0x80000004 }
+//
+// Keys of `init` and `expect` are pc, register names (ABI or x0..x31) and CSR names. `init_mem` and
+// `expect_mem` map word addresses to 32-bit words. The code is placed at `pc` (default 0x80000000)
+// and runs for `steps` steps (default: one per instruction) on a hart with the extensions of `isa`
+// (default: all of them). mtvec is 0 unless set, so a trap ends up with pc = 0 and the cause in mcause.
+//
+// Only the part of TOML these files need is understood: comments, [[vector]] headers, and key = value
+// lines whose values are integers, strings, arrays (which may span lines) and inline tables.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Int(i64),
+    Str(String),
+    Array(Vec<Value>),
+    Table(Vec<(String, Value)>),
+}
+
+struct Parser {
+    chars       : Vec<char>,
+    pos         : usize,
+    line        : usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error<T>(&self, msg: &str) -> Result<T, String> {
+        Err(format!("line {}: {}", self.line, msg))
+    }
+
+    fn skipSpaces(&mut self) {
+        while matches!(self.peek(), Some(' ') | Some('\t') | Some('\r')) {
+            self.bump();
+        }
+    }
+
+    // Spaces, newlines and comments (between the elements of an array).
+    fn skipBlank(&mut self) {
+        loop {
+            self.skipSpaces();
+            match self.peek() {
+                Some('\n')  => { self.bump(); },
+                Some('#')   => while !matches!(self.peek(), None | Some('\n')) { self.bump(); },
+                _           => return,
+            }
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.bump();
+            Ok(())
+        } else {
+            self.error(&format!("expected '{}'", c))
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        if self.peek() == Some('"') {
+            return self.string();
+        }
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            self.bump();
+        }
+        if self.pos == start {
+            return self.error("expected a key");
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('"')   => return Ok(s),
+                Some('\\')  => s.push(match self.bump() {
+                    Some('n')   => '\n',
+                    Some('t')   => '\t',
+                    Some(c)     => c,
+                    None        => return self.error("unterminated string"),
+                }),
+                Some('\n') | None => return self.error("unterminated string"),
+                Some(c)     => s.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('"')   => self.string().map(Value::Str),
+            Some('[')   => {
+                self.bump();
+                let mut items = vec![];
+                loop {
+                    self.skipBlank();
+                    if self.peek() == Some(']') {
+                        self.bump();
+                        return Ok(Value::Array(items));
+                    }
+                    items.push(self.value()?);
+                    self.skipBlank();
+                    match self.peek() {
+                        Some(',')   => { self.bump(); },
+                        Some(']')   => {},
+                        _           => return self.error("expected ',' or ']'"),
+                    }
+                }
+            },
+            Some('{')   => {
+                self.bump();
+                let mut entries = vec![];
+                loop {
+                    self.skipSpaces();
+                    if self.peek() == Some('}') {
+                        self.bump();
+                        return Ok(Value::Table(entries));
+                    }
+                    let k = self.key()?;
+                    self.skipSpaces();
+                    self.expect('=')?;
+                    self.skipSpaces();
+                    entries.push((k, self.value()?));
+                    self.skipSpaces();
+                    match self.peek() {
+                        Some(',')   => { self.bump(); },
+                        Some('}')   => {},
+                        _           => return self.error("expected ',' or '}'"),
+                    }
+                }
+            },
+            _           => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '+') {
+                    self.bump();
+                }
+                let word: String = self.chars[start..self.pos].iter().filter(|&&c| c != '_').collect();
+                let (neg, digits) = match word.strip_prefix('-') {
+                    Some(d) => (true, d.to_string()),
+                    None    => (false, word.trim_start_matches('+').to_string()),
+                };
+                let n = if let Some(h) = digits.strip_prefix("0x") {
+                    i64::from_str_radix(h, 16)
+                } else if let Some(b) = digits.strip_prefix("0b") {
+                    i64::from_str_radix(b, 2)
+                } else {
+                    digits.parse()
+                };
+                match n {
+                    Ok(n)   => Ok(Value::Int(if neg { -n } else { n })),
+                    Err(_)  => self.error(&format!("bad value '{}'", word)),
+                }
+            },
+        }
+    }
+
+    // The [[vector]] tables of a file.
+    fn document(&mut self) -> Result<Vec<Vec<(String, Value)>>, String> {
+        let mut tables: Vec<Vec<(String, Value)>> = vec![];
+        loop {
+            self.skipBlank();
+            if self.peek().is_none() {
+                return Ok(tables);
+            }
+            if self.peek() == Some('[') {
+                self.bump();
+                self.expect('[')?;
+                if self.key()? != "vector" {
+                    return self.error("only [[vector]] tables are supported");
+                }
+                self.expect(']')?;
+                self.expect(']')?;
+                tables.push(vec![]);
+            } else {
+                let k = self.key()?;
+                self.skipSpaces();
+                self.expect('=')?;
+                self.skipSpaces();
+                let v = self.value()?;
+                match tables.last_mut() {
+                    Some(t) => t.push((k, v)),
+                    None    => return self.error("key outside of a [[vector]] table"),
+                }
+            }
+            self.skipSpaces();
+            if self.peek() == Some('#') {
+                self.skipBlank();
+            } else if !matches!(self.peek(), None | Some('\n')) {
+                return self.error("expected the end of the line");
+            }
+        }
+    }
+}
+
+// What a key of `init` or `expect` names.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Pc,
+    Reg(u32),
+    Csr(u32),
+}
+
+impl Target {
+    fn parse(name: &str) -> Result<Target, String> {
+        if name == "pc" {
+            return Ok(Target::Pc);
+        }
+        if let Some(r) = Reg::parse(name) {
+            return Ok(Target::Reg(r));
+        }
+        csr::parse(name).map(Target::Csr).ok_or(format!("unknown register '{}'", name))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vector {
+    pub name        : String,
+    pub pc          : u32,
+    pub code        : Vec<u8>,
+    pub steps       : u32,
+    pub isa         : Isa,
+    pub init        : Vec<(String, Target, u32)>,
+    pub init_mem    : Vec<(u32, u32)>,
+    pub expect      : Vec<(String, Target, u32)>,
+    pub expect_mem  : Vec<(u32, u32)>,
+}
+
+fn word(v: &Value, what: &str) -> Result<u32, String> {
+    match v {
+        Value::Int(n) if *n >= -(1 << 31) && *n < (1 << 32) => Ok(*n as u32),
+        _   => Err(format!("{}: expected a 32-bit integer", what)),
+    }
+}
+
+fn table<'a>(v: &'a Value, what: &str) -> Result<&'a [(String, Value)], String> {
+    match v {
+        Value::Table(t) => Ok(t),
+        _               => Err(format!("{}: expected an inline table", what)),
+    }
+}
+
+fn registers(v: &Value, what: &str) -> Result<Vec<(String, Target, u32)>, String> {
+    table(v, what)?.iter().map(|(k, v)| Ok((k.clone(), Target::parse(k)?, word(v, k)?))).collect()
+}
+
+fn words(v: &Value, what: &str) -> Result<Vec<(u32, u32)>, String> {
+    table(v, what)?.iter().map(|(k, v)| {
+        let addr = match k.strip_prefix("0x") {
+            Some(h) => u32::from_str_radix(&h.replace('_', ""), 16).ok(),
+            None    => k.parse().ok(),
+        };
+        Ok((addr.ok_or(format!("{}: bad address '{}'", what, k))?, word(v, k)?))
+    }).collect()
+}
+
+impl Vector {
+    fn fromTable(t: &[(String, Value)]) -> Result<Vector, String> {
+        let get = |key: &str| t.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+        if let Some((k, _)) = t.iter().find(|(k, _)| !matches!(k.as_str(), "name" | "pc" | "code" | "steps" | "isa" | "init" | "init_mem" | "expect" | "expect_mem")) {
+            return Err(format!("unknown key '{}'", k));
+        }
+        let name = match get("name") {
+            Some(Value::Str(s)) => s.clone(),
+            _                   => return Err("a vector needs a name".to_string()),
+        };
+        let context = |e: String| format!("{}: {}", name, e);
+        let pc = get("pc").map(|v| word(v, "pc")).transpose().map_err(context)?.unwrap_or(memory::RAM_BASE);
+
+        // each element of code is assembled (or stored) at the address after the previous one
+        let mut code = vec![];
+        let items = match get("code") {
+            Some(Value::Array(items))   => items.clone(),
+            Some(v @ Value::Str(_))     => vec![v.clone()],
+            _                           => return Err(context("code must be an array of instructions".to_string())),
+        };
+        for item in &items {
+            let addr = pc.wrapping_add(code.len() as u32);
+            match item {
+                Value::Str(src) => code.extend(asm::assembleAt(src, addr).map_err(|e| context(format!("{}: {}", src, e)))?.image),
+                v               => code.extend(word(v, "code").map_err(context)?.to_le_bytes()),
+            }
+        }
+
+        let steps = match get("steps") {
+            Some(v) => word(v, "steps").map_err(context)?,
+            None    => (code.len() / 4) as u32,
+        };
+        let isa = match get("isa") {
+            Some(Value::Str(s)) => Isa::parse(s).and_then(|isa| isa.check().map(|_| isa)).map_err(context)?,
+            Some(_)             => return Err(context("isa must be a string".to_string())),
+            None                => Isa::parse(isa::DEFAULT).unwrap(),
+        };
+        let empty = Value::Table(vec![]);
+        Ok(Vector {
+            init        : registers(get("init").unwrap_or(&empty), "init").map_err(context)?,
+            init_mem    : words(get("init_mem").unwrap_or(&empty), "init_mem").map_err(context)?,
+            expect      : registers(get("expect").unwrap_or(&empty), "expect").map_err(context)?,
+            expect_mem  : words(get("expect_mem").unwrap_or(&empty), "expect_mem").map_err(context)?,
+            name        : name,
+            pc          : pc,
+            code        : code,
+            steps       : steps,
+            isa         : isa,
+        })
+    }
+
+    // Run the vector on a fresh hart with RAM at RAM_BASE. Returns the fields that do not have their
+    // expected values, one message each.
+    pub fn run(&self) -> Result<Vec<String>, String> {
+        let mut bus = Bus::new();
+        bus.attach(memory::RAM_BASE, Box::new(Memory::new())).unwrap();
+        bus.poke(self.pc, &self.code).map_err(|e| format!("code at 0x{:08x}: {}", self.pc, e))?;
+        for (addr, w) in &self.init_mem {
+            bus.poke(*addr, &w.to_le_bytes()).map_err(|e| format!("init_mem 0x{:08x}: {}", addr, e))?;
+        }
+
+        let mut cpu = CPU::withIsa(bus, self.pc, self.isa.clone());
+        for (_, t, v) in &self.init {
+            match *t {
+                Target::Pc      => cpu.register().setPC(*v),
+                Target::Reg(r)  => cpu.register().setReg(r, *v),
+                Target::Csr(a)  => cpu.csr().writeCsr(a, *v),
+            }
+        }
+        for _ in 0..self.steps {
+            if cpu.step().is_some() {
+                break;
+            }
+        }
+
+        let mut mismatches = vec![];
+        for (name, t, want) in &self.expect {
+            let got = match *t {
+                Target::Pc      => cpu.register().getPC(),
+                Target::Reg(r)  => cpu.register().getReg(r),
+                Target::Csr(a)  => cpu.csr().readCsr(a),
+            };
+            if got != *want {
+                mismatches.push(format!("{}: expected 0x{:08x}, got 0x{:08x}", name, want, got));
+            }
+        }
+        for (addr, want) in &self.expect_mem {
+            match cpu.bus().peek(*addr, 4) {
+                Ok(b) if u32::from_le_bytes([b[0], b[1], b[2], b[3]]) == *want => {},
+                Ok(b)   => mismatches.push(format!("mem[0x{:08x}]: expected 0x{:08x}, got 0x{:08x}", addr, want, u32::from_le_bytes([b[0], b[1], b[2], b[3]]))),
+                Err(e)  => mismatches.push(format!("mem[0x{:08x}]: {}", addr, e)),
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+pub fn parse(text: &str) -> Result<Vec<Vector>, String> {
+    let mut p = Parser { chars: text.chars().collect(), pos: 0, line: 1 };
+    p.document()?.iter().map(|t| Vector::fromTable(t)).collect()
+}
+
+// Run the vectors of a file; returns (passed, failed) and prints a line per failure.
+pub fn runFile(path: &Path) -> Result<(usize, usize), String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let vectors = parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let (mut passed, mut failed) = (0, 0);
+    for v in &vectors {
+        match v.run() {
+            Ok(m) if m.is_empty()   => passed += 1,
+            Ok(m)                   => {
+                failed += 1;
+                println!("FAIL {}: {}", path.display(), v.name);
+                for line in m {
+                    println!("    {}", line);
+                }
+            },
+            Err(e)                  => {
+                failed += 1;
+                println!("FAIL {}: {}: {}", path.display(), v.name, e);
+            },
+        }
+    }
+    Ok((passed, failed))
+}
+
+// `vectors` subcommand: run the vector files given, or the *.toml files of the directories given.
+pub fn main(args: &[String]) -> i32 {
+    if args.len() < 3 {
+        eprintln!("usage: {} vectors <file.toml|directory>...", args[0]);
+        return 1;
+    }
+    let mut files = vec![];
+    for a in &args[2..] {
+        let path = Path::new(a);
+        if path.is_dir() {
+            let mut found: Vec<_> = std::fs::read_dir(path).map(|d| d.filter_map(|e| e.ok()).map(|e| e.path())
+                .filter(|p| p.extension().is_some_and(|x| x == "toml")).collect()).unwrap_or_default();
+            found.sort();
+            files.extend(found);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    let (mut passed, mut failed) = (0, 0);
+    for f in &files {
+        match runFile(f) {
+            Ok((p, n))  => {
+                passed += p;
+                failed += n;
+            },
+            Err(e)      => {
+                eprintln!("{}", e);
+                return 1;
+            },
+        }
+    }
+    println!("{} vectors: {} passed, {} failed", passed + failed, passed, failed);
+    if failed == 0 { 0 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vectors::*;
+
+    #[test]
+    fn test_parse() {
+        let text = r#"
+# comment
+[[vector]]
+name   = "li and store"
+code   = [
+    "li a0, -1",        # assembly
+    0x00a5a023,         # sw a0, 0(a1)
+]
+init   = { a1 = 0x8000_1000 }
+expect = { a0 = -1, pc = 0x80000008 }
+expect_mem = { 0x80001000 = 0xffffffff }
+"#;
+        let v = &parse(text).unwrap()[0];
+        assert_eq!(v.name, "li and store");
+        assert_eq!(v.code, vec![0x13, 0x05, 0xf0, 0xff, 0x23, 0xa0, 0xa5, 0x00]);
+        assert_eq!(v.steps, 2);
+        assert_eq!(v.init, vec![("a1".to_string(), Target::Reg(11), 0x8000_1000)]);
+        assert_eq!(v.expect[1], ("pc".to_string(), Target::Pc, 0x8000_0008));
+        assert_eq!(v.run().unwrap(), Vec::<String>::new());
+
+        assert_eq!(parse("[[vector]]\nname = \"x\"\ncode = [1]\nexpect = { q9 = 1 }\n").unwrap_err(), "x: unknown register 'q9'");
+        assert_eq!(parse("name = \"x\"\n").unwrap_err(), "line 1: key outside of a [[vector]] table");
+    }
+
+    #[test]
+    fn test_mismatch_report() {
+        let v = &parse("[[vector]]\nname = \"add\"\ncode = [\"add a0, a1, a2\"]\ninit = { a1 = 1, a2 = 2 }\nexpect = { a0 = 4 }\n").unwrap()[0];
+        assert_eq!(v.run().unwrap(), vec!["a0: expected 0x00000004, got 0x00000003"]);
+    }
+
+    // Every vector file of the repository (tests/vectors).
+    #[test]
+    fn test_vector_files() {
+        let dir = Path::new(option_env!("CARGO_MANIFEST_DIR").unwrap_or(".")).join("tests/vectors");
+        let mut files: Vec<_> = std::fs::read_dir(&dir).unwrap().filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        files.sort();
+        let mut failed = 0;
+        for f in files.iter().filter(|p| p.extension().is_some_and(|x| x == "toml")) {
+            failed += runFile(f).unwrap().1;
+        }
+        assert_eq!(failed, 0);
+    }
+}
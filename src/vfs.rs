@@ -0,0 +1,821 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::raw::{c_char, c_void};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// c.f., include/uapi/asm-generic/errno-base.h and errno.h
+const ENOENT            : i32 = 2;
+const EEXIST            : i32 = 17;
+const ENOTDIR           : i32 = 20;
+const EISDIR            : i32 = 21;
+const EINVAL            : i32 = 22;
+const EROFS             : i32 = 30;
+const ENOTEMPTY         : i32 = 39;
+const ELOOP             : i32 = 40;
+
+// Host open flags (c.f., include/uapi/asm-generic/fcntl.h; arm and arm64 move O_NOFOLLOW).
+const O_RDONLY          : i32 = 0o0;
+const O_WRONLY          : i32 = 0o1;
+const O_RDWR            : i32 = 0o2;
+const O_CREAT           : i32 = 0o100;
+const O_EXCL            : i32 = 0o200;
+const O_TRUNC           : i32 = 0o1000;
+const O_APPEND          : i32 = 0o2000;
+const O_CLOEXEC         : i32 = 0o2000000;
+#[cfg(target_os = "linux")]
+const O_PATH            : i32 = 0o10000000;
+#[cfg(not(any(target_arch = "arm", target_arch = "aarch64")))]
+const O_NOFOLLOW        : i32 = 0o400000;
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+const O_NOFOLLOW        : i32 = 0o100000;
+const AT_REMOVEDIR      : i32 = 0x200;
+
+// Symlinks followed while resolving one path (c.f., MAXSYMLINKS in include/linux/namei.h).
+const MAX_SYMLINKS      : usize = 40;
+
+// c.f., include/uapi/linux/stat.h
+pub const S_IFMT        : u32 = 0o170000;
+pub const S_IFDIR       : u32 = 0o040000;
+pub const S_IFREG       : u32 = 0o100000;
+
+// Inode numbers handed out to overlay files, well away from anything a host filesystem uses.
+const OVERLAY_INO       : u64 = 0xFFFF_0000_0000;
+
+fn err(errno: i32) -> io::Error {
+    io::Error::from_raw_os_error(errno)
+}
+
+// A host directory visible to the guest at `guest`.
+#[derive(Debug, Clone)]
+pub struct Mount {
+    pub guest       : PathBuf,
+    pub host        : PathBuf,
+    pub readonly    : bool,
+}
+
+impl Mount {
+    // HOST:GUEST[:ro]
+    pub fn parse(s: &str) -> Result<Mount, String> {
+        let parts: Vec<&str> = s.split(':').collect();
+        match parts.as_slice() {
+            [host, guest]           => Ok(Mount { guest: PathBuf::from(guest), host: PathBuf::from(host), readonly: false }),
+            [host, guest, "ro"]     => Ok(Mount { guest: PathBuf::from(guest), host: PathBuf::from(host), readonly: true }),
+            [host, guest, "rw"]     => Ok(Mount { guest: PathBuf::from(guest), host: PathBuf::from(host), readonly: false }),
+            _                       => Err(format!("invalid mount '{}' (expected HOST:GUEST[:ro])", s)),
+        }
+    }
+}
+
+// File attributes in the form both the syscall layer (struct stat64 / statx) and semihosting need.
+#[derive(Debug, Clone, Default)]
+pub struct Stat {
+    pub dev         : u64,
+    pub ino         : u64,
+    pub mode        : u32,
+    pub nlink       : u32,
+    pub uid         : u32,
+    pub gid         : u32,
+    pub rdev        : u64,
+    pub size        : u64,
+    pub blksize     : u32,
+    pub blocks      : u64,
+    pub atime       : (i64, u32),
+    pub mtime       : (i64, u32),
+    pub ctime       : (i64, u32),
+}
+
+impl Stat {
+    pub fn fromMetadata(m: &fs::Metadata) -> Stat {
+        Stat {
+            dev         : m.dev(),
+            ino         : m.ino(),
+            mode        : m.mode(),
+            nlink       : m.nlink() as u32,
+            uid         : m.uid(),
+            gid         : m.gid(),
+            rdev        : m.rdev(),
+            size        : m.size(),
+            blksize     : m.blksize() as u32,
+            blocks      : m.blocks(),
+            atime       : (m.atime(), m.atime_nsec() as u32),
+            mtime       : (m.mtime(), m.mtime_nsec() as u32),
+            ctime       : (m.ctime(), m.ctime_nsec() as u32),
+        }
+    }
+
+    pub fn isDir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+}
+
+// How a file is opened, decoded from the O_* flags or the semihosting fopen() mode.
+#[derive(Debug, Clone, Default)]
+pub struct OpenFlags {
+    pub read        : bool,
+    pub write       : bool,
+    pub append      : bool,
+    pub create      : bool,
+    pub excl        : bool,
+    pub truncate    : bool,
+}
+
+impl OpenFlags {
+    fn modifies(&self) -> bool {
+        self.write || self.append || self.create || self.truncate
+    }
+
+    fn host(&self) -> i32 {
+        let access = match (self.read, self.write || self.append) {
+            (true, true)    => O_RDWR,
+            (false, true)   => O_WRONLY,
+            _               => O_RDONLY,
+        };
+        let mut flags = access;
+        if self.append {
+            flags |= O_APPEND;
+        }
+        if self.create {
+            flags |= if self.excl { O_CREAT | O_EXCL } else { O_CREAT };
+        }
+        if self.truncate {
+            flags |= O_TRUNC;
+        }
+        flags
+    }
+}
+
+// A file kept in memory by the overlay.
+#[derive(Debug)]
+pub struct Node {
+    data            : Vec<u8>,
+    ino             : u64,
+    mtime           : (i64, u32),
+}
+
+#[derive(Debug, Clone)]
+enum Entry {
+    File(Rc<RefCell<Node>>),
+    Dir(u64),
+    Whiteout,           // deleted in the overlay, hides the host file
+}
+
+// An open file: either a host file or an overlay file with its own position.
+#[derive(Debug)]
+pub enum VFile {
+    Host(fs::File),
+    Overlay { node: Rc<RefCell<Node>>, pos: u64, append: bool },
+    Dir(Stat),
+}
+
+impl VFile {
+    pub fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            VFile::Host(f)                      => f.read(buf),
+            VFile::Overlay { node, pos, .. }    => {
+                let data = &node.borrow().data;
+                let start = (*pos as usize).min(data.len());
+                let n = (data.len() - start).min(buf.len());
+                buf[..n].copy_from_slice(&data[start..start + n]);
+                *pos += n as u64;
+                Ok(n)
+            },
+            VFile::Dir(_)                       => Err(err(EISDIR)),
+        }
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            VFile::Host(f)                          => f.write(buf),
+            VFile::Overlay { node, pos, append }    => {
+                let mut n = node.borrow_mut();
+                if *append {
+                    *pos = n.data.len() as u64;
+                }
+                let end = *pos as usize + buf.len();
+                if n.data.len() < end {
+                    n.data.resize(end, 0);
+                }
+                n.data[*pos as usize..end].copy_from_slice(buf);
+                n.mtime = now();
+                *pos = end as u64;
+                Ok(buf.len())
+            },
+            VFile::Dir(_)                           => Err(err(EISDIR)),
+        }
+    }
+
+    pub fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        match self {
+            VFile::Host(f)                      => f.seek(to),
+            VFile::Overlay { node, pos, .. }    => {
+                let new = match to {
+                    SeekFrom::Start(p)      => p as i64,
+                    SeekFrom::Current(d)    => *pos as i64 + d,
+                    SeekFrom::End(d)        => node.borrow().data.len() as i64 + d,
+                };
+                if new < 0 {
+                    return Err(err(EINVAL));
+                }
+                *pos = new as u64;
+                Ok(*pos)
+            },
+            VFile::Dir(_)                       => Err(err(EISDIR)),
+        }
+    }
+
+    pub fn stat(&self) -> io::Result<Stat> {
+        match self {
+            VFile::Host(f)                      => f.metadata().map(|m| Stat::fromMetadata(&m)),
+            VFile::Overlay { node, .. }         => Ok(nodeStat(&node.borrow())),
+            VFile::Dir(s)                       => Ok(s.clone()),
+        }
+    }
+}
+
+fn now() -> (i64, u32) {
+    let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    (t.as_secs() as i64, t.subsec_nanos())
+}
+
+fn nodeStat(n: &Node) -> Stat {
+    Stat {
+        ino         : n.ino,
+        mode        : S_IFREG | 0o644,
+        nlink       : 1,
+        size        : n.data.len() as u64,
+        blksize     : 4096,
+        blocks      : (n.data.len() as u64).div_ceil(512),
+        atime       : n.mtime,
+        mtime       : n.mtime,
+        ctime       : n.mtime,
+        ..Default::default()
+    }
+}
+
+fn dirStat(ino: u64) -> Stat {
+    Stat { ino: ino, mode: S_IFDIR | 0o755, nlink: 2, blksize: 4096, ..Default::default() }
+}
+
+// The host side relies on O_PATH, the *at() calls and the struct dirent layout of 64-bit Linux.
+#[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+compile_error!("the VFS is only supported on 64-bit Linux hosts");
+
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+extern "C" {
+    fn openat(dirfd: i32, path: *const c_char, flags: i32, ...) -> i32;
+    fn readlinkat(dirfd: i32, path: *const c_char, buf: *mut c_char, size: usize) -> isize;
+    fn mkdirat(dirfd: i32, path: *const c_char, mode: u32) -> i32;
+    fn unlinkat(dirfd: i32, path: *const c_char, flags: i32) -> i32;
+    fn renameat(olddirfd: i32, oldpath: *const c_char, newdirfd: i32, newpath: *const c_char) -> i32;
+    fn fdopendir(fd: i32) -> *mut c_void;
+    fn readdir(dir: *mut c_void) -> *const Dirent;
+    fn closedir(dir: *mut c_void) -> i32;
+}
+
+// struct dirent of glibc and musl on 64-bit hosts
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+#[repr(C)]
+struct Dirent {
+    d_ino           : u64,
+    d_off           : i64,
+    d_reclen        : u16,
+    d_type          : u8,
+    d_name          : [c_char; 256],
+}
+
+fn cName(name: &OsStr) -> io::Result<CString> {
+    CString::new(name.as_bytes()).map_err(|_| err(EINVAL))
+}
+
+fn check(r: i32) -> io::Result<()> {
+    if r < 0 { Err(io::Error::last_os_error()) } else { Ok(()) }
+}
+
+// openat(2) in an open directory. A symlink as `name` is never followed.
+fn openAt(dir: &fs::File, name: &OsStr, flags: i32, mode: u32) -> io::Result<fs::File> {
+    let name = cName(name)?;
+    let fd = unsafe { openat(dir.as_raw_fd(), name.as_ptr(), flags | O_NOFOLLOW | O_CLOEXEC, mode) };
+    check(fd)?;
+    Ok(unsafe { fs::File::from_raw_fd(fd) })
+}
+
+fn readlinkAt(dir: &fs::File, name: &OsStr) -> io::Result<PathBuf> {
+    let name = cName(name)?;
+    let mut buf = vec![0u8; 4096];
+    let n = unsafe { readlinkat(dir.as_raw_fd(), name.as_ptr(), buf.as_mut_ptr() as *mut c_char, buf.len()) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    buf.truncate(n as usize);
+    Ok(PathBuf::from(OsString::from_vec(buf)))
+}
+
+// The entries of the directory `name`, without "." and "..".
+fn listAt(dir: &fs::File, name: &OsStr) -> io::Result<Vec<OsString>> {
+    let fd = openAt(dir, name, O_RDONLY, 0)?.into_raw_fd();
+    unsafe {
+        let d = fdopendir(fd);
+        if d.is_null() {
+            let e = io::Error::last_os_error();
+            drop(fs::File::from_raw_fd(fd));
+            return Err(e);
+        }
+        let mut names = vec![];
+        loop {
+            let e = readdir(d);
+            if e.is_null() {
+                break;
+            }
+            let n = CStr::from_ptr((*e).d_name.as_ptr()).to_bytes();
+            if n != b"." && n != b".." {
+                names.push(OsStr::from_bytes(n).to_os_string());
+            }
+        }
+        closedir(d);
+        Ok(names)
+    }
+}
+
+fn readAll(mut f: fs::File) -> io::Result<Vec<u8>> {
+    let mut data = vec![];
+    f.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+// A guest path resolved on the host: the directory it is in, held open, and its last component.
+// `dir` is None when that directory does not exist on the host (it may in the overlay).
+struct Resolved {
+    dir             : Option<fs::File>,
+    name            : OsString,
+    host            : PathBuf,              // the host path, with symlinks resolved (keys the overlay)
+    readonly        : bool,
+}
+
+impl Resolved {
+    fn dir(&self) -> io::Result<&fs::File> {
+        self.dir.as_ref().ok_or_else(|| err(ENOENT))
+    }
+
+    fn open(&self, flags: i32, mode: u32) -> io::Result<fs::File> {
+        openAt(self.dir()?, &self.name, flags, mode)
+    }
+
+    // lstat(): a symlink here was not to be followed
+    fn metadata(&self) -> io::Result<fs::Metadata> {
+        self.open(O_PATH, 0)?.metadata()
+    }
+}
+
+// Guest view of the filesystem. Guest paths are resolved against the mount table (the longest
+// matching mount wins) and may not leave the host directory of their mount, neither with ".." nor
+// through symlinks (see resolve()). With the overlay enabled, host files are never modified: writes, creations and
+// deletions are kept in memory and vanish when the simulation ends.
+#[derive(Debug)]
+pub struct Vfs {
+    mounts          : Vec<Mount>,
+    cwd             : PathBuf,
+    overlay         : Option<HashMap<PathBuf, Entry>>,
+    next_ino        : u64,
+}
+
+impl Vfs {
+    // The host root as guest "/", starting in the simulator's working directory: no sandboxing.
+    pub fn host() -> Vfs {
+        let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+        Vfs::new(Path::new("/"), cwd)
+    }
+
+    // `root` becomes the guest's "/" and the guest starts in `cwd` (a guest path).
+    pub fn new(root: &Path, cwd: PathBuf) -> Vfs {
+        Vfs {
+            mounts      : vec![Mount { guest: PathBuf::from("/"), host: root.to_path_buf(), readonly: false }],
+            cwd         : cwd,
+            overlay     : None,
+            next_ino    : OVERLAY_INO,
+        }
+    }
+
+    pub fn mount(&mut self, m: Mount) {
+        let guest = normalize(Path::new("/"), &m.guest);
+        self.mounts.retain(|x| x.guest != guest);
+        self.mounts.push(Mount { guest: guest, ..m });
+    }
+
+    pub fn setReadonlyRoot(&mut self, readonly: bool) {
+        for m in self.mounts.iter_mut().filter(|m| m.guest == Path::new("/")) {
+            m.readonly = readonly;
+        }
+    }
+
+    pub fn enableOverlay(&mut self) {
+        self.overlay = Some(HashMap::new());
+    }
+
+    pub fn cwd(&self) -> &Path {
+        &self.cwd
+    }
+
+    pub fn chdir(&mut self, path: &str) -> io::Result<()> {
+        let guest = normalize(&self.cwd, Path::new(path));
+        if !self.stat(path, true)?.isDir() {
+            return Err(err(ENOTDIR));
+        }
+        self.cwd = guest;
+        Ok(())
+    }
+
+    // Guest path -> host location. The path is walked a component at a time from the host directory of
+    // its mount, holding every directory open and never letting the host follow a symlink: symlinks are
+    // read and resolved here, absolute ones against the mount's host directory, and ".." stops there
+    // (as RESOLVE_IN_ROOT of openat2()), so a path cannot leave its mount, nor be redirected between the
+    // check and the use. A symlink as the last component is followed only with `follow`.
+    fn resolve(&self, path: &str, follow: bool) -> io::Result<Resolved> {
+        let guest = normalize(&self.cwd, Path::new(path));
+        let m = self.mounts.iter()
+            .filter(|m| guest.starts_with(&m.guest))
+            .max_by_key(|m| m.guest.components().count())
+            .ok_or_else(|| err(ENOENT))?;
+        let root = fs::OpenOptions::new().read(true).custom_flags(O_PATH | O_CLOEXEC).open(&m.host)?;
+
+        // dirs[i] is the directory names[..i], as long as the host has it
+        let mut todo: Vec<OsString> = guest.strip_prefix(&m.guest).unwrap().iter().rev().map(|c| c.to_os_string()).collect();
+        let mut dirs = vec![root];
+        let mut names: Vec<OsString> = vec![];
+        let mut links = 0;
+        while let Some(c) = todo.pop() {
+            if c == ".." {
+                if dirs.len() > names.len() && !names.is_empty() {
+                    dirs.pop();
+                }
+                names.pop();
+                continue;
+            }
+            let last = todo.is_empty();
+            if dirs.len() <= names.len() {
+                // below a directory that is not on the host: nothing to follow
+                names.push(c);
+                continue;
+            }
+            let dir = dirs.last().unwrap();
+            let f = match openAt(dir, &c, O_PATH, 0) {
+                Ok(f)                                       => f,
+                Err(e) if e.raw_os_error() == Some(ENOENT)  => {
+                    names.push(c);
+                    continue;
+                },
+                Err(e)                                      => return Err(e),
+            };
+            let t = f.metadata()?.file_type();
+            if t.is_symlink() && (follow || !last) {
+                links += 1;
+                if links > MAX_SYMLINKS {
+                    return Err(err(ELOOP));
+                }
+                let target = readlinkAt(dir, &c)?;
+                if target.is_absolute() {
+                    dirs.truncate(1);
+                    names.clear();
+                }
+                todo.extend(target.components().rev().filter_map(|c| match c {
+                    Component::Normal(n)    => Some(n.to_os_string()),
+                    Component::ParentDir    => Some(OsString::from("..")),
+                    _                       => None,
+                }));
+            } else if last {
+                names.push(c);
+            } else if t.is_dir() {
+                dirs.push(f);
+                names.push(c);
+            } else {
+                return Err(err(ENOTDIR));
+            }
+        }
+
+        let host = names.iter().fold(m.host.clone(), |p, n| p.join(n));
+        let (dir, name) = match names.pop() {
+            Some(name)  => (dirs.into_iter().nth(names.len()), name),
+            None        => (dirs.into_iter().next(), OsString::from(".")),
+        };
+        Ok(Resolved { dir: dir, name: name, host: host, readonly: m.readonly })
+    }
+
+    fn entry(&self, host: &Path) -> Option<Entry> {
+        self.overlay.as_ref().and_then(|o| o.get(host).cloned())
+    }
+
+    fn newNode(&mut self, data: Vec<u8>) -> Rc<RefCell<Node>> {
+        self.next_ino += 1;
+        Rc::new(RefCell::new(Node { data: data, ino: self.next_ino, mtime: now() }))
+    }
+
+    fn insert(&mut self, host: PathBuf, e: Entry) {
+        if let Some(o) = self.overlay.as_mut() {
+            o.insert(host, e);
+        }
+    }
+
+    pub fn open(&mut self, path: &str, flags: &OpenFlags, mode: u32) -> io::Result<VFile> {
+        let r = self.resolve(path, true)?;
+        if r.readonly && flags.modifies() {
+            return Err(err(EROFS));
+        }
+        if self.overlay.is_none() {
+            return r.open(flags.host(), mode).map(VFile::Host);
+        }
+
+        let node = match self.entry(&r.host) {
+            Some(Entry::File(n))    => {
+                if flags.create && flags.excl {
+                    return Err(err(EEXIST));
+                }
+                if flags.truncate {
+                    n.borrow_mut().data.clear();
+                }
+                n
+            },
+            Some(Entry::Dir(ino))   => {
+                if flags.modifies() {
+                    return Err(err(EISDIR));
+                }
+                return Ok(VFile::Dir(dirStat(ino)));
+            },
+            Some(Entry::Whiteout)   => {
+                if !flags.create {
+                    return Err(err(ENOENT));
+                }
+                let n = self.newNode(vec![]);
+                self.insert(r.host, Entry::File(n.clone()));
+                n
+            },
+            None if !flags.modifies() => return r.open(O_RDONLY, 0).map(VFile::Host),
+            None                    => {
+                // copy-up: the first modification pulls the host file into memory
+                let data = match r.metadata() {
+                    Ok(m) if m.is_dir()     => return Err(err(EISDIR)),
+                    Ok(_) if flags.create && flags.excl => return Err(err(EEXIST)),
+                    Ok(_) if flags.truncate => vec![],
+                    Ok(_)                   => readAll(r.open(O_RDONLY, 0)?)?,
+                    Err(_) if flags.create  => vec![],
+                    Err(e)                  => return Err(e),
+                };
+                let n = self.newNode(data);
+                self.insert(r.host, Entry::File(n.clone()));
+                n
+            },
+        };
+        Ok(VFile::Overlay { node: node, pos: 0, append: flags.append })
+    }
+
+    pub fn stat(&self, path: &str, follow: bool) -> io::Result<Stat> {
+        self.statResolved(&self.resolve(path, follow)?)
+    }
+
+    fn statResolved(&self, r: &Resolved) -> io::Result<Stat> {
+        match self.entry(&r.host) {
+            Some(Entry::File(n))    => Ok(nodeStat(&n.borrow())),
+            Some(Entry::Dir(ino))   => Ok(dirStat(ino)),
+            Some(Entry::Whiteout)   => Err(err(ENOENT)),
+            None                    => r.metadata().map(|m| Stat::fromMetadata(&m)),
+        }
+    }
+
+    // Target of the symlink `path`, as stored in the link (EINVAL if it is not a symlink).
+    pub fn readlink(&self, path: &str) -> io::Result<PathBuf> {
+        let r = self.resolve(path, false)?;
+        match self.entry(&r.host) {
+            Some(Entry::Whiteout)   => Err(err(ENOENT)),
+            Some(_)                 => Err(err(EINVAL)),
+            None                    => readlinkAt(r.dir()?, &r.name),
+        }
+    }
+
+    pub fn unlink(&mut self, path: &str) -> io::Result<()> {
+        self.remove(path, false)
+    }
+
+    pub fn rmdir(&mut self, path: &str) -> io::Result<()> {
+        self.remove(path, true)
+    }
+
+    fn remove(&mut self, path: &str, dir: bool) -> io::Result<()> {
+        let r = self.resolve(path, false)?;
+        if r.readonly {
+            return Err(err(EROFS));
+        }
+        if self.overlay.is_none() {
+            let name = cName(&r.name)?;
+            return check(unsafe { unlinkat(r.dir()?.as_raw_fd(), name.as_ptr(), if dir { AT_REMOVEDIR } else { 0 }) });
+        }
+        let s = self.statResolved(&r)?;
+        if s.isDir() != dir {
+            return Err(err(if dir { ENOTDIR } else { EISDIR }));
+        }
+        if dir && !self.isEmpty(&r)? {
+            return Err(err(ENOTEMPTY));
+        }
+        self.insert(r.host, Entry::Whiteout);
+        Ok(())
+    }
+
+    // Whether a directory has no entries left, in the overlay or on the host.
+    fn isEmpty(&self, r: &Resolved) -> io::Result<bool> {
+        let o = self.overlay.as_ref().unwrap();
+        if o.iter().any(|(p, e)| p.parent() == Some(&r.host) && !matches!(e, Entry::Whiteout)) {
+            return Ok(false);
+        }
+        if let Some(Entry::Dir(_)) = self.entry(&r.host) {
+            return Ok(true);
+        }
+        let names = listAt(r.dir()?, &r.name)?;
+        Ok(names.iter().all(|n| matches!(self.entry(&r.host.join(n)), Some(Entry::Whiteout))))
+    }
+
+    pub fn mkdir(&mut self, path: &str, mode: u32) -> io::Result<()> {
+        let r = self.resolve(path, false)?;
+        if r.readonly {
+            return Err(err(EROFS));
+        }
+        if self.overlay.is_none() {
+            let name = cName(&r.name)?;
+            return check(unsafe { mkdirat(r.dir()?.as_raw_fd(), name.as_ptr(), mode) });
+        }
+        if self.statResolved(&r).is_ok() {
+            return Err(err(EEXIST));
+        }
+        self.next_ino += 1;
+        let ino = self.next_ino;
+        self.insert(r.host, Entry::Dir(ino));
+        Ok(())
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) -> io::Result<()> {
+        let src = self.resolve(from, false)?;
+        let dst = self.resolve(to, false)?;
+        if src.readonly || dst.readonly {
+            return Err(err(EROFS));
+        }
+        if self.overlay.is_none() {
+            let (s, d) = (cName(&src.name)?, cName(&dst.name)?);
+            return check(unsafe { renameat(src.dir()?.as_raw_fd(), s.as_ptr(), dst.dir()?.as_raw_fd(), d.as_ptr()) });
+        }
+        let node = match self.entry(&src.host) {
+            Some(Entry::File(n))    => n,
+            Some(Entry::Dir(_))     => return Err(err(EINVAL)),  // memo: オーバーレイ内のディレクトリ移動は未対応
+            Some(Entry::Whiteout)   => return Err(err(ENOENT)),
+            None                    => {
+                if src.metadata()?.is_dir() {
+                    return Err(err(EINVAL));
+                }
+                self.newNode(readAll(src.open(O_RDONLY, 0)?)?)
+            },
+        };
+        self.insert(src.host, Entry::Whiteout);
+        self.insert(dst.host, Entry::File(node));
+        Ok(())
+    }
+}
+
+// Lexically resolve "." and ".." against an absolute guest directory. ".." at the root stays at the
+// root, as on Linux.
+fn normalize(cwd: &Path, path: &Path) -> PathBuf {
+    let mut out = if path.is_absolute() { PathBuf::from("/") } else { cwd.to_path_buf() };
+    for c in path.components() {
+        match c {
+            Component::ParentDir    => { out.pop(); },
+            Component::Normal(n)    => out.push(n),
+            _                       => {},
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use crate::testutil::TempDir;
+    use crate::vfs::*;
+
+    // A fresh host directory holding a guest root ("root") and a file outside of it ("outside").
+    fn sandbox(name: &str) -> TempDir {
+        let dir = TempDir::new(&format!("vfs-{}", name));
+        fs::create_dir_all(dir.join("root/etc")).unwrap();
+        fs::write(dir.join("root/etc/passwd"), "guest").unwrap();
+        fs::write(dir.join("outside"), "host").unwrap();
+        dir
+    }
+
+    fn read(v: &mut Vfs, path: &str) -> io::Result<String> {
+        let mut f = v.open(path, &OpenFlags { read: true, ..Default::default() }, 0)?;
+        let mut buf = [0; 64];
+        let n = f.read(&mut buf)?;
+        Ok(String::from_utf8_lossy(&buf[..n]).into_owned())
+    }
+
+    fn create(v: &mut Vfs, path: &str) -> io::Result<()> {
+        let mut f = v.open(path, &OpenFlags { write: true, create: true, ..Default::default() }, 0o644)?;
+        f.write(b"guest").map(|_| ())
+    }
+
+    #[test]
+    fn test_dotdot() {
+        let dir = sandbox("dotdot");
+        let mut v = Vfs::new(&dir.join("root"), PathBuf::from("/etc"));
+        assert_eq!(read(&mut v, "/../../outside").unwrap_err().raw_os_error(), Some(ENOENT));
+        assert_eq!(read(&mut v, "../../etc/passwd").unwrap(), "guest");
+
+        // a relative symlink climbing out stops at the root as well
+        symlink("../../../outside", dir.join("root/etc/up")).unwrap();
+        assert_eq!(read(&mut v, "up").unwrap_err().raw_os_error(), Some(ENOENT));
+        fs::write(dir.join("root/outside"), "root").unwrap();
+        assert_eq!(read(&mut v, "up").unwrap(), "root");
+    }
+
+    #[test]
+    fn test_absolute_symlink() {
+        let dir = sandbox("absolute");
+        let mut v = Vfs::new(&dir.join("root"), PathBuf::from("/"));
+        symlink(dir.join("outside"), dir.join("root/link")).unwrap();
+        symlink("/etc", dir.join("root/etc2")).unwrap();
+
+        // resolved against the root: <root>/<host path of outside> does not exist
+        assert_eq!(read(&mut v, "/link").unwrap_err().raw_os_error(), Some(ENOENT));
+        assert_eq!(read(&mut v, "/etc2/passwd").unwrap(), "guest");
+        assert!(v.stat("/etc2", true).unwrap().isDir());
+        assert!(!v.stat("/etc2", false).unwrap().isDir());
+        assert_eq!(v.readlink("/etc2").unwrap(), PathBuf::from("/etc"));
+        assert_eq!(v.readlink("/etc").unwrap_err().raw_os_error(), Some(EINVAL));
+        assert_eq!(v.readlink("/missing").unwrap_err().raw_os_error(), Some(ENOENT));
+    }
+
+    #[test]
+    fn test_dangling_symlink_create() {
+        let dir = sandbox("dangling");
+        let mut v = Vfs::new(&dir.join("root"), PathBuf::from("/"));
+        symlink(dir.join("created"), dir.join("root/a")).unwrap();
+        symlink("../../created", dir.join("root/b")).unwrap();
+
+        // O_CREAT through a dangling symlink creates the file inside the root, never next to it
+        create(&mut v, "/a").unwrap_err();
+        create(&mut v, "/b").unwrap();
+        assert!(!dir.join("created").exists());
+        assert_eq!(fs::read_to_string(dir.join("root/created")).unwrap(), "guest");
+
+        // without following it, the link itself is removed
+        v.unlink("/b").unwrap();
+        assert!(fs::symlink_metadata(dir.join("root/b")).is_err());
+        assert!(dir.join("root/created").exists());
+    }
+
+    #[test]
+    fn test_readonly_mount() {
+        let dir = sandbox("readonly");
+        fs::create_dir(dir.join("data")).unwrap();
+        fs::write(dir.join("data/f"), "data").unwrap();
+        let mut v = Vfs::new(&dir.join("root"), PathBuf::from("/"));
+        v.mount(Mount::parse(&format!("{}:/mnt/data:ro", dir.join("data").display())).unwrap());
+
+        assert_eq!(read(&mut v, "/mnt/data/f").unwrap(), "data");
+        assert_eq!(create(&mut v, "/mnt/data/g").unwrap_err().raw_os_error(), Some(EROFS));
+        assert_eq!(v.unlink("/mnt/data/f").unwrap_err().raw_os_error(), Some(EROFS));
+        assert_eq!(v.mkdir("/mnt/data/d", 0o755).unwrap_err().raw_os_error(), Some(EROFS));
+        assert_eq!(v.rename("/etc/passwd", "/mnt/data/p").unwrap_err().raw_os_error(), Some(EROFS));
+        assert_eq!(read(&mut v, "/mnt/data/../../etc/passwd").unwrap(), "guest");
+
+        v.setReadonlyRoot(true);
+        assert_eq!(create(&mut v, "/etc/new").unwrap_err().raw_os_error(), Some(EROFS));
+    }
+
+    #[test]
+    fn test_overlay() {
+        let dir = sandbox("overlay");
+        let mut v = Vfs::new(&dir.join("root"), PathBuf::from("/"));
+        v.enableOverlay();
+
+        create(&mut v, "/etc/passwd").unwrap();
+        v.mkdir("/tmp", 0o755).unwrap();
+        create(&mut v, "/tmp/f").unwrap();
+        assert_eq!(read(&mut v, "/tmp/f").unwrap(), "guest");
+
+        // rmdir only removes empty directories, counting host and overlay entries
+        assert_eq!(v.rmdir("/etc").unwrap_err().raw_os_error(), Some(ENOTEMPTY));
+        assert_eq!(v.rmdir("/tmp").unwrap_err().raw_os_error(), Some(ENOTEMPTY));
+        v.unlink("/etc/passwd").unwrap();
+        v.unlink("/tmp/f").unwrap();
+        v.rmdir("/etc").unwrap();
+        v.rmdir("/tmp").unwrap();
+        assert_eq!(v.stat("/etc", true).unwrap_err().raw_os_error(), Some(ENOENT));
+
+        // the host is untouched
+        assert_eq!(fs::read_to_string(dir.join("root/etc/passwd")).unwrap(), "guest");
+        assert!(!dir.join("root/tmp").exists());
+    }
+}